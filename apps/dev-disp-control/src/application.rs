@@ -7,6 +7,7 @@ use iced::{
 
 use crate::{
     backend::{self, ApiFactory, BackendRef},
+    util::api_error_to_display_string,
     widgets::simple_device_info,
 };
 
@@ -26,6 +27,8 @@ where
     connection_state: ConnectionState,
     available_devices: Vec<DisplayHostRef>,
     connected_devices: Vec<DisplayHostRef>,
+    /// Why the last device request failed, until the next one is made
+    last_error: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -65,6 +68,7 @@ where
             connection_state: ConnectionState::Disconnected,
             available_devices: Vec::new(),
             connected_devices: Vec::new(),
+            last_error: None,
         };
 
         (this, Task::batch(tasks))
@@ -77,8 +81,13 @@ where
             ConnectionState::Connected(addr) => format!("Connected to {}", addr),
         });
 
-        let c = Column::new()
-            .push(connected_text)
+        let mut c = Column::new().push(connected_text);
+
+        if let Some(error) = &self.last_error {
+            c = c.push(text(error));
+        }
+
+        let c = c
             .padding(20)
             .spacing(10)
             .push(text("Available Devices:").size(24));
@@ -86,7 +95,11 @@ where
         let mut available = Column::new().padding(10).spacing(10);
 
         for device in &self.available_devices {
-            available = available.push(Container::new(simple_device_info(device, false)))
+            available = available.push(Container::new(simple_device_info(
+                device,
+                false,
+                &self.connected_devices,
+            )))
         }
 
         if self.available_devices.is_empty() {
@@ -104,7 +117,7 @@ where
         let mut connected = Column::new().padding(10).spacing(10);
 
         for device in &self.connected_devices {
-            connected = connected.push(Container::new(simple_device_info(device, true)))
+            connected = connected.push(Container::new(simple_device_info(device, true, &[])))
         }
 
         if self.connected_devices.is_empty() {
@@ -138,8 +151,23 @@ where
                     self.connected_devices = device_collection_status.in_use_devices;
                     Task::none()
                 }
+                backend::BackendEvent::DeviceRequestFailed(error) => {
+                    self.last_error = Some(api_error_to_display_string(&error));
+                    Task::none()
+                }
             },
-            UiAction::BackendCommand(cmd) => Task::future(self.backend_ref.send(cmd)).discard(),
+            UiAction::BackendCommand(cmd) => {
+                if matches!(
+                    cmd,
+                    backend::BackendCommand::InitializeDevice(..)
+                        | backend::BackendCommand::DisconnectDevice(..)
+                        | backend::BackendCommand::MirrorDevice(..)
+                        | backend::BackendCommand::SetRecording(..)
+                ) {
+                    self.last_error = None;
+                }
+                Task::future(self.backend_ref.send(cmd)).discard()
+            }
         }
     }
 }
@@ -1,4 +1,4 @@
-use dev_disp_core::daemon::api::{DeviceCollectionStatus, DiscoveryId, DisplayHostId};
+use dev_disp_core::daemon::api::{ApiError, DeviceCollectionStatus, DiscoveryId, DisplayHostId};
 
 /// Events to communicate to the frontend
 #[derive(Debug, Clone)]
@@ -6,6 +6,8 @@ pub enum BackendEvent {
     Connected(String),
     Disconnected,
     DeviceListUpdated(DeviceCollectionStatus),
+    /// Initializing, mirroring, recording or disconnecting a device failed.
+    DeviceRequestFailed(ApiError),
 }
 
 #[derive(Debug, Clone)]
@@ -16,6 +18,11 @@ pub enum BackendCommand {
     InitializeDevice(DisplayHostId, DiscoveryId),
     /// Attempt to stop using the specified device.
     DisconnectDevice(DisplayHostId, DiscoveryId),
+    /// Attempt to have the specified device mirror the screen of the connected one
+    /// after it.
+    MirrorDevice(DisplayHostId, DiscoveryId, DisplayHostId, DiscoveryId),
+    /// Attempt to start or stop recording the specified device.
+    SetRecording(DisplayHostId, DiscoveryId, bool),
 
     StreamDevices,
 }
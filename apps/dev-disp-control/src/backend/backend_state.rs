@@ -1,9 +1,10 @@
 use crate::{
     backend::{ApiFactory, BackendEvent, DisconnectableApi},
-    util::{MyStreamExt, OkOrLog},
+    util::OkOrLog,
 };
 use dev_disp_core::{
-    daemon::api::{DevDispApi, DeviceCollectionStatus, DiscoveryId, DisplayHostId},
+    core::ErrorKind,
+    daemon::api::{ApiError, DevDispApi, DeviceCollectionStatus, DiscoveryId, DisplayHostId},
     util::{PinnedFuture, PinnedStream},
 };
 use futures::{
@@ -43,6 +44,11 @@ pub enum BackendAction<T, A> {
     InitializeDevice(DisplayHostId, DiscoveryId),
     /// Attempt to stop using the specified device.
     DisconnectDevice(DisplayHostId, DiscoveryId),
+    /// Attempt to have the specified device mirror the screen of the connected one
+    /// after it.
+    MirrorDevice(DisplayHostId, DiscoveryId, DisplayHostId, DiscoveryId),
+    /// Attempt to start or stop recording the specified device.
+    SetRecording(DisplayHostId, DiscoveryId, bool),
     /// A no-op event that can be used when futures need to throw away side-effects
     NoOp,
     /// Emits when the backend loop exits. This signals *no more* reconnectivity,
@@ -149,13 +155,32 @@ where
             }
             BackendAction::InitializeDevice(host_id, discovery_id) => self
                 .initialize_device(host_id, discovery_id)
+                .map(device_request_failed)
                 .into_stream()
-                .no_output()
+                .filter_map(future::ready)
                 .boxed(),
             BackendAction::DisconnectDevice(host_id, discovery_id) => self
                 .disconnect_device(host_id, discovery_id)
+                .map(device_request_failed)
                 .into_stream()
-                .no_output()
+                .filter_map(future::ready)
+                .boxed(),
+            BackendAction::MirrorDevice(
+                host_id,
+                discovery_id,
+                source_host_id,
+                source_discovery_id,
+            ) => self
+                .mirror_device(host_id, discovery_id, source_host_id, source_discovery_id)
+                .map(device_request_failed)
+                .into_stream()
+                .filter_map(future::ready)
+                .boxed(),
+            BackendAction::SetRecording(host_id, discovery_id, enabled) => self
+                .set_recording(host_id, discovery_id, enabled)
+                .map(device_request_failed)
+                .into_stream()
+                .filter_map(future::ready)
                 .boxed(),
             BackendAction::Connected(api, display_endpoint) => {
                 info!(
@@ -270,7 +295,7 @@ where
         &mut self,
         dev_id: DisplayHostId,
         discovery_id: DiscoveryId,
-    ) -> PinnedFuture<'static, Result<(), ()>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         log::info!(
             "Requesting device connection to device {:?} via discovery ID {:?}",
             dev_id,
@@ -280,15 +305,16 @@ where
             Some(api) => api,
             None => {
                 log::error!("Attempted to connect to device without a connected backend");
-                return futures::future::ready(Err(())).boxed();
+                return futures::future::ready(Err(not_connected())).boxed();
             }
         };
 
         backend_api
             .initialize_device(discovery_id, dev_id)
-            .map(|res| {
-                res.ok_or_log("Failed to initialize device");
-                Ok(())
+            .inspect(|res| {
+                if let Err(e) = res {
+                    log::error!("Failed to initialize device: {}", e);
+                }
             })
             .boxed()
     }
@@ -297,7 +323,7 @@ where
         &mut self,
         dev_id: DisplayHostId,
         discovery_id: DiscoveryId,
-    ) -> PinnedFuture<'static, Result<(), ()>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         log::info!(
             "Requesting device disconnection from device {:?} via discovery ID {:?}",
             dev_id,
@@ -307,16 +333,88 @@ where
             Some(api) => api,
             None => {
                 log::error!("Attempted to disconnect from device without a connected backend");
-                return futures::future::ready(Err(())).boxed();
+                return futures::future::ready(Err(not_connected())).boxed();
             }
         };
 
         backend_api
             .disconnect_device(discovery_id, dev_id)
-            .map(|res| {
-                res.ok_or_log("Failed to disconnect device");
-                Ok(())
+            .inspect(|res| {
+                if let Err(e) = res {
+                    log::error!("Failed to disconnect device: {}", e);
+                }
+            })
+            .boxed()
+    }
+
+    fn mirror_device(
+        &mut self,
+        dev_id: DisplayHostId,
+        discovery_id: DiscoveryId,
+        source_dev_id: DisplayHostId,
+        source_discovery_id: DiscoveryId,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        log::info!(
+            "Requesting device {:?} via discovery ID {:?} to mirror device {:?}",
+            dev_id,
+            discovery_id,
+            source_dev_id
+        );
+        let backend_api = match &self.backend_api {
+            Some(api) => api,
+            None => {
+                log::error!("Attempted to mirror a device without a connected backend");
+                return futures::future::ready(Err(not_connected())).boxed();
+            }
+        };
+
+        backend_api
+            .mirror_device(discovery_id, dev_id, source_discovery_id, source_dev_id)
+            .inspect(|res| {
+                if let Err(e) = res {
+                    log::error!("Failed to mirror device: {}", e);
+                }
             })
             .boxed()
     }
+
+    fn set_recording(
+        &mut self,
+        dev_id: DisplayHostId,
+        discovery_id: DiscoveryId,
+        enabled: bool,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        log::info!(
+            "Requesting recording {} for device {:?} via discovery ID {:?}",
+            if enabled { "start" } else { "stop" },
+            dev_id,
+            discovery_id
+        );
+        let backend_api = match &self.backend_api {
+            Some(api) => api,
+            None => {
+                log::error!("Attempted to record a device without a connected backend");
+                return futures::future::ready(Err(not_connected())).boxed();
+            }
+        };
+
+        backend_api
+            .set_recording(discovery_id, dev_id, enabled)
+            .inspect(|res| {
+                if let Err(e) = res {
+                    log::error!("Failed to set recording of device: {}", e);
+                }
+            })
+            .boxed()
+    }
+}
+
+fn not_connected() -> ApiError {
+    ApiError::new(ErrorKind::Unavailable, "Not connected to the daemon")
+}
+
+/// Lets the frontend know about a failed device request.
+fn device_request_failed<T, A>(res: Result<(), ApiError>) -> Option<EventType<T, A>> {
+    res.err()
+        .map(|e| EventType::ToFrontend(BackendEvent::DeviceRequestFailed(e)))
 }
@@ -32,6 +32,12 @@ where
         BackendCommand::DisconnectDevice(a, b) => {
             EventType::InternalState(BackendAction::DisconnectDevice(a, b))
         }
+        BackendCommand::MirrorDevice(a, b, c, d) => {
+            EventType::InternalState(BackendAction::MirrorDevice(a, b, c, d))
+        }
+        BackendCommand::SetRecording(a, b, c) => {
+            EventType::InternalState(BackendAction::SetRecording(a, b, c))
+        }
         BackendCommand::StreamDevices => EventType::InternalState(BackendAction::StreamDevices),
     });
 
@@ -1,4 +1,7 @@
-use dev_disp_core::daemon::api::{DisplayHostStatus, InitializationState};
+use dev_disp_core::{
+    core::ErrorKind,
+    daemon::api::{ApiError, DisplayHostStatus, InitializationState},
+};
 use futures::{FutureExt, Stream, StreamExt};
 
 pub trait OkOrLogMsg<T> {
@@ -77,6 +80,8 @@ pub fn status_to_display_string(status: &DisplayHostStatus) -> String {
                 InitializationState::NegotiatingCodecs => "Negotiating codecs with client",
                 InitializationState::InitializingEncoder => "Initializing encoder",
                 InitializationState::SettingClientCodec => "Setting client codec",
+                InitializationState::Renegotiating => "Renegotiating with client",
+                InitializationState::AwaitingReconnect => "Waiting for the client to reconnect",
             };
 
             format!("Initializing: {}", phase_display_str)
@@ -85,6 +90,22 @@ pub fn status_to_display_string(status: &DisplayHostStatus) -> String {
     }
 }
 
+pub fn api_error_to_display_string(error: &ApiError) -> String {
+    let kind_display_str = match error.kind {
+        ErrorKind::Unknown => "Error",
+        ErrorKind::NotFound => "Not found",
+        ErrorKind::PermissionDenied => "Permission denied",
+        ErrorKind::Unavailable => "Unavailable",
+        ErrorKind::Unsupported => "Not supported",
+        ErrorKind::Timeout => "Timed out",
+        ErrorKind::Disconnected => "Lost the connection",
+        ErrorKind::Cancelled => "Cancelled",
+        ErrorKind::Internal => "Daemon error",
+    };
+
+    format!("{}: {}", kind_display_str, error.message)
+}
+
 pub trait MyStreamExt: Stream {
     /// Transforms a stream of any type into a stream that discards all items and outputs nothing.
     /// This can be useful when you want to execute a stream for its side effects but don't care about the output.
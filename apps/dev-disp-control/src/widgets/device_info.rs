@@ -11,7 +11,13 @@ use crate::{
     widgets::{code_text, label},
 };
 
-pub fn simple_device_info(device: &DisplayHostRef, connected: bool) -> Container<'_, UiAction> {
+/// Available devices get to mirror any of `mirror_sources` that runs a session of its
+/// own.
+pub fn simple_device_info<'a>(
+    device: &'a DisplayHostRef,
+    connected: bool,
+    mirror_sources: &'a [DisplayHostRef],
+) -> Container<'a, UiAction> {
     Container::new(
         Column::new()
             .push(label("Name:", text(&device.name)))
@@ -21,6 +27,17 @@ pub fn simple_device_info(device: &DisplayHostRef, connected: bool) -> Container
                 "Status:",
                 text(status_to_display_string(&device.status)),
             ))
+            .extend(
+                device
+                    .stream_policy
+                    .map(|policy| label("Stream:", text(policy.to_string())).into()),
+            )
+            .extend(
+                device
+                    .mirror_of
+                    .as_ref()
+                    .map(|source| label("Mirroring:", code_text(&source.device_id)).into()),
+            )
             .push(if connected {
                 let mut disconnect_button = button("Disconnect");
                 if device.status == DisplayHostStatus::InUse
@@ -46,6 +63,45 @@ pub fn simple_device_info(device: &DisplayHostRef, connected: bool) -> Container
                 };
                 connect_button
             })
+            .extend(
+                // Mirrors are shown what their source is sent, which is what gets
+                // recorded
+                (connected
+                    && device.mirror_of.is_none()
+                    && device.status == DisplayHostStatus::InUse)
+                    .then(|| {
+                        let (title, enabled) = match device.recording {
+                            true => ("Stop recording", false),
+                            false => ("Record", true),
+                        };
+                        button(title)
+                            .on_press(UiAction::BackendCommand(BackendCommand::SetRecording(
+                                device.id.clone(),
+                                device.discovery_id.clone(),
+                                enabled,
+                            )))
+                            .into()
+                    }),
+            )
+            .extend(
+                mirror_sources
+                    .iter()
+                    .filter(|source| {
+                        device.status == DisplayHostStatus::Available
+                            && source.mirror_of.is_none()
+                            && source.status == DisplayHostStatus::InUse
+                    })
+                    .map(|source| {
+                        button(text(format!("Mirror {}", source.name)))
+                            .on_press(UiAction::BackendCommand(BackendCommand::MirrorDevice(
+                                device.id.clone(),
+                                device.discovery_id.clone(),
+                                source.id.clone(),
+                                source.discovery_id.clone(),
+                            )))
+                            .into()
+                    }),
+            )
             .spacing(5)
             .width(400),
     )
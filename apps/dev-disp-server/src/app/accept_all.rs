@@ -1,9 +1,11 @@
+use dev_disp_clipboard::SystemClipboardProvider;
 use dev_disp_core::{
     client::ScreenTransport,
-    core::handle_display_host,
-    host::{ConnectableDevice, ScreenProvider, StreamingDeviceDiscovery},
+    core::{SessionContext, StreamPolicy, handle_display_host},
+    host::{ConnectableDevice, DeviceEvent, ScreenProvider, StreamingDeviceDiscovery},
 };
 use dev_disp_encoders::ffmpeg::{FfmpegEncoderProvider, config_file::FfmpegConfiguration};
+use dev_disp_input::UinputInjectorProvider;
 use futures_util::{StreamExt, sink, stream::empty};
 use log::{error, info};
 
@@ -27,9 +29,10 @@ where
         })
         .unwrap_or_default();
 
-    while let Some(devices) = discovery.next().await {
-        info!("Discovered {} device(s)", devices.len());
-        for device in devices {
+    while let Some(event) = discovery.next().await {
+        // Devices that were already around were connected when they showed up
+        if let DeviceEvent::Added(device) = event {
+            info!("Discovered device '{}'", device.get_info().name);
             let connect_result = device.connect().await;
 
             if let Err(e) = connect_result {
@@ -44,12 +47,23 @@ where
 
             let ffmpeg_config = ffmpeg_config.clone();
             let _ = tokio::task::spawn_local(async move {
+                let context = SessionContext {
+                    policy: StreamPolicy::default(),
+                    input_provider: UinputInjectorProvider::new(),
+                    clipboard_provider: None::<SystemClipboardProvider>,
+                    mirrors: empty().boxed_local(),
+                    recordings: empty().boxed_local(),
+                    status_sink: sink::drain(),
+                    policy_sink: sink::drain(),
+                    stats_sink: sink::drain(),
+                };
                 let handle_result = handle_display_host(
                     provider_1,
                     FfmpegEncoderProvider::new(ffmpeg_config),
                     display,
+                    None,
+                    context,
                     empty(),
-                    sink::drain(),
                 )
                 .await;
 
@@ -1,54 +1,138 @@
-use crate::util::BroadcastSink;
+use crate::{device_config::DeviceConfiguration, util::BroadcastSink};
 use arc_swap::ArcSwap;
 use dev_disp_core::{
-    client::ScreenTransport,
-    core::{SystemState, handle_display_host},
+    client::{DisplayHost, ScreenTransport, SomeScreenTransport, TransportError},
+    core::{
+        ErrorKind, GoodbyeReason, MirrorJoin, SessionContext, SessionError, SessionResume,
+        SessionStats, SessionToken, StreamPolicy, SystemState, get_default_recordings_path,
+        handle_display_host,
+    },
     daemon::api::{
-        DevDispApi, DeviceCollectionStatus, DiscoveryId, DiscoveryRef, DisplayHostId,
-        DisplayHostRef, DisplayHostStatus, InitializationState,
+        ApiError, DevDispApi, DeviceCollectionStatus, DiscoveryId, DiscoveryRef, DisplayHostId,
+        DisplayHostRef, DisplayHostStatus, InitializationState, MirrorSourceRef,
     },
     host::{
-        ConnectableDevice, DeviceDiscovery, EncoderProvider, PollingDeviceDiscovery,
-        ScreenProvider, StreamingDeviceDiscovery,
+        ClipboardProvider, ConnectableDevice, ConnectableDeviceInfo, DeviceDiscovery, DeviceEvent,
+        EncoderProvider, InputInjectorProvider, PollingDeviceDiscovery, ScreenProvider,
+        StreamingDeviceDiscovery,
     },
-    util::{PinnedFuture, PinnedLocalFuture, PinnedStream},
+    util::{PinnedFuture, PinnedLocalFuture, PinnedLocalStream, PinnedStream},
 };
-use futures_util::{FutureExt, StreamExt};
+use futures_util::{FutureExt, StreamExt, TryFutureExt};
 use log::{debug, error, info, warn};
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
 use tokio::{
     sync::{
         RwLock, broadcast,
         mpsc::{self, error::SendError},
+        oneshot, watch,
     },
     task::JoinSet,
 };
-use tokio_stream::wrappers::{BroadcastStream, ReceiverStream, errors::BroadcastStreamRecvError};
+use tokio_stream::wrappers::{
+    BroadcastStream, ReceiverStream, WatchStream, errors::BroadcastStreamRecvError,
+};
+
+/// Told how the session of a taken device got started.
+type StartedTx = oneshot::Sender<Result<(), ApiError>>;
+
+/// Connects a device that is to mirror a session.
+type ConnectMirror = Box<
+    dyn FnOnce() -> PinnedLocalFuture<
+            'static,
+            Result<DisplayHost<SomeScreenTransport>, Box<dyn std::error::Error + Send + Sync>>,
+        > + Send,
+>;
+
+/// Takes a device, for a session of its own or to mirror the session of another.
+struct TakeRequest {
+    started: StartedTx,
+    mirror_of: Option<MirrorTarget>,
+}
+
+/// The session a taken device is to mirror.
+struct MirrorTarget {
+    source: MirrorSourceRef,
+    requests: mpsc::Sender<MirrorRequest>,
+}
+
+/// A device asking to join a running session as a mirror. It's connected on the
+/// session's thread, like the session's own device.
+struct MirrorRequest {
+    connect: ConnectMirror,
+    cancel: PinnedStream<'static, GoodbyeReason>,
+    started: futures::channel::oneshot::Sender<Result<(), SessionError>>,
+    left: futures::channel::oneshot::Sender<()>,
+}
 
 #[derive(Debug, Clone)]
 pub struct ReadyDeviceRef {
     pub name: String,
     pub discovery_id: String,
     pub id: String,
-    take_tx: mpsc::Sender<()>,
+    /// Set when the device is listed but can't be initialized.
+    pub unavailable_reason: Option<String>,
+    take_tx: mpsc::Sender<TakeRequest>,
 }
 
 impl ReadyDeviceRef {
-    pub fn new(name: String, discovery_id: String, id: String) -> (Self, mpsc::Receiver<()>) {
+    pub fn new(
+        name: String,
+        discovery_id: String,
+        id: String,
+        unavailable_reason: Option<String>,
+    ) -> (Self, mpsc::Receiver<TakeRequest>) {
         let (take_tx, take_rx) = mpsc::channel(1);
         (
             Self {
                 name,
                 discovery_id,
                 id,
+                unavailable_reason,
                 take_tx,
             },
             take_rx,
         )
     }
 
-    pub async fn take(&self) -> Result<(), SendError<()>> {
-        self.take_tx.send(()).await
+    pub fn status(&self) -> DisplayHostStatus {
+        match &self.unavailable_reason {
+            Some(reason) => DisplayHostStatus::Error(reason.clone()),
+            None => DisplayHostStatus::Available,
+        }
+    }
+
+    /// Takes the device for a session, and waits for the session to get going.
+    pub async fn take(&self) -> Result<(), ApiError> {
+        self.request(None).await
+    }
+
+    /// Takes the device to mirror another's session, and waits for it to be shown the
+    /// screen.
+    async fn take_as_mirror(&self, target: MirrorTarget) -> Result<(), ApiError> {
+        self.request(Some(target)).await
+    }
+
+    async fn request(&self, mirror_of: Option<MirrorTarget>) -> Result<(), ApiError> {
+        let (started_tx, started_rx) = oneshot::channel();
+        let request = TakeRequest {
+            started: started_tx,
+            mirror_of,
+        };
+        self.take_tx
+            .send(request)
+            .await
+            .map_err(|_| ApiError::new(ErrorKind::Unavailable, "The device is gone"))?;
+        started_rx.await.unwrap_or_else(|_| {
+            Err(ApiError::new(
+                ErrorKind::Cancelled,
+                "The session ended before it got going",
+            ))
+        })
     }
 }
 
@@ -58,9 +142,19 @@ pub struct InUseDeviceRef {
     pub discovery_id: String,
     pub id: String,
     pub status: Arc<ArcSwap<SystemState>>,
+    /// Set once the session settled on how to stream
+    pub stream_policy: Arc<ArcSwap<Option<StreamPolicy>>>,
+    /// Set when the device mirrors the session of another
+    pub mirror_of: Option<MirrorSourceRef>,
     // TODO: current status atomic slot!
-    disconnect_tx: mpsc::Sender<()>,
+    disconnect_tx: mpsc::Sender<GoodbyeReason>,
+    /// Devices asking to mirror the session, for devices with a session of their own
+    mirror_tx: Option<mpsc::Sender<MirrorRequest>>,
+    /// Where the session records to, if it does. Only for devices with a session of
+    /// their own.
+    recording_tx: Option<Arc<watch::Sender<Option<PathBuf>>>>,
     status_tx: broadcast::Sender<SystemState>,
+    stats_tx: broadcast::Sender<SessionStats>,
 }
 
 impl InUseDeviceRef {
@@ -69,36 +163,69 @@ impl InUseDeviceRef {
         discovery_id: String,
         id: String,
         status: Arc<ArcSwap<SystemState>>,
-    ) -> (Self, mpsc::Receiver<()>) {
+        stream_policy: Arc<ArcSwap<Option<StreamPolicy>>>,
+        mirror_of: Option<MirrorSourceRef>,
+        mirror_tx: Option<mpsc::Sender<MirrorRequest>>,
+        recording_tx: Option<watch::Sender<Option<PathBuf>>>,
+    ) -> (Self, mpsc::Receiver<GoodbyeReason>) {
         let (disconnect_tx, disconnect_rx) = mpsc::channel(1);
         let (status_tx, _) = broadcast::channel(16);
+        let (stats_tx, _) = broadcast::channel(16);
         (
             Self {
                 name,
                 discovery_id,
                 id,
                 disconnect_tx,
+                mirror_tx,
+                recording_tx: recording_tx.map(Arc::new),
                 status_tx,
+                stats_tx,
                 status,
+                stream_policy,
+                mirror_of,
             },
             disconnect_rx,
         )
     }
 
-    pub async fn disconnect(&self) -> Result<(), SendError<()>> {
+    /// Closes the session, telling the display host why.
+    pub async fn disconnect(&self, reason: GoodbyeReason) -> Result<(), SendError<GoodbyeReason>> {
         let _ = self.status_tx.send(SystemState::Stopped);
         // TODO: Actually wait for the disconnection to complete!
-        self.disconnect_tx.send(()).await
+        self.disconnect_tx.send(reason).await
     }
 
     pub fn listen_status(&self) -> BroadcastStream<SystemState> {
         BroadcastStream::new(self.status_tx.subscribe())
     }
 
+    /// Statistics of the session, as the session reports them.
+    pub fn listen_stats(&self) -> BroadcastStream<SessionStats> {
+        BroadcastStream::new(self.stats_tx.subscribe())
+    }
+
     pub fn get_current_status(&self) -> SystemState {
         // Note that this is driven by handler code in the app.
         **self.status.load()
     }
+
+    pub fn get_current_stream_policy(&self) -> Option<StreamPolicy> {
+        **self.stream_policy.load()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording_tx
+            .as_ref()
+            .is_some_and(|recording_tx| recording_tx.borrow().is_some())
+    }
+}
+
+/// A session that takes its device back if it reconnects while the session waits
+/// for it.
+struct ResumableSession<C> {
+    status: Arc<ArcSwap<SystemState>>,
+    reconnect_tx: mpsc::Sender<C>,
 }
 
 #[derive(Debug, Clone)]
@@ -130,29 +257,46 @@ impl DiscoveryMethod {
 
 /// App keeps track of the current available devices, and in-use devices.
 #[derive(Debug, Clone)]
-pub struct App<S, E>
+pub struct App<S, E, I, K>
 where
     S: ScreenProvider + Clone + Send + 'static,
     E: EncoderProvider + Clone + Send + 'static,
+    I: InputInjectorProvider + Clone + Send + 'static,
+    K: ClipboardProvider + Clone + Send + 'static,
 {
     screen_provider: S,
     encoder_provider: E,
+    input_provider: I,
+    /// Only handed to devices that opted in to clipboard sharing
+    clipboard_provider: K,
+    device_config: DeviceConfiguration,
     available_devices: Arc<RwLock<HashMap<DiscoveryId, HashMap<DisplayHostId, ReadyDeviceRef>>>>,
     in_use_devices: Arc<RwLock<HashMap<DiscoveryId, HashMap<DisplayHostId, InUseDeviceRef>>>>,
     discovery_methods: Arc<RwLock<HashMap<DiscoveryId, DiscoveryMethod>>>,
     devices_change_tx: broadcast::Sender<()>,
 }
 
-impl<S, E> App<S, E>
+impl<S, E, I, K> App<S, E, I, K>
 where
     S: ScreenProvider + Clone + Send + 'static,
     E: EncoderProvider + Clone + Send + 'static,
+    I: InputInjectorProvider + Clone + Send + 'static,
+    K: ClipboardProvider + Clone + Send + 'static,
 {
-    pub fn new(screen_provider: S, encoder_provider: E) -> Self {
+    pub fn new(
+        screen_provider: S,
+        encoder_provider: E,
+        input_provider: I,
+        clipboard_provider: K,
+        device_config: DeviceConfiguration,
+    ) -> Self {
         let (devices_change_tx, _) = broadcast::channel(128);
         Self {
             screen_provider,
             encoder_provider,
+            input_provider,
+            clipboard_provider,
+            device_config,
             available_devices: Arc::new(RwLock::new(HashMap::new())),
             in_use_devices: Arc::new(RwLock::new(HashMap::new())),
             discovery_methods: Arc::new(RwLock::new(HashMap::new())),
@@ -184,7 +328,7 @@ where
                 let device_ref = device_ref.clone();
                 disconnect_tasks.spawn(async move {
                     info!("Disconnecting device '{}'", device_ref.name);
-                    match device_ref.disconnect().await {
+                    match device_ref.disconnect(GoodbyeReason::HostShutdown).await {
                         Ok(_) => {
                             info!("Device '{}' disconnected successfully", device_ref.name);
                         }
@@ -214,19 +358,11 @@ where
     }
 
     /// Given a device discovery instance, listen to the devices it discovers and hold
-    /// them in the available devices list.
-    ///
-    /// ### Implementation note:
+    /// them in the available devices list. Started discoveries come in as
+    /// `Box<dyn DynDeviceDiscovery>`, but any streaming discovery will do.
     ///
-    /// A lot of logic is defined in this single function because we can't quite pass
-    /// the generics around easily. We play nice with Rust's monomorphization rules, and
-    /// leave the entire generic logic here inside this function.
-    ///
-    /// The alternative would be to box a lot of things, which wouldn't be super performant.
-    /// However, performance doesn't quite matter at this stage. But... it does matter at
-    /// the display handling stage, so we keep that part generic and monomorphized. We
-    /// cannot go generic -> boxed -> generic again (as far as I know), so we keep the
-    /// generics here.
+    /// A listed device that changes keeps its place in the list, and is connected as
+    /// it was last listed when it's taken.
     pub fn setup_discovery<D, C, T>(
         &self,
         discovery: D,
@@ -244,8 +380,13 @@ where
         let in_use_devices = self.in_use_devices.clone();
         let screen_provider = self.screen_provider.clone();
         let encoder_provider = self.encoder_provider.clone();
+        let input_provider = self.input_provider.clone();
+        let clipboard_provider = self.clipboard_provider.clone();
+        let device_config = self.device_config.clone();
         let devices_change_tx = self.devices_change_tx.clone();
         let discovery_methods = self.discovery_methods.clone();
+        let resumable_sessions: Arc<Mutex<HashMap<SessionToken, ResumableSession<C>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
 
         // TODO: Handle the indentation party below (make functions to reduce indentation)
 
@@ -254,6 +395,9 @@ where
             let discovery_id = discovery_id;
             let screen_provider = screen_provider;
             let encoder_provider = encoder_provider;
+            let input_provider = input_provider;
+            let clipboard_provider = clipboard_provider;
+            let device_config = device_config;
             let devices_change_tx = devices_change_tx;
             let discovery_methods = discovery_methods;
 
@@ -269,28 +413,65 @@ where
 
             let stop_discover_fut = stop_discovery_rx.recv().boxed();
             let mut discovery = discovery.take_until(stop_discover_fut);
+            // Where the take task of each listed device hears about it changing
+            let mut device_updates: HashMap<DisplayHostId, mpsc::UnboundedSender<C>> =
+                HashMap::new();
 
-            while let Some(devices) = discovery.next().await {
+            while let Some(event) = discovery.next().await {
                 let mut write_guard = available_devices.write().await;
 
                 let entry = write_guard
                     .entry(discovery_id.clone())
                     .or_insert_with(HashMap::new);
 
-                entry.clear();
+                let device = match event {
+                    DeviceEvent::Added(device) | DeviceEvent::Changed(device) => Some(device),
+                    DeviceEvent::Removed(id) => {
+                        device_updates.remove(&id);
+                        if let Some(device_ref) = entry.remove(&id) {
+                            info!(
+                                "Device '{}' is gone from interface '{}'",
+                                device_ref.name, discovery_name
+                            );
+                        }
+                        None
+                    }
+                };
+                // Devices coming back to their session aren't up for grabs
+                let device = device.and_then(|device| {
+                    let id = device.get_info().id;
+                    let device = resume_session(&resumable_sessions, device);
+                    if device.is_none() {
+                        entry.remove(&id);
+                        device_updates.remove(&id);
+                    }
+                    device
+                });
+                // Ones that changed while listed keep their take task
+                let device =
+                    device.and_then(|device| update_listed_device(entry, &device_updates, device));
 
-                for device in devices {
+                if let Some(device) = device {
                     let info = device.get_info();
+
                     let (device_ref, mut take_rx) = ReadyDeviceRef::new(
                         info.name.clone(),
                         discovery_id.clone(),
                         info.id.clone(),
+                        info.unavailable_reason.clone(),
                     );
 
                     entry.insert(device_ref.id.clone(), device_ref);
+                    let (update_tx, mut update_rx) = mpsc::unbounded_channel();
+                    device_updates.insert(info.id.clone(), update_tx);
 
                     let screen_provider_clone = screen_provider.clone();
                     let encoder_provider_clone = encoder_provider.clone();
+                    let input_provider_clone = input_provider.clone();
+                    let clipboard_provider_clone = clipboard_provider.clone();
+                    let device_config_clone = device_config.clone();
+                    let resume_grace_period = device_config.session_resume_grace_period();
+                    let resumable_sessions = resumable_sessions.clone();
                     let available_devices = available_devices.clone();
                     let in_use_devices = in_use_devices.clone();
                     let discovery_id = discovery_id.clone();
@@ -299,19 +480,19 @@ where
                     // Spawn a task to handle if/when this device is taken.
                     tokio::task::spawn_local(async move {
                         let info = info;
-                        let device = device;
+                        let mut device = device;
                         let screen_provider = screen_provider_clone;
                         let encoder_provider = encoder_provider_clone;
+                        let input_provider = input_provider_clone;
+                        let device_config = device_config_clone;
                         let available_devices = available_devices;
                         let in_use_devices = in_use_devices;
                         let discovery_id = discovery_id;
                         let device_change_tx = devices_change_tx_clone;
-                        if take_rx.recv().await.is_none() {
+                        let Some(TakeRequest { started, mirror_of }) = take_rx.recv().await else {
                             // Device was not taken before other half dropped
                             return;
-                        }
-                        info!("Initiating device '{}'", info.name);
-
+                        };
                         available_devices
                             .write()
                             .await
@@ -320,15 +501,67 @@ where
                                 devices_map.remove(&info.id);
                             });
 
+                        // Connect the device as it was last listed. Off the list, it
+                        // doesn't change anymore.
+                        while let Ok(changed) = update_rx.try_recv() {
+                            device = changed;
+                        }
+                        let info = device.get_info();
+                        let clipboard_provider = device_config
+                            .settings_for(&info.name)
+                            .clipboard
+                            .then_some(clipboard_provider_clone);
+                        let stream_policy = device_config.stream_policy_for(&info.name);
+                        info!("Initiating device '{}'", info.name);
+
+                        if let Some(target) = mirror_of {
+                            run_mirror(
+                                device,
+                                info,
+                                discovery_id,
+                                target,
+                                started,
+                                in_use_devices,
+                                device_change_tx,
+                            )
+                            .await;
+                            return;
+                        }
+                        let started_tx = Arc::new(Mutex::new(Some(started)));
+
                         let status_slot = Arc::new(ArcSwap::from_pointee(SystemState::Unknown));
+                        let policy_slot = Arc::new(ArcSwap::from_pointee(None));
+                        let (mirror_tx, mirror_rx) = mpsc::channel(4);
+                        let (recording_tx, recording_rx) = watch::channel(None);
                         let (in_use_device_ref, cancel_rx) = InUseDeviceRef::new(
                             info.name.clone(),
                             discovery_id.clone(),
                             info.id.clone(),
                             status_slot.clone(),
+                            policy_slot.clone(),
+                            None,
+                            Some(mirror_tx),
+                            Some(recording_tx),
                         );
+                        let (policy_tx, policy_rx) = broadcast::channel(4);
 
                         let device_status_tx = in_use_device_ref.status_tx.clone();
+                        let stats_tx = in_use_device_ref.stats_tx.clone();
+
+                        // Let the device come back to this session if it gets lost
+                        let resume = resume_grace_period.map(|grace_period| {
+                            let token = SessionToken::new(uuid::Uuid::new_v4().to_string());
+                            let (reconnect_tx, reconnect_rx) = mpsc::channel(1);
+                            resumable_sessions.lock().unwrap().insert(
+                                token.clone(),
+                                ResumableSession {
+                                    status: status_slot.clone(),
+                                    reconnect_tx,
+                                },
+                            );
+                            (token, grace_period, reconnect_rx)
+                        });
+                        let resume_token = resume.as_ref().map(|(token, _, _)| token.clone());
 
                         in_use_devices
                             .write()
@@ -348,6 +581,7 @@ where
                         let device_change_tx_clone = device_change_tx.clone();
                         let device_name = info.name.clone();
                         let device_name_clone = device_name.clone();
+                        let started_tx_clone = started_tx.clone();
 
                         // Fork off screen handling on a new thread with its own runtime.
                         // This is done because some transport or screen implementations
@@ -367,20 +601,42 @@ where
                                 let device_status_tx = device_status_tx_clone;
                                 let device_name = device_name_clone;
                                 let device_change_tx = device_change_tx_clone;
+                                let started_tx = started_tx_clone;
                                 match device.connect().await {
                                     Ok(display) => {
                                         info!("Device '{}' initiated successfully", device_name);
+                                        let resume =
+                                            resume.map(|(token, grace_period, reconnect_rx)| {
+                                                SessionResume {
+                                                    token,
+                                                    grace_period,
+                                                    reconnections: reconnections(reconnect_rx),
+                                                }
+                                            });
+                                        let context = SessionContext {
+                                            policy: stream_policy,
+                                            input_provider,
+                                            clipboard_provider,
+                                            mirrors: mirror_joins(mirror_rx),
+                                            recordings: WatchStream::new(recording_rx)
+                                                .boxed_local(),
+                                            status_sink: BroadcastSink::new(device_status_tx),
+                                            policy_sink: BroadcastSink::new(policy_tx),
+                                            stats_sink: BroadcastSink::new(stats_tx),
+                                        };
                                         let handle_result = handle_display_host(
                                             screen_provider,
                                             encoder_provider,
                                             display,
+                                            resume,
+                                            context,
                                             ReceiverStream::new(cancel_rx),
-                                            BroadcastSink::new(device_status_tx),
                                         )
                                         .await;
 
                                         if let Err(e) = handle_result {
                                             error!("Error handling display host: {}", e);
+                                            report_started(&started_tx, Err(e.into()));
                                         } else {
                                             info!("Display host handling completed successfully");
                                         }
@@ -390,9 +646,21 @@ where
                                             "Failed to initiate device '{}': {}",
                                             device_name, e
                                         );
+                                        report_started(
+                                            &started_tx,
+                                            Err(ApiError::new(
+                                                ErrorKind::Unavailable,
+                                                format!("Failed to connect: {}", e),
+                                            )),
+                                        );
                                     }
                                 };
 
+                                // The session is over, nothing to resume anymore
+                                if let Some(token) = &resume_token {
+                                    resumable_sessions.lock().unwrap().remove(token);
+                                }
+
                                 // After above is done, remove from in-use devices
                                 in_use_devices
                                     .write()
@@ -418,12 +686,32 @@ where
                             })
                         });
 
+                        // Keep the policy the session reports around for the API
+                        let policy_change_tx = device_change_tx.clone();
+                        let policy_device_name = device_name.clone();
+                        tokio::task::spawn_local(async move {
+                            let mut policy_rx = BroadcastStream::new(policy_rx);
+                            while let Some(policy_res) = policy_rx.next().await {
+                                let Ok(policy) = policy_res else {
+                                    continue;
+                                };
+                                info!("Device '{}' streams with {}", policy_device_name, policy);
+                                policy_slot.store(Arc::new(Some(policy)));
+                                if policy_change_tx.send(()).is_err() {
+                                    debug!("Failed to notify device change listeners");
+                                }
+                            }
+                        });
+
                         // This task will remain to listen for device updates
                         let mut status_rx = BroadcastStream::new(device_status_tx.subscribe());
                         while let Some(status_res) = status_rx.next().await {
                             match status_res {
                                 Ok(status) => {
                                     debug!("Device '{}' status update: {:?}", device_name, status);
+                                    if status == SystemState::Running {
+                                        report_started(&started_tx, Ok(()));
+                                    }
                                     status_slot.store(Arc::new(status));
                                     match device_change_tx.send(()) {
                                         Ok(a) => {
@@ -448,7 +736,7 @@ where
                 }
 
                 info!(
-                    "Discovered {} device(s) on interface '{}'",
+                    "{} device(s) available on interface '{}'",
                     entry.len(),
                     discovery_name
                 );
@@ -503,23 +791,133 @@ where
     }
 
     /// Attempt to connect to an available device, using its discovery ID and device ID.
+    /// Resolves once the device's session is running.
     pub fn initialize_device(
         &self,
         from_discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), ()>> {
-        // TODO: Better error types!
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         let available_devices = self.available_devices.clone();
         async move {
-            let read_guard = available_devices.read().await;
-            let device = read_guard
-                .get(&from_discovery_id)
-                .and_then(|devices_map| devices_map.get(&device_id))
-                .cloned()
-                .ok_or(())?;
+            let device = find_ready_device(
+                &*available_devices.read().await,
+                &from_discovery_id,
+                &device_id,
+            )?;
+
+            // The session takes the device off the available list, so nothing may be
+            // locked while waiting for it
+            device.take().await
+        }
+        .boxed()
+    }
 
-            device.take().await.map_err(|_| ())?;
+    /// Attempt to have an available device mirror the session of a connected one.
+    /// Resolves once the device is shown the screen.
+    pub fn mirror_device(
+        &self,
+        from_discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        source_discovery_id: DiscoveryId,
+        source_device_id: DisplayHostId,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        let available_devices = self.available_devices.clone();
+        let in_use_devices = self.in_use_devices.clone();
+        async move {
+            let requests = in_use_devices
+                .read()
+                .await
+                .get(&source_discovery_id)
+                .and_then(|devices_map| devices_map.get(&source_device_id))
+                .ok_or_else(|| {
+                    ApiError::new(
+                        ErrorKind::NotFound,
+                        format!("No connected device '{}'", source_device_id),
+                    )
+                })?
+                .mirror_tx
+                .clone()
+                .ok_or_else(|| {
+                    ApiError::new(
+                        ErrorKind::Unavailable,
+                        format!("Device '{}' is a mirror itself", source_device_id),
+                    )
+                })?;
+            let device = find_ready_device(
+                &*available_devices.read().await,
+                &from_discovery_id,
+                &device_id,
+            )?;
+
+            let target = MirrorTarget {
+                source: MirrorSourceRef {
+                    discovery_id: source_discovery_id,
+                    device_id: source_device_id,
+                },
+                requests,
+            };
+            device.take_as_mirror(target).await
+        }
+        .boxed()
+    }
 
+    /// Starts or stops recording the session of a connected device. Recordings go
+    /// where the device configuration says, or to the local data directory.
+    pub fn set_recording(
+        &self,
+        from_discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        enabled: bool,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        let in_use_devices = self.in_use_devices.clone();
+        let devices_change_tx = self.devices_change_tx.clone();
+        let recordings_path = self
+            .device_config
+            .recordings_path
+            .clone()
+            .or_else(get_default_recordings_path);
+        async move {
+            let recording_tx = in_use_devices
+                .read()
+                .await
+                .get(&from_discovery_id)
+                .and_then(|devices_map| devices_map.get(&device_id))
+                .ok_or_else(|| {
+                    ApiError::new(
+                        ErrorKind::NotFound,
+                        format!("No connected device '{}'", device_id),
+                    )
+                })?
+                .recording_tx
+                .clone()
+                .ok_or_else(|| {
+                    ApiError::new(
+                        ErrorKind::Unavailable,
+                        format!(
+                            "Device '{}' is a mirror, record the device it mirrors",
+                            device_id
+                        ),
+                    )
+                })?;
+
+            let directory = match enabled {
+                true => Some(recordings_path.ok_or_else(|| {
+                    ApiError::new(
+                        ErrorKind::Unavailable,
+                        "There is nowhere to put recordings, set a recordings path",
+                    )
+                })?),
+                false => None,
+            };
+            info!(
+                "{} recording device '{}'",
+                if enabled { "Started" } else { "Stopped" },
+                device_id
+            );
+            recording_tx.send_replace(directory);
+            if devices_change_tx.send(()).is_err() {
+                debug!("Failed to notify device change listeners");
+            }
             Ok(())
         }
         .boxed()
@@ -529,17 +927,26 @@ where
         &self,
         from_discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), ()>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         let in_use_devices = self.in_use_devices.clone();
         async move {
             let read_guard = in_use_devices.read().await;
+            let not_found = || {
+                ApiError::new(
+                    ErrorKind::NotFound,
+                    format!("No connected device '{}'", device_id),
+                )
+            };
             let device = read_guard
                 .get(&from_discovery_id)
                 .and_then(|devices_map| devices_map.get(&device_id))
                 .cloned()
-                .ok_or(())?;
+                .ok_or_else(not_found)?;
 
-            device.disconnect().await.map_err(|_| ())?;
+            device
+                .disconnect(GoodbyeReason::UserDisconnect)
+                .await
+                .map_err(|_| not_found())?;
 
             Ok(())
         }
@@ -547,10 +954,12 @@ where
     }
 }
 
-impl<S, E> DevDispApi for App<S, E>
+impl<S, E, I, K> DevDispApi for App<S, E, I, K>
 where
     S: ScreenProvider + Clone + Send + 'static,
     E: EncoderProvider + Clone + Send + 'static,
+    I: InputInjectorProvider + Clone + Send + 'static,
+    K: ClipboardProvider + Clone + Send + 'static,
 {
     fn get_devices(
         &self,
@@ -569,10 +978,13 @@ where
                 .iter()
                 .flat_map(|(_, devices_map)| devices_map.values().cloned())
                 .map(|device_ref| DisplayHostRef {
+                    status: device_ref.status(),
                     name: device_ref.name,
                     discovery_id: device_ref.discovery_id,
                     id: device_ref.id,
-                    status: DisplayHostStatus::Available,
+                    stream_policy: None,
+                    mirror_of: None,
+                    recording: false,
                 })
                 .collect();
 
@@ -582,10 +994,13 @@ where
                 .map(|device_ref| {
                     let status = device_status_from_system_state(&device_ref.get_current_status());
                     DisplayHostRef {
+                        stream_policy: device_ref.get_current_stream_policy(),
                         name: device_ref.name,
                         discovery_id: device_ref.discovery_id,
                         id: device_ref.id,
                         status,
+                        mirror_of: device_ref.mirror_of,
+                        recording: device_ref.is_recording(),
                     }
                 })
                 .collect();
@@ -620,10 +1035,13 @@ where
                         .iter()
                         .flat_map(|(_, devices_map)| devices_map.values().cloned())
                         .map(|device_ref| DisplayHostRef {
+                            status: device_ref.status(),
                             name: device_ref.name,
                             discovery_id: device_ref.discovery_id,
                             id: device_ref.id,
-                            status: DisplayHostStatus::Available,
+                            stream_policy: None,
+                            mirror_of: None,
+                            recording: false,
                         })
                         .collect();
 
@@ -634,10 +1052,13 @@ where
                             let status =
                                 device_status_from_system_state(&device_ref.get_current_status());
                             DisplayHostRef {
+                                stream_policy: device_ref.get_current_stream_policy(),
                                 name: device_ref.name,
                                 discovery_id: device_ref.discovery_id,
                                 id: device_ref.id,
                                 status,
+                                mirror_of: device_ref.mirror_of,
+                                recording: device_ref.is_recording(),
                             }
                         })
                         .collect();
@@ -655,20 +1076,40 @@ where
         &self,
         discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), String>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         self.initialize_device(discovery_id, device_id)
-            .map(|res| res.map_err(|_| "Failed to initialize device".to_string()))
-            .boxed()
     }
 
     fn disconnect_device(
         &self,
         discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), String>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         self.disconnect_device(discovery_id, device_id)
-            .map(|res| res.map_err(|_| "Failed to disconnect device".to_string()))
-            .boxed()
+    }
+
+    fn mirror_device(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        source_discovery_id: DiscoveryId,
+        source_device_id: DisplayHostId,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        self.mirror_device(
+            discovery_id,
+            device_id,
+            source_discovery_id,
+            source_device_id,
+        )
+    }
+
+    fn set_recording(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        enabled: bool,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        self.set_recording(discovery_id, device_id, enabled)
     }
 
     fn get_discovery_methods(
@@ -691,6 +1132,255 @@ where
         }
         .boxed()
     }
+
+    fn stream_session_stats(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+    ) -> PinnedStream<'static, SessionStats> {
+        let in_use_devices = self.in_use_devices.clone();
+        async move {
+            let read_guard = in_use_devices.read().await;
+            let Some(device) = read_guard
+                .get(&discovery_id)
+                .and_then(|devices_map| devices_map.get(&device_id))
+            else {
+                debug!("No session to stream stats of for device '{}'", device_id);
+                return futures_util::stream::empty().boxed();
+            };
+
+            // Listeners that fall behind just miss a few reports
+            device
+                .listen_stats()
+                .filter_map(|stats_res| async move { stats_res.ok() })
+                .boxed()
+        }
+        .flatten_stream()
+        .boxed()
+    }
+}
+
+/// Tells whoever took the device how its session got started, if nobody was told yet.
+fn report_started(started_tx: &Mutex<Option<StartedTx>>, result: Result<(), ApiError>) {
+    if let Some(started_tx) = started_tx.lock().unwrap().take() {
+        let _ = started_tx.send(result);
+    }
+}
+
+/// Looks up an available device that can be taken.
+fn find_ready_device(
+    available_devices: &HashMap<DiscoveryId, HashMap<DisplayHostId, ReadyDeviceRef>>,
+    discovery_id: &DiscoveryId,
+    device_id: &DisplayHostId,
+) -> Result<ReadyDeviceRef, ApiError> {
+    let device = available_devices
+        .get(discovery_id)
+        .and_then(|devices_map| devices_map.get(device_id))
+        .cloned()
+        .ok_or_else(|| {
+            ApiError::new(
+                ErrorKind::NotFound,
+                format!("No available device '{}'", device_id),
+            )
+        })?;
+
+    if let Some(reason) = &device.unavailable_reason {
+        warn!("Refusing to take device '{}': {}", device.name, reason);
+        return Err(ApiError::new(ErrorKind::Unavailable, reason.clone()));
+    }
+    Ok(device)
+}
+
+/// Hands a listed device that changed to the take task it already has, and updates
+/// its listing. Gives the device back when it isn't listed, or is being taken.
+fn update_listed_device<C>(
+    listed: &mut HashMap<DisplayHostId, ReadyDeviceRef>,
+    updates: &HashMap<DisplayHostId, mpsc::UnboundedSender<C>>,
+    device: C,
+) -> Option<C>
+where
+    C: ConnectableDevice,
+{
+    let info = device.get_info();
+    let (Some(device_ref), Some(update_tx)) = (listed.get_mut(&info.id), updates.get(&info.id))
+    else {
+        return Some(device);
+    };
+    if let Err(SendError(device)) = update_tx.send(device) {
+        return Some(device);
+    }
+    device_ref.name = info.name;
+    device_ref.unavailable_reason = info.unavailable_reason;
+    None
+}
+
+/// Has a taken device mirror the session it was taken for, keeping it on the in-use
+/// list for as long as it does.
+async fn run_mirror<C, T>(
+    device: C,
+    info: ConnectableDeviceInfo,
+    discovery_id: DiscoveryId,
+    target: MirrorTarget,
+    started_tx: StartedTx,
+    in_use_devices: Arc<RwLock<HashMap<DiscoveryId, HashMap<DisplayHostId, InUseDeviceRef>>>>,
+    device_change_tx: broadcast::Sender<()>,
+) where
+    C: ConnectableDevice<Transport = T> + Send + 'static,
+    T: ScreenTransport + 'static,
+{
+    let MirrorTarget { source, requests } = target;
+    let ConnectableDeviceInfo { name, id, .. } = info;
+    let status_slot = Arc::new(ArcSwap::from_pointee(SystemState::Initializing));
+    let (in_use_device_ref, cancel_rx) = InUseDeviceRef::new(
+        name.clone(),
+        discovery_id.clone(),
+        id.clone(),
+        status_slot.clone(),
+        Arc::new(ArcSwap::from_pointee(None)),
+        Some(source.clone()),
+        None,
+        None,
+    );
+    in_use_devices
+        .write()
+        .await
+        .entry(discovery_id.clone())
+        .or_insert_with(HashMap::new)
+        .insert(id.clone(), in_use_device_ref);
+    if device_change_tx.send(()).is_err() {
+        debug!("Failed to notify device change listeners");
+    }
+
+    let (joined_tx, joined_rx) = futures::channel::oneshot::channel();
+    let (left_tx, left_rx) = futures::channel::oneshot::channel();
+    let request = MirrorRequest {
+        connect: Box::new(move || {
+            device
+                .connect()
+                .map_ok(DisplayHost::to_some_transport)
+                .boxed_local()
+        }),
+        cancel: ReceiverStream::new(cancel_rx).boxed(),
+        started: joined_tx,
+        left: left_tx,
+    };
+    let started = match requests.send(request).await {
+        Ok(()) => match joined_rx.await {
+            Ok(result) => result.map_err(ApiError::from),
+            Err(_) => Err(ApiError::new(
+                ErrorKind::Cancelled,
+                "The session ended before the device joined it",
+            )),
+        },
+        Err(_) => Err(ApiError::new(
+            ErrorKind::Unavailable,
+            format!("Device '{}' is not connected anymore", source.device_id),
+        )),
+    };
+
+    match &started {
+        Ok(()) => {
+            info!("Device '{}' mirrors '{}'", name, source.device_id);
+            status_slot.store(Arc::new(SystemState::Running));
+            if device_change_tx.send(()).is_err() {
+                debug!("Failed to notify device change listeners");
+            }
+        }
+        Err(e) => error!(
+            "Device '{}' failed to mirror '{}': {}",
+            name, source.device_id, e
+        ),
+    }
+    let joined = started.is_ok();
+    let _ = started_tx.send(started);
+    if joined {
+        // Also resolves when the session ends without telling
+        let _ = left_rx.await;
+        info!("Device '{}' stopped mirroring '{}'", name, source.device_id);
+    }
+
+    in_use_devices
+        .write()
+        .await
+        .entry(discovery_id)
+        .and_modify(|devices_map| {
+            devices_map.remove(&id);
+        });
+    if device_change_tx.send(()).is_err() {
+        debug!("Failed to notify device change listeners");
+    }
+}
+
+/// Connects the devices asking to mirror a session, for them to join it.
+fn mirror_joins(
+    mirror_rx: mpsc::Receiver<MirrorRequest>,
+) -> PinnedLocalStream<'static, MirrorJoin> {
+    ReceiverStream::new(mirror_rx)
+        .filter_map(|request| async move {
+            let MirrorRequest {
+                connect,
+                cancel,
+                started,
+                left,
+            } = request;
+            match connect().await {
+                Ok(host) => Some(MirrorJoin {
+                    host,
+                    cancel,
+                    started,
+                    left,
+                }),
+                Err(e) => {
+                    warn!("Failed to connect a mirror: {}", e);
+                    let _ = started.send(Err(TransportError::Other(e).into()));
+                    None
+                }
+            }
+        })
+        .boxed_local()
+}
+
+/// Hands a device that came back over to the session it had, if that session is
+/// still around. Gives the device back otherwise, to be listed as available.
+fn resume_session<C>(
+    sessions: &Mutex<HashMap<SessionToken, ResumableSession<C>>>,
+    device: C,
+) -> Option<C>
+where
+    C: ConnectableDevice,
+{
+    let info = device.get_info();
+    let Some(token) = &info.resume_token else {
+        return Some(device);
+    };
+    let sessions = sessions.lock().unwrap();
+    let Some(session) = sessions.get(token) else {
+        return Some(device);
+    };
+    if **session.status.load() == SystemState::AwaitingReconnect {
+        info!("Device '{}' is back, resuming its session", info.name);
+        if session.reconnect_tx.try_send(device).is_err() {
+            debug!("Session of '{}' is already resuming", info.name);
+        }
+    }
+    None
+}
+
+/// Connects the devices that come back to a session, for it to resume with.
+fn reconnections<C, T>(
+    reconnect_rx: mpsc::Receiver<C>,
+) -> PinnedLocalStream<'static, DisplayHost<T>>
+where
+    C: ConnectableDevice<Transport = T> + 'static,
+    T: ScreenTransport + 'static,
+{
+    ReceiverStream::new(reconnect_rx)
+        .then(|device| device.connect())
+        .filter_map(|res| async move {
+            res.inspect_err(|e| warn!("Failed to reconnect device: {}", e))
+                .ok()
+        })
+        .boxed_local()
 }
 
 fn system_state_to_init_state(state: &SystemState) -> Option<InitializationState> {
@@ -707,6 +1397,8 @@ fn system_state_to_init_state(state: &SystemState) -> Option<InitializationState
         SystemState::NegotiatingCodecs => Some(InitializationState::NegotiatingCodecs),
         SystemState::InitializingEncoder => Some(InitializationState::InitializingEncoder),
         SystemState::SettingClientCodec => Some(InitializationState::SettingClientCodec),
+        SystemState::Renegotiating => Some(InitializationState::Renegotiating),
+        SystemState::AwaitingReconnect => Some(InitializationState::AwaitingReconnect),
         SystemState::Running | SystemState::Stopped => None,
     }
 }
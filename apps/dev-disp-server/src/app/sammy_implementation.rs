@@ -1,12 +1,14 @@
 use std::time::Duration;
 
+use dev_disp_clipboard::SystemClipboardProvider;
 use dev_disp_core::{
     client::ScreenTransport,
-    core::{get_default_config_path_for, handle_display_host},
+    core::{SessionContext, StreamPolicy, get_default_config_path_for, handle_display_host},
     host::{ConnectableDevice, DeviceDiscovery, ScreenProvider},
 };
 use dev_disp_encoders::ffmpeg::{FfmpegEncoderProvider, config_file::FfmpegConfiguration};
-use futures_util::{sink, stream::empty};
+use dev_disp_input::UinputInjectorProvider;
+use futures_util::{StreamExt, sink, stream::empty};
 use log::{error, info, trace};
 
 use crate::config;
@@ -80,12 +82,23 @@ where
                 }
             };
 
+            let context = SessionContext {
+                policy: StreamPolicy::default(),
+                input_provider: UinputInjectorProvider::new(),
+                clipboard_provider: None::<SystemClipboardProvider>,
+                mirrors: empty().boxed_local(),
+                recordings: empty().boxed_local(),
+                status_sink: sink::drain(),
+                policy_sink: sink::drain(),
+                stats_sink: sink::drain(),
+            };
             let handle_result = handle_display_host(
                 provider_1,
                 FfmpegEncoderProvider::new(ffmpeg_config),
                 display,
+                None,
+                context,
                 empty(),
-                sink::drain(),
             )
             .await;
 
@@ -0,0 +1,118 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use dev_disp_core::{
+    core::{ConfigurationFile, ConfigurationFilePathError, StreamPolicy, StreamPolicyOverrides},
+    util::PinnedLocalFuture,
+};
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+
+use crate::screen::ScreenSource;
+
+/// Settings for individual display hosts, by device name. Devices may get a new
+/// ID every time they connect, but keep their name.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceConfiguration {
+    pub devices: HashMap<String, DeviceSettings>,
+    /// How every device streams, unless its settings say otherwise
+    #[serde(default)]
+    pub stream_policy: StreamPolicy,
+    /// How long a session waits for its device to come back after losing the
+    /// connection. 0 ends sessions right away.
+    #[serde(default = "default_session_resume_grace_secs")]
+    pub session_resume_grace_secs: u64,
+    /// Where recordings of sessions go. The local data directory, unless set.
+    #[serde(default)]
+    pub recordings_path: Option<PathBuf>,
+    /// Ways of finding devices to run, by name, like `websocket` or `usb`
+    #[serde(default = "default_discoveries")]
+    pub discoveries: Vec<String>,
+    /// Where virtual screens come from, like test patterns instead of evdi
+    #[serde(default)]
+    pub screen_source: ScreenSource,
+}
+
+fn default_session_resume_grace_secs() -> u64 {
+    30
+}
+
+fn default_discoveries() -> Vec<String> {
+    vec!["websocket".to_string()]
+}
+
+impl Default for DeviceConfiguration {
+    fn default() -> Self {
+        Self {
+            devices: HashMap::new(),
+            stream_policy: StreamPolicy::default(),
+            session_resume_grace_secs: default_session_resume_grace_secs(),
+            recordings_path: None,
+            discoveries: default_discoveries(),
+            screen_source: ScreenSource::default(),
+        }
+    }
+}
+
+impl DeviceConfiguration {
+    /// The settings for a device, or the defaults if it has none.
+    pub fn settings_for(&self, device_name: &str) -> DeviceSettings {
+        self.devices.get(device_name).cloned().unwrap_or_default()
+    }
+
+    /// The stream policy for a device, before what it reports about itself.
+    pub fn stream_policy_for(&self, device_name: &str) -> StreamPolicy {
+        self.stream_policy
+            .with_overrides(&self.settings_for(device_name).stream_policy)
+    }
+
+    /// How long sessions wait for a lost device, if they wait at all.
+    pub fn session_resume_grace_period(&self) -> Option<Duration> {
+        (self.session_resume_grace_secs > 0)
+            .then(|| Duration::from_secs(self.session_resume_grace_secs))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DeviceSettings {
+    /// Share the host's clipboard with the device. Off unless asked for, since
+    /// anything copied on the host ends up on the device.
+    pub clipboard: bool,
+    /// Changes to the global stream policy for this device
+    pub stream_policy: StreamPolicyOverrides,
+}
+
+impl ConfigurationFile for DeviceConfiguration {
+    fn display_name() -> String {
+        "Device Configuration".to_string()
+    }
+
+    fn get_default_path(project_path: &Path) -> Result<PathBuf, ConfigurationFilePathError> {
+        let mut path_buf = project_path.to_path_buf();
+        path_buf.push("devices.json");
+        Ok(path_buf)
+    }
+
+    fn serialize(&self) -> PinnedLocalFuture<'_, Result<Vec<u8>, Box<dyn std::error::Error>>> {
+        async move {
+            let data = serde_json::to_vec_pretty(&self)?;
+            Ok(data)
+        }
+        .boxed_local()
+    }
+
+    fn deserialize(
+        source: Vec<u8>,
+    ) -> PinnedLocalFuture<'static, Result<Self, Box<dyn std::error::Error>>> {
+        async move {
+            let config = serde_json::from_slice::<DeviceConfiguration>(&source)?;
+            Ok(config)
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,23 @@
+use dev_disp_core::host::{DeviceDiscoveryRegistry, StartedDiscovery};
+use dev_disp_transports::usb::discovery::UsbDiscovery;
+use futures_util::FutureExt;
+use log::error;
+
+use crate::websocket;
+
+/// Every way of finding devices the server knows, by the name the device
+/// configuration uses for it.
+pub fn discovery_registry() -> DeviceDiscoveryRegistry {
+    let mut registry = DeviceDiscoveryRegistry::new();
+    registry.register("websocket", || async {
+        let (ws_discovery, ws_listen) = websocket::create_websocket_and_bg_task().await;
+        let listen = ws_listen.map(|res| {
+            if let Err(e) = res {
+                error!("Error accepting WebSocket connections: {}", e);
+            }
+        });
+        Ok(StartedDiscovery::new(ws_discovery).with_background(listen))
+    });
+    registry.register("usb", || async { Ok(StartedDiscovery::new(UsbDiscovery)) });
+    registry
+}
@@ -1,20 +1,29 @@
 use std::process::exit;
 
 use dev_disp_api::grpc::endpoint::DevDispGrpcEndpoint;
+use dev_disp_clipboard::SystemClipboardProvider;
 use dev_disp_core::{
     daemon::endpoint::DevDispApiEndpoint,
-    host::{EncoderProvider, ScreenProvider},
+    host::{ClipboardProvider, EncoderProvider, InputInjectorProvider},
 };
 use dev_disp_encoders::ffmpeg::{FfmpegEncoderProvider, config_file::FfmpegConfiguration};
-use dev_disp_provider_evdi::EvdiScreenProvider;
-use futures_util::FutureExt;
+use dev_disp_input::UinputInjectorProvider;
+use futures_util::{FutureExt, future::join_all};
 use log::{LevelFilter, error, info, warn};
 use tokio::{signal::ctrl_c, task::LocalSet};
 
-use crate::{app::App, config::default_path_read_or_write_default_config_for};
+use crate::{
+    app::App,
+    config::default_path_read_or_write_default_config_for,
+    device_config::DeviceConfiguration,
+    screen::{ScreenSource, ServerScreenProvider},
+};
 
 mod app;
 mod config;
+mod device_config;
+mod discovery;
+mod screen;
 mod util;
 mod websocket;
 
@@ -31,30 +40,57 @@ async fn main() {
         .filter_module("h2::codec", LevelFilter::Warn)
         .init();
 
-    let screen_provider = get_screen_provider().await;
+    let device_config = get_device_config().await;
+    let screen_provider = get_screen_provider(&device_config.screen_source).await;
     let encoder_provider = get_encoder_provider().await;
+    let input_provider = get_input_provider().await;
+    let clipboard_provider = get_clipboard_provider().await;
+    let discoveries = device_config.discoveries.clone();
     let mut endpoint = get_endpoint().await;
-    let app = App::new(screen_provider.clone(), encoder_provider);
+    let app = App::new(
+        screen_provider.clone(),
+        encoder_provider,
+        input_provider,
+        clipboard_provider,
+        device_config,
+    );
 
     tokio::spawn(endpoint.serve_api(app.clone()));
 
     let local_set = LocalSet::new();
     // TODO: Is this single-threaded work necessary?
     let single_thread_work = local_set.run_until(async move {
-        let (ws_discovery, ws_listen) = websocket::create_websocket_and_bg_task().await;
-        let listen = tokio::task::spawn_local(ws_listen).map(|res| {
-            if let Err(e) = res {
-                error!("Error setting up websocket listen task: {}", e);
-            } else if let Ok(Err(e)) = res {
-                error!("Error accepting WebSocket connections: {}", e);
+        let registry = discovery::discovery_registry();
+        let mut discovery_tasks = Vec::new();
+        for name in &discoveries {
+            let started = match registry.start(name).await {
+                Ok(started) => started,
+                Err(e) => {
+                    error!("Not discovering devices with \"{}\": {}", name, e);
+                    continue;
+                }
+            };
+            if let Some(background) = started.background {
+                discovery_tasks.push(tokio::task::spawn_local(background));
             }
-        });
-
-        let app_ws_discovery =
-            tokio::task::spawn_local(app.setup_discovery(ws_discovery, "websocket".to_string()));
+            discovery_tasks.push(tokio::task::spawn_local(
+                app.setup_discovery(started.discovery, name.clone()),
+            ));
+        }
+        if discovery_tasks.is_empty() {
+            let known: Vec<_> = registry.names().collect();
+            warn!(
+                "No device discovery is running, pick some of {:?} in the device configuration",
+                known
+            );
+        }
 
         let running_tasks = async move {
-            _ = tokio::join!(listen, app_ws_discovery);
+            for result in join_all(discovery_tasks).await {
+                if let Err(e) = result {
+                    error!("Device discovery task failed: {}", e);
+                }
+            }
         }
         .shared();
 
@@ -108,9 +144,11 @@ async fn main() {
     info!("Exiting");
 }
 
-async fn get_screen_provider() -> impl ScreenProvider + Clone + 'static {
-    let evdi_provider = EvdiScreenProvider::new();
-    evdi_provider
+async fn get_screen_provider(source: &ScreenSource) -> ServerScreenProvider {
+    if let ScreenSource::Synthetic(config) = source {
+        info!("Showing display hosts a {:?} test pattern", config.pattern);
+    }
+    ServerScreenProvider::new(source)
 }
 
 async fn get_encoder_provider() -> impl EncoderProvider + Clone + 'static {
@@ -126,6 +164,27 @@ async fn get_encoder_provider() -> impl EncoderProvider + Clone + 'static {
     ffmpeg_provider
 }
 
+async fn get_input_provider() -> impl InputInjectorProvider + Clone + Send + 'static {
+    if !UinputInjectorProvider::is_available() {
+        warn!("Cannot open /dev/uinput, input from display hosts will be ignored");
+    }
+    UinputInjectorProvider::new()
+}
+
+async fn get_clipboard_provider() -> impl ClipboardProvider + Clone + Send + 'static {
+    SystemClipboardProvider::new()
+}
+
+async fn get_device_config() -> DeviceConfiguration {
+    default_path_read_or_write_default_config_for::<DeviceConfiguration>()
+        .await
+        .map_err(|e| {
+            error!("Failed to read or write device configuration: {}", e);
+            e
+        })
+        .unwrap_or_default()
+}
+
 async fn get_endpoint() -> impl DevDispApiEndpoint {
     DevDispGrpcEndpoint
 }
@@ -0,0 +1,125 @@
+use dev_disp_core::{
+    host::{
+        CursorUpdate, DamageRect, DesktopPlacement, DisplayParameters, Screen, ScreenError,
+        ScreenOutputParameters, ScreenProvider, ScreenReadyStatus,
+    },
+    util::{PinnedFuture, PinnedLocalFuture, PinnedLocalStream},
+};
+use dev_disp_provider_evdi::{EvdiScreen, EvdiScreenProvider};
+use dev_disp_provider_synthetic::{
+    SyntheticScreen, SyntheticScreenConfiguration, SyntheticScreenProvider,
+};
+use serde::{Deserialize, Serialize};
+
+/// Where virtual screens come from.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", tag = "kind")]
+pub enum ScreenSource {
+    /// Real screens on the desktop, through the evdi kernel module
+    #[default]
+    Evdi,
+    /// Test patterns, which need no kernel support or permissions
+    Synthetic(SyntheticScreenConfiguration),
+}
+
+/// The screen provider picked at startup.
+#[derive(Debug, Clone)]
+pub enum ServerScreenProvider {
+    Evdi(EvdiScreenProvider),
+    Synthetic(SyntheticScreenProvider),
+}
+
+impl ServerScreenProvider {
+    pub fn new(source: &ScreenSource) -> Self {
+        match source {
+            ScreenSource::Evdi => Self::Evdi(EvdiScreenProvider::new()),
+            ScreenSource::Synthetic(config) => {
+                Self::Synthetic(SyntheticScreenProvider::new(config.clone()))
+            }
+        }
+    }
+}
+
+impl ScreenProvider for ServerScreenProvider {
+    type ScreenType = ServerScreen;
+
+    async fn get_screen(&self, params: DisplayParameters) -> Result<Self::ScreenType, ScreenError> {
+        match self {
+            Self::Evdi(provider) => provider.get_screen(params).await.map(ServerScreen::Evdi),
+            Self::Synthetic(provider) => provider
+                .get_screen(params)
+                .await
+                .map(ServerScreen::Synthetic),
+        }
+    }
+}
+
+pub enum ServerScreen {
+    Evdi(EvdiScreen),
+    Synthetic(SyntheticScreen),
+}
+
+impl Screen for ServerScreen {
+    fn get_format_parameters(&self) -> ScreenOutputParameters {
+        match self {
+            Self::Evdi(screen) => screen.get_format_parameters(),
+            Self::Synthetic(screen) => screen.get_format_parameters(),
+        }
+    }
+
+    fn background<'s, 'a>(&'s mut self) -> PinnedFuture<'a, Result<(), ScreenError>> {
+        match self {
+            Self::Evdi(screen) => screen.background(),
+            Self::Synthetic(screen) => screen.background(),
+        }
+    }
+
+    async fn get_ready(&mut self) -> Result<ScreenReadyStatus, ScreenError> {
+        match self {
+            Self::Evdi(screen) => screen.get_ready().await,
+            Self::Synthetic(screen) => screen.get_ready().await,
+        }
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Self::Evdi(screen) => screen.get_bytes(),
+            Self::Synthetic(screen) => screen.get_bytes(),
+        }
+    }
+
+    fn get_damage(&self) -> Option<Vec<DamageRect>> {
+        match self {
+            Self::Evdi(screen) => screen.get_damage(),
+            Self::Synthetic(screen) => screen.get_damage(),
+        }
+    }
+
+    fn get_desktop_placement(&self) -> Option<DesktopPlacement> {
+        match self {
+            Self::Evdi(screen) => screen.get_desktop_placement(),
+            Self::Synthetic(screen) => screen.get_desktop_placement(),
+        }
+    }
+
+    fn take_cursor_stream(&mut self) -> Option<PinnedLocalStream<'static, CursorUpdate>> {
+        match self {
+            Self::Evdi(screen) => screen.take_cursor_stream(),
+            Self::Synthetic(screen) => screen.take_cursor_stream(),
+        }
+    }
+
+    async fn reconfigure(&mut self, params: DisplayParameters) -> Result<(), ScreenError> {
+        match self {
+            Self::Evdi(screen) => screen.reconfigure(params).await,
+            Self::Synthetic(screen) => screen.reconfigure(params).await,
+        }
+    }
+
+    fn close(self) -> PinnedLocalFuture<'static, Result<(), ScreenError>> {
+        match self {
+            Self::Evdi(screen) => screen.close(),
+            Self::Synthetic(screen) => screen.close(),
+        }
+    }
+}
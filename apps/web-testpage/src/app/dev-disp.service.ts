@@ -3,6 +3,10 @@ import {
   DevDispEvent,
   JsDisplayParameters,
   JsEncoderPossibleConfiguration,
+  JsFrameHeader,
+  JsFramePresented,
+  JsInputEvent,
+  JsReceiverReport,
   WsDispatchers,
   WsHandlers,
   connectDevDispServer,
@@ -31,6 +35,46 @@ export class DevDispService {
   }
 }
 
+/** How often the server hears how well we keep up with the stream */
+const RECEIVER_REPORT_INTERVAL_MS = 1000;
+
+/** The only kind of clipboard content the page shares with the host */
+const CLIPBOARD_TEXT_MIME_TYPE = 'text/plain;charset=utf-8';
+
+/** What happened to the stream since the last receiver report */
+type ReceiverStats = {
+  since: number;
+  bytesReceived: number;
+  framesDecoded: number;
+  totalDecodeTimeUs: number;
+  droppedFrames: number;
+};
+
+/** A frame on its way through the decoder */
+type PendingFrame = {
+  sequence: number;
+  /** `performance.now()` when the frame came in */
+  receivedAt: number;
+};
+
+/**
+ * Microseconds since the Unix epoch for a `performance.now()` timestamp, the
+ * clock the server syncs with.
+ */
+function clientTimeUs(now: number): number {
+  return Math.round((performance.timeOrigin + now) * 1000);
+}
+
+function newReceiverStats(): ReceiverStats {
+  return {
+    since: performance.now(),
+    bytesReceived: 0,
+    framesDecoded: 0,
+    totalDecodeTimeUs: 0,
+    droppedFrames: 0,
+  };
+}
+
 export type DevDispEventDisconnect = {
   intentional: boolean;
   wsReason?: number;
@@ -65,13 +109,22 @@ export class DevDispConnection {
     this._configuredEncoding$.asObservable();
 
   private readonly drawer?: ((frame: VideoFrame) => void) | null;
-  private readonly decoder = new VideoDecoder({
-    output: this.onDecode.bind(this),
-    error: this.onDecodeError.bind(this),
-  });
+  private decoder = this._createDecoder();
+  private decoderConfig?: VideoDecoderConfig;
+  /** Set when we lost track of the stream, until a keyframe comes in */
+  private awaitingKeyframe = false;
+  private lastSequence?: number;
+
+  private stats = newReceiverStats();
+  /** The chunks still in the decoder, by timestamp */
+  private pendingFrames = new Map<number, PendingFrame>();
+  private receiverReportTimer?: ReturnType<typeof setInterval>;
 
   private supportedDecoderConfigurations: SearchCodecResult[] = [];
   private intentionalDisconnect = false;
+  /** Tells the host when something on the page is copied */
+  private readonly onCopy = () =>
+    this.dispatchers.offerClipboard([CLIPBOARD_TEXT_MIME_TYPE]);
 
   constructor(
     public readonly address: string,
@@ -86,15 +139,22 @@ export class DevDispConnection {
     const handlers: WsHandlers = {
       onConnect: this.onConnect.bind(this),
       onDisconnect: this.onDisconnect.bind(this),
+      onGoodbye: this.onGoodbye.bind(this),
+      onPreInitRejected: this.onPreInitRejected.bind(this),
       handleRequestDeviceInfo: this.onRequestDeviceInfo.bind(this),
       handleScreenData: this.onScreenData.bind(this),
       handleRequestDisplayParameters: this.onRequestDeviceInfo.bind(this),
       handleRequestPreferredEncoding:
         this.handleRequestPreferredEncodings.bind(this),
       handleSetEncoding: this.handleSetEncoding.bind(this),
+      handleClipboardOffer: this.handleClipboardOffer.bind(this),
+      handleClipboardRequest: this.handleClipboardRequest.bind(this),
+      handleClipboardData: this.handleClipboardData.bind(this),
     };
 
     this.dispatchers = connectDevDispServer(address, handlers);
+    document.addEventListener('copy', this.onCopy);
+    document.addEventListener('cut', this.onCopy);
   }
 
   private _getDrawer(canvas: OffscreenCanvas) {
@@ -122,7 +182,61 @@ export class DevDispConnection {
     return this.dispatchers.updateDisplayParameters(params);
   }
 
+  /**
+   * Resizes the canvas and asks the server for a screen of the new size. The
+   * server renegotiates the encoding without dropping the connection.
+   */
+  resize(width: number, height: number) {
+    if (this.canvas.width === width && this.canvas.height === height) {
+      return;
+    }
+    this.canvas.width = width;
+    this.canvas.height = height;
+    return this.updateDisplayParameters(this.onRequestDeviceInfo(null));
+  }
+
+  sendInput(event: JsInputEvent) {
+    return this.dispatchers.sendInput(event);
+  }
+
+  private _createDecoder() {
+    return new VideoDecoder({
+      output: this.onDecode.bind(this),
+      error: this.onDecodeError.bind(this),
+    });
+  }
+
+  /** Ask the server for a keyframe, and drop frames until it arrives */
+  private _requestKeyframe() {
+    if (this.awaitingKeyframe) {
+      return;
+    }
+    this.awaitingKeyframe = true;
+    this.dispatchers.requestKeyframe();
+  }
+
+  private _sendReceiverReport() {
+    const now = performance.now();
+    const stats = this.stats;
+    this.stats = newReceiverStats();
+
+    const report: JsReceiverReport = {
+      intervalMs: Math.round(now - stats.since),
+      bytesReceived: stats.bytesReceived,
+      framesDecoded: stats.framesDecoded,
+      avgDecodeTimeUs: stats.framesDecoded
+        ? Math.round(stats.totalDecodeTimeUs / stats.framesDecoded)
+        : 0,
+      queueDepth: this.decoder.decodeQueueSize,
+      droppedFrames: stats.droppedFrames,
+    };
+    this.dispatchers.sendReceiverReport(report);
+  }
+
   private _complete() {
+    clearInterval(this.receiverReportTimer);
+    document.removeEventListener('copy', this.onCopy);
+    document.removeEventListener('cut', this.onCopy);
     this._decodedFrame$.complete();
     this._disconnect$.complete();
     this._connected$.complete();
@@ -130,24 +244,61 @@ export class DevDispConnection {
   }
 
   private onDecode(frame: VideoFrame) {
+    const decodedAt = performance.now();
+    const pending = this.pendingFrames.get(frame.timestamp);
+    this.pendingFrames.delete(frame.timestamp);
+    if (pending) {
+      this.stats.totalDecodeTimeUs += (decodedAt - pending.receivedAt) * 1000;
+    }
+    this.stats.framesDecoded += 1;
     this.drawer?.(frame);
     frame.close();
     this._decodedFrame$.next();
+
+    if (pending) {
+      const presented: JsFramePresented = {
+        sequence: pending.sequence,
+        receivedAtUs: clientTimeUs(pending.receivedAt),
+        decodedAtUs: clientTimeUs(decodedAt),
+        presentedAtUs: clientTimeUs(performance.now()),
+      };
+      this.dispatchers.framePresented(presented);
+    }
   }
 
   private onDecodeError(e: DOMException) {
     console.error('VideoDecoder error:', e);
-    if (this.decoder.state === 'closed') {
+    if (this.decoder.state !== 'closed') {
+      return;
+    }
+    if (!this.decoderConfig) {
       console.warn('Decoder is closed, cannot recover from error');
       // Do a non-intentional disconnect
       this.intentionalDisconnect = false;
       this.dispatchers.closeConnection();
+      return;
     }
+
+    // A closed decoder can't be used again, start over from the next keyframe
+    console.warn('Decoder is closed, recovering from the next keyframe');
+    this.decoder = this._createDecoder();
+    this.decoder.configure(this.decoderConfig);
+    this.pendingFrames.clear();
+    this.awaitingKeyframe = false;
+    this._requestKeyframe();
   }
 
   private onConnect(e: unknown) {
     console.log('Dev-disp connected', e);
     this._connected$.next(true);
+    this.receiverReportTimer = setInterval(
+      () => this._sendReceiverReport(),
+      RECEIVER_REPORT_INTERVAL_MS,
+    );
+  }
+
+  private onGoodbye(e: DevDispEvent) {
+    console.log('Dev-disp host said goodbye:', e.data);
   }
 
   private onDisconnect(e: DevDispEvent) {
@@ -165,19 +316,28 @@ export class DevDispConnection {
     this._complete();
   }
 
-  private onRequestDeviceInfo(e: DevDispEvent): JsDisplayParameters {
+  private onPreInitRejected(e: DevDispEvent) {
+    console.error('Dev-disp server rejected this client:', e.error);
+  }
+
+  private onRequestDeviceInfo(e: DevDispEvent | null): JsDisplayParameters {
     console.log('Dev-disp device info requested', e);
     return {
       name: 'Web Testpage Display',
       resolution: [this.canvas.width, this.canvas.height],
+      // Browsers don't tell us the screen's physical size, but the pixel
+      // ratio is a fair hint for the scale to use
+      preferredScale: window.devicePixelRatio,
     };
   }
 
-  private onScreenData(e: DevDispEvent | null): void {
+  private onScreenData(e: DevDispEvent | null, header: JsFrameHeader): void {
     if (!e?.data) {
       return;
     }
 
+    const receivedAt = performance.now();
+
     // If we have a shared buffer and the data is a number, use that
     // as the byte-length to read from the shared buffer
     let data: Uint8Array;
@@ -194,13 +354,34 @@ export class DevDispConnection {
       return;
     }
 
+    // A gap in the sequence means a frame was lost, and the frames after it
+    // can't be decoded until the next keyframe.
+    const sequence = Number(header.sequence);
+    if (this.lastSequence !== undefined && sequence !== this.lastSequence + 1) {
+      console.warn(
+        `Frame(s) lost between #${this.lastSequence} and #${sequence}, requesting a keyframe`,
+      );
+      this._requestKeyframe();
+      this.stats.droppedFrames += Math.max(sequence - this.lastSequence - 1, 0);
+    }
+    this.lastSequence = sequence;
+    this.stats.bytesReceived += data.byteLength;
+
+    if (this.awaitingKeyframe) {
+      if (!header.keyframe) {
+        this.stats.droppedFrames += 1;
+        return;
+      }
+      this.awaitingKeyframe = false;
+    }
+
     const chunk = new EncodedVideoChunk({
       data,
-      timestamp: 0,
-      // I don't know what this is doing
-      type: 'key',
+      timestamp: header.captureTimestampUs,
+      type: header.keyframe ? 'key' : 'delta',
     });
 
+    this.pendingFrames.set(chunk.timestamp, { sequence, receivedAt });
     this.decoder.decode(chunk);
   }
 
@@ -291,6 +472,37 @@ export class DevDispConnection {
     return possibleConfigurations;
   }
 
+  /** The host copied something, take it if it's text */
+  private handleClipboardOffer(mimeTypes: string[]) {
+    if (mimeTypes.includes(CLIPBOARD_TEXT_MIME_TYPE)) {
+      this.dispatchers.requestClipboard(CLIPBOARD_TEXT_MIME_TYPE);
+    }
+  }
+
+  private async handleClipboardRequest(
+    mimeType: string,
+  ): Promise<Uint8Array | null> {
+    if (mimeType !== CLIPBOARD_TEXT_MIME_TYPE) {
+      return null;
+    }
+    try {
+      const text = await navigator.clipboard.readText();
+      return new TextEncoder().encode(text);
+    } catch (e) {
+      console.warn('Failed to read the clipboard for the host', e);
+      return null;
+    }
+  }
+
+  private handleClipboardData(mimeType: string, data: Uint8Array | null) {
+    if (mimeType !== CLIPBOARD_TEXT_MIME_TYPE || !data) {
+      return;
+    }
+    navigator.clipboard
+      .writeText(new TextDecoder().decode(data))
+      .catch((e) => console.warn('Failed to copy from the host', e));
+  }
+
   private handleSetEncoding(encodingConfig: JsEncoderPossibleConfiguration) {
     console.log('Dev-disp set encoding requested', encodingConfig);
 
@@ -316,11 +528,12 @@ export class DevDispConnection {
       Object.fromEntries(encodingConfig.parameters),
     );
 
-    this.decoder.configure({
+    this.decoderConfig = {
       codec: webCodecString,
       codedWidth: encodingConfig.encodedResolution[0],
       codedHeight: encodingConfig.encodedResolution[1],
-    });
+    };
+    this.decoder.configure(this.decoderConfig);
 
     this._configuredEncoding$.next({
       encoderName: encodingConfig.encoderName,
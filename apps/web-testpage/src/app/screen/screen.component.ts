@@ -6,11 +6,15 @@ import {
   viewChild,
 } from '@angular/core';
 import {
+  debounceTime,
   distinctUntilChanged,
   endWith,
   filter,
   fromEvent,
+  ignoreElements,
   map,
+  merge,
+  Observable,
   retry,
   scan,
   share,
@@ -22,7 +26,12 @@ import {
   throttleTime,
 } from 'rxjs';
 import { DevDispService, ofDevDispConnection } from '../dev-disp.service';
-import { toObservable, toSignal } from '@angular/core/rxjs-interop';
+import { JsInputEvent, JsPointerButton, JsTouchPhase } from 'dev-disp-ws-js';
+import {
+  takeUntilDestroyed,
+  toObservable,
+  toSignal,
+} from '@angular/core/rxjs-interop';
 import { slidingWindow } from 'web-util';
 
 @Component({
@@ -38,15 +47,19 @@ export class ScreenComponent {
 
   private readonly canvas$ = toObservable(this.canvas);
 
+  /** The canvas size in device pixels, following rotations and resizes */
   private readonly resolution$ = this.canvas$.pipe(
     filter((canvas): canvas is ElementRef<HTMLCanvasElement> => !!canvas),
-    // TODO: Use ResizeObserver to detect canvas size changes
+    switchMap((canvas) => elementResizes(canvas.nativeElement)),
+    // Resizing a window fires a lot of these, only renegotiate once it settles
+    debounceTime(RESIZE_DEBOUNCE_MS),
     map((canvas) => {
       return [
-        canvas.nativeElement.clientWidth * window.devicePixelRatio,
-        canvas.nativeElement.clientHeight * window.devicePixelRatio,
+        Math.round(canvas.clientWidth * window.devicePixelRatio),
+        Math.round(canvas.clientHeight * window.devicePixelRatio),
       ] as const;
     }),
+    distinctUntilChanged((a, b) => a[0] === b[0] && a[1] === b[1]),
     shareReplay(1),
   );
 
@@ -138,6 +151,37 @@ export class ScreenComponent {
 
   constructor() {
     this._effectConnectedLockScreen();
+    this._forwardInput();
+    this._forwardResize();
+  }
+
+  private _forwardResize() {
+    this.client$
+      .pipe(
+        switchMap((client) =>
+          this.resolution$.pipe(
+            tap(([width, height]) => client.resize(width, height)),
+          ),
+        ),
+        takeUntilDestroyed(),
+      )
+      .subscribe();
+  }
+
+  private _forwardInput() {
+    const input$ = this.canvas$.pipe(
+      filter((canvas): canvas is ElementRef<HTMLCanvasElement> => !!canvas),
+      switchMap((canvas) => canvasInputEvents(canvas.nativeElement)),
+    );
+
+    this.client$
+      .pipe(
+        switchMap((client) =>
+          input$.pipe(tap((event) => client.sendInput(event))),
+        ),
+        takeUntilDestroyed(),
+      )
+      .subscribe();
   }
 
   private _effectConnectedLockScreen() {
@@ -171,6 +215,154 @@ export class ScreenComponent {
   }
 }
 
+const POINTER_BUTTONS: JsPointerButton[] = [
+  'left',
+  'middle',
+  'right',
+  'back',
+  'forward',
+];
+
+/** How long the canvas size has to stay put before asking for a new screen */
+const RESIZE_DEBOUNCE_MS = 300;
+
+/** Emits the element whenever its size changes, starting with its current size. */
+function elementResizes<T extends Element>(element: T): Observable<T> {
+  return new Observable<T>((subscriber) => {
+    const observer = new ResizeObserver(() => subscriber.next(element));
+    observer.observe(element);
+    return () => observer.disconnect();
+  });
+}
+
+/** How many pixels of a pixel-mode `WheelEvent` make up one wheel notch */
+const PIXELS_PER_SCROLL_NOTCH = 100;
+
+/**
+ * Turns pointer, wheel and keyboard events on the canvas into input events
+ * for the server, with positions normalized to the canvas.
+ */
+function canvasInputEvents(
+  canvas: HTMLCanvasElement,
+): Observable<JsInputEvent> {
+  // Keep the browser from turning touches into scrolling or zooming
+  canvas.style.touchAction = 'none';
+  canvas.tabIndex = 0;
+
+  const normalize = (e: MouseEvent) => {
+    const rect = canvas.getBoundingClientRect();
+    return {
+      x: (e.clientX - rect.left) / rect.width,
+      y: (e.clientY - rect.top) / rect.height,
+    };
+  };
+
+  const pointerPhase = (e: PointerEvent): JsTouchPhase => {
+    switch (e.type) {
+      case 'pointerdown':
+        return 'start';
+      case 'pointerup':
+        return 'end';
+      case 'pointercancel':
+        return 'cancel';
+      default:
+        return 'move';
+    }
+  };
+
+  const pointer$ = merge(
+    fromEvent<PointerEvent>(canvas, 'pointerdown'),
+    fromEvent<PointerEvent>(canvas, 'pointermove'),
+    fromEvent<PointerEvent>(canvas, 'pointerup'),
+    fromEvent<PointerEvent>(canvas, 'pointercancel'),
+  ).pipe(
+    map((e): JsInputEvent | null => {
+      e.preventDefault();
+      if (e.type === 'pointerdown') {
+        canvas.setPointerCapture(e.pointerId);
+        canvas.focus();
+      }
+      const { x, y } = normalize(e);
+
+      switch (e.pointerType) {
+        case 'touch':
+          return {
+            type: 'touch',
+            id: e.pointerId,
+            phase: pointerPhase(e),
+            x,
+            y,
+          };
+        case 'pen':
+          return {
+            type: 'pen',
+            phase: pointerPhase(e),
+            x,
+            y,
+            pressure: e.pressure,
+            tiltX: e.tiltX,
+            tiltY: e.tiltY,
+            // Bit 5 (32) is the eraser button, bit 1 (2) is the barrel button
+            eraser: (e.buttons & 32) !== 0,
+            barrelButton: (e.buttons & 2) !== 0,
+          };
+        default: {
+          if (e.type === 'pointermove') {
+            return { type: 'pointerMove', x, y };
+          }
+          const button = POINTER_BUTTONS[e.button];
+          if (!button || e.type === 'pointercancel') {
+            return null;
+          }
+          return {
+            type: 'pointerButton',
+            x,
+            y,
+            button,
+            pressed: e.type === 'pointerdown',
+          };
+        }
+      }
+    }),
+    filter((e): e is JsInputEvent => !!e),
+  );
+
+  const wheel$ = fromEvent<WheelEvent>(canvas, 'wheel', {
+    passive: false,
+  }).pipe(
+    map((e): JsInputEvent => {
+      e.preventDefault();
+      const scale =
+        e.deltaMode === WheelEvent.DOM_DELTA_PIXEL
+          ? 1 / PIXELS_PER_SCROLL_NOTCH
+          : 1;
+      return {
+        type: 'scroll',
+        deltaX: e.deltaX * scale,
+        deltaY: e.deltaY * scale,
+      };
+    }),
+  );
+
+  const key$ = merge(
+    fromEvent<KeyboardEvent>(canvas, 'keydown'),
+    fromEvent<KeyboardEvent>(canvas, 'keyup'),
+  ).pipe(
+    filter((e) => !e.repeat),
+    map((e): JsInputEvent => {
+      e.preventDefault();
+      return { type: 'key', code: e.code, pressed: e.type === 'keydown' };
+    }),
+  );
+
+  const contextMenu$ = fromEvent(canvas, 'contextmenu').pipe(
+    tap((e) => e.preventDefault()),
+    ignoreElements(),
+  );
+
+  return merge(pointer$, wheel$, key$, contextMenu$);
+}
+
 export class WakeLocker {
   private wantLocked = false;
   private wakeLock?: WakeLockSentinel;
@@ -1,7 +1,10 @@
+use std::time::Duration;
+
 use dev_disp_core::{
+    core::{ErrorKind, LatencyPercentiles, QualityTier, SessionStats, StreamPolicy},
     daemon::api::{
-        DevDispApi, DeviceCollectionStatus, DiscoveryId, DisplayHostId, DisplayHostRef,
-        DisplayHostStatus, InitializationState,
+        ApiError, DevDispApi, DeviceCollectionStatus, DiscoveryId, DisplayHostId, DisplayHostRef,
+        DisplayHostStatus, InitializationState, MirrorSourceRef,
     },
     util::{PinnedFuture, PinnedStream},
 };
@@ -11,8 +14,8 @@ use futures_util::StreamExt;
 
 use crate::grpc::proto::{
     self, ConnectDeviceRequest, DisconnectDeviceRequest, ListAvailableDevicesRequest,
-    ListConnectedDevicesRequest, StreamDevicesRequest,
-    dev_disp_service_client::DevDispServiceClient,
+    ListConnectedDevicesRequest, MirrorDeviceRequest, SetRecordingRequest, StreamDevicesRequest,
+    StreamSessionStatsRequest, dev_disp_service_client::DevDispServiceClient,
 };
 
 use async_broadcast::{Receiver, Sender};
@@ -89,6 +92,9 @@ impl DevDispApi for DevDispGrpcClient {
                                 discovery_id: d.discovery_id,
                                 id: d.id,
                                 status: d.status.unwrap_or_default().into(),
+                                stream_policy: d.stream_policy.map(Into::into),
+                                mirror_of: d.mirror_of.map(Into::into),
+                                recording: d.recording,
                             })
                             .collect(),
                         in_use_devices: connected_devices
@@ -98,6 +104,9 @@ impl DevDispApi for DevDispGrpcClient {
                                 discovery_id: d.discovery_id,
                                 id: d.id,
                                 status: d.status.unwrap_or_default().into(),
+                                stream_policy: d.stream_policy.map(Into::into),
+                                mirror_of: d.mirror_of.map(Into::into),
+                                recording: d.recording,
                             })
                             .collect(),
                     })
@@ -138,6 +147,9 @@ impl DevDispApi for DevDispGrpcClient {
                                                 discovery_id: d.discovery_id,
                                                 id: d.id,
                                                 status: d.status.unwrap_or_default().into(),
+                                                stream_policy: d.stream_policy.map(Into::into),
+                                                mirror_of: d.mirror_of.map(Into::into),
+                                                recording: d.recording,
                                             })
                                             .collect(),
                                         in_use_devices: connected_devices
@@ -147,6 +159,9 @@ impl DevDispApi for DevDispGrpcClient {
                                                 discovery_id: d.discovery_id,
                                                 id: d.id,
                                                 status: d.status.unwrap_or_default().into(),
+                                                stream_policy: d.stream_policy.map(Into::into),
+                                                mirror_of: d.mirror_of.map(Into::into),
+                                                recording: d.recording,
                                             })
                                             .collect(),
                                     }
@@ -176,7 +191,7 @@ impl DevDispApi for DevDispGrpcClient {
         &self,
         discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), String>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         let mut inner = self.inner.clone();
         let error_tx = self.client_error_tx.clone();
 
@@ -187,10 +202,20 @@ impl DevDispApi for DevDispGrpcClient {
             });
 
             match inner.connect_device(request).await {
-                Ok(_) => Ok(()),
+                Ok(response) => {
+                    let response = response.into_inner();
+                    if response.success {
+                        Ok(())
+                    } else {
+                        Err(api_error(response.error_kind, response.message))
+                    }
+                }
                 Err(e) => {
                     error_tx.broadcast_direct(()).await.ok();
-                    Err(format!("gRPC error: {}", e))
+                    Err(ApiError::new(
+                        ErrorKind::Unavailable,
+                        format!("gRPC error: {}", e),
+                    ))
                 }
             }
         }
@@ -201,7 +226,7 @@ impl DevDispApi for DevDispGrpcClient {
         &self,
         discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), String>> {
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
         let mut inner = self.inner.clone();
         let error_tx = self.client_error_tx.clone();
 
@@ -212,10 +237,96 @@ impl DevDispApi for DevDispGrpcClient {
             });
 
             match inner.disconnect_device(request).await {
-                Ok(_) => Ok(()),
+                Ok(response) => {
+                    let response = response.into_inner();
+                    if response.success {
+                        Ok(())
+                    } else {
+                        Err(api_error(response.error_kind, response.message))
+                    }
+                }
                 Err(e) => {
                     error_tx.broadcast_direct(()).await.ok();
-                    Err(format!("gRPC error: {}", e))
+                    Err(ApiError::new(
+                        ErrorKind::Unavailable,
+                        format!("gRPC error: {}", e),
+                    ))
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn mirror_device(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        source_discovery_id: DiscoveryId,
+        source_device_id: DisplayHostId,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        let mut inner = self.inner.clone();
+        let error_tx = self.client_error_tx.clone();
+
+        async move {
+            let request = tonic::Request::new(MirrorDeviceRequest {
+                device_id,
+                discovery_id,
+                source_device_id,
+                source_discovery_id,
+            });
+
+            match inner.mirror_device(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    if response.success {
+                        Ok(())
+                    } else {
+                        Err(api_error(response.error_kind, response.message))
+                    }
+                }
+                Err(e) => {
+                    error_tx.broadcast_direct(()).await.ok();
+                    Err(ApiError::new(
+                        ErrorKind::Unavailable,
+                        format!("gRPC error: {}", e),
+                    ))
+                }
+            }
+        }
+        .boxed()
+    }
+
+    fn set_recording(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        enabled: bool,
+    ) -> PinnedFuture<'static, Result<(), ApiError>> {
+        let mut inner = self.inner.clone();
+        let error_tx = self.client_error_tx.clone();
+
+        async move {
+            let request = tonic::Request::new(SetRecordingRequest {
+                device_id,
+                discovery_id,
+                enabled,
+            });
+
+            match inner.set_recording(request).await {
+                Ok(response) => {
+                    let response = response.into_inner();
+                    if response.success {
+                        Ok(())
+                    } else {
+                        Err(api_error(response.error_kind, response.message))
+                    }
+                }
+                Err(e) => {
+                    error_tx.broadcast_direct(()).await.ok();
+                    Err(ApiError::new(
+                        ErrorKind::Unavailable,
+                        format!("gRPC error: {}", e),
+                    ))
                 }
             }
         }
@@ -237,6 +348,46 @@ impl DevDispApi for DevDispGrpcClient {
         }
         .boxed()
     }
+
+    fn stream_session_stats(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+    ) -> PinnedStream<'static, SessionStats> {
+        let mut inner = self.inner.clone();
+        let error_tx = self.client_error_tx.clone();
+
+        async move {
+            let request = StreamSessionStatsRequest {
+                device_id,
+                discovery_id,
+            };
+            match inner.stream_session_stats(request).await {
+                Ok(response) => {
+                    let stream = response.into_inner().filter_map(move |res| {
+                        let error_tx = error_tx.clone();
+                        async move {
+                            match res {
+                                Ok(stats) => Some(stats.into()),
+                                Err(_) => {
+                                    let _ = error_tx.broadcast_direct(()).await;
+                                    None
+                                }
+                            }
+                        }
+                    });
+                    stream.boxed()
+                }
+                Err(e) => {
+                    error_tx.broadcast_direct(()).await.ok();
+                    log::error!("Failed to start session stats stream: {}", e);
+                    stream::empty().boxed()
+                }
+            }
+        }
+        .flatten_stream()
+        .boxed()
+    }
 }
 
 impl From<proto::DeviceStatus> for DisplayHostStatus {
@@ -283,6 +434,95 @@ impl From<proto::InitializationPhase> for InitializationState {
             proto::InitializationPhase::SettingClientCodec => {
                 InitializationState::SettingClientCodec
             }
+            proto::InitializationPhase::Renegotiating => InitializationState::Renegotiating,
+            proto::InitializationPhase::AwaitingReconnect => InitializationState::AwaitingReconnect,
+        }
+    }
+}
+
+impl From<proto::ErrorKind> for ErrorKind {
+    fn from(kind: proto::ErrorKind) -> Self {
+        match kind {
+            proto::ErrorKind::Unknown => ErrorKind::Unknown,
+            proto::ErrorKind::NotFound => ErrorKind::NotFound,
+            proto::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            proto::ErrorKind::Unavailable => ErrorKind::Unavailable,
+            proto::ErrorKind::Unsupported => ErrorKind::Unsupported,
+            proto::ErrorKind::Timeout => ErrorKind::Timeout,
+            proto::ErrorKind::Disconnected => ErrorKind::Disconnected,
+            proto::ErrorKind::Cancelled => ErrorKind::Cancelled,
+            proto::ErrorKind::Internal => ErrorKind::Internal,
+        }
+    }
+}
+
+/// An error the daemon answered a request with.
+fn api_error(error_kind: i32, message: String) -> ApiError {
+    let kind = proto::ErrorKind::try_from(error_kind)
+        .unwrap_or(proto::ErrorKind::Unknown)
+        .into();
+    ApiError::new(kind, message)
+}
+
+impl From<proto::MirrorSource> for MirrorSourceRef {
+    fn from(source: proto::MirrorSource) -> Self {
+        MirrorSourceRef {
+            discovery_id: source.discovery_id,
+            device_id: source.device_id,
+        }
+    }
+}
+
+impl From<proto::StreamPolicy> for StreamPolicy {
+    fn from(policy: proto::StreamPolicy) -> Self {
+        StreamPolicy {
+            target_bitrate: policy.target_bitrate,
+            max_bitrate: policy.max_bitrate,
+            max_fps: policy.max_fps,
+            keyframe_interval: policy.keyframe_interval,
+            quality: proto::QualityTier::try_from(policy.quality)
+                .unwrap_or(proto::QualityTier::QualityBalanced)
+                .into(),
+            max_encode_resolution: policy
+                .max_encode_resolution
+                .map(|resolution| (resolution.width, resolution.height)),
+        }
+    }
+}
+
+impl From<proto::QualityTier> for QualityTier {
+    fn from(quality: proto::QualityTier) -> Self {
+        match quality {
+            proto::QualityTier::QualityLow => QualityTier::Low,
+            proto::QualityTier::QualityBalanced => QualityTier::Balanced,
+            proto::QualityTier::QualityHigh => QualityTier::High,
+        }
+    }
+}
+
+impl From<proto::SessionStats> for SessionStats {
+    fn from(stats: proto::SessionStats) -> Self {
+        let resolution = stats.resolution.unwrap_or_default();
+        SessionStats {
+            fps: stats.fps,
+            bitrate: stats.bitrate,
+            encode_latency: stats.encode_latency.map(Into::into),
+            send_latency: stats.send_latency.map(Into::into),
+            dropped_frames: stats.dropped_frames,
+            transport_errors: stats.transport_errors,
+            codec: stats.codec,
+            encoder: stats.encoder,
+            resolution: (resolution.width, resolution.height),
+        }
+    }
+}
+
+impl From<proto::LatencyPercentiles> for LatencyPercentiles {
+    fn from(percentiles: proto::LatencyPercentiles) -> Self {
+        LatencyPercentiles {
+            p50: Duration::from_micros(percentiles.p50_us),
+            p90: Duration::from_micros(percentiles.p90_us),
+            p99: Duration::from_micros(percentiles.p99_us),
         }
     }
 }
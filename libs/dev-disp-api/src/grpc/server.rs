@@ -1,6 +1,7 @@
 use super::proto::{self, dev_disp_service_server::DevDispService};
 use dev_disp_core::{
-    daemon::api::{DevDispApi, DisplayHostStatus, InitializationState},
+    core::{ErrorKind, LatencyPercentiles, QualityTier, SessionStats, StreamPolicy},
+    daemon::api::{ApiError, DevDispApi, DisplayHostStatus, InitializationState, MirrorSourceRef},
     util::PinnedStream,
 };
 use futures_util::StreamExt;
@@ -28,6 +29,7 @@ where
     T: DevDispApi + Send + Sync + 'static,
 {
     type StreamDevicesStream = PinnedStream<'static, Result<proto::StreamDevicesResponse, Status>>;
+    type StreamSessionStatsStream = PinnedStream<'static, Result<proto::SessionStats, Status>>;
 
     async fn list_available_devices(
         &self,
@@ -47,6 +49,9 @@ where
                     discovery_id: device_ref.discovery_id,
                     id: device_ref.id,
                     status: Some(device_ref.status.into()),
+                    stream_policy: device_ref.stream_policy.map(Into::into),
+                    mirror_of: device_ref.mirror_of.map(Into::into),
+                    recording: device_ref.recording,
                 })
                 .collect(),
         }))
@@ -70,6 +75,9 @@ where
                     discovery_id: device_ref.discovery_id,
                     id: device_ref.id,
                     status: Some(device_ref.status.into()),
+                    stream_policy: device_ref.stream_policy.map(Into::into),
+                    mirror_of: device_ref.mirror_of.map(Into::into),
+                    recording: device_ref.recording,
                 })
                 .collect(),
         }))
@@ -80,15 +88,24 @@ where
         request: Request<proto::ConnectDeviceRequest>,
     ) -> std::result::Result<Response<proto::ConnectDeviceResponse>, Status> {
         let req = request.into_inner();
-        self.inner
+        let response = match self
+            .inner
             .initialize_device(req.discovery_id, req.device_id)
             .await
-            .map_err(|e| Status::internal(e))?;
+        {
+            Ok(()) => proto::ConnectDeviceResponse {
+                success: true,
+                message: "".to_string(),
+                error_kind: proto::ErrorKind::Unknown as i32,
+            },
+            Err(ApiError { kind, message }) => proto::ConnectDeviceResponse {
+                success: false,
+                message,
+                error_kind: proto::ErrorKind::from(kind) as i32,
+            },
+        };
 
-        Ok(Response::new(proto::ConnectDeviceResponse {
-            success: true,
-            message: "".to_string(),
-        }))
+        Ok(Response::new(response))
     }
 
     async fn disconnect_device(
@@ -96,15 +113,79 @@ where
         request: Request<proto::DisconnectDeviceRequest>,
     ) -> std::result::Result<Response<proto::DisconnectDeviceResponse>, Status> {
         let req = request.into_inner();
-        self.inner
+        let response = match self
+            .inner
             .disconnect_device(req.discovery_id, req.device_id)
             .await
-            .map_err(|e| Status::internal(e))?;
+        {
+            Ok(()) => proto::DisconnectDeviceResponse {
+                success: true,
+                message: "".to_string(),
+                error_kind: proto::ErrorKind::Unknown as i32,
+            },
+            Err(ApiError { kind, message }) => proto::DisconnectDeviceResponse {
+                success: false,
+                message,
+                error_kind: proto::ErrorKind::from(kind) as i32,
+            },
+        };
 
-        Ok(Response::new(proto::DisconnectDeviceResponse {
-            success: true,
-            message: "".to_string(),
-        }))
+        Ok(Response::new(response))
+    }
+
+    async fn mirror_device(
+        &self,
+        request: Request<proto::MirrorDeviceRequest>,
+    ) -> std::result::Result<Response<proto::MirrorDeviceResponse>, Status> {
+        let req = request.into_inner();
+        let response = match self
+            .inner
+            .mirror_device(
+                req.discovery_id,
+                req.device_id,
+                req.source_discovery_id,
+                req.source_device_id,
+            )
+            .await
+        {
+            Ok(()) => proto::MirrorDeviceResponse {
+                success: true,
+                message: "".to_string(),
+                error_kind: proto::ErrorKind::Unknown as i32,
+            },
+            Err(ApiError { kind, message }) => proto::MirrorDeviceResponse {
+                success: false,
+                message,
+                error_kind: proto::ErrorKind::from(kind) as i32,
+            },
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn set_recording(
+        &self,
+        request: Request<proto::SetRecordingRequest>,
+    ) -> std::result::Result<Response<proto::SetRecordingResponse>, Status> {
+        let req = request.into_inner();
+        let response = match self
+            .inner
+            .set_recording(req.discovery_id, req.device_id, req.enabled)
+            .await
+        {
+            Ok(()) => proto::SetRecordingResponse {
+                success: true,
+                message: "".to_string(),
+                error_kind: proto::ErrorKind::Unknown as i32,
+            },
+            Err(ApiError { kind, message }) => proto::SetRecordingResponse {
+                success: false,
+                message,
+                error_kind: proto::ErrorKind::from(kind) as i32,
+            },
+        };
+
+        Ok(Response::new(response))
     }
 
     async fn stream_devices(
@@ -121,6 +202,9 @@ where
                         discovery_id: device_ref.discovery_id,
                         id: device_ref.id,
                         status: Some(device_ref.status.into()),
+                        stream_policy: device_ref.stream_policy.map(Into::into),
+                        mirror_of: device_ref.mirror_of.map(Into::into),
+                        recording: device_ref.recording,
                     })
                     .collect(),
                 connected_devices: device_stats
@@ -131,6 +215,9 @@ where
                         discovery_id: device_ref.discovery_id,
                         id: device_ref.id,
                         status: Some(device_ref.status.into()),
+                        stream_policy: device_ref.stream_policy.map(Into::into),
+                        mirror_of: device_ref.mirror_of.map(Into::into),
+                        recording: device_ref.recording,
                     })
                     .collect(),
             })
@@ -160,6 +247,19 @@ where
                 .collect(),
         }))
     }
+
+    async fn stream_session_stats(
+        &self,
+        request: Request<proto::StreamSessionStatsRequest>,
+    ) -> std::result::Result<Response<Self::StreamSessionStatsStream>, Status> {
+        let req = request.into_inner();
+        let stream = self
+            .inner
+            .stream_session_stats(req.discovery_id, req.device_id)
+            .map(|stats| Ok(stats.into()));
+
+        Ok(Response::new(stream.boxed()))
+    }
 }
 
 impl From<DisplayHostStatus> for proto::DeviceStatus {
@@ -223,6 +323,87 @@ impl From<InitializationState> for proto::InitializationPhase {
             InitializationState::SettingClientCodec => {
                 proto::InitializationPhase::SettingClientCodec
             }
+            InitializationState::Renegotiating => proto::InitializationPhase::Renegotiating,
+            InitializationState::AwaitingReconnect => proto::InitializationPhase::AwaitingReconnect,
+        }
+    }
+}
+
+impl From<ErrorKind> for proto::ErrorKind {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::Unknown => proto::ErrorKind::Unknown,
+            ErrorKind::NotFound => proto::ErrorKind::NotFound,
+            ErrorKind::PermissionDenied => proto::ErrorKind::PermissionDenied,
+            ErrorKind::Unavailable => proto::ErrorKind::Unavailable,
+            ErrorKind::Unsupported => proto::ErrorKind::Unsupported,
+            ErrorKind::Timeout => proto::ErrorKind::Timeout,
+            ErrorKind::Disconnected => proto::ErrorKind::Disconnected,
+            ErrorKind::Cancelled => proto::ErrorKind::Cancelled,
+            ErrorKind::Internal => proto::ErrorKind::Internal,
+        }
+    }
+}
+
+impl From<MirrorSourceRef> for proto::MirrorSource {
+    fn from(source: MirrorSourceRef) -> Self {
+        proto::MirrorSource {
+            discovery_id: source.discovery_id,
+            device_id: source.device_id,
+        }
+    }
+}
+
+impl From<StreamPolicy> for proto::StreamPolicy {
+    fn from(policy: StreamPolicy) -> Self {
+        proto::StreamPolicy {
+            target_bitrate: policy.target_bitrate,
+            max_bitrate: policy.max_bitrate,
+            max_fps: policy.max_fps,
+            keyframe_interval: policy.keyframe_interval,
+            quality: proto::QualityTier::from(policy.quality) as i32,
+            max_encode_resolution: policy
+                .max_encode_resolution
+                .map(|(width, height)| proto::Resolution { width, height }),
+        }
+    }
+}
+
+impl From<QualityTier> for proto::QualityTier {
+    fn from(quality: QualityTier) -> Self {
+        match quality {
+            QualityTier::Low => proto::QualityTier::QualityLow,
+            QualityTier::Balanced => proto::QualityTier::QualityBalanced,
+            QualityTier::High => proto::QualityTier::QualityHigh,
+        }
+    }
+}
+
+impl From<SessionStats> for proto::SessionStats {
+    fn from(stats: SessionStats) -> Self {
+        proto::SessionStats {
+            fps: stats.fps,
+            bitrate: stats.bitrate,
+            encode_latency: stats.encode_latency.map(Into::into),
+            send_latency: stats.send_latency.map(Into::into),
+            dropped_frames: stats.dropped_frames,
+            transport_errors: stats.transport_errors,
+            codec: stats.codec,
+            encoder: stats.encoder,
+            resolution: Some(proto::Resolution {
+                width: stats.resolution.0,
+                height: stats.resolution.1,
+            }),
+        }
+    }
+}
+
+impl From<LatencyPercentiles> for proto::LatencyPercentiles {
+    fn from(percentiles: LatencyPercentiles) -> Self {
+        proto::LatencyPercentiles {
+            p50_us: percentiles.p50.as_micros() as u64,
+            p90_us: percentiles.p90.as_micros() as u64,
+            p99_us: percentiles.p99.as_micros() as u64,
         }
     }
 }
@@ -0,0 +1,3 @@
+mod system;
+
+pub use system::*;
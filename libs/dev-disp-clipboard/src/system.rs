@@ -0,0 +1,249 @@
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use arboard::{Clipboard, Error as ArboardError};
+use dev_disp_core::{
+    host::{CLIPBOARD_TEXT_MIME_TYPE, ClipboardBackend, ClipboardError, ClipboardProvider},
+    util::{PinnedLocalFuture, PinnedLocalStream},
+};
+use futures::{
+    FutureExt, StreamExt,
+    channel::{mpsc as futures_mpsc, oneshot},
+};
+use log::{debug, trace};
+
+pub const CLIPBOARD_HTML_MIME_TYPE: &str = "text/html";
+
+/// Neither Wayland's data control protocol nor X11 tell arboard when the
+/// clipboard changes, so we have to go look.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Shares the desktop's clipboard through Wayland's data control protocol, or X11
+/// when that isn't there.
+#[derive(Debug, Clone, Default)]
+pub struct SystemClipboardProvider;
+
+impl SystemClipboardProvider {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl ClipboardProvider for SystemClipboardProvider {
+    type BackendType = SystemClipboard;
+
+    fn create_backend(&self) -> PinnedLocalFuture<'_, Result<Self::BackendType, ClipboardError>> {
+        SystemClipboard::new().boxed_local()
+    }
+}
+
+enum Command {
+    Read {
+        mime_type: String,
+        reply: oneshot::Sender<Result<Vec<u8>, ClipboardError>>,
+    },
+    Write {
+        mime_type: String,
+        data: Vec<u8>,
+        reply: oneshot::Sender<Result<(), ClipboardError>>,
+    },
+}
+
+/// What was on the clipboard when we last looked.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Snapshot {
+    text: Option<String>,
+    html: Option<String>,
+}
+
+impl Snapshot {
+    fn take(clipboard: &mut Clipboard) -> Self {
+        Self {
+            text: clipboard.get_text().ok(),
+            html: clipboard.get().html().ok(),
+        }
+    }
+
+    fn mime_types(&self) -> Vec<String> {
+        let mut mime_types = Vec::new();
+        if self.html.is_some() {
+            mime_types.push(CLIPBOARD_HTML_MIME_TYPE.to_string());
+        }
+        if self.text.is_some() {
+            mime_types.push(CLIPBOARD_TEXT_MIME_TYPE.to_string());
+        }
+        mime_types
+    }
+}
+
+/// The desktop's clipboard. arboard blocks, so the clipboard lives on a thread of
+/// its own, which stops when this is dropped.
+pub struct SystemClipboard {
+    commands: mpsc::Sender<Command>,
+    changes: Option<futures_mpsc::UnboundedReceiver<Vec<String>>>,
+}
+
+impl SystemClipboard {
+    pub async fn new() -> Result<Self, ClipboardError> {
+        let (commands_tx, commands_rx) = mpsc::channel();
+        let (changes_tx, changes_rx) = futures_mpsc::unbounded();
+        let (ready_tx, ready_rx) = oneshot::channel();
+
+        thread::Builder::new()
+            .name("dev-disp-clipboard".to_string())
+            .spawn(move || {
+                let clipboard = match Clipboard::new() {
+                    Ok(clipboard) => clipboard,
+                    Err(e) => {
+                        _ = ready_tx.send(Err(ClipboardError::Unavailable(format!(
+                            "Failed to open the clipboard: {}",
+                            e
+                        ))));
+                        return;
+                    }
+                };
+                _ = ready_tx.send(Ok(()));
+                run_clipboard(clipboard, commands_rx, changes_tx);
+            })
+            .map_err(|e| {
+                ClipboardError::Other(format!("Failed to start the clipboard thread: {}", e))
+            })?;
+
+        ready_rx.await.map_err(|_| {
+            ClipboardError::Other("Clipboard thread exited before starting".to_string())
+        })??;
+
+        Ok(Self {
+            commands: commands_tx,
+            changes: Some(changes_rx),
+        })
+    }
+
+    async fn send<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<T, ClipboardError>>) -> Command,
+    ) -> Result<T, ClipboardError> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.commands
+            .send(command(reply_tx))
+            .map_err(|_| ClipboardError::Unavailable("Clipboard thread has stopped".to_string()))?;
+        reply_rx
+            .await
+            .map_err(|_| ClipboardError::Unavailable("Clipboard thread has stopped".to_string()))?
+    }
+}
+
+impl ClipboardBackend for SystemClipboard {
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![
+            CLIPBOARD_HTML_MIME_TYPE.to_string(),
+            CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+        ]
+    }
+
+    fn take_change_stream(&mut self) -> Option<PinnedLocalStream<'static, Vec<String>>> {
+        self.changes.take().map(|rx| rx.boxed_local())
+    }
+
+    fn read(&mut self, mime_type: &str) -> PinnedLocalFuture<'_, Result<Vec<u8>, ClipboardError>> {
+        let mime_type = mime_type.to_string();
+        self.send(|reply| Command::Read { mime_type, reply })
+            .boxed_local()
+    }
+
+    fn write(
+        &mut self,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> PinnedLocalFuture<'_, Result<(), ClipboardError>> {
+        let mime_type = mime_type.to_string();
+        self.send(|reply| Command::Write {
+            mime_type,
+            data,
+            reply,
+        })
+        .boxed_local()
+    }
+}
+
+fn run_clipboard(
+    mut clipboard: Clipboard,
+    commands: mpsc::Receiver<Command>,
+    changes: futures_mpsc::UnboundedSender<Vec<String>>,
+) {
+    let mut last_seen = Snapshot::take(&mut clipboard);
+
+    loop {
+        match commands.recv_timeout(POLL_INTERVAL) {
+            Ok(Command::Read { mime_type, reply }) => {
+                _ = reply.send(read_clipboard(&mut clipboard, &mime_type));
+            }
+            Ok(Command::Write {
+                mime_type,
+                data,
+                reply,
+            }) => {
+                let result = write_clipboard(&mut clipboard, &mime_type, data);
+                // Don't report our own write as a change
+                last_seen = Snapshot::take(&mut clipboard);
+                _ = reply.send(result);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let snapshot = Snapshot::take(&mut clipboard);
+                if snapshot != last_seen {
+                    trace!("Clipboard changed: {:?}", snapshot.mime_types());
+                    if changes.unbounded_send(snapshot.mime_types()).is_err() {
+                        debug!("Nobody is following clipboard changes anymore");
+                    }
+                    last_seen = snapshot;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                debug!("Clipboard was dropped, stopping the clipboard thread");
+                return;
+            }
+        }
+    }
+}
+
+fn read_clipboard(clipboard: &mut Clipboard, mime_type: &str) -> Result<Vec<u8>, ClipboardError> {
+    let content = match mime_type {
+        CLIPBOARD_TEXT_MIME_TYPE => clipboard.get_text(),
+        CLIPBOARD_HTML_MIME_TYPE => clipboard.get().html(),
+        _ => return Err(ClipboardError::UnsupportedType(mime_type.to_string())),
+    };
+    content.map(String::into_bytes).map_err(|e| match e {
+        ArboardError::ContentNotAvailable => ClipboardError::NoContent(mime_type.to_string()),
+        e => ClipboardError::Other(format!(
+            "Failed to read {} from the clipboard: {}",
+            mime_type, e
+        )),
+    })
+}
+
+fn write_clipboard(
+    clipboard: &mut Clipboard,
+    mime_type: &str,
+    data: Vec<u8>,
+) -> Result<(), ClipboardError> {
+    let content = String::from_utf8(data).map_err(|_| {
+        ClipboardError::Other(format!(
+            "Clipboard {} content is not valid UTF-8",
+            mime_type
+        ))
+    })?;
+    let result = match mime_type {
+        CLIPBOARD_TEXT_MIME_TYPE => clipboard.set_text(content),
+        CLIPBOARD_HTML_MIME_TYPE => clipboard.set_html(content, None::<String>),
+        _ => return Err(ClipboardError::UnsupportedType(mime_type.to_string())),
+    };
+    result.map_err(|e| {
+        ClipboardError::Other(format!(
+            "Failed to write {} to the clipboard: {}",
+            mime_type, e
+        ))
+    })
+}
@@ -1,14 +1,22 @@
 use std::{
     fmt::{Debug, Display},
     pin::Pin,
+    time::Duration,
 };
 
 use futures_util::FutureExt;
 
 use crate::{
     client::{ScreenTransport, SomeScreenTransport, TransportError},
-    host::{DisplayParameters, EncoderPossibleConfiguration},
-    util::PinnedFuture,
+    core::{
+        ClockPing, ClockPong, FramePresented, GoodbyeReason, InputEvent, NegotiatedProtocol,
+        ReceiverReport, SessionToken,
+    },
+    host::{
+        ClipboardMessage, CursorUpdate, DisplayParameters, EncodedFrame, EncodedRegion,
+        EncoderPossibleConfiguration, FrameHeader,
+    },
+    util::{PinnedFuture, PinnedStream},
 };
 
 /// The display host is the device that is hosting the screen, not
@@ -47,6 +55,66 @@ where
         self.client_id
     }
 
+    /// The protocol negotiated with this display host, if its transport negotiates one.
+    pub fn protocol(&self) -> Option<&NegotiatedProtocol> {
+        self.transport.protocol()
+    }
+
+    /// Takes the stream of input events coming from this display host, if its
+    /// transport carries input. Only the first call gets the stream.
+    pub fn take_input_stream(&mut self) -> Option<PinnedStream<'static, InputEvent>> {
+        self.transport.take_input_stream()
+    }
+
+    /// Takes the stream of keyframe requests coming from this display host, if its
+    /// transport carries them. Only the first call gets the stream.
+    pub fn take_keyframe_request_stream(&mut self) -> Option<PinnedStream<'static, ()>> {
+        self.transport.take_keyframe_request_stream()
+    }
+
+    /// Takes the stream of display parameter changes coming from this display host,
+    /// if its transport carries them. Only the first call gets the stream.
+    pub fn take_display_parameters_stream(
+        &mut self,
+    ) -> Option<PinnedStream<'static, DisplayParameters>> {
+        self.transport.take_display_parameters_stream()
+    }
+
+    /// Takes the stream of receiver reports coming from this display host, if its
+    /// transport carries them. Only the first call gets the stream.
+    pub fn take_receiver_report_stream(&mut self) -> Option<PinnedStream<'static, ReceiverReport>> {
+        self.transport.take_receiver_report_stream()
+    }
+
+    /// Takes the stream of answers to `send_clock_ping` coming from this display
+    /// host, if its transport carries them. Only the first call gets the stream.
+    pub fn take_clock_pong_stream(&mut self) -> Option<PinnedStream<'static, ClockPong>> {
+        self.transport.take_clock_pong_stream()
+    }
+
+    /// Takes the stream of frame presentation reports coming from this display
+    /// host, if its transport carries them. Only the first call gets the stream.
+    pub fn take_frame_presented_stream(&mut self) -> Option<PinnedStream<'static, FramePresented>> {
+        self.transport.take_frame_presented_stream()
+    }
+
+    /// Takes the stream of clipboard messages coming from this display host, if its
+    /// transport carries them. Only the first call gets the stream.
+    pub fn take_clipboard_stream(&mut self) -> Option<PinnedStream<'static, ClipboardMessage>> {
+        self.transport.take_clipboard_stream()
+    }
+
+    /// Takes the stream of goodbyes coming from this display host, if its transport
+    /// carries them. Only the first call gets the stream.
+    pub fn take_goodbye_stream(&mut self) -> Option<PinnedStream<'static, GoodbyeReason>> {
+        self.transport.take_goodbye_stream()
+    }
+
+    /// How often this display host wants a heartbeat, if at all.
+    pub fn heartbeat_interval(&self) -> Option<Duration> {
+        self.transport.heartbeat_interval()
+    }
+
     pub fn get_background_task<'s, 'a>(&'s mut self) -> PinnedFuture<'a, Result<(), TransportError>>
     where
         'a: 's,
@@ -102,12 +170,62 @@ where
     /// TODO: Consider changing the future to be non-boxed if possible for performance
     pub fn send_screen_data<'s, 'a>(
         &'s mut self,
-        data: &'a [u8],
+        frame: EncodedFrame<'a>,
     ) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + Send + 's>>
     where
         'a: 's,
     {
-        self.transport.send_screen_data(data)
+        self.transport.send_screen_data(frame)
+    }
+
+    pub fn send_screen_regions<'s, 'a>(
+        &'s mut self,
+        header: FrameHeader,
+        regions: &'a [EncodedRegion<'a>],
+    ) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + Send + 's>>
+    where
+        'a: 's,
+    {
+        self.transport.send_screen_regions(header, regions)
+    }
+
+    pub fn send_cursor_update(
+        &mut self,
+        update: CursorUpdate,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.transport.send_cursor_update(update)
+    }
+
+    pub fn send_clock_ping(
+        &mut self,
+        ping: ClockPing,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.transport.send_clock_ping(ping)
+    }
+
+    pub fn send_clipboard_message(
+        &mut self,
+        msg: ClipboardMessage,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.transport.send_clipboard_message(msg)
+    }
+
+    pub fn send_heartbeat(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.transport.send_heartbeat()
+    }
+
+    pub fn send_goodbye(
+        &mut self,
+        reason: GoodbyeReason,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.transport.send_goodbye(reason)
+    }
+
+    pub fn send_session_token(
+        &mut self,
+        token: SessionToken,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.transport.send_session_token(token)
     }
 
     pub async fn close(&mut self) -> Result<(), TransportError> {
@@ -1,40 +1,88 @@
 use std::{
     fmt::{Debug, Display},
     future,
+    pin::pin,
+    time::Duration,
 };
 
-use futures_util::FutureExt;
+use futures_util::{FutureExt, future::Either};
 use log::debug;
 use thiserror::Error;
 
 use crate::{
-    host::{DisplayParameters, EncoderPossibleConfiguration},
-    util::PinnedFuture,
+    core::{
+        ClockPing, ClockPong, ErrorKind, FramePresented, GoodbyeReason, InputEvent,
+        NegotiatedProtocol, ProtocolRejection, ReceiverReport, SessionToken,
+    },
+    host::{
+        ClipboardMessage, CursorUpdate, DisplayParameters, EncodedFrame, EncodedRegion,
+        EncoderPossibleConfiguration, FrameHeader,
+    },
+    util::{PinnedFuture, PinnedStream},
 };
 
 #[derive(Debug, Error)]
 pub enum TransportError {
     NoConnection,
     Timeout,
+    /// The client turned down what it was asked for
+    Rejected(String),
+    /// The client speaks no protocol we can agree on
+    Incompatible(ProtocolRejection),
+    /// The client sent something that makes no sense at this point
+    UnexpectedMessage(String),
+    /// The transport was used in a way it can't be, like taking something twice
+    InvalidState(String),
     Other(Box<dyn std::error::Error + Send + Sync>),
-    Unknown,
     NotImplemented,
     SerializationError,
 }
 
+impl TransportError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            TransportError::NoConnection => ErrorKind::Disconnected,
+            TransportError::Timeout => ErrorKind::Timeout,
+            TransportError::Rejected(_)
+            | TransportError::Incompatible(_)
+            | TransportError::NotImplemented => ErrorKind::Unsupported,
+            TransportError::UnexpectedMessage(_)
+            | TransportError::InvalidState(_)
+            | TransportError::SerializationError => ErrorKind::Internal,
+            TransportError::Other(_) => ErrorKind::Unknown,
+        }
+    }
+}
+
 impl Display for TransportError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TransportError::NoConnection => write!(f, "No connection"),
             TransportError::Timeout => write!(f, "Timeout"),
+            TransportError::Rejected(what) => write!(f, "Rejected: {}", what),
+            TransportError::Incompatible(rejection) => write!(f, "{}", rejection),
+            TransportError::UnexpectedMessage(what) => write!(f, "Unexpected message: {}", what),
+            TransportError::InvalidState(what) => write!(f, "Invalid state: {}", what),
             TransportError::Other(e) => write!(f, "Other error: {}", e),
-            TransportError::Unknown => write!(f, "Unknown error"),
             TransportError::NotImplemented => write!(f, "Not Implemented"),
             TransportError::SerializationError => write!(f, "Serialization Error"),
         }
     }
 }
 
+/// Waits for a transport operation, failing with `TransportError::Timeout` if it
+/// takes longer than `timeout`.
+pub async fn with_timeout<F, T>(timeout: Duration, operation: F) -> Result<T, TransportError>
+where
+    F: Future<Output = T>,
+{
+    let operation = pin!(operation);
+    match futures_util::future::select(operation, futures_timer::Delay::new(timeout)).await {
+        Either::Left((output, _)) => Ok(output),
+        Either::Right(_) => Err(TransportError::Timeout),
+    }
+}
+
 /// The transport needs to be a sink that sends the screen data to the
 /// client via whatever means possible.
 pub trait ScreenTransport {
@@ -47,6 +95,72 @@ pub trait ScreenTransport {
     fn get_display_config(&mut self)
     -> PinnedFuture<'_, Result<DisplayParameters, TransportError>>;
 
+    /// The protocol negotiated with the client, if this transport negotiates one.
+    fn protocol(&self) -> Option<&NegotiatedProtocol> {
+        None
+    }
+
+    /// Takes the stream of input events coming from the client. Transports that
+    /// don't carry input, or whose stream was already taken, return `None`.
+    fn take_input_stream(&mut self) -> Option<PinnedStream<'static, InputEvent>> {
+        None
+    }
+
+    /// Takes the stream of keyframe requests coming from the client, sent when it
+    /// can't decode the stream anymore. Transports that don't carry them, or whose
+    /// stream was already taken, return `None`.
+    fn take_keyframe_request_stream(&mut self) -> Option<PinnedStream<'static, ()>> {
+        None
+    }
+
+    /// Takes the stream of display parameters the client sends after the first ones,
+    /// like when it's rotated or resized. Transports that can't carry them, or whose
+    /// stream was already taken, return `None`.
+    fn take_display_parameters_stream(
+        &mut self,
+    ) -> Option<PinnedStream<'static, DisplayParameters>> {
+        None
+    }
+
+    /// Takes the stream of receiver reports the client sends to tell how well it's
+    /// keeping up. Transports that don't carry them, or whose stream was already
+    /// taken, return `None`.
+    fn take_receiver_report_stream(&mut self) -> Option<PinnedStream<'static, ReceiverReport>> {
+        None
+    }
+
+    /// Takes the stream of the client's answers to `send_clock_ping`. Transports
+    /// that don't carry them, or whose stream was already taken, return `None`.
+    fn take_clock_pong_stream(&mut self) -> Option<PinnedStream<'static, ClockPong>> {
+        None
+    }
+
+    /// Takes the stream of the client's reports of when it presented each frame.
+    /// Transports that don't carry them, or whose stream was already taken, return
+    /// `None`.
+    fn take_frame_presented_stream(&mut self) -> Option<PinnedStream<'static, FramePresented>> {
+        None
+    }
+
+    /// Takes the stream of clipboard messages from the client. Transports that
+    /// don't carry them, or whose stream was already taken, return `None`.
+    fn take_clipboard_stream(&mut self) -> Option<PinnedStream<'static, ClipboardMessage>> {
+        None
+    }
+
+    /// Takes the stream of goodbyes from the client, telling why it closed the
+    /// session. Transports that don't carry them, or whose stream was already
+    /// taken, return `None`.
+    fn take_goodbye_stream(&mut self) -> Option<PinnedStream<'static, GoodbyeReason>> {
+        None
+    }
+
+    /// How often `send_heartbeat` should be called to keep the connection alive.
+    /// Transports that don't need heartbeats return `None`.
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        None
+    }
+
     fn close(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
         future::ready(Ok(())).boxed()
     }
@@ -71,10 +185,75 @@ pub trait ScreenTransport {
     /// TODO: Consider changing the future to be non-boxed if possible for performance
     fn send_screen_data<'s, 'a>(
         &'s mut self,
-        data: &'a [u8],
+        frame: EncodedFrame<'a>,
     ) -> PinnedFuture<'s, Result<(), TransportError>>
     where
         'a: 's;
+
+    /// Sends only the changed regions of a frame. Transports that cannot carry
+    /// region updates should leave this as `TransportError::NotImplemented`, and the
+    /// caller will fall back to whole frames.
+    fn send_screen_regions<'s, 'a>(
+        &'s mut self,
+        _header: FrameHeader,
+        _regions: &'a [EncodedRegion<'a>],
+    ) -> PinnedFuture<'s, Result<(), TransportError>>
+    where
+        'a: 's,
+    {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
+
+    /// Sends a cursor change, for clients that draw the cursor themselves. Transports
+    /// that cannot carry it should leave this as `TransportError::NotImplemented`, and
+    /// the caller will draw the cursor into the frames instead.
+    fn send_cursor_update(
+        &mut self,
+        _update: CursorUpdate,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
+
+    /// Sends a clock sync ping, which the client answers through
+    /// `take_clock_pong_stream`.
+    fn send_clock_ping(
+        &mut self,
+        _ping: ClockPing,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
+
+    /// Sends a clipboard message to the client, which answers through
+    /// `take_clipboard_stream`.
+    fn send_clipboard_message(
+        &mut self,
+        _msg: ClipboardMessage,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
+
+    /// Tells the client the connection is still alive, every `heartbeat_interval`.
+    fn send_heartbeat(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
+
+    /// Tells the client why the session is being closed, right before `close`.
+    fn send_goodbye(
+        &mut self,
+        _reason: GoodbyeReason,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
+
+    /// Hands the client the token to resume its session with, right after
+    /// `initialize`. Transports that can't carry it should leave this as
+    /// `TransportError::NotImplemented`, and the session won't be resumed.
+    fn send_session_token(
+        &mut self,
+        _token: SessionToken,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async { Err(TransportError::NotImplemented) }.boxed()
+    }
 }
 
 pub struct SomeScreenTransport {
@@ -107,6 +286,48 @@ impl ScreenTransport for SomeScreenTransport {
         self.inner.get_display_config()
     }
 
+    fn protocol(&self) -> Option<&NegotiatedProtocol> {
+        self.inner.protocol()
+    }
+
+    fn take_input_stream(&mut self) -> Option<PinnedStream<'static, InputEvent>> {
+        self.inner.take_input_stream()
+    }
+
+    fn take_keyframe_request_stream(&mut self) -> Option<PinnedStream<'static, ()>> {
+        self.inner.take_keyframe_request_stream()
+    }
+
+    fn take_display_parameters_stream(
+        &mut self,
+    ) -> Option<PinnedStream<'static, DisplayParameters>> {
+        self.inner.take_display_parameters_stream()
+    }
+
+    fn take_receiver_report_stream(&mut self) -> Option<PinnedStream<'static, ReceiverReport>> {
+        self.inner.take_receiver_report_stream()
+    }
+
+    fn take_clock_pong_stream(&mut self) -> Option<PinnedStream<'static, ClockPong>> {
+        self.inner.take_clock_pong_stream()
+    }
+
+    fn take_frame_presented_stream(&mut self) -> Option<PinnedStream<'static, FramePresented>> {
+        self.inner.take_frame_presented_stream()
+    }
+
+    fn take_clipboard_stream(&mut self) -> Option<PinnedStream<'static, ClipboardMessage>> {
+        self.inner.take_clipboard_stream()
+    }
+
+    fn take_goodbye_stream(&mut self) -> Option<PinnedStream<'static, GoodbyeReason>> {
+        self.inner.take_goodbye_stream()
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        self.inner.heartbeat_interval()
+    }
+
     fn background<'s, 'a>(&'s mut self) -> PinnedFuture<'a, Result<(), TransportError>> {
         self.inner.background()
     }
@@ -131,12 +352,59 @@ impl ScreenTransport for SomeScreenTransport {
 
     fn send_screen_data<'s, 'a>(
         &'s mut self,
-        data: &'a [u8],
+        frame: EncodedFrame<'a>,
+    ) -> PinnedFuture<'s, Result<(), TransportError>>
+    where
+        'a: 's,
+    {
+        self.inner.send_screen_data(frame)
+    }
+
+    fn send_screen_regions<'s, 'a>(
+        &'s mut self,
+        header: FrameHeader,
+        regions: &'a [EncodedRegion<'a>],
     ) -> PinnedFuture<'s, Result<(), TransportError>>
     where
         'a: 's,
     {
-        self.inner.send_screen_data(data)
+        self.inner.send_screen_regions(header, regions)
+    }
+
+    fn send_cursor_update(
+        &mut self,
+        update: CursorUpdate,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.inner.send_cursor_update(update)
+    }
+
+    fn send_clock_ping(&mut self, ping: ClockPing) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.inner.send_clock_ping(ping)
+    }
+
+    fn send_clipboard_message(
+        &mut self,
+        msg: ClipboardMessage,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.inner.send_clipboard_message(msg)
+    }
+
+    fn send_heartbeat(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.inner.send_heartbeat()
+    }
+
+    fn send_goodbye(
+        &mut self,
+        reason: GoodbyeReason,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.inner.send_goodbye(reason)
+    }
+
+    fn send_session_token(
+        &mut self,
+        token: SessionToken,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        self.inner.send_session_token(token)
     }
 
     fn close(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
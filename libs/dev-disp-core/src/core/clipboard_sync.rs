@@ -0,0 +1,202 @@
+use log::{debug, warn};
+
+use crate::{
+    host::{ClipboardBackend, ClipboardMessage},
+    util::PinnedLocalStream,
+};
+
+/// Keeps the host's clipboard in sync with a display host's, by turning changes on
+/// either side into `ClipboardMessage`s for the other.
+///
+/// The host's clipboard is offered to the display host as it changes, and read
+/// when the display host asks for it. Offers from the display host are fetched
+/// right away, since the host's clipboard needs the content up front.
+pub struct ClipboardSync<B> {
+    backend: B,
+    /// Serial of our latest offer
+    offer_serial: u32,
+    offered_mime_types: Vec<String>,
+    /// The display host's offer we asked content for, and which type
+    pending_request: Option<(u32, String)>,
+}
+
+impl<B> ClipboardSync<B>
+where
+    B: ClipboardBackend,
+{
+    pub fn new(backend: B) -> Self {
+        Self {
+            backend,
+            offer_serial: 0,
+            offered_mime_types: Vec::new(),
+            pending_request: None,
+        }
+    }
+
+    /// Takes the stream of changes to the host's clipboard, to be handed to
+    /// `on_host_change`. Only the first call gets the stream.
+    pub fn take_host_changes(&mut self) -> Option<PinnedLocalStream<'static, Vec<String>>> {
+        self.backend.take_change_stream()
+    }
+
+    /// The host's clipboard changed. Returns the offer for the display host.
+    pub fn on_host_change(&mut self, mime_types: Vec<String>) -> ClipboardMessage {
+        self.offer_serial = self.offer_serial.wrapping_add(1);
+        self.offered_mime_types = mime_types.clone();
+        ClipboardMessage::Offer {
+            serial: self.offer_serial,
+            mime_types,
+        }
+    }
+
+    /// Handles a message from the display host, returning the answer to send back,
+    /// if any.
+    pub async fn on_message(&mut self, msg: ClipboardMessage) -> Option<ClipboardMessage> {
+        match msg {
+            ClipboardMessage::Offer { serial, mime_types } => {
+                // Take the type the host's clipboard likes best
+                let Some(mime_type) = self
+                    .backend
+                    .supported_mime_types()
+                    .into_iter()
+                    .find(|mime_type| mime_types.contains(mime_type))
+                else {
+                    debug!(
+                        "Ignoring clipboard offer #{} with no supported types ({})",
+                        serial,
+                        mime_types.join(", ")
+                    );
+                    self.pending_request = None;
+                    return None;
+                };
+                self.pending_request = Some((serial, mime_type.clone()));
+                Some(ClipboardMessage::Request { serial, mime_type })
+            }
+            ClipboardMessage::Request { serial, mime_type } => {
+                let data = if serial != self.offer_serial
+                    || !self.offered_mime_types.contains(&mime_type)
+                {
+                    debug!(
+                        "Clipboard request for {} is not for our latest offer",
+                        mime_type
+                    );
+                    None
+                } else {
+                    match self.backend.read(&mime_type).await {
+                        Ok(data) => Some(data),
+                        Err(e) => {
+                            warn!("Failed to read the host clipboard: {}", e);
+                            None
+                        }
+                    }
+                };
+                Some(ClipboardMessage::Data {
+                    serial,
+                    mime_type,
+                    data,
+                })
+            }
+            ClipboardMessage::Data {
+                serial,
+                mime_type,
+                data,
+            } => {
+                if self.pending_request.as_ref() != Some(&(serial, mime_type.clone())) {
+                    debug!(
+                        "Ignoring clipboard content for an outdated offer #{}",
+                        serial
+                    );
+                    return None;
+                }
+                self.pending_request = None;
+                let Some(data) = data else {
+                    debug!("Clipboard offer #{} was gone before we got it", serial);
+                    return None;
+                };
+                if let Err(e) = self.backend.write(&mime_type, data).await {
+                    warn!("Failed to write the host clipboard: {}", e);
+                }
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::{FutureExt, StreamExt};
+
+    use super::ClipboardSync;
+    use crate::host::{
+        CLIPBOARD_TEXT_MIME_TYPE, ClipboardMessage, ClipboardProvider, MemoryClipboard,
+        MemoryClipboardProvider,
+    };
+
+    fn sync(provider: &MemoryClipboardProvider) -> ClipboardSync<MemoryClipboard> {
+        let backend = provider.create_backend().now_or_never().unwrap().unwrap();
+        ClipboardSync::new(backend)
+    }
+
+    #[test]
+    fn test_client_copy_lands_on_host() {
+        let provider = MemoryClipboardProvider::new();
+        let mut sync = sync(&provider);
+        let mut host_changes = sync.take_host_changes().unwrap();
+
+        let offer = ClipboardMessage::Offer {
+            serial: 3,
+            mime_types: vec![
+                "image/png".to_string(),
+                CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+            ],
+        };
+        let request = sync.on_message(offer).now_or_never().unwrap().unwrap();
+        assert_eq!(
+            request,
+            ClipboardMessage::Request {
+                serial: 3,
+                mime_type: CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+            }
+        );
+
+        let data = ClipboardMessage::Data {
+            serial: 3,
+            mime_type: CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+            data: Some(b"hello".to_vec()),
+        };
+        assert_eq!(sync.on_message(data).now_or_never().unwrap(), None);
+        assert_eq!(
+            provider.contents(CLIPBOARD_TEXT_MIME_TYPE),
+            Some(b"hello".to_vec())
+        );
+        // Our own write must not be offered back to the client
+        assert!(host_changes.next().now_or_never().is_none());
+    }
+
+    #[test]
+    fn test_host_copy_is_offered_and_served() {
+        let provider = MemoryClipboardProvider::new();
+        let mut sync = sync(&provider);
+        let mut host_changes = sync.take_host_changes().unwrap();
+
+        provider.copy(CLIPBOARD_TEXT_MIME_TYPE, b"from the host".to_vec());
+        let mime_types = host_changes.next().now_or_never().flatten().unwrap();
+        let ClipboardMessage::Offer { serial, .. } = sync.on_host_change(mime_types) else {
+            panic!("Expected an offer");
+        };
+
+        let request = ClipboardMessage::Request {
+            serial,
+            mime_type: CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+        };
+        let data = sync.on_message(request).now_or_never().unwrap();
+        assert_eq!(
+            data,
+            Some(ClipboardMessage::Data {
+                serial,
+                mime_type: CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+                data: Some(b"from the host".to_vec()),
+            })
+        );
+    }
+}
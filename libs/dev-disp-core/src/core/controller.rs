@@ -1,28 +1,147 @@
 use std::{
-    sync::{Arc, atomic::AtomicBool},
+    cell::{Cell, RefCell},
+    fmt::Display,
+    path::PathBuf,
+    pin::pin,
+    rc::Rc,
     time::{Duration, Instant},
 };
 
-use futures::{Sink, SinkExt, Stream, StreamExt};
+use futures::{
+    Sink, SinkExt, Stream, StreamExt,
+    channel::{mpsc, oneshot},
+    future::{self, Either},
+    lock::Mutex,
+    stream::{self, AbortHandle, FuturesUnordered, SelectAll},
+};
 use futures_util::FutureExt;
 use log::{debug, error, info, trace, warn};
 
 use crate::{
-    client::{DisplayHost, ScreenTransport},
+    client::{DisplayHost, ScreenTransport, SomeScreenTransport, TransportError, with_timeout},
+    core::{
+        ClipboardSync, ClockPing, ClockPong, EncodeRecovery, EncoderFallback, FramePacer,
+        FramePresented, FrameQueue, FrameTimings, GoodbyeReason, InputEvent, LatencyStage,
+        LatencyTracker, MIRROR_QUEUE_DEPTH, Mirror, MirrorControl, MirrorFrame, MirrorJoin, Pacing,
+        ProtocolCapability, RateController, ReceiverReport, SESSION_STATS_INTERVAL,
+        STATIC_REFRESH_INTERVAL, SessionError, SessionStats, SessionStatsCollector, SessionToken,
+        StreamPolicy, StreamRecorder, frame_hash, mirror_transmit_loop, negotiate_mirror,
+    },
     host::{
-        DisplayHostResult, Encoder, EncoderContentParameters, EncoderProvider, Screen,
-        ScreenProvider, ScreenReadyStatus,
+        ClipboardBackend, ClipboardMessage, ClipboardProvider, CursorState, CursorUpdate,
+        DamageRect, DisplayHostResult, DisplayParameters, EncodeTimings, EncodedFrame,
+        EncodedRegion, Encoder, EncoderContentParameters, EncoderError,
+        EncoderPossibleConfiguration, EncoderProvider, FrameHeader, InputInjector,
+        InputInjectorProvider, InputTarget, RateChange, RateTarget, Screen, ScreenError,
+        ScreenOutputParameters, ScreenProvider, ScreenReadyStatus,
     },
+    util::{PinnedLocalFuture, PinnedLocalStream, PinnedStream, unix_time_micros},
 };
 
 const NOT_READY_DELAY: Duration = Duration::from_millis(100);
 
+/// How often the display host's clock is synced with ours, for latency measurement.
+const CLOCK_SYNC_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a cancelled session gets to say goodbye to its display host before it's
+/// dropped.
+const CLOSE_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// How long saying goodbye may take, since the display host may not be listening
+/// anymore.
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often the latency percentiles of a session are logged.
+const LATENCY_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many queued display parameter updates are looked at at once, to only act on
+/// the latest.
+const DISPLAY_PARAMETERS_BURST_SIZE: usize = 16;
+
+/// Past this fraction of the screen being damaged, a whole frame is sent instead of
+/// individual regions.
+const REGION_UPDATE_MAX_COVERAGE: f64 = 0.5;
+
+/// How many encoded frames may wait to be sent. Past this, the network has fallen
+/// behind and the waiting frames are dropped for a fresh one.
+const TRANSMIT_QUEUE_DEPTH: usize = 2;
+
+/// How many captured frames may wait to be encoded. Only the latest is worth
+/// encoding, the encoder falling behind means the others are stale.
+const ENCODE_QUEUE_DEPTH: usize = 1;
+
 #[derive(Debug)]
-struct InitializedSystem<T, S, E, St> {
+struct InitializedSystem<P, S, E, St, Pl> {
+    /// Kept to make a new screen when the display host changes its parameters
+    screen_provider: P,
+    display_params: DisplayParameters,
+    /// The policy before the display host's hints, to resolve again when its
+    /// parameters change
+    base_policy: StreamPolicy,
+    policy: StreamPolicy,
     screen: S,
     encoder: E,
-    display_host: DisplayHost<T>,
+    /// The rate the encoder was set up with
+    rate: RateTarget,
+    /// What the encoder was set up with
+    encoding: EncoderPossibleConfiguration,
+    /// What the encoder may fall back to when it keeps failing
+    fallback: EncoderFallback,
+    /// The sequence number of the next frame. A resumed display host carries on
+    /// from where it was.
+    sequence: u64,
+    /// Bumped every time the encoding is renegotiated
+    config_generation: u32,
     status_sink: St,
+    policy_sink: Pl,
+    /// Whether the display host took a session token, so the session may outlive its
+    /// connection
+    resumable: bool,
+    /// Set when a reconnected display host took over the session, to the display
+    /// parameters it reported
+    resumed: Option<DisplayParameters>,
+}
+
+impl<P, S, E, St, Pl> InitializedSystem<P, S, E, St, Pl> {
+    /// Swaps the encoder for another, giving back the one it had. A running session
+    /// shares its encoder between stages, and hands it back once it's over.
+    fn swap_encoder<F>(self, encoder: F) -> (InitializedSystem<P, S, F, St, Pl>, E) {
+        let InitializedSystem {
+            screen_provider,
+            display_params,
+            base_policy,
+            policy,
+            screen,
+            encoder: old_encoder,
+            rate,
+            encoding,
+            fallback,
+            sequence,
+            config_generation,
+            status_sink,
+            policy_sink,
+            resumable,
+            resumed,
+        } = self;
+        let system = InitializedSystem {
+            screen_provider,
+            display_params,
+            base_policy,
+            policy,
+            screen,
+            encoder,
+            rate,
+            encoding,
+            fallback,
+            sequence,
+            config_generation,
+            status_sink,
+            policy_sink,
+            resumable,
+            resumed,
+        };
+        (system, old_encoder)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -39,65 +158,335 @@ pub enum SystemState {
     InitializingEncoder,
     SettingClientCodec,
     Running,
+    /// The display host changed its display parameters, and the screen and encoding
+    /// are being set up again
+    Renegotiating,
+    /// The connection to the display host was lost, and the session waits for it to
+    /// come back
+    AwaitingReconnect,
     Stopped,
 }
 
-/// Given all the ingredients to screen cast, handle a display host connection.
-pub async fn handle_display_host<T, P, E, C, St>(
+/// Lets a session outlive its connection for a while. When the connection to the
+/// display host is lost, its virtual screen is kept for `grace_period`, and the
+/// session carries on with the first of `reconnections` that makes it back in time.
+pub struct SessionResume<T> {
+    /// Handed to the display host, for it to show when it reconnects
+    pub token: SessionToken,
+    pub grace_period: Duration,
+    /// Display hosts that showed the token since, as they reconnect
+    pub reconnections: PinnedLocalStream<'static, DisplayHost<T>>,
+}
+
+/// What a session goes on with besides its display host, for as long as it lasts.
+pub struct SessionContext<I, K, St, Pl, Ss> {
+    /// Narrowed down to what the display host reports about itself
+    pub policy: StreamPolicy,
+    pub input_provider: I,
+    /// The clipboard is only shared when this is given
+    pub clipboard_provider: Option<K>,
+    /// Display hosts joining the session as mirrors, to be shown the same screen until
+    /// they leave or the session is over
+    pub mirrors: PinnedLocalStream<'static, MirrorJoin>,
+    /// What the display host is sent is recorded while this last yielded a directory
+    /// to record into, and not after it yields `None`
+    pub recordings: PinnedLocalStream<'static, Option<PathBuf>>,
+    pub status_sink: St,
+    /// Told every policy the session settles on
+    pub policy_sink: Pl,
+    /// Told the statistics of the running session every `SESSION_STATS_INTERVAL`
+    pub stats_sink: Ss,
+}
+
+/// Given all the ingredients to screen cast, handle a display host connection, in the
+/// session's `context`.
+///
+/// Sessions given a `resume` survive losing the connection to a display host that
+/// took their token, for as long as it takes it to come back.
+///
+/// The session is closed when `cancel_notification` yields, telling the display host
+/// why. A notification stream that ends without yielding never cancels the session.
+pub async fn handle_display_host<T, P, E, I, K, C, St, Pl, Ss>(
     screen_provider: P,
     encoder_provider: E,
-    mut display_host: DisplayHost<T>,
+    display_host: DisplayHost<T>,
+    resume: Option<SessionResume<T>>,
+    context: SessionContext<I, K, St, Pl, Ss>,
     cancel_notification: C,
-    status_sink: St,
 ) -> DisplayHostResult<T>
 where
     T: ScreenTransport + 'static,
     E: EncoderProvider + 'static,
     P: ScreenProvider + 'static,
-    C: Stream<Item = ()> + Unpin + 'static,
+    I: InputInjectorProvider + 'static,
+    K: ClipboardProvider + 'static,
+    C: Stream<Item = GoodbyeReason> + Unpin + 'static,
     St: Sink<SystemState> + Unpin + 'static,
+    Pl: Sink<StreamPolicy> + Unpin + 'static,
+    Ss: Sink<SessionStats> + Unpin + 'static,
 {
-    let stopped = Arc::new(AtomicBool::new(false));
-    debug!("Getting background task for {display_host}...");
-    let _background_stopped = stopped.clone();
     let host_name = display_host.to_string();
     let host_name_1 = host_name.clone();
-    let display_host_background_task = display_host
-        .get_background_task()
-        .map(|r| r.map_err(|e| e.to_string()))
-        .boxed_local();
 
-    let screen_task = async move {
-        let initialized_system =
-            match screen_init(screen_provider, encoder_provider, display_host, status_sink).await {
-                Ok(system) => system,
-                Err(e) => {
-                    error!("Failed to initialize screen system: {}", e);
-                    return Err(e);
+    let cancelled = async move {
+        let mut cancel_notification = cancel_notification;
+        match cancel_notification.next().await {
+            Some(reason) => reason,
+            None => future::pending().await,
+        }
+    }
+    .boxed_local()
+    .shared();
+    let session_cancelled = cancelled.clone();
+    let SessionContext {
+        policy,
+        input_provider,
+        clipboard_provider,
+        mirrors,
+        recordings,
+        status_sink,
+        policy_sink,
+        stats_sink,
+    } = context;
+    let mut shared = SessionShared {
+        encoder_provider,
+        input_provider,
+        clipboard_provider,
+        mirror_joins: Rc::new(Mutex::new(mirrors.filter_map(prepare_mirror).boxed_local())),
+        recordings: Rc::new(Mutex::new(recordings)),
+        recorder: RefCell::new(None),
+        stats_sink,
+    };
+
+    let session_task = async move {
+        let mut resume = resume;
+        let token = resume.as_ref().map(|resume| resume.token.clone());
+        let (mut display_host, mut system) = match screen_init(
+            screen_provider,
+            &shared.encoder_provider,
+            policy,
+            display_host,
+            token,
+            status_sink,
+            policy_sink,
+        )
+        .await
+        {
+            Ok(system) => system,
+            Err(e) => {
+                error!("Failed to initialize screen system: {}", e);
+                return Err(e);
+            }
+        };
+
+        loop {
+            let detached =
+                run_display_host(display_host, system, &mut shared, session_cancelled.clone())
+                    .await?;
+            let mut parked = match detached {
+                Detached::Closed(host) => return Ok(host),
+                Detached::Lost(parked) => parked,
+            };
+
+            let reconnected = match resume.as_mut() {
+                Some(resume) => {
+                    await_reconnect(&host_name, &mut parked, resume, session_cancelled.clone())
+                        .await
                 }
+                None => None,
             };
+            let Some(host) = reconnected else {
+                if let Err(e) = parked.screen.close().await {
+                    error!("Error closing virtual screen: {}", e);
+                }
+                return Err(SessionError::ConnectionLost);
+            };
+            display_host = host;
+            system = parked;
+        }
+    };
 
-        match screen_loop(initialized_system).await {
-            Ok(host) => {
-                debug!("Screen loop completed successfully.");
-                display_host = host;
-                Ok(display_host)
+    let mut session_task = session_task.boxed_local().fuse();
+    futures::select! {
+        session_result = session_task => session_result,
+        reason = cancelled.fuse() => {
+            // A running screen loop says goodbye by itself, give it a moment to
+            futures::select! {
+                session_result = session_task => session_result,
+                _ = futures_timer::Delay::new(CLOSE_GRACE_PERIOD).fuse() => {
+                    warn!("Display host handling for {} didn't wrap up in time", host_name_1);
+                    Err(SessionError::Cancelled(reason))
+                }
             }
-            Err(e) => {
-                error!("Screen loop ended with error: {}", e);
-                Err(e)
+        }
+    }
+}
+
+/// Gets the transport of a mirror going, before it joins the session. Returns `None`
+/// if it couldn't, after telling whoever asked for the mirror.
+async fn prepare_mirror(mut join: MirrorJoin) -> Option<MirrorJoin> {
+    let prepared = async {
+        join.host.initialize().await?;
+        join.host.get_display_config().await
+    }
+    .await;
+    match prepared {
+        Ok(params) => {
+            debug!("{} is ready to mirror, with {params}", join.host);
+            Some(join)
+        }
+        Err(e) => {
+            warn!("Failed to get mirror {} going: {}", join.host, e);
+            if let Err(e) = join.host.close().await {
+                error!("Error closing mirror {}: {}", join.host, e);
             }
+            let _ = join.started.send(Err(e.into()));
+            None
         }
     }
-    .boxed_local();
+}
+
+/// How the session with one display host ended.
+enum Detached<T, Sys> {
+    /// The session is over
+    Closed(DisplayHost<T>),
+    /// The connection was lost, and the rest of the session is kept for the display
+    /// host to resume
+    Lost(Sys),
+}
+
+/// What the connections of a session share. A resumed session carries on with the
+/// same.
+struct SessionShared<EP, I, K, Ss> {
+    encoder_provider: EP,
+    input_provider: I,
+    clipboard_provider: Option<K>,
+    mirror_joins: Rc<Mutex<PinnedLocalStream<'static, MirrorJoin>>>,
+    recordings: Rc<Mutex<PinnedLocalStream<'static, Option<PathBuf>>>>,
+    /// A recording goes on across connections, too
+    recorder: RefCell<Option<StreamRecorder>>,
+    stats_sink: Ss,
+}
+
+/// Runs the session with one display host, until the session is over or the
+/// connection to the display host is lost.
+async fn run_display_host<T, P, E, EP, I, K, St, Pl, Ss>(
+    mut display_host: DisplayHost<T>,
+    system: InitializedSystem<P, P::ScreenType, E, St, Pl>,
+    shared: &mut SessionShared<EP, I, K, Ss>,
+    cancelled: impl Future<Output = GoodbyeReason> + 'static,
+) -> Result<Detached<T, InitializedSystem<P, P::ScreenType, E, St, Pl>>, SessionError>
+where
+    T: ScreenTransport + 'static,
+    P: ScreenProvider + 'static,
+    E: Encoder + 'static,
+    EP: EncoderProvider<EncoderType = E>,
+    I: InputInjectorProvider,
+    K: ClipboardProvider,
+    St: Sink<SystemState> + Unpin + 'static,
+    Pl: Sink<StreamPolicy> + Unpin + 'static,
+    Ss: Sink<SessionStats> + Unpin + 'static,
+{
+    let SessionShared {
+        encoder_provider,
+        input_provider,
+        clipboard_provider,
+        mirror_joins,
+        recordings,
+        recorder,
+        stats_sink,
+    } = shared;
+    let recorder = &*recorder;
+    debug!("Getting background task for {display_host}...");
+    let host_name = display_host.to_string();
+    let input_stream = display_host.take_input_stream();
+    let mut background_task = display_host.get_background_task().boxed_local().fuse();
+    // Lets the screen loop know when the transport stops on its own
+    let (transport_ended_tx, transport_ended_rx) = oneshot::channel();
+    let mut transport_ended_tx = Some(transport_ended_tx);
+
+    // The screen can change mid-session, so the input loop is handed a new target
+    // whenever it does.
+    let (input_target_tx, input_target_rx) = mpsc::unbounded();
+    let input_task = match input_stream {
+        Some(input_stream) => {
+            let _ = input_target_tx.unbounded_send(input_target(&system.screen));
+            input_loop(input_provider, input_target_rx, input_stream).boxed_local()
+        }
+        None => {
+            debug!("{host_name} does not send input");
+            futures::future::ready(()).boxed_local()
+        }
+    };
+
+    let clipboard = match clipboard_provider {
+        Some(provider) => match provider.create_backend().await {
+            Ok(backend) => Some(ClipboardSync::new(backend)),
+            Err(e) => {
+                warn!("Not sharing the clipboard with {host_name}: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
 
-    let composition_task = async move {
-        let mut screen_task = screen_task.fuse();
-        let mut background_task = display_host_background_task.fuse();
+    // Captured frames are encoded by their own stage, and encoded frames are sent by
+    // another, so a slow encoder or network doesn't hold up capturing the next ones
+    let host = Mutex::new(display_host);
+    let (system, encoder) = system.swap_encoder(());
+    let encoder = Mutex::new(encoder);
+    let encode_queue = FrameQueue::new(ENCODE_QUEUE_DEPTH);
+    let transmit_queue = FrameQueue::new(TRANSMIT_QUEUE_DEPTH);
+    let sequence = Cell::new(system.sequence);
+    // Mirrors are fed by the encode stage, and sent frames each by their own task
+    let mirrors = Mutex::new(Vec::new());
+    let (mirror_task_tx, mut mirror_task_rx) = mpsc::unbounded();
+    let mut mirror_tasks = FuturesUnordered::new();
+    let screen_loop_result = {
+        let (reports_tx, reports_rx) = mpsc::unbounded();
+        let (control_tx, control_rx) = mpsc::unbounded();
+        let stages = Stages {
+            host: &host,
+            encoder: &encoder,
+            encode_queue: &encode_queue,
+            transmit_queue: &transmit_queue,
+            mirrors: &mirrors,
+            sequence: &sequence,
+            control: control_tx,
+            reports: reports_rx,
+            mirror_tasks: mirror_task_tx,
+            transport_ended: transport_ended_rx,
+        };
+        let context = LoopContext {
+            encoder_provider,
+            mirror_joins,
+            recordings,
+            recorder,
+            stats_sink,
+            clipboard,
+            input_targets: input_target_tx,
+        };
+        let mut screen_loop_task = screen_loop(system, stages, context, cancelled)
+            .boxed_local()
+            .fuse();
+        let mut encode_task = encode_loop(
+            &encoder,
+            &encode_queue,
+            &transmit_queue,
+            &mirrors,
+            &sequence,
+            reports_tx.clone(),
+        )
+        .boxed_local()
+        .fuse();
+        let mut transmit_task =
+            transmit_loop(&host, &transmit_queue, control_rx, recorder, reports_tx)
+                .boxed_local()
+                .fuse();
+        let mut input_task = input_task.fuse();
 
-        // Compose the tasks in such a way that if the background task finishes, we
-        // still continue to poll the screen task. But... if the screen task finishes,
-        // we do *not* continue polling the background task.
+        // Input ending doesn't end the session, the screen loop does. Neither does
+        // the transport's background task, the screen loop decides what that means.
         loop {
             futures::select! {
                 background_result = background_task => {
@@ -105,35 +494,115 @@ where
                         "Background task for {host_name} finished with result: {:?}",
                         background_result
                     );
-                    // And no-op, `.fuse()` will keep that future pending forever after
-                    // it completes.
+                    if let Some(tx) = transport_ended_tx.take() {
+                        let _ = tx.send(background_result);
+                    }
                 },
-                screen_result = screen_task => {
-                    return screen_result;
-                }
+                _ = input_task => {
+                    debug!("Input from {host_name} has ended");
+                },
+                _ = encode_task => {
+                    debug!("Stopped encoding frames for {host_name}");
+                },
+                _ = transmit_task => {
+                    debug!("Stopped sending frames to {host_name}");
+                },
+                task = mirror_task_rx.select_next_some() => mirror_tasks.push(task),
+                () = mirror_tasks.select_next_some() => {},
+                screen_result = screen_loop_task => break screen_result,
             }
         }
     };
 
-    futures::select! {
-        screen_result = composition_task.fuse() => screen_result,
-        _ = cancel_notification.into_future().fuse() => {
-            Err(format!("Display host handling for {} was cancelled", host_name_1))
+    match screen_loop_result {
+        Ok(Some(parked)) => {
+            let (parked, ()) = parked.swap_encoder(encoder.into_inner());
+            Ok(Detached::Lost(parked))
+        }
+        Ok(None) => {
+            debug!("Screen loop completed successfully.");
+            Ok(Detached::Closed(host.into_inner()))
+        }
+        Err(e) => {
+            error!("Screen loop ended with error: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Waits out the grace period of a session that lost its connection, for its display
+/// host to come back. Returns the display host that resumed the session, or `None`
+/// if none did in time.
+async fn await_reconnect<T, P, S, E, St, Pl>(
+    host_name: &str,
+    parked: &mut InitializedSystem<P, S, E, St, Pl>,
+    resume: &mut SessionResume<T>,
+    cancelled: impl Future<Output = GoodbyeReason>,
+) -> Option<DisplayHost<T>>
+where
+    T: ScreenTransport,
+    S: Screen,
+    E: Encoder,
+    St: Sink<SystemState> + Unpin,
+{
+    info!(
+        "Keeping the session of {host_name} for {}s, in case it reconnects",
+        resume.grace_period.as_secs()
+    );
+    if parked
+        .status_sink
+        .send(SystemState::AwaitingReconnect)
+        .await
+        .is_err()
+    {
+        warn!("Failed to send awaiting reconnect status");
+    }
+
+    let mut cancelled = pin!(cancelled.fuse());
+    let mut grace_period = futures_timer::Delay::new(resume.grace_period).fuse();
+    loop {
+        let mut host = futures::select! {
+            host = resume.reconnections.next().fuse() => host?,
+            reason = cancelled => {
+                info!("Closing the session of {host_name} while it was away: {reason}");
+                return None;
+            },
+            _ = grace_period => {
+                info!("{host_name} didn't come back in time, closing its session");
+                return None;
+            },
+        };
+        match resume_init(&mut host, parked, &resume.token).await {
+            Ok(()) => {
+                info!("{host} resumed the session of {host_name}");
+                return Some(host);
+            }
+            Err(e) => warn!("{host} failed to resume the session of {host_name}: {}", e),
         }
     }
 }
 
-async fn screen_init<T, P, E, St>(
+async fn screen_init<T, P, E, St, Pl>(
     screen_provider: P,
-    encoder_provider: E,
+    encoder_provider: &E,
+    base_policy: StreamPolicy,
     mut display_host: DisplayHost<T>,
+    token: Option<SessionToken>,
     mut status_sink: St,
-) -> Result<InitializedSystem<T, P::ScreenType, E::EncoderType, St>, String>
+    mut policy_sink: Pl,
+) -> Result<
+    (
+        DisplayHost<T>,
+        InitializedSystem<P, P::ScreenType, E::EncoderType, St, Pl>,
+    ),
+    SessionError,
+>
 where
     T: ScreenTransport,
     E: EncoderProvider,
     P: ScreenProvider,
     St: Sink<SystemState> + Unpin + 'static,
+    Pl: Sink<StreamPolicy> + Unpin + 'static,
 {
     // Handle the display-host connection here
     info!("Handling display-host: {display_host}");
@@ -153,9 +622,13 @@ where
     if let Err(e) = display_host.initialize().await {
         error!("Failed to initialize transport: {}", e);
         close_dev(&mut display_host).await;
-        return Err("Failed to initialize transport".to_string());
+        return Err(e.into());
     }
     debug!("Initialized transport");
+    let resumable = match token {
+        Some(token) => issue_session_token(&mut display_host, token).await,
+        None => false,
+    };
 
     debug!("Getting display parameters...");
     match status_sink
@@ -170,11 +643,12 @@ where
         Err(e) => {
             error!("Failed to get display parameters: {}", e);
             close_dev(&mut display_host).await;
-            return Err("Failed to get display parameters".to_string());
+            return Err(e.into());
         }
         Ok(display_params) => display_params,
     };
     debug!("Got display parameters: {:?}", display_params);
+    let policy = base_policy.with_client_hints(&display_params);
 
     match status_sink.send(SystemState::NotifyClientLoading).await {
         Err(_) => warn!("Failed to send notify client loading status"),
@@ -195,11 +669,11 @@ where
         _ => {}
     };
     // Get the virtual screen
-    let screen = match screen_provider.get_screen(display_params).await {
+    let screen = match screen_provider.get_screen(display_params.clone()).await {
         Err(e) => {
             error!("Failed to create virtual screen: {}", e);
             close_dev(&mut display_host).await;
-            return Err("Failed to create virtual screen".to_string());
+            return Err(e.into());
         }
         Ok(screen) => screen,
     };
@@ -215,25 +689,197 @@ where
         Err(e) => {
             error!("Failed to create encoder: {}", e);
             close_dev(&mut display_host).await;
-            return Err("Failed to create encoder".to_string());
+            return Err(e.into());
         }
         Ok(encoder) => encoder,
     };
     debug!("Created encoder.");
 
+    let format_params = screen.get_format_parameters();
+    let encoded_resolution = policy.encode_resolution((format_params.width, format_params.height));
+    let rate = policy.initial_rate(encoded_resolution);
+    let (encoding, fallback) = match negotiate_encoding(
+        &screen,
+        &mut encoder,
+        rate,
+        encoded_resolution,
+        &mut display_host,
+        &mut status_sink,
+    )
+    .await
+    {
+        Ok(encoding) => encoding,
+        Err(e) => {
+            close_dev(&mut display_host).await;
+            return Err(e);
+        }
+    };
+    info!("Streaming to {display_host} with {policy}");
+    report_policy(&mut policy_sink, policy, rate).await;
+
+    Ok((
+        display_host,
+        InitializedSystem {
+            screen_provider,
+            display_params,
+            base_policy,
+            policy,
+            screen,
+            encoder,
+            rate,
+            encoding,
+            fallback,
+            sequence: 0,
+            config_generation: 0,
+            status_sink,
+            policy_sink,
+            resumable,
+            resumed: None,
+        },
+    ))
+}
+
+/// Gets a reconnected display host going with a session that lost its connection.
+/// The virtual screen is kept as it is, only the transport and the encoding are set
+/// up again.
+async fn resume_init<T, P, S, E, St, Pl>(
+    host: &mut DisplayHost<T>,
+    system: &mut InitializedSystem<P, S, E, St, Pl>,
+    token: &SessionToken,
+) -> Result<(), SessionError>
+where
+    T: ScreenTransport,
+    S: Screen,
+    E: Encoder,
+    St: Sink<SystemState> + Unpin,
+{
+    if system
+        .status_sink
+        .send(SystemState::InitializingTransport)
+        .await
+        .is_err()
+    {
+        warn!("Failed to send initializing status");
+    }
+    if let Err(e) = host.initialize().await {
+        let _ = host.close().await;
+        return Err(e.into());
+    }
+    // Handed out again, in case the connection is lost another time
+    system.resumable = issue_session_token(host, token.clone()).await;
+
+    let display_params = match host.get_display_config().await {
+        Ok(display_params) => display_params,
+        Err(e) => {
+            let _ = host.close().await;
+            return Err(e.into());
+        }
+    };
+
+    // Frames only go out once the display host knows the encoding, even if its new
+    // display parameters call for another one right after
+    let format_params = system.screen.get_format_parameters();
+    let encoded_resolution = system
+        .policy
+        .encode_resolution((format_params.width, format_params.height));
+    (system.encoding, system.fallback) = match negotiate_encoding(
+        &system.screen,
+        &mut system.encoder,
+        system.rate,
+        encoded_resolution,
+        host,
+        &mut system.status_sink,
+    )
+    .await
+    {
+        Ok(negotiated) => negotiated,
+        Err(e) => {
+            let _ = host.close().await;
+            return Err(e);
+        }
+    };
+    // The frames carry on from where they were, in a new encoding
+    system.config_generation += 1;
+    system.resumed = Some(display_params);
+    Ok(())
+}
+
+/// Hands the display host the token to resume the session with. Returns whether it
+/// took it.
+async fn issue_session_token<T: ScreenTransport>(
+    host: &mut DisplayHost<T>,
+    token: SessionToken,
+) -> bool {
+    match host.send_session_token(token).await {
+        Ok(()) => {
+            debug!("Issued a session token to {host}");
+            true
+        }
+        Err(TransportError::NotImplemented) => {
+            debug!("{host} can't resume sessions");
+            false
+        }
+        Err(e) => {
+            warn!("Failed to issue a session token to {host}: {}", e);
+            false
+        }
+    }
+}
+
+/// Tells whoever is watching the session which policy it streams with, with the
+/// bitrate it actually runs at.
+async fn report_policy<Pl>(policy_sink: &mut Pl, policy: StreamPolicy, rate: RateTarget)
+where
+    Pl: Sink<StreamPolicy> + Unpin,
+{
+    let resolved = StreamPolicy {
+        target_bitrate: Some(rate.bitrate),
+        ..policy
+    };
+    if policy_sink.send(resolved).await.is_err() {
+        warn!("Failed to send stream policy");
+    }
+}
+
+fn encoder_parameters<S: Screen>(
+    screen: &S,
+    rate: RateTarget,
+    encoded_resolution: (u32, u32),
+) -> EncoderContentParameters {
     debug!("Getting format parameters...");
     let format_params = screen.get_format_parameters();
     debug!("Got format parameters: {:?}", format_params);
 
-    let encoder_parameters = EncoderContentParameters {
-        // Note here, formatting to the same width/height as the screen
-        width: format_params.width,
-        height: format_params.height,
+    let (width, height) = encoded_resolution;
+    EncoderContentParameters {
+        width,
+        height,
 
-        bitrate: 1000000, // TODO: Make this configurable?
-        fps: 60,          // TODO: Make this configurable?
+        bitrate: rate.bitrate,
+        fps: rate.fps,
         encoder_input_parameters: format_params,
-    };
+    }
+}
+
+/// Negotiate an encoding for the screen with the display host, and get the encoder
+/// and the display host ready for it. The screen is scaled to `encoded_resolution`.
+/// Returns the configuration the encoder was set up with, and what it may fall back
+/// to.
+async fn negotiate_encoding<T, S, E, St>(
+    screen: &S,
+    encoder: &mut E,
+    rate: RateTarget,
+    encoded_resolution: (u32, u32),
+    host: &mut DisplayHost<T>,
+    status_sink: &mut St,
+) -> Result<(EncoderPossibleConfiguration, EncoderFallback), SessionError>
+where
+    T: ScreenTransport,
+    S: Screen,
+    E: Encoder,
+    St: Sink<SystemState> + Unpin,
+{
+    let encoder_parameters = encoder_parameters(screen, rate, encoded_resolution);
 
     match status_sink.send(SystemState::NegotiatingCodecs).await {
         Err(_) => warn!("Failed to send negotiating codecs status"),
@@ -243,32 +889,32 @@ where
     let supported_configurations = match encoder.get_supported_configurations(&encoder_parameters) {
         Err(e) => {
             error!("Failed to get supported encoder configurations: {}", e);
-            close_dev(&mut display_host).await;
-            return Err("Failed to get supported encoder configurations".to_string());
+            return Err(e.into());
         }
         Ok(configs) => configs,
     };
 
     if supported_configurations.is_empty() {
         error!("No supported encoder configurations available");
-        close_dev(&mut display_host).await;
-        return Err("No supported encoder configurations available".to_string());
+        return Err(EncoderError::NoUsableConfiguration.into());
     }
 
-    let preferred_configurations = match display_host
-        .get_preferred_encodings(supported_configurations)
-        .await
-    {
-        Err(e) => {
-            error!(
-                "Failed to get preferred encoder configurations from host: {}",
-                e
-            );
-            close_dev(&mut display_host).await;
-            return Err("Failed to get preferred encoder configurations".to_string());
-        }
-        Ok(configs) => configs,
-    };
+    let preferred_configurations =
+        match host.get_preferred_encodings(supported_configurations).await {
+            Err(e) => {
+                error!(
+                    "Failed to get preferred encoder configurations from host: {}",
+                    e
+                );
+                return Err(e.into());
+            }
+            Ok(configs) => configs,
+        };
+
+    if preferred_configurations.is_empty() {
+        error!("{host} can't use any of our encoder configurations");
+        return Err(SessionError::NoCommonCodec);
+    }
 
     debug!(
         "Got supported {} encoder configurations: {:#?}",
@@ -282,13 +928,12 @@ where
         _ => {}
     };
     let encoder_init_result = encoder
-        .init(encoder_parameters, Some(preferred_configurations))
+        .init(encoder_parameters, Some(preferred_configurations.clone()))
         .await;
     let initialized_codec = match encoder_init_result {
         Err(e) => {
             error!("Failed to initialize encoder: {}", e);
-            close_dev(&mut display_host).await;
-            return Err("Failed to initialize encoder".to_string());
+            return Err(e.into());
         }
         Ok(config) => config,
     };
@@ -304,126 +949,2113 @@ where
         _ => {}
     };
 
-    if let Err(e) = display_host.set_encoding(initialized_codec).await {
+    if let Err(e) = host.set_encoding(initialized_codec.clone()).await {
         error!("Failed to set encoding on host: {}", e);
-        close_dev(&mut display_host).await;
-        return Err("Failed to set encoding on host".to_string());
+        return Err(e.into());
     }
     debug!("Set encoding on host.");
 
-    Ok(InitializedSystem {
-        screen,
-        encoder,
-        display_host,
-        status_sink,
-    })
+    let fallback = EncoderFallback::new(preferred_configurations, &initialized_codec);
+    Ok((initialized_codec, fallback))
 }
 
-async fn screen_loop<S, T, E, St>(
-    initialized_system: InitializedSystem<T, S, E, St>,
-) -> DisplayHostResult<T>
+/// Sets the encoder up again with the first of `configurations` that works, and
+/// tells the display host. Unlike renegotiating, the screen and what the display
+/// host agreed to stay as they are.
+async fn fall_back_encoding<T, S, E>(
+    screen: &S,
+    encoder: &mut E,
+    configurations: Vec<EncoderPossibleConfiguration>,
+    rate: RateTarget,
+    encoded_resolution: (u32, u32),
+    host: &mut DisplayHost<T>,
+) -> Result<EncoderPossibleConfiguration, SessionError>
 where
+    T: ScreenTransport,
     S: Screen,
+    E: Encoder,
+{
+    let encoder_parameters = encoder_parameters(screen, rate, encoded_resolution);
+    let encoding = encoder
+        .init(encoder_parameters, Some(configurations))
+        .await?;
+    host.set_encoding(encoding.clone()).await?;
+    Ok(encoding)
+}
+
+/// The stages of a connection besides the screen loop, and how the screen loop
+/// reaches them.
+struct Stages<'a, T, E> {
+    host: &'a Mutex<DisplayHost<T>>,
+    encoder: &'a Mutex<E>,
+    encode_queue: &'a FrameQueue<RawFrame>,
+    transmit_queue: &'a FrameQueue<OutgoingFrame>,
+    /// Fed by the encode stage
+    mirrors: &'a Mutex<Vec<Mirror<E>>>,
+    /// Sequence number of the next frame, kept by the encode stage
+    sequence: &'a Cell<u64>,
+    /// Control messages for the transmit stage
+    control: mpsc::UnboundedSender<ControlMessage>,
+    /// How the encode and transmit stages are getting on
+    reports: mpsc::UnboundedReceiver<LoopEvent>,
+    /// Tasks of the mirrors, run alongside the stages
+    mirror_tasks: mpsc::UnboundedSender<PinnedLocalFuture<'static, ()>>,
+    transport_ended: oneshot::Receiver<Result<(), TransportError>>,
+}
+
+/// What the screen loop takes of its session, for one connection.
+struct LoopContext<'a, EP, B, Ss> {
+    encoder_provider: &'a EP,
+    mirror_joins: &'a Rc<Mutex<PinnedLocalStream<'static, MirrorJoin>>>,
+    recordings: &'a Rc<Mutex<PinnedLocalStream<'static, Option<PathBuf>>>>,
+    recorder: &'a RefCell<Option<StreamRecorder>>,
+    stats_sink: &'a mut Ss,
+    clipboard: Option<ClipboardSync<B>>,
+    /// Where input from the display host goes, told whenever the screen changes
+    input_targets: mpsc::UnboundedSender<InputTarget>,
+}
+
+/// Streams the screen to the display host until the session is over. Returns what's
+/// left of the session when the connection to the display host was lost, if it may
+/// be resumed.
+async fn screen_loop<P, T, E, EP, B, St, Pl, Ss>(
+    initialized_system: InitializedSystem<P, P::ScreenType, (), St, Pl>,
+    stages: Stages<'_, T, E>,
+    context: LoopContext<'_, EP, B, Ss>,
+    cancelled: impl Future<Output = GoodbyeReason> + 'static,
+) -> Result<Option<InitializedSystem<P, P::ScreenType, (), St, Pl>>, SessionError>
+where
+    P: ScreenProvider,
     T: ScreenTransport,
     E: Encoder,
+    EP: EncoderProvider<EncoderType = E>,
+    B: ClipboardBackend,
     St: Sink<SystemState> + Unpin + 'static,
+    Pl: Sink<StreamPolicy> + Unpin + 'static,
+    Ss: Sink<SessionStats> + Unpin + 'static,
 {
+    let Stages {
+        host,
+        encoder,
+        encode_queue,
+        transmit_queue,
+        mirrors,
+        sequence,
+        control,
+        reports: stage_reports,
+        mirror_tasks,
+        transport_ended,
+    } = stages;
+    let LoopContext {
+        encoder_provider,
+        mirror_joins,
+        recordings,
+        recorder,
+        stats_sink,
+        clipboard,
+        input_targets,
+    } = context;
     let mut bad_transmission_start: Option<Instant> = None;
     let mut bad_transmission_count = 0u32;
 
-    let mut err: Option<String> = None;
+    let mut err: Option<SessionError> = None;
+    // What to tell the display host once the loop is over, if it's still listening
+    let mut goodbye: Option<GoodbyeReason> = None;
+    // Whether the loop ended for losing the connection to the display host
+    let mut lost = false;
     let InitializedSystem {
+        screen_provider,
+        mut display_params,
+        base_policy,
+        mut policy,
         mut screen,
-        display_host: mut host,
-        mut encoder,
+        encoder: (),
+        rate,
+        mut encoding,
+        mut fallback,
+        // Shared with the encode stage, which numbers the frames
+        sequence: _,
+        mut config_generation,
         mut status_sink,
+        mut policy_sink,
+        resumable,
+        resumed,
     } = initialized_system;
+    let host_name = host.lock().await.to_string();
 
     match status_sink.send(SystemState::Running).await {
         Err(_) => warn!("Failed to send running status"),
         _ => {}
     };
 
+    let mut format_params = screen.get_format_parameters();
+    // Damage and cursor positions are in screen coordinates, which a scaled down
+    // stream doesn't share
+    let mut scaled = is_scaled(&policy, &format_params);
+    // Region updates are used until the display host tells us it can't take them.
+    let mut use_region_updates = !is_recording(recorder)
+        && !scaled
+        && region_updates_supported(&*encoder.lock().await, &*host.lock().await);
+    let mut full_area = format_params.width as u64 * format_params.height as u64;
+
+    let mut stats = SessionStatsCollector::new(&encoding, Instant::now());
+    recording_follows(recorder, config_generation, &encoding);
+
+    // Everything besides the screen that can wake the loop up
+    let mut events: SelectAll<PinnedLocalStream<'static, LoopEvent>> = SelectAll::new();
+    match resumed {
+        // The kept screen may not fit the display host anymore
+        Some(params) if params != display_params => events.push(
+            stream::once(future::ready(LoopEvent::DisplayParametersUpdate(params))).boxed_local(),
+        ),
+        Some(_) => events.push(stream::once(future::ready(LoopEvent::Resumed)).boxed_local()),
+        None => {}
+    }
+    events.push(
+        stream::once(cancelled)
+            .map(LoopEvent::Cancelled)
+            .boxed_local(),
+    );
+    events.push(
+        stream::once(transport_ended)
+            .filter_map(|result| future::ready(result.ok()))
+            .map(LoopEvent::TransportEnded)
+            .boxed_local(),
+    );
+    events.push(stage_reports.boxed_local());
+    events.push(
+        ticks(SESSION_STATS_INTERVAL)
+            .skip(1)
+            .map(|_| LoopEvent::StatsTick)
+            .boxed_local(),
+    );
+    if let Some(goodbyes) = host.lock().await.take_goodbye_stream() {
+        events.push(goodbyes.map(LoopEvent::Goodbye).boxed_local());
+    }
+    if let Some(interval) = host.lock().await.heartbeat_interval() {
+        events.push(
+            ticks(interval)
+                .map(|_| LoopEvent::HeartbeatTick)
+                .boxed_local(),
+        );
+    }
+    if let Some(keyframe_requests) = host.lock().await.take_keyframe_request_stream() {
+        events.push(
+            keyframe_requests
+                .map(|_| LoopEvent::KeyframeRequest)
+                .boxed_local(),
+        );
+    }
+    if let Some(display_params_updates) = host.lock().await.take_display_parameters_stream() {
+        events.push(
+            display_params_updates
+                // Only the latest of a burst of updates (like a window being
+                // resized) is worth renegotiating for
+                .ready_chunks(DISPLAY_PARAMETERS_BURST_SIZE)
+                .filter_map(|burst| future::ready(burst.into_iter().last()))
+                .map(LoopEvent::DisplayParametersUpdate)
+                .boxed_local(),
+        );
+    }
+
+    // Adapt the rate to the display host's receiver reports, until the encoder
+    // turns out to not support changing it
+    let mut rate_controller = RateController::new(rate, policy.rate_limits());
+    let mut adaptive_rate = true;
+    // Frames come no faster than the rate allows, and only when they changed
+    let mut pacer = FramePacer::new(rate.fps);
+    let mut frame_due_scheduled = false;
+    events.push(
+        ticks(STATIC_REFRESH_INTERVAL)
+            .map(|_| LoopEvent::RefreshTick)
+            .boxed_local(),
+    );
+    let receiver_reports = {
+        let mut host = host.lock().await;
+        // Older clients don't report, and the rate stays where the policy puts it
+        let reports = host
+            .protocol()
+            .is_some_and(|p| p.has_capability(ProtocolCapability::ReceiverReports));
+        reports
+            .then(|| host.take_receiver_report_stream())
+            .flatten()
+    };
+    if let Some(receiver_reports) = receiver_reports {
+        events.push(
+            receiver_reports
+                .map(LoopEvent::ReceiverReport)
+                .boxed_local(),
+        );
+    }
+
+    // Measure glass-to-glass latency for display hosts that report when they
+    // presented each frame
+    let mut latency = LatencyTracker::new();
+    let mut last_latency_log = Instant::now();
+    let mut clock_ping_id: u32 = 0;
+    let measure_latency = host
+        .lock()
+        .await
+        .protocol()
+        .is_some_and(|p| p.has_capability(ProtocolCapability::ClockSync));
+    let mut clock_sync_abort = None;
+    if measure_latency {
+        let (ticks, abort) = stream::abortable(ticks(CLOCK_SYNC_INTERVAL));
+        events.push(ticks.map(|_| LoopEvent::ClockSyncTick).boxed_local());
+        clock_sync_abort = Some(abort);
+        if let Some(pongs) = host.lock().await.take_clock_pong_stream() {
+            events.push(pongs.map(LoopEvent::ClockPong).boxed_local());
+        }
+        if let Some(presented) = host.lock().await.take_frame_presented_stream() {
+            events.push(presented.map(LoopEvent::FramePresented).boxed_local());
+        }
+    }
+
+    let mut clipboard = match clipboard {
+        Some(sync) => setup_clipboard(sync, &mut *host.lock().await, &mut events),
+        None => None,
+    };
+
+    let (mut cursor_mode, mut cursor_abort) =
+        setup_cursor(&mut screen, &*host.lock().await, scaled, &mut events);
+    let mut cursor_state = CursorState::new();
+    // The last frame as the screen gave it, and the same frame with the cursor drawn
+    // on top. Only used when compositing.
+    let mut screen_frame: Vec<u8> = Vec::new();
+    let mut composite_frame: Vec<u8> = Vec::new();
+    // When the content of the latest frame was captured, for frames held back by
+    // pacing
+    let mut latest_capture = (Instant::now(), unix_time_micros());
+
+    // The mirrors as the screen loop reaches them, while the encode stage feeds them
+    let mut mirror_controls: Vec<MirrorControl> = Vec::new();
+    let mut next_mirror_id: u64 = 0;
+    let joins = mirror_joins.clone();
+    events.push(
+        stream::unfold(joins, |joins| async move {
+            let join = joins.lock().await.next().await?;
+            Some((LoopEvent::MirrorReady(join), joins))
+        })
+        .boxed_local(),
+    );
+    let recordings = recordings.clone();
+    events.push(
+        stream::unfold(recordings, |recordings| async move {
+            let directory = recordings.lock().await.next().await?;
+            Some((LoopEvent::Recording(directory), recordings))
+        })
+        .boxed_local(),
+    );
+
+    let mut screen = WaitingScreen::new(screen);
     loop {
-        match screen.get_ready().await {
-            Ok(status) => match status {
-                ScreenReadyStatus::Finished => {
-                    info!("Virtual screen has finished");
-                    break;
+        let Some(event) = screen.next_event(&mut events).await else {
+            debug!("Cursor, keyframe request and display parameter streams have ended");
+            continue;
+        };
+        // Frames are timed from their capture. Those sent again on purpose count
+        // from now, as nothing new was captured for them.
+        let mut captured = (Instant::now(), unix_time_micros());
+        // Frames sent again on purpose skip pacing and duplicate checks
+        let mut resend = false;
+        // Frames held back by pacing already waited for their slot
+        let mut paced = false;
+        // Frames of only the cursor moving are for the mirrors it's drawn in for
+        let mut cursor_only = false;
+        let (data, damage) = match event {
+            LoopEvent::DisplayParametersUpdate(params) => {
+                if params == display_params {
+                    trace!("Display parameters of {host_name} didn't change, ignoring update");
+                    continue;
                 }
-                ScreenReadyStatus::NotReady => {
-                    futures_timer::Delay::new(NOT_READY_DELAY).await;
+                info!("{host_name} changed its display parameters to {params}, renegotiating");
+                match status_sink.send(SystemState::Renegotiating).await {
+                    Err(_) => warn!("Failed to send renegotiating status"),
+                    _ => {}
+                };
+
+                // The transport stays up, only the screen and the encoding are redone
+                match status_sink.send(SystemState::GettingScreen).await {
+                    Err(_) => warn!("Failed to send getting screen status"),
+                    _ => {}
+                };
+                if let Some(abort) = cursor_abort.take() {
+                    abort.abort();
                 }
-                ScreenReadyStatus::Ready => {
-                    if let Some(data) = screen.get_bytes() {
-                        let now = Instant::now();
-                        let encoded_data = match encoder.encode(data).await {
-                            Ok(ed) => ed,
-                            Err(e) => {
-                                error!("Failed to encode screen data: {}", e);
-                                err = Some("Failed to encode screen data".to_string());
-                                break;
-                            }
-                        };
-                        let encode_time = now.elapsed();
-                        let send_result = host.send_screen_data(encoded_data).await;
-                        let send_time = now.elapsed();
-                        if let Err(e) = send_result {
-                            error!("Error during transmission to screen host: {}", e);
-                            let bad_transmission_elapsed =
-                                if let Some(start) = bad_transmission_start {
-                                    start.elapsed()
-                                } else {
-                                    bad_transmission_start = Some(Instant::now());
-                                    Duration::ZERO
-                                };
-                            bad_transmission_count += 1;
-
-                            if bad_transmission_elapsed >= Duration::from_secs(5)
-                                && bad_transmission_count >= 5
-                            {
-                                error!(
-                                    "Too many bad transmissions ({} errors in {}ms), closing connection",
-                                    bad_transmission_count,
-                                    bad_transmission_elapsed.as_millis()
-                                );
-                                err =
-                                    Some("Too many bad transmissions to display host".to_string());
-                                break;
-                            }
-                        } else {
-                            bad_transmission_start = None;
-                            bad_transmission_count = 0;
-                            let kbs = encoded_data.len() as f64 / 1024.0 / send_time.as_secs_f64();
-                            trace!(
-                                "Sent {} bytes to display host in {}ms ({:.2} KB/s, encode time: {}ms, send time: {}ms)",
-                                encoded_data.len(),
-                                send_time.as_millis(),
-                                kbs,
-                                encode_time.as_millis(),
-                                (send_time - encode_time).as_millis()
-                            );
+                // Waits out the frame being encoded. Frames of the old screen and
+                // encoding are of no use to the display host anymore.
+                let mut encoder = encoder.lock().await;
+                encode_queue.clear();
+                transmit_queue.clear();
+                // Only screens that can't take the new parameters are made again
+                if let Err(e) = screen.reconfigure(params.clone()).await {
+                    debug!(
+                        "Couldn't reconfigure the virtual screen, replacing it: {}",
+                        e
+                    );
+                    let new_screen = match screen_provider.get_screen(params.clone()).await {
+                        Ok(screen) => screen,
+                        Err(e) => {
+                            error!("Failed to create virtual screen for {params}: {}", e);
+                            err = Some(e.into());
+                            goodbye = Some(GoodbyeReason::ScreenFailure);
+                            break;
                         }
-                    } else {
-                        error!("Bytes were missing after declared ready!");
+                    };
+                    let old_screen = screen.replace(new_screen).await;
+                    if let Err(e) = old_screen.close().await {
+                        error!("Error closing previous virtual screen: {}", e);
                     }
                 }
-            },
-            Err(e) => {
-                error!("Virtual screen error: {}", e);
-                err = Some("Virtual screen runtime error".to_string());
-                break;
-            }
-        }
-    }
+                display_params = params;
+                policy = base_policy.with_client_hints(&display_params);
+                rate_controller =
+                    RateController::new(rate_controller.target(), policy.rate_limits());
+                format_params = screen.get().await.get_format_parameters();
+                let encoded_resolution =
+                    policy.encode_resolution((format_params.width, format_params.height));
 
-    if let Err(e) = host.close().await {
-        error!("Error closing display host: {}", e);
-    }
+                (encoding, fallback) = match negotiate_encoding(
+                    screen.get().await,
+                    &mut *encoder,
+                    rate_controller.target(),
+                    encoded_resolution,
+                    &mut *host.lock().await,
+                    &mut status_sink,
+                )
+                .await
+                {
+                    Ok(negotiated) => negotiated,
+                    Err(e) => {
+                        error!("Failed to renegotiate encoding with {host_name}: {}", e);
+                        err = Some(e);
+                        goodbye = Some(GoodbyeReason::EncoderFailure);
+                        break;
+                    }
+                };
+                config_generation += 1;
+                stats.set_encoding(&encoding);
+                recording_follows(recorder, config_generation, &encoding);
+                report_policy(&mut policy_sink, policy, rate_controller.target()).await;
+                pacer.set_max_fps(rate_controller.target().fps);
+                pacer.reset();
+                scaled = is_scaled(&policy, &format_params);
+                // Mirrors follow with the new cursor mode, which decides whether they
+                // may share the encoder
+                (cursor_mode, cursor_abort) =
+                    setup_cursor(screen.get().await, &*host.lock().await, scaled, &mut events);
+                let mut mirrors = mirrors.lock().await;
+                renegotiate_mirrors(
+                    &mut mirrors,
+                    &mut mirror_controls,
+                    &mut encoder,
+                    &encoding,
+                    encoder_provider,
+                    encoder_parameters(
+                        screen.get().await,
+                        rate_controller.target(),
+                        encoded_resolution,
+                    ),
+                    cursor_mode,
+                )
+                .await;
 
-    if let Err(e) = screen.close().await {
-        error!("Error closing virtual screen: {}", e);
-    }
+                use_region_updates = mirrors.is_empty()
+                    && !is_recording(recorder)
+                    && !scaled
+                    && region_updates_supported(&*encoder, &*host.lock().await);
+                drop(mirrors);
+                drop(encoder);
+                full_area = format_params.width as u64 * format_params.height as u64;
+                cursor_state = CursorState::new();
+                screen_frame.clear();
+                composite_frame.clear();
+                let _ = input_targets.unbounded_send(input_target(screen.get().await));
 
-    if let Some(e) = err { Err(e) } else { Ok(host) }
+                match status_sink.send(SystemState::Running).await {
+                    Err(_) => warn!("Failed to send running status"),
+                    _ => {}
+                };
+                info!(
+                    "Renegotiated {host_name} to {}x{}",
+                    format_params.width, format_params.height
+                );
+                continue;
+            }
+            LoopEvent::Cancelled(reason) => {
+                info!("Closing the session with {host_name}: {reason}");
+                goodbye = Some(reason);
+                break;
+            }
+            LoopEvent::Goodbye(reason) => {
+                info!("{host_name} closed the session: {reason}");
+                break;
+            }
+            LoopEvent::TransportEnded(Ok(())) => {
+                debug!("Transport of {host_name} stopped in the background");
+                continue;
+            }
+            LoopEvent::TransportEnded(Err(e)) => {
+                error!("Lost the connection to {host_name}: {}", e);
+                if let TransportError::Timeout = e {
+                    // In case only our side of the connection went quiet
+                    goodbye = Some(GoodbyeReason::Idle);
+                }
+                err = Some(SessionError::ConnectionLost);
+                lost = true;
+                break;
+            }
+            LoopEvent::HeartbeatTick => {
+                let _ = control.unbounded_send(ControlMessage::Heartbeat);
+                continue;
+            }
+            LoopEvent::MirrorReady(join) => {
+                let MirrorJoin {
+                    host: mut mirror_host,
+                    cancel,
+                    started,
+                    left,
+                } = join;
+                let encoded_resolution =
+                    policy.encode_resolution((format_params.width, format_params.height));
+                // A mirror that can't draw the cursor while the display host does gets
+                // other frames than the session's
+                let may_share =
+                    cursor_mode != CursorMode::Forward || has_cursor_plane(&mirror_host);
+                let mut encoder = encoder.lock().await;
+                let mirror_encoder = match negotiate_mirror(
+                    &mut mirror_host,
+                    &mut *encoder,
+                    &encoding,
+                    encoder_provider,
+                    encoder_parameters(
+                        screen.get().await,
+                        rate_controller.target(),
+                        encoded_resolution,
+                    ),
+                    may_share,
+                )
+                .await
+                {
+                    Ok(mirror_encoder) => mirror_encoder,
+                    Err(e) => {
+                        warn!("{mirror_host} can't mirror {host_name}: {}", e);
+                        if let Err(e) = mirror_host.close().await {
+                            error!("Error closing mirror {mirror_host}: {}", e);
+                        }
+                        let _ = started.send(Err(e));
+                        continue;
+                    }
+                };
+                let (mirror, mirror_control, mirror_events) = start_mirror(
+                    next_mirror_id,
+                    mirror_host,
+                    mirror_encoder,
+                    cancel,
+                    left,
+                    &mirror_tasks,
+                );
+                next_mirror_id += 1;
+                info!(
+                    "{} mirrors {host_name}, {}",
+                    mirror.name,
+                    if mirror.shares_encoder() {
+                        "sharing its encoder"
+                    } else {
+                        "with its own encoder"
+                    }
+                );
+                let _ = started.send(Ok(()));
+                events.push(mirror_events);
+                mirrors.lock().await.push(mirror);
+                mirror_controls.push(mirror_control);
+                // Mirrors sharing the encoder need whole frames, and the mirror needs
+                // a keyframe to start off
+                use_region_updates = false;
+                events.push(stream::once(future::ready(LoopEvent::Resync)).boxed_local());
+                continue;
+            }
+            LoopEvent::Mirror(id, event) => {
+                let Some(index) = mirror_controls.iter().position(|mirror| mirror.id == id) else {
+                    continue;
+                };
+                let name = &mirror_controls[index].name;
+                let goodbye = match event {
+                    MirrorEvent::KeyframeRequest => {
+                        debug!("Mirror {name} requested a keyframe");
+                        let mut encoder = encoder.lock().await;
+                        let mut mirrors = mirrors.lock().await;
+                        if let Some(mirror) = mirrors.iter_mut().find(|mirror| mirror.id == id) {
+                            mirror.request_keyframe(&mut encoder);
+                        }
+                        continue;
+                    }
+                    MirrorEvent::HeartbeatTick => {
+                        let _ = mirror_controls[index]
+                            .control
+                            .unbounded_send(ControlMessage::Heartbeat);
+                        continue;
+                    }
+                    MirrorEvent::Cancelled(reason) => {
+                        info!("Closing mirror {name}: {reason}");
+                        Some(reason)
+                    }
+                    MirrorEvent::Goodbye(reason) => {
+                        info!("Mirror {name} left: {reason}");
+                        None
+                    }
+                    MirrorEvent::Lost(e) => {
+                        warn!("Lost the connection to mirror {name}: {}", e);
+                        None
+                    }
+                    MirrorEvent::EncodeFailed(e) => {
+                        error!("Failed to encode screen data for mirror {name}: {}", e);
+                        Some(GoodbyeReason::EncoderFailure)
+                    }
+                };
+                mirror_controls.swap_remove(index);
+                let encoder = encoder.lock().await;
+                let mut mirrors = mirrors.lock().await;
+                let Some(index) = mirrors.iter().position(|mirror| mirror.id == id) else {
+                    continue;
+                };
+                let mirror = mirrors.swap_remove(index);
+                if mirrors.is_empty() && !is_recording(recorder) {
+                    use_region_updates =
+                        !scaled && region_updates_supported(&*encoder, &*host.lock().await);
+                }
+                drop(mirrors);
+                drop(encoder);
+                leave_mirror(mirror, goodbye).await;
+                continue;
+            }
+            LoopEvent::Recording(Some(directory)) => {
+                match StreamRecorder::new(
+                    directory,
+                    &host_name,
+                    config_generation,
+                    encoding.clone(),
+                ) {
+                    Ok(new_recorder) => {
+                        info!("Recording what {host_name} is sent");
+                        *recorder.borrow_mut() = Some(new_recorder);
+                    }
+                    Err(e) => {
+                        error!("Failed to start recording {host_name}: {}", e);
+                        continue;
+                    }
+                }
+                // Regions can't be recorded, and the recording needs a keyframe to start
+                // off
+                use_region_updates = false;
+                events.push(stream::once(future::ready(LoopEvent::Resync)).boxed_local());
+                continue;
+            }
+            LoopEvent::Recording(None) => {
+                if recorder.borrow_mut().take().is_none() {
+                    continue;
+                }
+                info!("Stopped recording {host_name}");
+                let encoder = encoder.lock().await;
+                if mirrors.lock().await.is_empty() {
+                    use_region_updates =
+                        !scaled && region_updates_supported(&*encoder, &*host.lock().await);
+                }
+                continue;
+            }
+            LoopEvent::ReceiverReport(report) => {
+                trace!("Receiver report from {host_name}: {report}");
+                if !adaptive_rate {
+                    continue;
+                }
+                let Some(rate) = rate_controller.on_report(&report) else {
+                    continue;
+                };
+                let mut encoder = encoder.lock().await;
+                let reopened = match encoder.set_rate(rate) {
+                    Ok(RateChange::Live) => None,
+                    Ok(RateChange::Reopened(new_encoding)) => Some(new_encoding),
+                    Err(e) => {
+                        warn!("Not adapting the rate for {host_name}: {}", e);
+                        adaptive_rate = false;
+                        continue;
+                    }
+                };
+                // The encoder only keeps the bitrate, frames past the frame rate are
+                // never encoded
+                pacer.set_max_fps(rate.fps);
+                debug!(
+                    "Changed rate for {host_name} to {} kbps at {} fps",
+                    rate.bitrate / 1000,
+                    rate.fps
+                );
+                let Some(new_encoding) = reopened else {
+                    continue;
+                };
+                // A reopened encoder starts a new stream, as if renegotiated
+                if let Err(e) = host.lock().await.set_encoding(new_encoding.clone()).await {
+                    error!("Failed to send the reopened encoding to {host_name}: {}", e);
+                    err = Some(e.into());
+                    break;
+                }
+                encoding = new_encoding;
+                config_generation += 1;
+                // Frames waiting to be encoded were meant for the old encoding
+                encode_queue.clear();
+                stats.set_encoding(&encoding);
+                recording_follows(recorder, config_generation, &encoding);
+                let encoded_resolution =
+                    policy.encode_resolution((format_params.width, format_params.height));
+                renegotiate_mirrors(
+                    &mut *mirrors.lock().await,
+                    &mut mirror_controls,
+                    &mut encoder,
+                    &encoding,
+                    encoder_provider,
+                    encoder_parameters(screen.get().await, rate, encoded_resolution),
+                    cursor_mode,
+                )
+                .await;
+                events.push(stream::once(future::ready(LoopEvent::Resync)).boxed_local());
+                continue;
+            }
+            LoopEvent::ClockSyncTick => {
+                let ping = ClockPing {
+                    id: clock_ping_id,
+                    server_time_us: unix_time_micros(),
+                };
+                clock_ping_id = clock_ping_id.wrapping_add(1);
+                let _ = control.unbounded_send(ControlMessage::ClockPing(ping));
+                continue;
+            }
+            LoopEvent::ClockPong(pong) => {
+                latency.on_pong(&pong, unix_time_micros());
+                continue;
+            }
+            LoopEvent::FramePresented(presented) => {
+                if let Some(frame) = latency.on_frame_presented(&presented) {
+                    trace!(
+                        "Latency of frame {} on {host_name}: {:?}",
+                        frame.sequence, frame
+                    );
+                }
+                if last_latency_log.elapsed() >= LATENCY_LOG_INTERVAL {
+                    last_latency_log = Instant::now();
+                    log_latency(&host_name, &latency);
+                }
+                continue;
+            }
+            LoopEvent::HostClipboardChange(mime_types) => {
+                let Some(sync) = clipboard.as_mut() else {
+                    continue;
+                };
+                let offer = sync.on_host_change(mime_types);
+                trace!("Sending clipboard message to {host_name}: {offer}");
+                let _ = control.unbounded_send(ControlMessage::Clipboard(offer));
+                continue;
+            }
+            LoopEvent::Clipboard(msg) => {
+                let Some(sync) = clipboard.as_mut() else {
+                    continue;
+                };
+                trace!("Clipboard message from {host_name}: {msg}");
+                let Some(answer) = sync.on_message(msg).await else {
+                    continue;
+                };
+                trace!("Sending clipboard message to {host_name}: {answer}");
+                let _ = control.unbounded_send(ControlMessage::Clipboard(answer));
+                continue;
+            }
+            LoopEvent::ControlFailed(message, e) => {
+                match (message, e) {
+                    (ControlMessage::Cursor(_), TransportError::NotImplemented) => {
+                        if cursor_mode == CursorMode::Forward {
+                            warn!(
+                                "{host_name} does not support a cursor plane, drawing the cursor into frames"
+                            );
+                            // The cursor is drawn in from the next frame on
+                            cursor_mode = CursorMode::Composite;
+                        }
+                    }
+                    (ControlMessage::ClockPing(_), TransportError::NotImplemented) => {
+                        warn!("{host_name} does not support clock sync, not measuring latency");
+                        if let Some(abort) = clock_sync_abort.take() {
+                            abort.abort();
+                        }
+                    }
+                    (ControlMessage::Clipboard(_), TransportError::NotImplemented) => {
+                        warn!(
+                            "{host_name} does not support clipboard messages, not sharing the clipboard"
+                        );
+                        clipboard = None;
+                    }
+                    (message, e) => warn!("Failed to send {message} to {host_name}: {}", e),
+                }
+                continue;
+            }
+            LoopEvent::Transmitted(report) => {
+                match report.result {
+                    Ok(()) => {
+                        bad_transmission_start = None;
+                        bad_transmission_count = 0;
+                        stats.on_frame_sent(report.sent_bytes, &report.timings);
+                        if measure_latency {
+                            latency.on_frame_sent(report.header.sequence, report.timings);
+                        }
+                        trace!(
+                            "Sent frame {} to {host_name}, {} bytes (encode time: {}ms, queue time: {}ms, send time: {}ms)",
+                            report.header.sequence,
+                            report.sent_bytes,
+                            report.timings.encode.as_millis(),
+                            report.timings.queue.as_millis(),
+                            report.timings.send.as_millis()
+                        );
+                    }
+                    Err(TransportError::NotImplemented) if report.regions => {
+                        warn!(
+                            "{host_name} does not support region updates, falling back to whole frames"
+                        );
+                        use_region_updates = false;
+                        // The display host missed the regions, so it needs a whole frame
+                        events.push(stream::once(future::ready(LoopEvent::Resync)).boxed_local());
+                    }
+                    Err(e) => {
+                        error!("Error during transmission to screen host: {}", e);
+                        stats.on_transport_error();
+                        let bad_transmission_elapsed = if let Some(start) = bad_transmission_start {
+                            start.elapsed()
+                        } else {
+                            bad_transmission_start = Some(Instant::now());
+                            Duration::ZERO
+                        };
+                        bad_transmission_count += 1;
+
+                        if bad_transmission_elapsed >= Duration::from_secs(5)
+                            && bad_transmission_count >= 5
+                        {
+                            error!(
+                                "Too many bad transmissions ({} errors in {}ms), closing connection",
+                                bad_transmission_count,
+                                bad_transmission_elapsed.as_millis()
+                            );
+                            err = Some(SessionError::ConnectionLost);
+                            lost = true;
+                            break;
+                        }
+                    }
+                }
+                continue;
+            }
+            LoopEvent::Encoded(report) => {
+                if report.dropped > 0 {
+                    stats.on_frames_dropped(report.dropped as u64);
+                    debug!(
+                        "Sending to {host_name} fell behind, dropped {} frames",
+                        report.dropped
+                    );
+                }
+                let e = match report.result {
+                    Ok(()) => {
+                        fallback.on_encoded();
+                        continue;
+                    }
+                    // The encoding it failed for was already moved on from
+                    Err(_) if report.config_generation != config_generation => continue,
+                    Err(e) => e,
+                };
+                match fallback.on_failure() {
+                    EncodeRecovery::Retry => {
+                        warn!(
+                            "Failed to encode screen data for {host_name}, retrying: {}",
+                            e
+                        );
+                        events.push(stream::once(future::ready(LoopEvent::Resync)).boxed_local());
+                    }
+                    EncodeRecovery::FallBack(configurations) => {
+                        warn!(
+                            "{} keeps failing to encode for {host_name}, falling back: {}",
+                            encoding.encoder_name, e
+                        );
+                        let mut encoder = encoder.lock().await;
+                        let encoded_resolution =
+                            policy.encode_resolution((format_params.width, format_params.height));
+                        match fall_back_encoding(
+                            screen.get().await,
+                            &mut *encoder,
+                            configurations,
+                            rate_controller.target(),
+                            encoded_resolution,
+                            &mut *host.lock().await,
+                        )
+                        .await
+                        {
+                            Ok(new_encoding) => {
+                                info!(
+                                    "Fell back from {} to {} for {host_name}",
+                                    encoding.encoder_name, new_encoding.encoder_name
+                                );
+                                fallback.on_fell_back(&new_encoding);
+                                encoding = new_encoding;
+                            }
+                            Err(e) => {
+                                error!("Failed to fall back to another encoder: {}", e);
+                                err = Some(e);
+                                goodbye = Some(GoodbyeReason::EncoderFailure);
+                                break;
+                            }
+                        }
+                        config_generation += 1;
+                        // Frames waiting to be encoded were meant for the old encoding
+                        encode_queue.clear();
+                        stats.set_encoding(&encoding);
+                        recording_follows(recorder, config_generation, &encoding);
+                        let mut mirrors = mirrors.lock().await;
+                        renegotiate_mirrors(
+                            &mut mirrors,
+                            &mut mirror_controls,
+                            &mut encoder,
+                            &encoding,
+                            encoder_provider,
+                            encoder_parameters(
+                                screen.get().await,
+                                rate_controller.target(),
+                                encoded_resolution,
+                            ),
+                            cursor_mode,
+                        )
+                        .await;
+                        use_region_updates = mirrors.is_empty()
+                            && !is_recording(recorder)
+                            && !scaled
+                            && region_updates_supported(&*encoder, &*host.lock().await);
+                        // The display host needs a keyframe of the new encoding
+                        events.push(stream::once(future::ready(LoopEvent::Resync)).boxed_local());
+                    }
+                    EncodeRecovery::GiveUp => {
+                        error!("Failed to encode screen data: {}", e);
+                        err = Some(e.into());
+                        goodbye = Some(GoodbyeReason::EncoderFailure);
+                        break;
+                    }
+                }
+                continue;
+            }
+            LoopEvent::StatsTick => {
+                let report = stats.report(Instant::now());
+                trace!("Stats of {host_name}: {report}");
+                // Nobody may be watching, which is fine
+                let _ = stats_sink.send(report).await;
+                continue;
+            }
+            LoopEvent::KeyframeRequest | LoopEvent::Resync | LoopEvent::Resumed => {
+                if let LoopEvent::KeyframeRequest = event {
+                    debug!("{host_name} requested a keyframe");
+                }
+                // Anything still waiting to be sent would be decoded against the
+                // wrong reference
+                transmit_queue.clear();
+                // Send the last frame again right away as a keyframe, instead of
+                // waiting for the screen to change. A resumed display host gets the
+                // one the screen kept. Before anything was sent, the first frame is
+                // a keyframe anyway.
+                if sequence.get() == 0 && !matches!(event, LoopEvent::Resumed) {
+                    continue;
+                }
+                let Some(data) = latest_frame(
+                    screen.get().await.get_bytes(),
+                    cursor_mode,
+                    &composite_frame,
+                ) else {
+                    continue;
+                };
+                resend = true;
+                (data, None)
+            }
+            LoopEvent::RefreshTick => {
+                if sequence.get() == 0 || !pacer.refresh_due(Instant::now()) {
+                    continue;
+                }
+                let Some(data) = latest_frame(
+                    screen.get().await.get_bytes(),
+                    cursor_mode,
+                    &composite_frame,
+                ) else {
+                    continue;
+                };
+                debug!("Screen of {host_name} stayed still, refreshing it with a keyframe");
+                resend = true;
+                (data, None)
+            }
+            LoopEvent::FrameDue => {
+                frame_due_scheduled = false;
+                let Some(damage) = pacer.take_pending() else {
+                    continue;
+                };
+                let Some(data) = latest_frame(
+                    screen.get().await.get_bytes(),
+                    cursor_mode,
+                    &composite_frame,
+                ) else {
+                    continue;
+                };
+                paced = true;
+                captured = latest_capture;
+                (data, damage)
+            }
+            LoopEvent::Cursor(update) => {
+                if cursor_mode == CursorMode::Forward {
+                    let _ = control.unbounded_send(ControlMessage::Cursor(update.clone()));
+                    for mirror in mirror_controls.iter().filter(|mirror| mirror.cursor_plane) {
+                        let _ = mirror
+                            .control
+                            .unbounded_send(ControlMessage::Cursor(update.clone()));
+                    }
+                    // Kept up to date in case we need to start compositing, and for the
+                    // mirrors that can't draw the cursor themselves
+                    let cursor_damage =
+                        cursor_state.apply(update, format_params.width, format_params.height);
+                    if cursor_damage.is_empty()
+                        || mirror_controls.iter().all(|mirror| mirror.cursor_plane)
+                    {
+                        continue;
+                    }
+                    // Those mirrors are sent the screen again, with the cursor drawn
+                    // where it moved to
+                    let Some(data) = screen.get().await.get_bytes() else {
+                        continue;
+                    };
+                    cursor_only = true;
+                    (data, None)
+                } else {
+                    let cursor_damage =
+                        cursor_state.apply(update, format_params.width, format_params.height);
+                    // Redraw the last frame if the cursor moved over it
+                    if cursor_damage.is_empty() || screen_frame.is_empty() {
+                        continue;
+                    }
+                    composite_frame.clone_from(&screen_frame);
+                    cursor_state.composite_onto(&mut composite_frame, &format_params);
+                    latest_capture = captured;
+                    let damage = use_region_updates.then_some(cursor_damage);
+                    (composite_frame.as_slice(), damage)
+                }
+            }
+            LoopEvent::Screen(Err(e)) => {
+                error!("Virtual screen error: {}", e);
+                err = Some(e.into());
+                goodbye = Some(GoodbyeReason::ScreenFailure);
+                break;
+            }
+            LoopEvent::Screen(Ok(ScreenReadyStatus::Finished)) => {
+                info!("Virtual screen has finished");
+                goodbye = Some(GoodbyeReason::HostShutdown);
+                break;
+            }
+            LoopEvent::Screen(Ok(ScreenReadyStatus::NotReady)) => {
+                // The screen gets a moment, everything else carries on meanwhile
+                screen.rest();
+                events.push(
+                    stream::once(futures_timer::Delay::new(NOT_READY_DELAY))
+                        .map(|_| LoopEvent::ScreenRetry)
+                        .boxed_local(),
+                );
+                continue;
+            }
+            LoopEvent::ScreenRetry => {
+                screen.wake();
+                continue;
+            }
+            LoopEvent::Screen(Ok(ScreenReadyStatus::Ready)) => {
+                let screen = screen.get().await;
+                let Some(data) = screen.get_bytes() else {
+                    error!("Bytes were missing after declared ready!");
+                    continue;
+                };
+                latest_capture = captured;
+
+                let mut damage = if use_region_updates {
+                    screen.get_damage()
+                } else {
+                    None
+                };
+
+                if cursor_mode == CursorMode::Composite {
+                    screen_frame.clear();
+                    screen_frame.extend_from_slice(data);
+                    composite_frame.clone_from(&screen_frame);
+                    cursor_state.composite_onto(&mut composite_frame, &format_params);
+                    // Whatever changed under the cursor must be sent with the cursor
+                    // drawn over it again.
+                    if let Some(rects) = damage.as_mut().filter(|rects| !rects.is_empty()) {
+                        rects.extend(
+                            cursor_state.drawn_rect(format_params.width, format_params.height),
+                        );
+                    }
+                    (composite_frame.as_slice(), damage)
+                } else {
+                    (data, damage)
+                }
+            }
+        };
+
+        let damage = if resend || paced {
+            damage
+        } else {
+            match pacer.pace(damage, Instant::now()) {
+                Pacing::Send(damage) => damage,
+                Pacing::Wait(wait) => {
+                    if !frame_due_scheduled {
+                        frame_due_scheduled = true;
+                        events.push(
+                            stream::once(futures_timer::Delay::new(wait))
+                                .map(|_| LoopEvent::FrameDue)
+                                .boxed_local(),
+                        );
+                    }
+                    continue;
+                }
+            }
+        };
+        // Mirrors that can't draw the cursor while the display host does are sent it
+        // drawn in
+        let with_cursor = (cursor_mode == CursorMode::Forward
+            && mirror_controls.iter().any(|mirror| !mirror.cursor_plane))
+        .then(|| {
+            let mut frame = data.to_vec();
+            cursor_state.composite_onto(&mut frame, &format_params);
+            frame
+        });
+        let hash = frame_hash(with_cursor.as_deref().unwrap_or(data));
+        if !resend && pacer.is_duplicate(hash) {
+            trace!("Frame for {host_name} didn't change, skipping it");
+            continue;
+        }
+
+        let regions = match damage {
+            Some(rects) if rects.is_empty() => {
+                trace!("Screen reported no damage, skipping frame");
+                continue;
+            }
+            Some(rects) if is_partial_update(&rects, full_area) => Some(rects),
+            _ => None,
+        };
+
+        // A frame still waiting to be encoded is stale by now, so it makes way for
+        // this one. This one is encoded whole, as the display host never sees what
+        // the stale one changed.
+        let stale = encode_queue.drain();
+        let dropped = stale.iter().filter(|frame| !frame.cursor_only).count();
+        if dropped > 0 {
+            stats.on_frames_dropped(dropped as u64);
+            trace!("Encoding for {host_name} fell behind, dropped a frame");
+        }
+        let (captured_at, captured_at_us) = captured;
+        encode_queue.push(RawFrame {
+            data: data.to_vec(),
+            with_cursor,
+            cursor_only: cursor_only && dropped == 0,
+            regions: regions.filter(|_| dropped == 0),
+            keyframe: resend || stale.iter().any(|frame| frame.keyframe),
+            keyframe_interval: policy.keyframe_interval,
+            config_generation,
+            captured_at,
+            captured_at_us,
+        });
+        pacer.on_sent(hash, Instant::now());
+    }
+
+    // Stops the encode stage at its next frame, so nothing new goes out from here
+    let _encoder = encoder.lock().await;
+    encode_queue.clear();
+
+    // Mirrors only show what the session does, so they go along with it
+    let mirror_goodbye = goodbye.unwrap_or(GoodbyeReason::HostShutdown);
+    for mirror in std::mem::take(&mut *mirrors.lock().await) {
+        leave_mirror(mirror, Some(mirror_goodbye)).await;
+    }
+
+    // A display host that may come back keeps its session, and isn't told otherwise
+    let keep_session = lost && resumable;
+    if let Some(reason) = goodbye.filter(|_| !keep_session) {
+        // Only the frame being sent right now goes before the goodbye
+        transmit_queue.clear();
+        say_goodbye(&mut *host.lock().await, reason).await;
+    }
+
+    if let Err(e) = host.lock().await.close().await {
+        error!("Error closing display host: {}", e);
+    }
+
+    let screen = screen.into_inner().await;
+    if keep_session {
+        return Ok(Some(InitializedSystem {
+            screen_provider,
+            display_params,
+            base_policy,
+            policy,
+            screen,
+            encoder: (),
+            rate: rate_controller.target(),
+            encoding,
+            fallback,
+            sequence: sequence.get(),
+            config_generation,
+            status_sink,
+            policy_sink,
+            resumable,
+            resumed: None,
+        }));
+    }
+
+    if let Err(e) = screen.close().await {
+        error!("Error closing virtual screen: {}", e);
+    }
+
+    if let Some(e) = err { Err(e) } else { Ok(None) }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CursorMode {
+    /// The screen draws the cursor into its frames
+    InFrames,
+    /// The display host draws the cursor from our updates
+    Forward,
+    /// We draw the cursor into the frames for the display host
+    Composite,
+}
+
+enum LoopEvent {
+    Screen(Result<ScreenReadyStatus, ScreenError>),
+    Cursor(CursorUpdate),
+    KeyframeRequest,
+    DisplayParametersUpdate(DisplayParameters),
+    ReceiverReport(ReceiverReport),
+    ClockSyncTick,
+    ClockPong(ClockPong),
+    FramePresented(FramePresented),
+    /// Someone on the host copied something, of these types
+    HostClipboardChange(Vec<String>),
+    Clipboard(ClipboardMessage),
+    /// We were asked to close the session
+    Cancelled(GoodbyeReason),
+    /// The display host closed the session
+    Goodbye(GoodbyeReason),
+    /// The transport's background task stopped
+    TransportEnded(Result<(), TransportError>),
+    HeartbeatTick,
+    /// Frames held back by pacing may be sent now
+    FrameDue,
+    /// The screen had nothing ready a while ago, time to ask it again
+    ScreenRetry,
+    /// Time to check if a still screen needs refreshing
+    RefreshTick,
+    /// The encode stage is done with a frame
+    Encoded(EncodeReport),
+    /// The transmit stage is done with a frame
+    Transmitted(TransmitReport),
+    /// The transmit stage couldn't send a control message
+    ControlFailed(ControlMessage, TransportError),
+    /// The display host needs a keyframe, without having asked for one
+    Resync,
+    /// Time to report the session's statistics
+    StatsTick,
+    /// A reconnected display host took over the session, and needs the screen as it
+    /// is
+    Resumed,
+    /// A display host is ready to join the session as a mirror
+    MirrorReady(MirrorJoin),
+    /// Something happened with the mirror of this ID
+    Mirror(u64, MirrorEvent),
+    /// Start recording into this directory, or stop recording
+    Recording(Option<PathBuf>),
+}
+
+/// What the streams of a mirror tell the screen loop.
+enum MirrorEvent {
+    KeyframeRequest,
+    HeartbeatTick,
+    /// We were asked to let the mirror go
+    Cancelled(GoodbyeReason),
+    /// The mirror left the session
+    Goodbye(GoodbyeReason),
+    /// The connection to the mirror was lost
+    Lost(TransportError),
+    /// The mirror's own encoder failed
+    EncodeFailed(EncoderError),
+}
+
+/// A captured frame waiting for the encode stage.
+#[derive(Debug)]
+struct RawFrame {
+    data: Vec<u8>,
+    /// The frame with the cursor drawn in, for mirrors that can't draw it themselves
+    /// while the display host does
+    with_cursor: Option<Vec<u8>>,
+    /// Whether only the cursor moved, which only mirrors it's drawn in for need
+    cursor_only: bool,
+    /// The damaged regions, when only those are to be encoded
+    regions: Option<Vec<DamageRect>>,
+    /// Whether the frame has to be a keyframe
+    keyframe: bool,
+    keyframe_interval: Option<u32>,
+    config_generation: u32,
+    captured_at: Instant,
+    captured_at_us: u64,
+}
+
+/// How encoding a frame went, for the screen loop.
+#[derive(Debug)]
+struct EncodeReport {
+    /// The generation of the encoding the frame was encoded for
+    config_generation: u32,
+    /// How many encoded frames were dropped for the transmit stage falling behind
+    dropped: usize,
+    result: Result<(), EncoderError>,
+}
+
+/// An encoded frame waiting for the transmit stage.
+#[derive(Debug)]
+struct OutgoingFrame {
+    header: FrameHeader,
+    payload: EncodedPayload,
+    encoded_at: Instant,
+    /// Timings up to and including encoding
+    timings: FrameTimings,
+}
+
+#[derive(Debug)]
+enum EncodedPayload {
+    Whole(Rc<[u8]>),
+    Regions(Vec<(DamageRect, Vec<u8>)>),
+}
+
+/// How sending a frame went, for the screen loop.
+#[derive(Debug)]
+struct TransmitReport {
+    header: FrameHeader,
+    /// Whether the frame was sent as regions
+    regions: bool,
+    sent_bytes: usize,
+    result: Result<(), TransportError>,
+    timings: FrameTimings,
+}
+
+/// A small message for the display host, sent by the transmit stage in between frames
+/// so it never waits behind more than the frame being sent.
+#[derive(Debug)]
+pub(crate) enum ControlMessage {
+    Heartbeat,
+    Cursor(CursorUpdate),
+    ClockPing(ClockPing),
+    Clipboard(ClipboardMessage),
+}
+
+impl Display for ControlMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ControlMessage::Heartbeat => write!(f, "heartbeat"),
+            ControlMessage::Cursor(_) => write!(f, "cursor update"),
+            ControlMessage::ClockPing(_) => write!(f, "clock ping"),
+            ControlMessage::Clipboard(_) => write!(f, "clipboard message"),
+        }
+    }
+}
+
+pub(crate) async fn send_control_message<T: ScreenTransport>(
+    host: &mut DisplayHost<T>,
+    message: &ControlMessage,
+) -> Result<(), TransportError> {
+    match message {
+        ControlMessage::Heartbeat => host.send_heartbeat().await,
+        ControlMessage::Cursor(update) => host.send_cursor_update(update.clone()).await,
+        ControlMessage::ClockPing(ping) => host.send_clock_ping(*ping).await,
+        ControlMessage::Clipboard(msg) => host.send_clipboard_message(msg.clone()).await,
+    }
+}
+
+/// The encode stage. Encodes captured frames as they're queued, for the display
+/// host and the mirrors, for as long as the screen loop listens for how it went.
+async fn encode_loop<E: Encoder>(
+    encoder: &Mutex<E>,
+    queue: &FrameQueue<RawFrame>,
+    transmit_queue: &FrameQueue<OutgoingFrame>,
+    mirrors: &Mutex<Vec<Mirror<E>>>,
+    sequence: &Cell<u64>,
+    reports: mpsc::UnboundedSender<LoopEvent>,
+) {
+    loop {
+        let frame = queue.pop().await;
+        let mut encoder = encoder.lock().await;
+        // Frames of only the cursor moving are for the mirrors it's drawn in for
+        let shared = if frame.cursor_only {
+            None
+        } else {
+            // Frames still waiting to be sent are stale by now, so they make way for
+            // this one. It has to be a keyframe, as the display host never sees what
+            // they changed.
+            let dropped = if transmit_queue.is_full() {
+                transmit_queue.clear()
+            } else {
+                0
+            };
+            let (shared, result) =
+                match encode_frame(&mut *encoder, &frame, dropped > 0, transmit_queue, sequence)
+                    .await
+                {
+                    Ok(shared) => (shared, Ok(())),
+                    Err(e) => (None, Err(e)),
+                };
+            let failed = result.is_err();
+            let report = EncodeReport {
+                config_generation: frame.config_generation,
+                dropped,
+                result,
+            };
+            if reports.unbounded_send(LoopEvent::Encoded(report)).is_err() {
+                break;
+            }
+            if failed {
+                continue;
+            }
+            shared
+        };
+
+        // Mirrors that fail are let go by the screen loop
+        for mirror in mirrors.lock().await.iter_mut() {
+            let data = match &frame.with_cursor {
+                Some(with_cursor) if !mirror.cursor_plane => with_cursor,
+                _ if frame.cursor_only => continue,
+                _ => &frame.data,
+            };
+            if let Err(e) = mirror
+                .push_frame(data, frame.captured_at_us, shared.as_ref(), &mut encoder)
+                .await
+            {
+                let event = LoopEvent::Mirror(mirror.id, MirrorEvent::EncodeFailed(e));
+                if reports.unbounded_send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Encodes a frame for the display host, and queues it to be sent. Returns the
+/// encoded frame for the mirrors sharing the encoder, if it was a whole one.
+async fn encode_frame<E: Encoder>(
+    encoder: &mut E,
+    frame: &RawFrame,
+    force_keyframe: bool,
+    transmit_queue: &FrameQueue<OutgoingFrame>,
+    sequence: &Cell<u64>,
+) -> Result<Option<MirrorFrame>, EncoderError> {
+    let now = Instant::now();
+    let keyframe =
+        force_keyframe || frame.keyframe || keyframe_due(frame.keyframe_interval, sequence.get());
+    // Regions are never keyframes, so a keyframe is sent as a whole frame
+    let regions = if keyframe {
+        encoder.force_keyframe();
+        None
+    } else {
+        frame.regions.as_deref()
+    };
+
+    let header = FrameHeader::captured_at(
+        sequence.get(),
+        frame.config_generation,
+        frame.captured_at_us,
+    );
+    let (header, payload) = match regions {
+        Some(rects) => {
+            let regions = encoder
+                .encode_regions(&frame.data, rects)
+                .await?
+                .iter()
+                .map(|region| (region.rect, region.data.to_vec()))
+                .collect();
+            // Regions only patch the previous frame, so they are never keyframes
+            (header.encoded(false), EncodedPayload::Regions(regions))
+        }
+        None => {
+            let encoded = encoder.encode(&frame.data, header).await?;
+            (encoded.header, EncodedPayload::Whole(encoded.data.into()))
+        }
+    };
+    let encode_time = now.elapsed();
+    // Mirrors sharing the encoder are sent the same whole frames, the others encode
+    // their own
+    let shared = match &payload {
+        EncodedPayload::Whole(data) => Some(MirrorFrame {
+            header,
+            data: data.clone(),
+        }),
+        EncodedPayload::Regions(_) => None,
+    };
+    // The encoder's timings only cover whole frames
+    let encode_timings = encoder
+        .last_encode_timings()
+        .filter(|_| matches!(payload, EncodedPayload::Whole(_)))
+        .unwrap_or(EncodeTimings {
+            encode: encode_time,
+            ..Default::default()
+        });
+    transmit_queue.push(OutgoingFrame {
+        header,
+        payload,
+        encoded_at: Instant::now(),
+        timings: FrameTimings {
+            ready_at_us: frame.captured_at_us,
+            capture_wait: now.duration_since(frame.captured_at),
+            copy: encode_timings.copy,
+            scale: encode_timings.scale,
+            encode: encode_timings.encode,
+            ..Default::default()
+        },
+    });
+    sequence.set(sequence.get() + 1);
+    Ok(shared)
+}
+
+/// Whether the frame of this sequence number is due to be a keyframe, for the policy's
+/// keyframe interval.
+fn keyframe_due(keyframe_interval: Option<u32>, sequence: u64) -> bool {
+    keyframe_interval
+        .is_some_and(|interval| sequence > 0 && sequence.is_multiple_of(interval.max(1) as u64))
+}
+
+/// The transmit stage. Sends control messages and encoded frames as they're queued,
+/// control messages first, for as long as the screen loop listens for how it went.
+async fn transmit_loop<T: ScreenTransport>(
+    host: &Mutex<DisplayHost<T>>,
+    queue: &FrameQueue<OutgoingFrame>,
+    mut control: mpsc::UnboundedReceiver<ControlMessage>,
+    recorder: &RefCell<Option<StreamRecorder>>,
+    reports: mpsc::UnboundedSender<LoopEvent>,
+) {
+    loop {
+        let next = match future::select(control.next(), pin!(queue.pop())).await {
+            Either::Left((Some(message), _)) => Either::Left(message),
+            // The screen loop is gone
+            Either::Left((None, _)) => break,
+            Either::Right((frame, _)) => Either::Right(frame),
+        };
+        let OutgoingFrame {
+            header,
+            payload,
+            encoded_at,
+            mut timings,
+        } = match next {
+            Either::Left(message) => {
+                let Err(e) = send_control_message(&mut *host.lock().await, &message).await else {
+                    continue;
+                };
+                if reports
+                    .unbounded_send(LoopEvent::ControlFailed(message, e))
+                    .is_err()
+                {
+                    break;
+                }
+                continue;
+            }
+            Either::Right(frame) => frame,
+        };
+        let mut host = host.lock().await;
+        let send_start = Instant::now();
+        timings.queue = send_start.duration_since(encoded_at);
+        let (result, sent_bytes) = match &payload {
+            EncodedPayload::Whole(data) => {
+                let frame = EncodedFrame { header, data };
+                (host.send_screen_data(frame).await, data.len())
+            }
+            EncodedPayload::Regions(regions) => {
+                let regions: Vec<EncodedRegion> = regions
+                    .iter()
+                    .map(|(rect, data)| EncodedRegion { rect: *rect, data })
+                    .collect();
+                let sent_bytes = regions.iter().map(|r| r.data.len()).sum();
+                (host.send_screen_regions(header, &regions).await, sent_bytes)
+            }
+        };
+        drop(host);
+        timings.send = send_start.elapsed();
+        timings.sent_at_us = unix_time_micros();
+        // Only what made it to the display host is recorded
+        if let (Ok(()), EncodedPayload::Whole(data)) = (&result, &payload) {
+            record_frame(recorder, &header, data);
+        }
+
+        let report = TransmitReport {
+            header,
+            regions: matches!(payload, EncodedPayload::Regions(_)),
+            sent_bytes,
+            result,
+            timings,
+        };
+        if reports
+            .unbounded_send(LoopEvent::Transmitted(report))
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+/// Starts the tasks of a mirror that joined the session. Returns the mirror, and the
+/// events the screen loop gets from it.
+fn start_mirror<E: Encoder>(
+    id: u64,
+    mut host: DisplayHost<SomeScreenTransport>,
+    encoder: Option<E>,
+    cancel: PinnedStream<'static, GoodbyeReason>,
+    left: oneshot::Sender<()>,
+    mirror_tasks: &mpsc::UnboundedSender<PinnedLocalFuture<'static, ()>>,
+) -> (
+    Mirror<E>,
+    MirrorControl,
+    PinnedLocalStream<'static, LoopEvent>,
+) {
+    let name = host.to_string();
+    let cursor_plane = has_cursor_plane(&host);
+    let (failures_tx, failures_rx) = mpsc::unbounded();
+    let mut streams = vec![
+        cancel.map(MirrorEvent::Cancelled).boxed_local(),
+        failures_rx.map(MirrorEvent::Lost).boxed_local(),
+    ];
+    if let Some(goodbyes) = host.take_goodbye_stream() {
+        streams.push(goodbyes.map(MirrorEvent::Goodbye).boxed_local());
+    }
+    if let Some(keyframe_requests) = host.take_keyframe_request_stream() {
+        streams.push(
+            keyframe_requests
+                .map(|_| MirrorEvent::KeyframeRequest)
+                .boxed_local(),
+        );
+    }
+    if let Some(interval) = host.heartbeat_interval() {
+        streams.push(
+            ticks(interval)
+                .map(|_| MirrorEvent::HeartbeatTick)
+                .boxed_local(),
+        );
+    }
+
+    // Like the session's own, these run alongside the screen loop
+    let background = host.get_background_task();
+    let background_failures = failures_tx.clone();
+    let (background_task, background_abort) = future::abortable(async move {
+        if let Err(e) = background.await {
+            let _ = background_failures.unbounded_send(e);
+        }
+    });
+    let host = Rc::new(Mutex::new(host));
+    let queue = Rc::new(FrameQueue::new(MIRROR_QUEUE_DEPTH));
+    let (control_tx, control_rx) = mpsc::unbounded();
+    let (transmit_task, transmit_abort) = future::abortable(mirror_transmit_loop(
+        host.clone(),
+        queue.clone(),
+        control_rx,
+        failures_tx,
+    ));
+    let _ = mirror_tasks.unbounded_send(background_task.map(|_| ()).boxed_local());
+    let _ = mirror_tasks.unbounded_send(transmit_task.map(|_| ()).boxed_local());
+
+    let (events, events_abort) = stream::abortable(stream::select_all(streams));
+    let control = MirrorControl {
+        id,
+        name,
+        control: control_tx,
+        cursor_plane,
+    };
+    let mirror = Mirror::new(
+        &control,
+        host,
+        queue,
+        encoder,
+        left,
+        vec![background_abort, transmit_abort, events_abort],
+    );
+    let events = events
+        .map(move |event| LoopEvent::Mirror(id, event))
+        .boxed_local();
+    (mirror, control, events)
+}
+
+/// Has a mirror leave the session, telling it why if it's still listening.
+async fn leave_mirror<E: Encoder>(mirror: Mirror<E>, goodbye: Option<GoodbyeReason>) {
+    let name = mirror.name.clone();
+    let (host, left) = mirror.finish();
+    let mut host = host.lock().await;
+    if let Some(reason) = goodbye {
+        say_goodbye(&mut host, reason).await;
+    }
+    if let Err(e) = host.close().await {
+        error!("Error closing mirror {name}: {}", e);
+    }
+    let _ = left.send(());
+}
+
+/// Has the mirrors follow the session to a new encoding. Mirrors that can't leave.
+async fn renegotiate_mirrors<E, EP>(
+    mirrors: &mut Vec<Mirror<E>>,
+    controls: &mut Vec<MirrorControl>,
+    encoder: &mut E,
+    encoding: &EncoderPossibleConfiguration,
+    encoder_provider: &EP,
+    parameters: EncoderContentParameters,
+    cursor_mode: CursorMode,
+) where
+    E: Encoder,
+    EP: EncoderProvider<EncoderType = E>,
+{
+    let mut index = 0;
+    while index < mirrors.len() {
+        let mirror = &mut mirrors[index];
+        match mirror
+            .renegotiate(
+                encoder,
+                encoding,
+                encoder_provider,
+                parameters.clone(),
+                cursor_mode == CursorMode::Forward,
+            )
+            .await
+        {
+            Ok(()) => index += 1,
+            Err(e) => {
+                warn!(
+                    "Mirror {} can't follow the new encoding: {}",
+                    mirror.name, e
+                );
+                let id = mirror.id;
+                controls.retain(|control| control.id != id);
+                leave_mirror(
+                    mirrors.swap_remove(index),
+                    Some(GoodbyeReason::EncoderFailure),
+                )
+                .await;
+            }
+        }
+    }
+}
+
+fn is_recording(recorder: &RefCell<Option<StreamRecorder>>) -> bool {
+    recorder.borrow().is_some()
+}
+
+/// Has the recording, if there is one, follow the session to a new encoding.
+fn recording_follows(
+    recorder: &RefCell<Option<StreamRecorder>>,
+    config_generation: u32,
+    encoding: &EncoderPossibleConfiguration,
+) {
+    let mut recorder = recorder.borrow_mut();
+    let result = match recorder.as_mut() {
+        Some(recording) => recording.set_encoding(config_generation, encoding.clone()),
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        error!("Failed to write recording, stopping it: {}", e);
+        *recorder = None;
+    }
+}
+
+fn record_frame(recorder: &RefCell<Option<StreamRecorder>>, header: &FrameHeader, data: &[u8]) {
+    let mut recorder = recorder.borrow_mut();
+    let result = match recorder.as_mut() {
+        Some(recording) => recording.record(header, data),
+        None => Ok(()),
+    };
+    if let Err(e) = result {
+        error!("Failed to write recording, stopping it: {}", e);
+        *recorder = None;
+    }
+}
+
+/// Ticks right away, then every `interval`.
+fn ticks(interval: Duration) -> PinnedLocalStream<'static, ()> {
+    stream::once(future::ready(()))
+        .chain(stream::unfold((), move |_| async move {
+            futures_timer::Delay::new(interval).await;
+            Some(((), ()))
+        }))
+        .boxed_local()
+}
+
+/// Tells the display host why the session is over, without waiting on it for long.
+async fn say_goodbye<T: ScreenTransport>(host: &mut DisplayHost<T>, reason: GoodbyeReason) {
+    debug!("Saying goodbye to {host}: {reason}");
+    match with_timeout(GOODBYE_TIMEOUT, host.send_goodbye(reason)).await {
+        Ok(Ok(())) => {}
+        Ok(Err(TransportError::NotImplemented)) => {
+            debug!("{host} can't be told goodbye");
+        }
+        Ok(Err(e)) | Err(e) => warn!("Failed to say goodbye to {host}: {}", e),
+    }
+}
+
+fn log_latency(host: &str, latency: &LatencyTracker) {
+    if let Some(rtt) = latency.clock().round_trip() {
+        debug!("Round trip to {host}: {}ms", rtt.as_millis());
+    }
+    for stage in LatencyStage::ALL {
+        if let Some(percentiles) = latency.percentiles(stage) {
+            debug!("Latency of {stage} on {host}: {percentiles}");
+        }
+    }
+}
+
+/// Whether region updates can be used with this encoder and display host.
+fn region_updates_supported<T, E>(encoder: &E, host: &DisplayHost<T>) -> bool
+where
+    T: ScreenTransport,
+    E: Encoder,
+{
+    encoder.supports_region_updates()
+        && host
+            .protocol()
+            .is_none_or(|p| p.has_capability(ProtocolCapability::RegionUpdates))
+}
+
+/// The last frame the display host got, or would have gotten, with the cursor drawn
+/// in when compositing. `screen_frame` is the last frame as the screen gave it.
+fn latest_frame<'a>(
+    screen_frame: Option<&'a [u8]>,
+    cursor_mode: CursorMode,
+    composite_frame: &'a [u8],
+) -> Option<&'a [u8]> {
+    if cursor_mode == CursorMode::Composite && !composite_frame.is_empty() {
+        Some(composite_frame)
+    } else {
+        screen_frame
+    }
+}
+
+/// Whether the policy scales the screen before encoding it.
+fn is_scaled(policy: &StreamPolicy, format_params: &ScreenOutputParameters) -> bool {
+    let screen = (format_params.width, format_params.height);
+    policy.encode_resolution(screen) != screen
+}
+
+/// Screens that keep the cursor out of their frames hand us its changes. The cursor
+/// is forwarded to display hosts that draw it themselves, and drawn into the frames
+/// for everyone else. The returned handle stops the cursor events, for when the
+/// screen is replaced.
+fn setup_cursor<S, T>(
+    screen: &mut S,
+    host: &DisplayHost<T>,
+    scaled: bool,
+    events: &mut SelectAll<PinnedLocalStream<'static, LoopEvent>>,
+) -> (CursorMode, Option<AbortHandle>)
+where
+    S: Screen,
+    T: ScreenTransport,
+{
+    let Some(cursor_stream) = screen.take_cursor_stream() else {
+        debug!("Cursor mode for {host}: {:?}", CursorMode::InFrames);
+        return (CursorMode::InFrames, None);
+    };
+
+    let (cursor_stream, abort) = stream::abortable(cursor_stream);
+    events.push(cursor_stream.map(LoopEvent::Cursor).boxed_local());
+    let mode = if !scaled && has_cursor_plane(host) {
+        CursorMode::Forward
+    } else {
+        CursorMode::Composite
+    };
+    debug!("Cursor mode for {host}: {:?}", mode);
+    (mode, Some(abort))
+}
+
+/// Whether the display host draws the cursor itself, from our updates.
+fn has_cursor_plane<T: ScreenTransport>(host: &DisplayHost<T>) -> bool {
+    host.protocol()
+        .is_some_and(|p| p.has_capability(ProtocolCapability::CursorPlane))
+}
+
+/// Shares the host's clipboard with display hosts that share theirs. Returns `None`
+/// if the clipboard can't be shared with this display host.
+fn setup_clipboard<B, T>(
+    mut sync: ClipboardSync<B>,
+    host: &mut DisplayHost<T>,
+    events: &mut SelectAll<PinnedLocalStream<'static, LoopEvent>>,
+) -> Option<ClipboardSync<B>>
+where
+    B: ClipboardBackend,
+    T: ScreenTransport,
+{
+    if !host
+        .protocol()
+        .is_some_and(|p| p.has_capability(ProtocolCapability::Clipboard))
+    {
+        debug!("{host} does not share its clipboard");
+        return None;
+    }
+    let Some(messages) = host.take_clipboard_stream() else {
+        debug!("{host}'s transport does not carry clipboard messages");
+        return None;
+    };
+    let Some(host_changes) = sync.take_host_changes() else {
+        warn!("Host clipboard changes were already taken, not sharing the clipboard");
+        return None;
+    };
+
+    events.push(messages.map(LoopEvent::Clipboard).boxed_local());
+    events.push(
+        host_changes
+            .map(LoopEvent::HostClipboardChange)
+            .boxed_local(),
+    );
+    debug!("Sharing the clipboard with {host}");
+    Some(sync)
+}
+
+fn input_target<S: Screen>(screen: &S) -> InputTarget {
+    let format_params = screen.get_format_parameters();
+    InputTarget::new(
+        format_params.width,
+        format_params.height,
+        screen.get_desktop_placement(),
+    )
+}
+
+/// The screen, and its wait for the next frame. The wait carries on across the
+/// loop's other events, so that a busy cursor can't keep cutting it short, and is
+/// only interrupted when the loop needs the screen itself.
+struct WaitingScreen<'a, S> {
+    screen: Option<S>,
+    wait: Option<ScreenWait<'a, S>>,
+    /// What an interrupted wait came back with anyways
+    status: Option<Result<ScreenReadyStatus, ScreenError>>,
+    /// Whether the screen is left be until woken, instead of waited for
+    resting: bool,
+}
+
+struct ScreenWait<'a, S> {
+    interrupt: oneshot::Sender<()>,
+    /// Hands the screen back, with its status unless interrupted before it was ready
+    done: PinnedLocalFuture<'a, (S, Option<Result<ScreenReadyStatus, ScreenError>>)>,
+}
+
+impl<'a, S: Screen + 'a> WaitingScreen<'a, S> {
+    fn new(screen: S) -> Self {
+        Self {
+            screen: Some(screen),
+            wait: None,
+            status: None,
+            resting: false,
+        }
+    }
+
+    /// Wait for the screen to have a frame ready, or for something else to happen.
+    /// Returns `None` once all the other events have ended.
+    async fn next_event(
+        &mut self,
+        events: &mut SelectAll<PinnedLocalStream<'static, LoopEvent>>,
+    ) -> Option<LoopEvent> {
+        if let Some(status) = self.status.take() {
+            return Some(LoopEvent::Screen(status));
+        }
+        if self.resting && self.wait.is_none() {
+            return events.next().await;
+        }
+        if let Some(screen) = self.screen.take() {
+            self.wait = Some(ScreenWait::start(screen));
+        }
+        let wait = self
+            .wait
+            .as_mut()
+            .expect("The screen is either waiting or idle");
+        let (screen, status) = if events.is_empty() {
+            wait.done.as_mut().await
+        } else {
+            match future::select(wait.done.as_mut(), events.next()).await {
+                Either::Left((done, _)) => done,
+                Either::Right((event, _)) => return event,
+            }
+        };
+        self.wait = None;
+        self.screen = Some(screen);
+        status.map(LoopEvent::Screen)
+    }
+
+    /// Leave the screen be until `wake`, for when it had nothing ready.
+    fn rest(&mut self) {
+        self.resting = true;
+    }
+
+    fn wake(&mut self) {
+        self.resting = false;
+    }
+
+    /// The screen, interrupting its wait if need be.
+    async fn get(&mut self) -> &mut S {
+        if let Some(wait) = self.wait.take() {
+            let _ = wait.interrupt.send(());
+            let (screen, status) = wait.done.await;
+            self.screen = Some(screen);
+            self.status = status;
+        }
+        self.screen
+            .as_mut()
+            .expect("The screen is back once its wait is over")
+    }
+
+    /// Give the screen new display parameters, see `Screen::reconfigure`.
+    async fn reconfigure(&mut self, params: DisplayParameters) -> Result<(), ScreenError> {
+        let screen = self.get().await;
+        let result = screen.reconfigure(params).await;
+        // Whatever the screen had ready is of the old parameters
+        self.status = None;
+        self.resting = false;
+        result
+    }
+
+    /// Swap in a new screen, giving back the old one.
+    async fn replace(&mut self, screen: S) -> S {
+        let old_screen = std::mem::replace(self.get().await, screen);
+        // Whatever the old screen had ready is of no use anymore
+        self.status = None;
+        self.resting = false;
+        old_screen
+    }
+
+    async fn into_inner(mut self) -> S {
+        self.get().await;
+        self.screen
+            .expect("The screen is back once its wait is over")
+    }
+}
+
+impl<'a, S: Screen + 'a> ScreenWait<'a, S> {
+    fn start(mut screen: S) -> Self {
+        let (interrupt, interrupted) = oneshot::channel();
+        let done = async move {
+            let status = match future::select(pin!(screen.get_ready()), interrupted).await {
+                Either::Left((status, _)) => Some(status),
+                Either::Right(_) => None,
+            };
+            (screen, status)
+        }
+        .boxed_local();
+        Self { interrupt, done }
+    }
+}
+
+enum InputLoopEvent {
+    Target(InputTarget),
+    /// `None` once the display host's input has ended
+    Input(Option<InputEvent>),
+}
+
+/// Inject every input event coming from the display host until its stream ends. The
+/// injector is made again whenever the screen it aims at changes.
+async fn input_loop<I>(
+    input_provider: &I,
+    targets: mpsc::UnboundedReceiver<InputTarget>,
+    input_stream: PinnedStream<'static, InputEvent>,
+) where
+    I: InputInjectorProvider,
+{
+    let input_stream = input_stream
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .map(InputLoopEvent::Input);
+    let mut events = stream::select(targets.map(InputLoopEvent::Target), input_stream);
+
+    let mut injector = None;
+    while let Some(event) = events.next().await {
+        match event {
+            InputLoopEvent::Target(target) => {
+                // Drop the old injector first, so its devices are gone before the new
+                // ones show up
+                drop(injector.take());
+                injector = match input_provider.create_injector(target).await {
+                    Ok(injector) => {
+                        debug!("Created input injector for {:?}", target);
+                        Some(injector)
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to create input injector, input will be ignored: {}",
+                            e
+                        );
+                        None
+                    }
+                };
+            }
+            InputLoopEvent::Input(Some(event)) => {
+                let Some(injector) = injector.as_mut() else {
+                    continue;
+                };
+                trace!("Injecting input event: {}", event);
+                if let Err(e) = injector.inject(event) {
+                    warn!("Failed to inject input event: {}", e);
+                }
+            }
+            InputLoopEvent::Input(None) => break,
+        }
+    }
+}
+
+/// Whether the given damage is small enough that sending only the damaged regions
+/// beats sending the whole frame.
+fn is_partial_update(damage: &[DamageRect], full_area: u64) -> bool {
+    let damaged_area: u64 = damage.iter().map(|r| r.area()).sum();
+    (damaged_area as f64) < (full_area as f64 * REGION_UPDATE_MAX_COVERAGE)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::host::DamageRect;
+
+    use super::{CursorMode, is_partial_update, keyframe_due, latest_frame};
+
+    #[test]
+    fn test_partial_update_boundaries() {
+        let partial = |rects: &[DamageRect]| is_partial_update(rects, 100 * 100);
+        assert!(partial(&[]));
+        assert!(partial(&[DamageRect::new(0, 0, 10, 10)]));
+        // Just under and at the coverage limit
+        assert!(partial(&[DamageRect::new(0, 0, 100, 49)]));
+        assert!(!partial(&[DamageRect::new(0, 0, 100, 50)]));
+        assert!(!partial(&[DamageRect::new(0, 0, 100, 100)]));
+        // Rects add up, even when they overlap
+        assert!(!partial(&[
+            DamageRect::new(0, 0, 100, 30),
+            DamageRect::new(0, 20, 100, 30)
+        ]));
+    }
+
+    #[test]
+    fn test_latest_frame_prefers_composite() {
+        let screen = [1u8; 4];
+        let composite = [2u8; 4];
+        assert_eq!(
+            latest_frame(Some(&screen), CursorMode::Composite, &composite),
+            Some(&composite[..])
+        );
+        // Nothing composited yet
+        assert_eq!(
+            latest_frame(Some(&screen), CursorMode::Composite, &[]),
+            Some(&screen[..])
+        );
+        assert_eq!(
+            latest_frame(Some(&screen), CursorMode::Forward, &composite),
+            Some(&screen[..])
+        );
+        assert_eq!(latest_frame(None, CursorMode::InFrames, &[]), None);
+    }
+
+    #[test]
+    fn test_keyframe_due() {
+        assert!(!keyframe_due(None, 30));
+        // The first frame is a keyframe anyway
+        assert!(!keyframe_due(Some(30), 0));
+        assert!(!keyframe_due(Some(30), 29));
+        assert!(keyframe_due(Some(30), 30));
+        assert!(keyframe_due(Some(30), 60));
+        // An interval of 0 is taken as every frame
+        assert!(keyframe_due(Some(0), 1));
+        assert!(keyframe_due(Some(1), 7));
+    }
 }
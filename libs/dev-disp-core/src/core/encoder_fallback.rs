@@ -0,0 +1,136 @@
+use crate::host::EncoderPossibleConfiguration;
+
+/// How many times in a row encoding may fail before the encoder is given up on.
+const ENCODE_RETRIES: u32 = 2;
+
+/// What to do about a frame that failed to encode.
+#[derive(Debug, Clone)]
+pub enum EncodeRecovery {
+    /// Try again with the same encoder
+    Retry,
+    /// Set the encoder up again with one of these, the most preferred first
+    FallBack(Vec<EncoderPossibleConfiguration>),
+    /// Nothing left to fall back to
+    GiveUp,
+}
+
+/// Keeps track of a session's encoder failing, and of the negotiated configurations
+/// it may fall back to.
+#[derive(Debug, Clone)]
+pub struct EncoderFallback {
+    /// Negotiated configurations besides the one in use, the most preferred first
+    remaining: Vec<EncoderPossibleConfiguration>,
+    /// Failures in a row of the encoder in use
+    failures: u32,
+}
+
+impl EncoderFallback {
+    /// `negotiated` is what the display host agreed to, of which the encoder was set
+    /// up with `chosen`.
+    pub fn new(
+        negotiated: Vec<EncoderPossibleConfiguration>,
+        chosen: &EncoderPossibleConfiguration,
+    ) -> Self {
+        Self {
+            remaining: negotiated
+                .into_iter()
+                .filter(|config| !is_same_encoder(config, chosen))
+                .collect(),
+            failures: 0,
+        }
+    }
+
+    pub fn on_encoded(&mut self) {
+        self.failures = 0;
+    }
+
+    pub fn on_failure(&mut self) -> EncodeRecovery {
+        self.failures += 1;
+        if self.failures <= ENCODE_RETRIES {
+            return EncodeRecovery::Retry;
+        }
+        if self.remaining.is_empty() {
+            return EncodeRecovery::GiveUp;
+        }
+        EncodeRecovery::FallBack(self.remaining.clone())
+    }
+
+    /// The encoder was set up again with `chosen`, after falling back.
+    pub fn on_fell_back(&mut self, chosen: &EncoderPossibleConfiguration) {
+        let remaining = std::mem::take(&mut self.remaining);
+        *self = Self::new(remaining, chosen);
+    }
+}
+
+/// Encoders tell configurations apart by these, not by the parameters.
+pub(crate) fn is_same_encoder(
+    a: &EncoderPossibleConfiguration,
+    b: &EncoderPossibleConfiguration,
+) -> bool {
+    a.encoder_name == b.encoder_name && a.encoder_family == b.encoder_family
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::host::EncoderPossibleConfiguration;
+
+    use super::{ENCODE_RETRIES, EncodeRecovery, EncoderFallback};
+
+    fn config(encoder_name: &str) -> EncoderPossibleConfiguration {
+        EncoderPossibleConfiguration {
+            encoder_name: encoder_name.to_string(),
+            encoded_resolution: (1920, 1080),
+            encoder_family: "h264".to_string(),
+            parameters: HashMap::new(),
+        }
+    }
+
+    fn fail_until_recovery(fallback: &mut EncoderFallback) -> EncodeRecovery {
+        for _ in 0..ENCODE_RETRIES {
+            assert!(matches!(fallback.on_failure(), EncodeRecovery::Retry));
+        }
+        fallback.on_failure()
+    }
+
+    #[test]
+    fn test_retries_before_falling_back() {
+        let negotiated = vec![
+            config("h264_nvenc"),
+            config("h264_vaapi"),
+            config("libx264"),
+        ];
+        let mut fallback = EncoderFallback::new(negotiated, &config("h264_nvenc"));
+
+        // A success in between means the failures weren't in a row
+        fallback.on_failure();
+        fallback.on_encoded();
+
+        let EncodeRecovery::FallBack(configurations) = fail_until_recovery(&mut fallback) else {
+            panic!("Expected to fall back");
+        };
+        let names: Vec<_> = configurations
+            .iter()
+            .map(|c| c.encoder_name.as_str())
+            .collect();
+        assert_eq!(names, ["h264_vaapi", "libx264"]);
+    }
+
+    #[test]
+    fn test_gives_up_when_everything_failed() {
+        let negotiated = vec![config("h264_nvenc"), config("libx264")];
+        let mut fallback = EncoderFallback::new(negotiated, &config("h264_nvenc"));
+
+        assert!(matches!(
+            fail_until_recovery(&mut fallback),
+            EncodeRecovery::FallBack(_)
+        ));
+        fallback.on_fell_back(&config("libx264"));
+
+        assert!(matches!(
+            fail_until_recovery(&mut fallback),
+            EncodeRecovery::GiveUp
+        ));
+    }
+}
@@ -0,0 +1,60 @@
+use thiserror::Error;
+
+use crate::{
+    client::TransportError,
+    core::GoodbyeReason,
+    host::{EncoderError, ScreenError},
+};
+
+/// What kind of failure an error is, so it can be told apart without reading its
+/// message, like by the daemon API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ErrorKind {
+    #[default]
+    Unknown,
+    /// The thing asked for doesn't exist, like an unknown device
+    NotFound,
+    /// Not allowed to, like without access to the virtual screen devices
+    PermissionDenied,
+    /// Exists, but can't be used right now
+    Unavailable,
+    /// The two sides can't agree, like on a codec
+    Unsupported,
+    Timeout,
+    /// The connection to the display host is gone
+    Disconnected,
+    Cancelled,
+    /// Something went wrong on our side
+    Internal,
+}
+
+/// Why a session with a display host failed.
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("Transport error: {0}")]
+    Transport(#[from] TransportError),
+    #[error("Virtual screen error: {0}")]
+    Screen(#[from] ScreenError),
+    #[error("Encoder error: {0}")]
+    Encoder(#[from] EncoderError),
+    /// None of the encodings we can offer are any good to the display host
+    #[error("No codec in common with the display host")]
+    NoCommonCodec,
+    #[error("Lost the connection to the display host")]
+    ConnectionLost,
+    #[error("The session was cancelled ({0})")]
+    Cancelled(GoodbyeReason),
+}
+
+impl SessionError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SessionError::Transport(e) => e.kind(),
+            SessionError::Screen(e) => e.kind(),
+            SessionError::Encoder(e) => e.kind(),
+            SessionError::NoCommonCodec => ErrorKind::Unsupported,
+            SessionError::ConnectionLost => ErrorKind::Disconnected,
+            SessionError::Cancelled(_) => ErrorKind::Cancelled,
+        }
+    }
+}
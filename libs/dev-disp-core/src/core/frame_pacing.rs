@@ -0,0 +1,297 @@
+use std::time::{Duration, Instant};
+
+use crate::host::DamageRect;
+
+/// How long the screen may stay still before the last frame is sent again as a
+/// keyframe, so a display host that missed something catches up on its own.
+pub const STATIC_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What to do with a frame the screen made ready.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pacing {
+    /// Send it now, with the damage of every frame held back before it
+    Send(Option<Vec<DamageRect>>),
+    /// Hold it back for this long. Frames made ready in the meantime are sent
+    /// together with it.
+    Wait(Duration),
+}
+
+/// Keeps a session to its frame rate, and keeps it from sending the same frame
+/// over and over.
+#[derive(Debug)]
+pub struct FramePacer {
+    min_interval: Duration,
+    last_sent_at: Option<Instant>,
+    last_hash: Option<u64>,
+    /// Damage of the frames held back so far. `Some(None)` means a held back
+    /// frame changed in unknown places.
+    pending: Option<Option<Vec<DamageRect>>>,
+}
+
+impl FramePacer {
+    pub fn new(max_fps: u32) -> Self {
+        Self {
+            min_interval: frame_interval(max_fps),
+            last_sent_at: None,
+            last_hash: None,
+            pending: None,
+        }
+    }
+
+    pub fn set_max_fps(&mut self, max_fps: u32) {
+        self.min_interval = frame_interval(max_fps);
+    }
+
+    /// Forgets the frames sent so far, for when the display host needs to start
+    /// from scratch.
+    pub fn reset(&mut self) {
+        self.last_hash = None;
+        self.pending = None;
+    }
+
+    /// Decides whether a frame with the given damage may be sent now.
+    pub fn pace(&mut self, damage: Option<Vec<DamageRect>>, now: Instant) -> Pacing {
+        let damage = merge_damage(self.pending.take(), damage);
+        let next_slot = self.last_sent_at.map(|sent_at| sent_at + self.min_interval);
+        match next_slot {
+            Some(next_slot) if next_slot > now => {
+                self.pending = Some(damage);
+                Pacing::Wait(next_slot - now)
+            }
+            _ => Pacing::Send(damage),
+        }
+    }
+
+    /// Takes the damage of the frames held back, once their slot came up. `None`
+    /// if nothing was held back.
+    pub fn take_pending(&mut self) -> Option<Option<Vec<DamageRect>>> {
+        self.pending.take()
+    }
+
+    /// Whether a frame with this content hash was the last one sent.
+    pub fn is_duplicate(&self, hash: u64) -> bool {
+        self.last_hash == Some(hash)
+    }
+
+    pub fn on_sent(&mut self, hash: u64, now: Instant) {
+        self.last_hash = Some(hash);
+        self.last_sent_at = Some(now);
+    }
+
+    /// Whether nothing was sent for long enough to send the last frame again.
+    pub fn refresh_due(&self, now: Instant) -> bool {
+        self.last_sent_at
+            .is_some_and(|sent_at| now.duration_since(sent_at) >= STATIC_REFRESH_INTERVAL)
+    }
+}
+
+fn frame_interval(max_fps: u32) -> Duration {
+    Duration::from_secs(1) / max_fps.max(1)
+}
+
+fn merge_damage(
+    pending: Option<Option<Vec<DamageRect>>>,
+    damage: Option<Vec<DamageRect>>,
+) -> Option<Vec<DamageRect>> {
+    match (pending, damage) {
+        (None, damage) => damage,
+        (Some(Some(mut pending)), Some(damage)) => {
+            pending.extend(damage);
+            Some(pending)
+        }
+        _ => None,
+    }
+}
+
+/// Hashes a frame's content to spot frames that didn't change. Not meant to
+/// resist collisions, only to be cheap enough to run on every frame.
+pub fn frame_hash(data: &[u8]) -> u64 {
+    const MULTIPLIER: u64 = 0x517c_c1b7_2722_0a95;
+    let mix = |hash: u64, word: u64| (hash.rotate_left(5) ^ word).wrapping_mul(MULTIPLIER);
+
+    let mut chunks = data.chunks_exact(8);
+    let mut hash = data.len() as u64;
+    for chunk in &mut chunks {
+        let word = u64::from_le_bytes(chunk.try_into().expect("Chunks are 8 bytes"));
+        hash = mix(hash, word);
+    }
+    for &byte in chunks.remainder() {
+        hash = mix(hash, byte as u64);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::host::DamageRect;
+
+    use super::{FramePacer, Pacing, STATIC_REFRESH_INTERVAL, frame_hash};
+
+    /// Plays frames made ready `gap` apart through the pacer like the screen loop
+    /// does. Frames that may go are sent unless they didn't change, held back ones
+    /// once their slot comes up. Returns when each sent frame was sent.
+    fn play(
+        pacer: &mut FramePacer,
+        frames: &[Vec<u8>],
+        start: Instant,
+        gap: Duration,
+    ) -> Vec<(Instant, Vec<u8>)> {
+        let mut sent = Vec::new();
+        let mut due_at: Option<Instant> = None;
+        let mut latest: Option<&[u8]> = None;
+        for (index, frame) in frames.iter().enumerate() {
+            let now = start + gap * index as u32;
+            if let Some(due) = due_at.filter(|due| *due <= now) {
+                due_at = None;
+                if let (Some(_), Some(held_back)) = (pacer.take_pending(), latest) {
+                    send(pacer, held_back, due, &mut sent);
+                }
+            }
+            latest = Some(frame);
+            match pacer.pace(None, now) {
+                Pacing::Send(_) => send(pacer, frame, now, &mut sent),
+                Pacing::Wait(wait) => {
+                    due_at.get_or_insert(now + wait);
+                }
+            }
+        }
+        sent
+    }
+
+    fn send(
+        pacer: &mut FramePacer,
+        frame: &[u8],
+        now: Instant,
+        sent: &mut Vec<(Instant, Vec<u8>)>,
+    ) {
+        let hash = frame_hash(frame);
+        if pacer.is_duplicate(hash) {
+            return;
+        }
+        pacer.on_sent(hash, now);
+        sent.push((now, frame.to_vec()));
+    }
+
+    #[test]
+    fn test_pacing_caps_fps() {
+        // 120 distinct frames over a second, at 30 fps
+        let frames: Vec<_> = (0..120u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        let mut pacer = FramePacer::new(30);
+        let sent = play(
+            &mut pacer,
+            &frames,
+            Instant::now(),
+            Duration::from_secs(1) / 120,
+        );
+        assert_eq!(sent.len(), 30);
+        for pair in sent.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= Duration::from_secs(1) / 30);
+        }
+    }
+
+    #[test]
+    fn test_held_back_frame_goes_out_in_its_slot() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(10);
+        let frames = [vec![1; 8], vec![2; 8], vec![3; 8]];
+        let sent = play(&mut pacer, &frames, start, Duration::from_millis(60));
+        // The second frame waited for the slot at 100ms, the third for the one after
+        let sent_at: Vec<_> = sent.iter().map(|(at, _)| *at - start).collect();
+        assert_eq!(sent_at, [Duration::ZERO, Duration::from_millis(100)]);
+        assert_eq!(sent[1].1, vec![2; 8]);
+        assert_eq!(pacer.take_pending(), Some(None));
+    }
+
+    #[test]
+    fn test_duplicate_frames_are_skipped() {
+        let frames = [
+            vec![1; 64],
+            vec![1; 64],
+            vec![2; 64],
+            vec![2; 64],
+            vec![1; 64],
+        ];
+        let mut pacer = FramePacer::new(60);
+        let sent = play(
+            &mut pacer,
+            &frames,
+            Instant::now(),
+            Duration::from_millis(100),
+        );
+        let sent: Vec<_> = sent.into_iter().map(|(_, frame)| frame).collect();
+        assert_eq!(sent, [vec![1; 64], vec![2; 64], vec![1; 64]]);
+    }
+
+    #[test]
+    fn test_reset_forgets_the_last_frame() {
+        let now = Instant::now();
+        let mut pacer = FramePacer::new(60);
+        pacer.on_sent(frame_hash(&[7; 64]), now);
+        assert!(pacer.is_duplicate(frame_hash(&[7; 64])));
+        pacer.reset();
+        assert!(!pacer.is_duplicate(frame_hash(&[7; 64])));
+    }
+
+    #[test]
+    fn test_lower_fps_holds_frames_back_longer() {
+        let now = Instant::now();
+        let mut pacer = FramePacer::new(50);
+        pacer.on_sent(0, now);
+        let soon = now + Duration::from_millis(20);
+        assert_eq!(pacer.pace(None, soon), Pacing::Send(None));
+
+        pacer.set_max_fps(10);
+        assert_eq!(
+            pacer.pace(None, soon),
+            Pacing::Wait(Duration::from_millis(80))
+        );
+    }
+
+    #[test]
+    fn test_static_screen_is_refreshed() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(60);
+        assert!(!pacer.refresh_due(start + STATIC_REFRESH_INTERVAL));
+
+        pacer.on_sent(frame_hash(&[7; 64]), start);
+        assert!(!pacer.refresh_due(start + STATIC_REFRESH_INTERVAL / 2));
+        let later = start + STATIC_REFRESH_INTERVAL;
+        assert!(pacer.refresh_due(later));
+
+        pacer.on_sent(frame_hash(&[7; 64]), later);
+        assert!(!pacer.refresh_due(later + Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_held_back_damage_is_merged() {
+        let now = Instant::now();
+        let mut pacer = FramePacer::new(10);
+        let rect = |x| DamageRect::new(x, 0, 1, 1);
+        assert_eq!(
+            pacer.pace(Some(vec![rect(0)]), now),
+            Pacing::Send(Some(vec![rect(0)]))
+        );
+        pacer.on_sent(0, now);
+
+        let soon = now + Duration::from_millis(10);
+        assert!(matches!(
+            pacer.pace(Some(vec![rect(1)]), soon),
+            Pacing::Wait(_)
+        ));
+        assert!(matches!(
+            pacer.pace(Some(vec![rect(2)]), soon),
+            Pacing::Wait(_)
+        ));
+        assert_eq!(pacer.take_pending(), Some(Some(vec![rect(1), rect(2)])));
+
+        // Damage in unknown places means the whole frame
+        assert!(matches!(pacer.pace(None, soon), Pacing::Wait(_)));
+        assert!(matches!(
+            pacer.pace(Some(vec![rect(3)]), soon),
+            Pacing::Wait(_)
+        ));
+        assert_eq!(pacer.take_pending(), Some(None));
+    }
+}
@@ -0,0 +1,129 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    task::{Poll, Waker},
+};
+
+use futures::future;
+
+/// A bounded queue between two stages of a session. When it's full, the oldest
+/// item is dropped to make room, so a slow stage only ever gets recent items.
+///
+/// Meant for stages running on the same thread, like the futures of a session.
+#[derive(Debug)]
+pub struct FrameQueue<T> {
+    capacity: usize,
+    state: RefCell<FrameQueueState<T>>,
+}
+
+#[derive(Debug)]
+struct FrameQueueState<T> {
+    items: VecDeque<T>,
+    /// The stage waiting on `pop`, if any
+    waker: Option<Waker>,
+}
+
+impl<T> FrameQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            capacity,
+            state: RefCell::new(FrameQueueState {
+                items: VecDeque::with_capacity(capacity),
+                waker: None,
+            }),
+        }
+    }
+
+    /// Adds an item, dropping the oldest one if the queue is full.
+    pub fn push(&self, item: T) {
+        let mut state = self.state.borrow_mut();
+        if state.items.len() >= self.capacity {
+            state.items.pop_front();
+        }
+        state.items.push_back(item);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.borrow().items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.capacity
+    }
+
+    /// Drops every waiting item, returning how many there were.
+    pub fn clear(&self) -> usize {
+        let mut state = self.state.borrow_mut();
+        let cleared = state.items.len();
+        state.items.clear();
+        cleared
+    }
+
+    /// Takes every waiting item, oldest first.
+    pub fn drain(&self) -> Vec<T> {
+        self.state.borrow_mut().items.drain(..).collect()
+    }
+
+    /// Waits for the oldest item.
+    pub async fn pop(&self) -> T {
+        future::poll_fn(|cx| {
+            let mut state = self.state.borrow_mut();
+            match state.items.pop_front() {
+                Some(item) => Poll::Ready(item),
+                None => {
+                    state.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use futures::FutureExt;
+
+    use super::FrameQueue;
+
+    #[test]
+    fn test_full_queue_drops_oldest() {
+        let queue = FrameQueue::new(2);
+        queue.push(1);
+        queue.push(2);
+        assert!(queue.is_full());
+        queue.push(3);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().now_or_never(), Some(2));
+        assert_eq!(queue.pop().now_or_never(), Some(3));
+        assert_eq!(queue.pop().now_or_never(), None);
+    }
+
+    #[test]
+    fn test_clear_counts_dropped_items() {
+        let queue = FrameQueue::new(4);
+        queue.push("a");
+        queue.push("b");
+        assert_eq!(queue.clear(), 2);
+        assert!(queue.is_empty());
+        queue.push("c");
+        assert_eq!(queue.pop().now_or_never(), Some("c"));
+    }
+
+    #[test]
+    fn test_drain_takes_items_oldest_first() {
+        let queue = FrameQueue::new(4);
+        queue.push(1);
+        queue.push(2);
+        assert_eq!(queue.drain(), vec![1, 2]);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// A position on the client's view of the display, normalized so that (0.0, 0.0)
+/// is the top-left corner of the frame and (1.0, 1.0) is the bottom-right.
+///
+/// Normalizing keeps clients from having to know the resolution the host encodes at.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct NormalizedPosition {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl NormalizedPosition {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    /// Clamps both coordinates into the 0.0 to 1.0 range.
+    pub fn clamped(self) -> Self {
+        Self {
+            x: self.x.clamp(0.0, 1.0),
+            y: self.y.clamp(0.0, 1.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PointerButton {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ButtonState {
+    Pressed,
+    Released,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TouchPhase {
+    /// The contact touched down
+    Start,
+    /// The contact moved
+    Move,
+    /// The contact lifted
+    End,
+    /// The contact was taken away by the client, such as by a system gesture
+    Cancel,
+}
+
+/// A single contact of a (multi-)touch screen.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TouchEvent {
+    /// Identifies the contact for as long as it is down. Ids may be reused once a
+    /// contact has ended.
+    pub id: u32,
+    pub phase: TouchPhase,
+    pub position: NormalizedPosition,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PenTool {
+    Pen,
+    Eraser,
+}
+
+/// A stylus event. A pen that is in range but not touching reports a pressure of 0.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PenEvent {
+    pub tool: PenTool,
+    pub phase: TouchPhase,
+    pub position: NormalizedPosition,
+    /// From 0.0 (hovering) to 1.0 (full pressure)
+    pub pressure: f32,
+    /// Tilt along the X axis in degrees, from -90.0 to 90.0
+    pub tilt_x: f32,
+    /// Tilt along the Y axis in degrees, from -90.0 to 90.0
+    pub tilt_y: f32,
+    /// Whether the barrel button is held
+    pub barrel_button: bool,
+}
+
+/// Scroll amounts, in wheel notches. Fractional values come from smooth scrolling
+/// devices such as touchpads.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ScrollEvent {
+    /// Positive scrolls right
+    pub delta_x: f32,
+    /// Positive scrolls down
+    pub delta_y: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    /// The physical key, named like the web's `KeyboardEvent.code`, such as "KeyA",
+    /// "Enter" or "ShiftLeft". Naming the physical key leaves the keyboard layout up
+    /// to the host.
+    pub code: String,
+    pub state: ButtonState,
+}
+
+/// An input event coming from a client, to be injected on the host.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum InputEvent {
+    /// A mouse-like pointer moved
+    PointerMove(NormalizedPosition),
+    /// A mouse-like pointer button was pressed or released
+    PointerButton {
+        position: NormalizedPosition,
+        button: PointerButton,
+        state: ButtonState,
+    },
+    Touch(TouchEvent),
+    Pen(PenEvent),
+    Scroll(ScrollEvent),
+    Key(KeyEvent),
+}
+
+impl Display for InputEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputEvent::PointerMove(position) => {
+                write!(f, "PointerMove ({:.3}, {:.3})", position.x, position.y)
+            }
+            InputEvent::PointerButton { button, state, .. } => {
+                write!(f, "PointerButton ({:?} {:?})", button, state)
+            }
+            InputEvent::Touch(touch) => {
+                write!(f, "Touch (#{} {:?})", touch.id, touch.phase)
+            }
+            InputEvent::Pen(pen) => {
+                write!(f, "Pen ({:?} {:?}, {:.2})", pen.tool, pen.phase, pen.pressure)
+            }
+            InputEvent::Scroll(scroll) => {
+                write!(f, "Scroll ({:.2}, {:.2})", scroll.delta_x, scroll.delta_y)
+            }
+            InputEvent::Key(key) => write!(f, "Key ({} {:?})", key.code, key.state),
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use std::{collections::VecDeque, fmt::Display, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// How many clock sync samples are kept. The one with the shortest round trip
+/// is trusted most, since it had the least room for asymmetric delays.
+const CLOCK_SYNC_SAMPLES: usize = 8;
+
+/// How many sent frames are remembered while waiting for the client to say when
+/// it presented them.
+const PENDING_FRAMES: usize = 256;
+
+/// How many presented frames the percentiles are computed over.
+const LATENCY_WINDOW: usize = 300;
+
+/// Sent by the server to measure the offset between its clock and the client's.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockPing {
+    pub id: u32,
+    /// When the ping was sent, in microseconds since the Unix epoch on the server
+    pub server_time_us: u64,
+}
+
+/// The client's answer to a `ClockPing`, sent right away.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockPong {
+    pub id: u32,
+    /// Copied from the ping
+    pub server_time_us: u64,
+    /// When the client got the ping, in microseconds on the client's clock
+    pub client_time_us: u64,
+}
+
+/// When the client received, decoded and presented a frame, all in microseconds
+/// on the client's clock.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FramePresented {
+    pub sequence: u64,
+    pub received_at_us: u64,
+    pub decoded_at_us: u64,
+    pub presented_at_us: u64,
+}
+
+/// Estimates the offset between the server's clock and the client's from
+/// ping/pong round trips.
+#[derive(Debug, Clone, Default)]
+pub struct ClockSync {
+    /// Round trip and offset (client minus server) of the latest pongs, in
+    /// microseconds
+    samples: VecDeque<(u64, i64)>,
+}
+
+impl ClockSync {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes in a pong that arrived at `now_us` on the server's clock.
+    pub fn on_pong(&mut self, pong: &ClockPong, now_us: u64) {
+        let Some(rtt) = now_us.checked_sub(pong.server_time_us) else {
+            return;
+        };
+        // Assume the ping took half the round trip to get there
+        let offset = pong.client_time_us as i64 - (pong.server_time_us + rtt / 2) as i64;
+        if self.samples.len() >= CLOCK_SYNC_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((rtt, offset));
+    }
+
+    fn best_sample(&self) -> Option<(u64, i64)> {
+        self.samples.iter().min_by_key(|(rtt, _)| *rtt).copied()
+    }
+
+    /// How far the client's clock is ahead of the server's, in microseconds.
+    pub fn offset_us(&self) -> Option<i64> {
+        self.best_sample().map(|(_, offset)| offset)
+    }
+
+    /// The shortest recent round trip.
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.best_sample()
+            .map(|(rtt, _)| Duration::from_micros(rtt))
+    }
+
+    /// Converts a time on the client's clock to the server's.
+    pub fn to_server_time(&self, client_time_us: u64) -> Option<u64> {
+        self.offset_us()
+            .map(|offset| (client_time_us as i64 - offset).max(0) as u64)
+    }
+}
+
+/// What the server did with a frame before it went out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameTimings {
+    /// When the frame was ready, in microseconds since the Unix epoch
+    pub ready_at_us: u64,
+    /// When the frame was done sending, in microseconds since the Unix epoch
+    pub sent_at_us: u64,
+    pub capture_wait: Duration,
+    pub copy: Duration,
+    pub scale: Duration,
+    pub encode: Duration,
+    /// From the frame being encoded to us starting to send it
+    pub queue: Duration,
+    pub send: Duration,
+}
+
+/// The steps a frame goes through, from the screen to the client's display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LatencyStage {
+    /// From the frame being ready to us starting on it
+    CaptureWait,
+    Copy,
+    Scale,
+    Encode,
+    /// From the frame being encoded to us starting to send it
+    Queue,
+    Send,
+    /// From the frame leaving us to it arriving at the client
+    Network,
+    Decode,
+    /// From the frame being decoded to it being on the client's display
+    Present,
+    /// The whole way, glass to glass
+    Total,
+}
+
+impl LatencyStage {
+    pub const ALL: [LatencyStage; 10] = [
+        LatencyStage::CaptureWait,
+        LatencyStage::Copy,
+        LatencyStage::Scale,
+        LatencyStage::Encode,
+        LatencyStage::Queue,
+        LatencyStage::Send,
+        LatencyStage::Network,
+        LatencyStage::Decode,
+        LatencyStage::Present,
+        LatencyStage::Total,
+    ];
+}
+
+impl Display for LatencyStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LatencyStage::CaptureWait => "capture wait",
+            LatencyStage::Copy => "copy",
+            LatencyStage::Scale => "scale",
+            LatencyStage::Encode => "encode",
+            LatencyStage::Queue => "queue",
+            LatencyStage::Send => "send",
+            LatencyStage::Network => "network",
+            LatencyStage::Decode => "decode",
+            LatencyStage::Present => "present",
+            LatencyStage::Total => "total",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How long each step took for one frame. The stages that cross between the
+/// server's and the client's clocks are only known once the clocks are synced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLatency {
+    pub sequence: u64,
+    pub capture_wait: Duration,
+    pub copy: Duration,
+    pub scale: Duration,
+    pub encode: Duration,
+    pub queue: Duration,
+    pub send: Duration,
+    pub network: Option<Duration>,
+    pub decode: Duration,
+    pub present: Duration,
+    pub total: Option<Duration>,
+}
+
+impl FrameLatency {
+    pub fn stage(&self, stage: LatencyStage) -> Option<Duration> {
+        match stage {
+            LatencyStage::CaptureWait => Some(self.capture_wait),
+            LatencyStage::Copy => Some(self.copy),
+            LatencyStage::Scale => Some(self.scale),
+            LatencyStage::Encode => Some(self.encode),
+            LatencyStage::Queue => Some(self.queue),
+            LatencyStage::Send => Some(self.send),
+            LatencyStage::Network => self.network,
+            LatencyStage::Decode => Some(self.decode),
+            LatencyStage::Present => Some(self.present),
+            LatencyStage::Total => self.total,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyPercentiles {
+    /// Percentiles of the given samples, if there are any.
+    pub fn of(mut values: Vec<Duration>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable();
+        let at = |p: f64| values[((values.len() - 1) as f64 * p).round() as usize];
+        Some(Self {
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+        })
+    }
+}
+
+impl Display for LatencyPercentiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "p50 {:.1}ms, p90 {:.1}ms, p99 {:.1}ms",
+            self.p50.as_secs_f64() * 1000.0,
+            self.p90.as_secs_f64() * 1000.0,
+            self.p99.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Matches the frames we sent with when the client presented them, and keeps
+/// rolling latency percentiles for a session.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyTracker {
+    clock: ClockSync,
+    pending: VecDeque<(u64, FrameTimings)>,
+    window: VecDeque<FrameLatency>,
+}
+
+impl LatencyTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clock(&self) -> &ClockSync {
+        &self.clock
+    }
+
+    /// Takes in a pong that arrived at `now_us` on the server's clock.
+    pub fn on_pong(&mut self, pong: &ClockPong, now_us: u64) {
+        self.clock.on_pong(pong, now_us);
+    }
+
+    /// Remembers what happened to a frame on our side.
+    pub fn on_frame_sent(&mut self, sequence: u64, timings: FrameTimings) {
+        if self.pending.len() >= PENDING_FRAMES {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((sequence, timings));
+    }
+
+    /// Takes in the client's account of a frame, returning its full latency if we
+    /// still remember sending it.
+    pub fn on_frame_presented(&mut self, presented: &FramePresented) -> Option<FrameLatency> {
+        let index = self
+            .pending
+            .iter()
+            .position(|(sequence, _)| *sequence == presented.sequence)?;
+        let (_, timings) = self.pending.remove(index)?;
+        // Frames before this one that are still pending were never presented
+        self.pending.drain(..index);
+
+        let client_delta = |from: u64, to: u64| Duration::from_micros(to.saturating_sub(from));
+        let received_at = self.clock.to_server_time(presented.received_at_us);
+        let presented_at = self.clock.to_server_time(presented.presented_at_us);
+        let latency = FrameLatency {
+            sequence: presented.sequence,
+            capture_wait: timings.capture_wait,
+            copy: timings.copy,
+            scale: timings.scale,
+            encode: timings.encode,
+            queue: timings.queue,
+            send: timings.send,
+            network: received_at.map(|at| client_delta(timings.sent_at_us, at)),
+            decode: client_delta(presented.received_at_us, presented.decoded_at_us),
+            present: client_delta(presented.decoded_at_us, presented.presented_at_us),
+            total: presented_at.map(|at| client_delta(timings.ready_at_us, at)),
+        };
+
+        if self.window.len() >= LATENCY_WINDOW {
+            self.window.pop_front();
+        }
+        self.window.push_back(latency);
+        Some(latency)
+    }
+
+    /// Percentiles of a stage over the latest presented frames.
+    pub fn percentiles(&self, stage: LatencyStage) -> Option<LatencyPercentiles> {
+        LatencyPercentiles::of(
+            self.window
+                .iter()
+                .filter_map(|latency| latency.stage(stage))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{ClockPong, ClockSync, FramePresented, FrameTimings, LatencyStage, LatencyTracker};
+
+    #[test]
+    fn test_clock_sync_prefers_shortest_round_trip() {
+        let mut clock = ClockSync::new();
+        // The client's clock is 1s ahead. The first pong was held up on the way
+        // back, which throws its estimate off.
+        clock.on_pong(
+            &ClockPong {
+                id: 0,
+                server_time_us: 0,
+                client_time_us: 1_005_000,
+            },
+            50_000,
+        );
+        clock.on_pong(
+            &ClockPong {
+                id: 1,
+                server_time_us: 100_000,
+                client_time_us: 1_105_000,
+            },
+            110_000,
+        );
+        assert_eq!(clock.offset_us(), Some(1_000_000));
+        assert_eq!(clock.round_trip(), Some(Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn test_frame_latency_breakdown() {
+        let mut tracker = LatencyTracker::new();
+        tracker.on_pong(
+            &ClockPong {
+                id: 0,
+                server_time_us: 0,
+                client_time_us: 1_000_000,
+            },
+            0,
+        );
+        tracker.on_frame_sent(
+            7,
+            FrameTimings {
+                ready_at_us: 10_000,
+                sent_at_us: 20_000,
+                encode: Duration::from_millis(8),
+                ..Default::default()
+            },
+        );
+
+        let latency = tracker
+            .on_frame_presented(&FramePresented {
+                sequence: 7,
+                received_at_us: 1_025_000,
+                decoded_at_us: 1_030_000,
+                presented_at_us: 1_040_000,
+            })
+            .unwrap();
+        assert_eq!(latency.network, Some(Duration::from_millis(5)));
+        assert_eq!(latency.decode, Duration::from_millis(5));
+        assert_eq!(latency.present, Duration::from_millis(10));
+        assert_eq!(latency.total, Some(Duration::from_millis(30)));
+
+        let total = tracker.percentiles(LatencyStage::Total).unwrap();
+        assert_eq!(total.p50, Duration::from_millis(30));
+    }
+}
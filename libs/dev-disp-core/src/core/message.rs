@@ -1,6 +1,15 @@
 use std::fmt::Display;
 
-use crate::host::{DisplayParameters, EncoderPossibleConfiguration};
+use crate::{
+    core::{
+        ClockPing, ClockPong, FramePresented, GoodbyeReason, InputEvent, ReceiverReport,
+        SessionToken,
+    },
+    host::{
+        ClipboardMessage, CursorUpdate, DisplayParameters, EncodedRegion,
+        EncoderPossibleConfiguration, FrameHeader,
+    },
+};
 use serde::{Deserialize, Serialize};
 
 /// A message coming from the data source, aka where the screen
@@ -17,9 +26,43 @@ pub enum DevDispMessageFromSource<'a> {
 
     /// A command do put the given screen data.
     ///
-    /// TODO: Allow region updates, or other metadata about the update
     /// TODO: Encode compression type! Or bundle in a library!
-    PutScreenData(&'a [u8]),
+    PutScreenData {
+        header: FrameHeader,
+        data: &'a [u8],
+    },
+
+    /// A command to put only the changed regions of the screen, leaving the rest
+    /// of the previously painted screen as it was.
+    ///
+    /// Only sent for screens that report damage, through encoders that encode
+    /// regions on their own, which so far means the synthetic screen and the
+    /// `RawEncoder`. Sessions on EVDI screens and FFmpeg encoders send whole frames.
+    PutScreenRegions {
+        header: FrameHeader,
+        #[serde(borrow)]
+        regions: Vec<EncodedRegion<'a>>,
+    },
+
+    /// A change to the cursor, for clients that draw it on top of the screen
+    /// themselves.
+    UpdateCursor(CursorUpdate),
+
+    /// A clock sync ping, to be answered with a `ClockPong` right away
+    ClockPing(ClockPing),
+
+    /// Clipboard offers, requests and content from the host
+    Clipboard(ClipboardMessage),
+
+    /// Tells the client the connection is still alive
+    Heartbeat,
+
+    /// The host is closing the session, and why. Nothing follows it.
+    Goodbye(GoodbyeReason),
+
+    /// The token to resume the session with, should the connection be lost. Only
+    /// sent to clients that can resume sessions.
+    SessionToken(SessionToken),
 }
 
 impl Display for DevDispMessageFromSource<'_> {
@@ -35,12 +78,34 @@ impl Display for DevDispMessageFromSource<'_> {
                     configs.len()
                 )
             }
-            DevDispMessageFromSource::PutScreenData(data) => {
-                write!(f, "PutScreenData ({} bytes)", data.len())
+            DevDispMessageFromSource::PutScreenData { header, data } => {
+                write!(
+                    f,
+                    "PutScreenData (#{}, {} bytes)",
+                    header.sequence,
+                    data.len()
+                )
+            }
+            DevDispMessageFromSource::PutScreenRegions { header, regions } => {
+                write!(
+                    f,
+                    "PutScreenRegions (#{}, {} regions, {} bytes)",
+                    header.sequence,
+                    regions.len(),
+                    regions.iter().map(|r| r.data.len()).sum::<usize>()
+                )
             }
             DevDispMessageFromSource::SetEncoding(config) => {
                 write!(f, "SetEncoding ({})", config.encoder_name)
             }
+            DevDispMessageFromSource::UpdateCursor(update) => {
+                write!(f, "UpdateCursor ({})", update)
+            }
+            DevDispMessageFromSource::ClockPing(ping) => write!(f, "ClockPing (#{})", ping.id),
+            DevDispMessageFromSource::Clipboard(msg) => write!(f, "Clipboard ({})", msg),
+            DevDispMessageFromSource::Heartbeat => write!(f, "Heartbeat"),
+            DevDispMessageFromSource::Goodbye(reason) => write!(f, "Goodbye ({})", reason),
+            DevDispMessageFromSource::SessionToken(token) => write!(f, "SessionToken ({})", token),
         }
     }
 }
@@ -55,6 +120,24 @@ pub enum DevDispMessageFromClient {
     SetEncodingResponse(bool),
     /// Update with the current display parameters of the client device
     DisplayParametersUpdate(DisplayParameters),
+    /// An input event to inject on the host, such as a touch or a key press
+    Input(InputEvent),
+    /// The client lost track of the stream (a decoder error, a dropped frame) and
+    /// needs a keyframe to recover
+    RequestKeyframe,
+    /// How well the client has been keeping up with the stream lately, used to
+    /// adapt the bitrate and frame rate
+    ReceiverReport(ReceiverReport),
+    /// The answer to a `ClockPing`
+    ClockPong(ClockPong),
+    /// When a frame made it onto the client's display
+    FramePresented(FramePresented),
+    /// Clipboard offers, requests and content from the client
+    Clipboard(ClipboardMessage),
+    /// Tells the host the connection is still alive
+    Heartbeat,
+    /// The client is closing the session, and why
+    Goodbye(GoodbyeReason),
 }
 
 impl Display for DevDispMessageFromClient {
@@ -73,6 +156,18 @@ impl Display for DevDispMessageFromClient {
             DevDispMessageFromClient::SetEncodingResponse(success) => {
                 write!(f, "SetEncodingResponse (success: {})", success)
             }
+            DevDispMessageFromClient::Input(event) => write!(f, "Input ({})", event),
+            DevDispMessageFromClient::RequestKeyframe => write!(f, "RequestKeyframe"),
+            DevDispMessageFromClient::ReceiverReport(report) => {
+                write!(f, "ReceiverReport ({})", report)
+            }
+            DevDispMessageFromClient::ClockPong(pong) => write!(f, "ClockPong (#{})", pong.id),
+            DevDispMessageFromClient::FramePresented(presented) => {
+                write!(f, "FramePresented (#{})", presented.sequence)
+            }
+            DevDispMessageFromClient::Clipboard(msg) => write!(f, "Clipboard ({})", msg),
+            DevDispMessageFromClient::Heartbeat => write!(f, "Heartbeat"),
+            DevDispMessageFromClient::Goodbye(reason) => write!(f, "Goodbye ({})", reason),
         }
     }
 }
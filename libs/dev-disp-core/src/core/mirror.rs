@@ -0,0 +1,295 @@
+use std::{pin::pin, rc::Rc};
+
+use futures::{
+    StreamExt,
+    channel::{mpsc, oneshot},
+    future::{self, Either},
+    lock::Mutex,
+    stream::AbortHandle,
+};
+use log::debug;
+
+use crate::{
+    client::{DisplayHost, SomeScreenTransport, TransportError},
+    core::{
+        ControlMessage, FrameQueue, GoodbyeReason, SessionError, is_same_encoder,
+        send_control_message,
+    },
+    host::{
+        EncodedFrame, Encoder, EncoderContentParameters, EncoderError,
+        EncoderPossibleConfiguration, EncoderProvider, FrameHeader,
+    },
+    util::PinnedStream,
+};
+
+/// How many encoded frames may wait to be sent to a mirror. Past this, the mirror
+/// has fallen behind and the waiting frames are dropped for a fresh keyframe.
+pub(crate) const MIRROR_QUEUE_DEPTH: usize = 2;
+
+/// A display host joining a running session, to be shown the same screen as the
+/// session's own display host. Mirrors only watch: they don't send input, share the
+/// clipboard or have a say in the screen's parameters.
+pub struct MirrorJoin {
+    pub host: DisplayHost<SomeScreenTransport>,
+    /// Has the mirror leave when it yields, telling it why
+    pub cancel: PinnedStream<'static, GoodbyeReason>,
+    /// Told once the mirror is shown the screen, or why it couldn't be
+    pub started: oneshot::Sender<Result<(), SessionError>>,
+    /// Told when the mirror left the session, after it started
+    pub left: oneshot::Sender<()>,
+}
+
+/// An encoded frame waiting to be sent to a mirror.
+pub(crate) struct MirrorFrame {
+    pub header: FrameHeader,
+    pub data: Rc<[u8]>,
+}
+
+/// What the screen loop keeps of a mirror, to reach it without waiting on the encode
+/// stage.
+pub(crate) struct MirrorControl {
+    pub id: u64,
+    pub name: String,
+    /// Control messages for the mirror's transmit stage
+    pub control: mpsc::UnboundedSender<ControlMessage>,
+    pub cursor_plane: bool,
+}
+
+/// A display host shown the screen of another one's session.
+pub(crate) struct Mirror<E> {
+    pub id: u64,
+    pub name: String,
+    pub host: Rc<Mutex<DisplayHost<SomeScreenTransport>>>,
+    pub queue: Rc<FrameQueue<MirrorFrame>>,
+    /// Whether the mirror draws the cursor from our updates. Otherwise it's drawn
+    /// into its frames, when the session's own display host draws it itself.
+    pub cursor_plane: bool,
+    /// The mirror's own encoder, when it can't take the frames of the session's
+    encoder: Option<E>,
+    sequence: u64,
+    config_generation: u32,
+    /// A mirror sharing the session's encoder can only start off a keyframe
+    awaiting_keyframe: bool,
+    left: oneshot::Sender<()>,
+    /// Stops the mirror's transmit stage, and the streams the session listens to it
+    /// with
+    aborts: Vec<AbortHandle>,
+}
+
+impl<E: Encoder> Mirror<E> {
+    /// Makes the mirror that `control` reaches.
+    pub fn new(
+        control: &MirrorControl,
+        host: Rc<Mutex<DisplayHost<SomeScreenTransport>>>,
+        queue: Rc<FrameQueue<MirrorFrame>>,
+        encoder: Option<E>,
+        left: oneshot::Sender<()>,
+        aborts: Vec<AbortHandle>,
+    ) -> Self {
+        Self {
+            id: control.id,
+            name: control.name.clone(),
+            host,
+            queue,
+            cursor_plane: control.cursor_plane,
+            awaiting_keyframe: encoder.is_none(),
+            encoder,
+            sequence: 0,
+            config_generation: 0,
+            left,
+            aborts,
+        }
+    }
+
+    pub fn shares_encoder(&self) -> bool {
+        self.encoder.is_none()
+    }
+
+    /// Follows the session to a new encoding, like after it renegotiated.
+    /// `session_forwards_cursor` is whether the session's own display host draws the
+    /// cursor itself.
+    pub async fn renegotiate<EP>(
+        &mut self,
+        session_encoder: &mut E,
+        session_encoding: &EncoderPossibleConfiguration,
+        encoder_provider: &EP,
+        parameters: EncoderContentParameters,
+        session_forwards_cursor: bool,
+    ) -> Result<(), SessionError>
+    where
+        EP: EncoderProvider<EncoderType = E>,
+    {
+        self.encoder = negotiate_mirror(
+            &mut *self.host.lock().await,
+            session_encoder,
+            session_encoding,
+            encoder_provider,
+            parameters,
+            self.cursor_plane || !session_forwards_cursor,
+        )
+        .await?;
+        self.config_generation += 1;
+        self.awaiting_keyframe = self.encoder.is_none();
+        // Frames of the old encoding are of no use to the mirror anymore
+        self.queue.clear();
+        Ok(())
+    }
+
+    /// Queues the frame the session just sent for the mirror. `shared` is the frame
+    /// as the session's encoder encoded it, if it was a whole one.
+    pub async fn push_frame(
+        &mut self,
+        data: &[u8],
+        captured_at_us: u64,
+        shared: Option<&MirrorFrame>,
+        session_encoder: &mut E,
+    ) -> Result<(), EncoderError> {
+        // The waiting frames are dropped for a keyframe, as the mirror never sees
+        // what they changed
+        let backed_up = self.queue.is_full();
+        if backed_up {
+            self.queue.clear();
+        }
+
+        let frame = match &mut self.encoder {
+            Some(encoder) => {
+                if backed_up {
+                    encoder.force_keyframe();
+                }
+                let header =
+                    FrameHeader::captured_at(self.sequence, self.config_generation, captured_at_us);
+                let frame = encoder.encode(data, header).await?;
+                MirrorFrame {
+                    header: frame.header,
+                    data: frame.data.into(),
+                }
+            }
+            None => {
+                if backed_up {
+                    self.request_keyframe(session_encoder);
+                }
+                // Regions only patch frames the mirror may have never seen
+                let Some(shared) = shared else {
+                    self.awaiting_keyframe = true;
+                    return Ok(());
+                };
+                if self.awaiting_keyframe && !shared.header.keyframe {
+                    return Ok(());
+                }
+                self.awaiting_keyframe = false;
+                MirrorFrame {
+                    header: FrameHeader {
+                        sequence: self.sequence,
+                        config_generation: self.config_generation,
+                        ..shared.header
+                    },
+                    data: shared.data.clone(),
+                }
+            }
+        };
+        self.queue.push(frame);
+        self.sequence += 1;
+        Ok(())
+    }
+
+    pub fn request_keyframe(&mut self, session_encoder: &mut E) {
+        match &mut self.encoder {
+            Some(encoder) => encoder.force_keyframe(),
+            None => {
+                self.awaiting_keyframe = true;
+                session_encoder.force_keyframe();
+            }
+        }
+    }
+
+    /// Stops listening to the mirror. Returns its display host, and who to tell that
+    /// it left.
+    pub fn finish(
+        self,
+    ) -> (
+        Rc<Mutex<DisplayHost<SomeScreenTransport>>>,
+        oneshot::Sender<()>,
+    ) {
+        for abort in &self.aborts {
+            abort.abort();
+        }
+        (self.host, self.left)
+    }
+}
+
+/// Picks how to encode for a mirror, and tells it. Returns the mirror's own encoder,
+/// or `None` if it shares the session's.
+///
+/// Sharing spares encoding every frame twice, so a mirror that can take the session's
+/// configuration gets it, even over configurations it prefers. Unless it `may_share`
+/// not, like when its frames have the cursor drawn in and the session's don't.
+pub(crate) async fn negotiate_mirror<E, EP>(
+    host: &mut DisplayHost<SomeScreenTransport>,
+    session_encoder: &mut E,
+    session_encoding: &EncoderPossibleConfiguration,
+    encoder_provider: &EP,
+    parameters: EncoderContentParameters,
+    may_share: bool,
+) -> Result<Option<E>, SessionError>
+where
+    E: Encoder,
+    EP: EncoderProvider<EncoderType = E>,
+{
+    let supported = session_encoder.get_supported_configurations(&parameters)?;
+    let preferred = host.get_preferred_encodings(supported).await?;
+    if preferred.is_empty() {
+        return Err(SessionError::NoCommonCodec);
+    }
+
+    if may_share
+        && preferred
+            .iter()
+            .any(|config| is_same_encoder(config, session_encoding))
+    {
+        host.set_encoding(session_encoding.clone()).await?;
+        return Ok(None);
+    }
+
+    let mut encoder = encoder_provider.create_encoder().await?;
+    let encoding = encoder.init(parameters, Some(preferred)).await?;
+    host.set_encoding(encoding).await?;
+    Ok(Some(encoder))
+}
+
+/// The transmit stage of a mirror. Sends control messages and frames as they're
+/// queued, control messages first, reporting the frames that failed to `failures`.
+pub(crate) async fn mirror_transmit_loop(
+    host: Rc<Mutex<DisplayHost<SomeScreenTransport>>>,
+    queue: Rc<FrameQueue<MirrorFrame>>,
+    mut control: mpsc::UnboundedReceiver<ControlMessage>,
+    failures: mpsc::UnboundedSender<TransportError>,
+) {
+    loop {
+        let frame = match future::select(control.next(), pin!(queue.pop())).await {
+            Either::Left((Some(message), _)) => {
+                let mut host = host.lock().await;
+                if let Err(e) = send_control_message(&mut host, &message).await {
+                    // Mirrors go without what they can't take
+                    debug!("Failed to send {message} to mirror {}: {}", *host, e);
+                }
+                continue;
+            }
+            // The session is done with the mirror
+            Either::Left((None, _)) => break,
+            Either::Right((frame, _)) => frame,
+        };
+        let result = host
+            .lock()
+            .await
+            .send_screen_data(EncodedFrame {
+                header: frame.header,
+                data: &frame.data,
+            })
+            .await;
+        if let Err(e) = result
+            && failures.unbounded_send(e).is_err()
+        {
+            break;
+        }
+    }
+}
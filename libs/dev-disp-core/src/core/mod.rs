@@ -1,7 +1,35 @@
+mod clipboard_sync;
 mod configuration_file;
 mod controller;
+mod encoder_fallback;
+mod error;
+mod frame_pacing;
+mod frame_queue;
+mod input;
+mod latency;
 mod message;
+mod mirror;
+mod protocol;
+mod rate_control;
+mod recorder;
+mod session;
+mod session_stats;
+mod stream_policy;
 
+pub use clipboard_sync::*;
 pub use configuration_file::*;
 pub use controller::*;
+pub use encoder_fallback::*;
+pub use error::*;
+pub use frame_pacing::*;
+pub use frame_queue::*;
+pub use input::*;
+pub use latency::*;
 pub use message::*;
+pub use mirror::*;
+pub use protocol::*;
+pub use rate_control::*;
+pub use recorder::*;
+pub use session::*;
+pub use session_stats::*;
+pub use stream_policy::*;
@@ -0,0 +1,285 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+pub type ProtocolVersion = u32;
+
+/// The newest protocol version this build speaks.
+///
+/// Bump this whenever a wire message changes shape, and raise
+/// `MIN_PROTOCOL_VERSION` once the older shape is no longer handled. Versions
+/// since the minimum only add messages, each behind a `ProtocolCapability`:
+///
+/// - v3: `Input` events from the client
+/// - v4: `RequestKeyframe` from the client
+/// - v5: `ClockPing`s from the host, and `ClockPong`s and `FramePresented` reports
+///   from the client
+/// - v6: `Clipboard` messages both ways
+/// - v7: `Heartbeat`s and `Goodbye`s both ways
+/// - v8: `SessionToken`s from the host, and resume token requests to the client
+/// - v9: `ReceiverReport`s from the client
+pub const PROTOCOL_VERSION: ProtocolVersion = 9;
+
+/// The oldest protocol version this build still speaks.
+pub const MIN_PROTOCOL_VERSION: ProtocolVersion = 2;
+
+/// An inclusive range of protocol versions.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolVersionRange {
+    pub min: ProtocolVersion,
+    pub max: ProtocolVersion,
+}
+
+impl ProtocolVersionRange {
+    /// The range of versions this build speaks.
+    pub const fn current() -> Self {
+        Self {
+            min: MIN_PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        }
+    }
+
+    /// Finds the highest version both ranges contain, if any.
+    pub fn highest_common(&self, other: &Self) -> Option<ProtocolVersion> {
+        let highest = self.max.min(other.max);
+        let lowest = self.min.max(other.min);
+        (highest >= lowest).then_some(highest)
+    }
+}
+
+impl Display for ProtocolVersionRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.min == self.max {
+            write!(f, "v{}", self.min)
+        } else {
+            write!(f, "v{}-v{}", self.min, self.max)
+        }
+    }
+}
+
+/// Optional protocol features a peer may or may not support.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProtocolCapability {
+    /// The client can paint `PutScreenRegions` updates.
+    RegionUpdates,
+
+    /// The client can draw the cursor itself from `UpdateCursor` messages, so it
+    /// doesn't need to be drawn into the frames.
+    CursorPlane,
+
+    /// The client answers `ClockPing`s and reports when it presents frames, so
+    /// latency can be measured end to end.
+    ClockSync,
+
+    /// The client shares its clipboard through `Clipboard` messages.
+    Clipboard,
+
+    /// The client sends `Heartbeat`s while the session is up, so it can be
+    /// given up on once they stop.
+    Heartbeat,
+
+    /// The client keeps the `SessionToken` it's given, and shows it when it
+    /// reconnects so it can resume its session.
+    SessionResume,
+
+    /// The client sends `Input` events for the host to inject.
+    Input,
+
+    /// The client asks for a keyframe with `RequestKeyframe` when it can't decode
+    /// the stream anymore.
+    KeyframeRequests,
+
+    /// The client sends `ReceiverReport`s about how well it keeps up, so the
+    /// bitrate and frame rate can follow.
+    ReceiverReports,
+
+    /// A capability this build doesn't know about, advertised by a newer peer.
+    #[serde(other)]
+    Unknown,
+}
+
+/// What each side advertises about itself during pre-init.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolHello {
+    pub versions: ProtocolVersionRange,
+    pub capabilities: Vec<ProtocolCapability>,
+}
+
+impl ProtocolHello {
+    /// A hello for the protocol versions this build speaks, with the given capabilities.
+    pub fn current(capabilities: Vec<ProtocolCapability>) -> Self {
+        Self {
+            versions: ProtocolVersionRange::current(),
+            capabilities,
+        }
+    }
+
+    /// Negotiates a protocol with a peer, as the side that makes the decision.
+    ///
+    /// The highest common version is picked, and only capabilities both sides
+    /// advertised are kept.
+    pub fn negotiate(&self, peer: &ProtocolHello) -> Result<NegotiatedProtocol, ProtocolRejection> {
+        let version = self.versions.highest_common(&peer.versions).ok_or(
+            ProtocolRejection::IncompatibleVersion {
+                ours: self.versions,
+                theirs: peer.versions,
+            },
+        )?;
+
+        let capabilities = self
+            .capabilities
+            .iter()
+            .filter(|c| **c != ProtocolCapability::Unknown && peer.capabilities.contains(c))
+            .copied()
+            .collect();
+
+        Ok(NegotiatedProtocol {
+            version,
+            capabilities,
+        })
+    }
+}
+
+/// The protocol both sides agreed on.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NegotiatedProtocol {
+    pub version: ProtocolVersion,
+    pub capabilities: Vec<ProtocolCapability>,
+}
+
+impl NegotiatedProtocol {
+    pub fn has_capability(&self, capability: ProtocolCapability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+/// Why a peer was turned away during pre-init.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolRejection {
+    /// The two sides share no protocol version.
+    IncompatibleVersion {
+        ours: ProtocolVersionRange,
+        theirs: ProtocolVersionRange,
+    },
+
+    /// The peer didn't advertise a protocol version at all, so it predates
+    /// version negotiation.
+    Unversioned { ours: ProtocolVersionRange },
+}
+
+impl Display for ProtocolRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolRejection::IncompatibleVersion { ours, theirs } => {
+                let advice = if theirs.max < ours.min {
+                    "update the client"
+                } else {
+                    "update the server"
+                };
+                write!(
+                    f,
+                    "Incompatible protocol: server speaks {}, client speaks {} ({})",
+                    ours, theirs, advice
+                )
+            }
+            ProtocolRejection::Unversioned { ours } => {
+                write!(
+                    f,
+                    "Incompatible protocol: server speaks {}, client is too old to say (update the client)",
+                    ours
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ProtocolCapability, ProtocolHello, ProtocolRejection, ProtocolVersionRange};
+
+    fn hello(min: u32, max: u32, capabilities: Vec<ProtocolCapability>) -> ProtocolHello {
+        ProtocolHello {
+            versions: ProtocolVersionRange { min, max },
+            capabilities,
+        }
+    }
+
+    #[test]
+    fn test_highest_common_version() {
+        let ours = ProtocolVersionRange { min: 2, max: 5 };
+        let range = |min, max| ProtocolVersionRange { min, max };
+        assert_eq!(ours.highest_common(&range(3, 9)), Some(5));
+        assert_eq!(ours.highest_common(&range(1, 3)), Some(3));
+        // Ranges that only touch at their edges
+        assert_eq!(ours.highest_common(&range(5, 9)), Some(5));
+        assert_eq!(ours.highest_common(&range(0, 2)), Some(2));
+        assert_eq!(ours.highest_common(&range(6, 9)), None);
+        assert_eq!(ours.highest_common(&range(0, 1)), None);
+    }
+
+    #[test]
+    fn test_no_common_version_is_rejected() {
+        let ours = hello(4, 6, vec![]);
+        let theirs = hello(2, 3, vec![]);
+        let rejection = ours.negotiate(&theirs).unwrap_err();
+        assert_eq!(
+            rejection,
+            ProtocolRejection::IncompatibleVersion {
+                ours: ours.versions,
+                theirs: theirs.versions,
+            }
+        );
+        assert!(rejection.to_string().contains("update the client"));
+
+        let rejection = theirs.negotiate(&ours).unwrap_err();
+        assert!(rejection.to_string().contains("update the server"));
+    }
+
+    #[test]
+    fn test_only_shared_capabilities_are_kept() {
+        let ours = hello(
+            2,
+            5,
+            vec![
+                ProtocolCapability::CursorPlane,
+                ProtocolCapability::Clipboard,
+                ProtocolCapability::Input,
+            ],
+        );
+        let theirs = hello(
+            3,
+            8,
+            vec![
+                ProtocolCapability::Input,
+                ProtocolCapability::Unknown,
+                ProtocolCapability::CursorPlane,
+                ProtocolCapability::Heartbeat,
+            ],
+        );
+        let protocol = ours.negotiate(&theirs).unwrap();
+        assert_eq!(protocol.version, 5);
+        assert_eq!(
+            protocol.capabilities,
+            vec![ProtocolCapability::CursorPlane, ProtocolCapability::Input]
+        );
+        assert!(!protocol.has_capability(ProtocolCapability::Clipboard));
+    }
+
+    #[test]
+    fn test_unknown_capabilities_are_never_agreed() {
+        let ours = hello(2, 5, vec![ProtocolCapability::Unknown]);
+        let theirs = hello(2, 5, vec![ProtocolCapability::Unknown]);
+        let protocol = ours.negotiate(&theirs).unwrap();
+        assert!(protocol.capabilities.is_empty());
+    }
+
+    #[test]
+    fn test_unversioned_rejection() {
+        let rejection = ProtocolRejection::Unversioned {
+            ours: ProtocolVersionRange { min: 2, max: 8 },
+        };
+        let message = rejection.to_string();
+        assert!(message.contains("v2-v8"));
+        assert!(message.contains("update the client"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::RateTarget;
+
+/// How many frames may sit in the client's decode queue before we consider it
+/// backed up.
+const MAX_HEALTHY_QUEUE_DEPTH: u32 = 3;
+
+/// How many healthy reports in a row it takes before we try a higher rate.
+const HEALTHY_REPORTS_BEFORE_INCREASE: u32 = 3;
+
+/// How much of the target bitrate has to actually arrive at the client before
+/// we believe the link could take more. A still screen barely uses any bitrate,
+/// and tells us nothing about the link.
+const MIN_UTILIZATION_FOR_INCREASE: f64 = 0.7;
+
+/// How much of the time between frames the client may spend decoding before we
+/// lower the frame rate.
+const MAX_DECODE_BUSY_RATIO: f64 = 0.8;
+
+/// Bitrate is cut by this factor when the client falls behind...
+const BITRATE_DECREASE_FACTOR: f64 = 0.75;
+/// ...and grows by this factor when it keeps up.
+const BITRATE_INCREASE_FACTOR: f64 = 1.1;
+
+const FPS_STEP: u32 = 5;
+
+/// Periodic feedback from the client on how well it is keeping up with the
+/// stream, covering the time since its previous report.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReceiverReport {
+    /// How long this report covers, in milliseconds
+    pub interval_ms: u32,
+    /// Bytes of frame data received
+    pub bytes_received: u64,
+    /// Frames decoded
+    pub frames_decoded: u32,
+    /// The average time it took to decode a frame, in microseconds
+    pub avg_decode_time_us: u32,
+    /// Frames waiting to be decoded when the report was made
+    pub queue_depth: u32,
+    /// Frames that were lost or thrown away without being shown
+    pub dropped_frames: u32,
+}
+
+impl ReceiverReport {
+    /// The bitrate the client actually received, in bits per second.
+    pub fn received_bitrate(&self) -> u64 {
+        if self.interval_ms == 0 {
+            return 0;
+        }
+        self.bytes_received * 8 * 1000 / self.interval_ms as u64
+    }
+}
+
+impl Display for ReceiverReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} kbps over {}ms, {} decoded ({}us avg), {} queued, {} dropped",
+            self.received_bitrate() / 1000,
+            self.interval_ms,
+            self.frames_decoded,
+            self.avg_decode_time_us,
+            self.queue_depth,
+            self.dropped_frames
+        )
+    }
+}
+
+/// The range the rate controller may move the rate in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimits {
+    pub min_bitrate: u32,
+    pub max_bitrate: u32,
+    pub min_fps: u32,
+    pub max_fps: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            min_bitrate: 500_000,
+            max_bitrate: 50_000_000,
+            min_fps: 15,
+            max_fps: 60,
+        }
+    }
+}
+
+/// Turns receiver reports into bitrate and frame rate changes.
+///
+/// The bitrate backs off quickly when the client drops frames or its queue
+/// builds up, and creeps back up while it keeps up. The frame rate follows how
+/// long the client takes to decode a frame.
+#[derive(Debug, Clone)]
+pub struct RateController {
+    target: RateTarget,
+    limits: RateLimits,
+    healthy_reports: u32,
+}
+
+impl RateController {
+    pub fn new(initial: RateTarget, limits: RateLimits) -> Self {
+        Self {
+            target: initial.clamped(&limits),
+            limits,
+            healthy_reports: 0,
+        }
+    }
+
+    /// The rate the encoder should currently be running at.
+    pub fn target(&self) -> RateTarget {
+        self.target
+    }
+
+    /// Takes in a report from the client, returning the new rate if it changed.
+    pub fn on_report(&mut self, report: &ReceiverReport) -> Option<RateTarget> {
+        let previous = self.target;
+        let mut next = previous;
+
+        let frame_interval_us = 1_000_000.0 / previous.fps.max(1) as f64;
+        let decode_busy_ratio = report.avg_decode_time_us as f64 / frame_interval_us;
+        let congested = report.dropped_frames > 0 || report.queue_depth > MAX_HEALTHY_QUEUE_DEPTH;
+
+        if congested {
+            self.healthy_reports = 0;
+            next.bitrate = (previous.bitrate as f64 * BITRATE_DECREASE_FACTOR) as u32;
+        } else {
+            self.healthy_reports += 1;
+        }
+
+        if decode_busy_ratio > MAX_DECODE_BUSY_RATIO {
+            // The client can't decode this many frames, no matter the bitrate
+            next.fps = previous.fps.saturating_sub(FPS_STEP);
+        } else if !congested
+            && self.healthy_reports >= HEALTHY_REPORTS_BEFORE_INCREASE
+            && decode_busy_ratio < MAX_DECODE_BUSY_RATIO / 2.0
+        {
+            next.fps = previous.fps + FPS_STEP;
+        }
+
+        let utilization = report.received_bitrate() as f64 / previous.bitrate.max(1) as f64;
+        if !congested
+            && self.healthy_reports >= HEALTHY_REPORTS_BEFORE_INCREASE
+            && utilization >= MIN_UTILIZATION_FOR_INCREASE
+        {
+            next.bitrate = (previous.bitrate as f64 * BITRATE_INCREASE_FACTOR) as u32;
+        }
+
+        if self.healthy_reports >= HEALTHY_REPORTS_BEFORE_INCREASE {
+            self.healthy_reports = 0;
+        }
+
+        self.target = next.clamped(&self.limits);
+        (self.target != previous).then_some(self.target)
+    }
+}
+
+impl RateTarget {
+    fn clamped(self, limits: &RateLimits) -> Self {
+        Self {
+            bitrate: self.bitrate.clamp(limits.min_bitrate, limits.max_bitrate),
+            fps: self.fps.clamp(limits.min_fps, limits.max_fps),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::host::RateTarget;
+
+    use super::{RateController, RateLimits, ReceiverReport};
+
+    fn report(bitrate: u64, dropped_frames: u32) -> ReceiverReport {
+        ReceiverReport {
+            interval_ms: 1000,
+            bytes_received: bitrate / 8,
+            frames_decoded: 60,
+            avg_decode_time_us: 2_000,
+            queue_depth: 0,
+            dropped_frames,
+        }
+    }
+
+    #[test]
+    fn test_backs_off_on_dropped_frames() {
+        let initial = RateTarget::new(8_000_000, 60);
+        let mut controller = RateController::new(initial, RateLimits::default());
+
+        let next = controller.on_report(&report(8_000_000, 4)).unwrap();
+        assert!(next.bitrate < initial.bitrate);
+        assert_eq!(next.fps, initial.fps);
+    }
+
+    #[test]
+    fn test_increases_only_when_link_is_used() {
+        let initial = RateTarget::new(8_000_000, 60);
+        let mut controller = RateController::new(initial, RateLimits::default());
+
+        // A still screen, nothing to learn
+        for _ in 0..6 {
+            assert_eq!(controller.on_report(&report(100_000, 0)), None);
+        }
+
+        let mut increased = None;
+        for _ in 0..3 {
+            increased = controller.on_report(&report(8_000_000, 0)).or(increased);
+        }
+        assert!(increased.unwrap().bitrate > initial.bitrate);
+    }
+
+    #[test]
+    fn test_lowers_fps_for_slow_decoder() {
+        let initial = RateTarget::new(8_000_000, 60);
+        let mut controller = RateController::new(initial, RateLimits::default());
+
+        let slow = ReceiverReport {
+            avg_decode_time_us: 20_000,
+            ..report(8_000_000, 0)
+        };
+        let next = controller.on_report(&slow).unwrap();
+        assert!(next.fps < initial.fps);
+    }
+}
@@ -0,0 +1,583 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+
+use log::{error, info};
+
+use crate::{
+    host::{EncoderPossibleConfiguration, FrameHeader},
+    util::unix_time_micros,
+};
+
+/// Clusters of a Matroska recording are cut before their blocks' timestamps, which
+/// are relative to the cluster's, run out of range.
+const MATROSKA_MAX_CLUSTER_MS: u64 = i16::MAX as u64;
+
+/// Clusters are kept in memory until they're cut, so they're cut at this size too.
+const MATROSKA_MAX_CLUSTER_BYTES: usize = 4 * 1024 * 1024;
+
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const RAW_MAGIC: &[u8; 8] = b"DDRAWREC";
+
+/// Where recordings go, unless asked to go somewhere else.
+pub fn get_default_recordings_path() -> Option<PathBuf> {
+    dirs::data_local_dir().map(|path| path.join("dev-disp").join("recordings"))
+}
+
+/// The container a recording is written in, picked by the encoder family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingFormat {
+    /// Matroska, with this codec ID. Frames are kept as the encoder made them.
+    Matroska(&'static str),
+    /// IVF, with this FourCC
+    Ivf(&'static [u8; 4]),
+    /// Every frame as it is, after its header
+    Raw,
+}
+
+impl RecordingFormat {
+    pub fn for_family(encoder_family: &str) -> Self {
+        match encoder_family {
+            "h264" => Self::Matroska("V_MPEG4/ISO/AVC"),
+            "hevc" => Self::Matroska("V_MPEGH/ISO/HEVC"),
+            "av1" => Self::Matroska("V_AV1"),
+            "vp8" => Self::Ivf(b"VP80"),
+            "vp9" | "vp09" => Self::Ivf(b"VP90"),
+            _ => Self::Raw,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Matroska(_) => "mkv",
+            Self::Ivf(_) => "ivf",
+            Self::Raw => "raw",
+        }
+    }
+}
+
+/// Records the frames a display host was sent into a directory. Every encoding the
+/// session goes through gets a file of its own, along with a CSV file of the frames'
+/// headers.
+///
+/// Files are written as frames come in, blocking while they are. Recording is meant
+/// for debugging, not for every session.
+pub struct StreamRecorder {
+    directory: PathBuf,
+    /// What the files of the recording start with
+    prefix: String,
+    encoding: EncoderPossibleConfiguration,
+    config_generation: u32,
+    /// When the first recorded frame was captured, which the recording starts at
+    started_us: Option<u64>,
+    last_timestamp_us: u64,
+    parts: u32,
+    part: Option<RecordingPart>,
+}
+
+/// The files of one encoding of a recording.
+struct RecordingPart {
+    container: ContainerWriter<BufWriter<File>>,
+    headers: BufWriter<File>,
+}
+
+impl StreamRecorder {
+    /// Starts recording `host_name`'s session, currently streaming with `encoding`.
+    pub fn new(
+        directory: PathBuf,
+        host_name: &str,
+        config_generation: u32,
+        encoding: EncoderPossibleConfiguration,
+    ) -> io::Result<Self> {
+        std::fs::create_dir_all(&directory)?;
+        let host_name: String = host_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        Ok(Self {
+            directory,
+            prefix: format!("{host_name}-{}", unix_time_micros() / 1_000_000),
+            encoding,
+            config_generation,
+            started_us: None,
+            last_timestamp_us: 0,
+            parts: 0,
+            part: None,
+        })
+    }
+
+    /// The session switched to another encoding. Its frames go to a new file, once
+    /// there's a keyframe to start it with.
+    pub fn set_encoding(
+        &mut self,
+        config_generation: u32,
+        encoding: EncoderPossibleConfiguration,
+    ) -> io::Result<()> {
+        self.config_generation = config_generation;
+        self.encoding = encoding;
+        self.finish_part()
+    }
+
+    /// Records a frame the display host was sent. Frames of an encoding the session
+    /// already left are skipped, and so is anything before the first keyframe.
+    pub fn record(&mut self, header: &FrameHeader, data: &[u8]) -> io::Result<()> {
+        if header.config_generation != self.config_generation {
+            return Ok(());
+        }
+        if self.part.is_none() {
+            if !header.keyframe {
+                return Ok(());
+            }
+            self.part = Some(self.start_part()?);
+        }
+
+        let started_us = *self.started_us.get_or_insert(header.capture_timestamp_us);
+        // Containers want their timestamps to only go forward
+        let timestamp_us = header
+            .capture_timestamp_us
+            .saturating_sub(started_us)
+            .max(self.last_timestamp_us);
+        self.last_timestamp_us = timestamp_us;
+
+        let Some(part) = &mut self.part else {
+            return Ok(());
+        };
+        part.container.write_frame(header, timestamp_us, data)?;
+        writeln!(
+            part.headers,
+            "{},{},{},{},{},{}",
+            header.sequence,
+            header.capture_timestamp_us,
+            header.encode_timestamp_us,
+            header.keyframe,
+            header.config_generation,
+            data.len()
+        )
+    }
+
+    fn start_part(&mut self) -> io::Result<RecordingPart> {
+        let format = RecordingFormat::for_family(&self.encoding.encoder_family);
+        let base = self
+            .directory
+            .join(format!("{}-{:03}", self.prefix, self.parts));
+        self.parts += 1;
+
+        let path = base.with_extension(format.extension());
+        let container =
+            ContainerWriter::new(format, BufWriter::new(File::create(&path)?), &self.encoding)?;
+        let mut headers = BufWriter::new(File::create(base.with_extension("frames.csv"))?);
+        writeln!(
+            headers,
+            "sequence,capture_timestamp_us,encode_timestamp_us,keyframe,config_generation,bytes"
+        )?;
+        info!(
+            "Recording {} frames to {}",
+            self.encoding.encoder_family,
+            path.display()
+        );
+        Ok(RecordingPart { container, headers })
+    }
+
+    /// Finishes the files of the current encoding, if it has any yet.
+    fn finish_part(&mut self) -> io::Result<()> {
+        let Some(mut part) = self.part.take() else {
+            return Ok(());
+        };
+        part.headers.flush()?;
+        part.container.finish()?.flush()
+    }
+}
+
+impl Drop for StreamRecorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_part() {
+            error!("Failed to finish recording: {}", e);
+        }
+    }
+}
+
+/// Writes the frames of one encoding into a container.
+enum ContainerWriter<W> {
+    Matroska(MatroskaWriter<W>),
+    Ivf(IvfWriter<W>),
+    Raw(W),
+}
+
+impl<W: Write + Seek> ContainerWriter<W> {
+    fn new(
+        format: RecordingFormat,
+        mut out: W,
+        encoding: &EncoderPossibleConfiguration,
+    ) -> io::Result<Self> {
+        let (width, height) = encoding.encoded_resolution;
+        Ok(match format {
+            RecordingFormat::Matroska(codec_id) => {
+                Self::Matroska(MatroskaWriter::new(out, codec_id, width, height)?)
+            }
+            RecordingFormat::Ivf(fourcc) => Self::Ivf(IvfWriter::new(out, fourcc, width, height)?),
+            RecordingFormat::Raw => {
+                out.write_all(RAW_MAGIC)?;
+                out.write_all(&width.to_le_bytes())?;
+                out.write_all(&height.to_le_bytes())?;
+                Self::Raw(out)
+            }
+        })
+    }
+
+    /// `timestamp_us` is when the frame was captured, from the start of the
+    /// recording.
+    fn write_frame(
+        &mut self,
+        header: &FrameHeader,
+        timestamp_us: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        match self {
+            Self::Matroska(writer) => writer.write_frame(header.keyframe, timestamp_us, data),
+            Self::Ivf(writer) => writer.write_frame(timestamp_us, data),
+            Self::Raw(out) => {
+                out.write_all(&header.sequence.to_le_bytes())?;
+                out.write_all(&header.capture_timestamp_us.to_le_bytes())?;
+                out.write_all(&header.encode_timestamp_us.to_le_bytes())?;
+                out.write_all(&[header.keyframe as u8])?;
+                out.write_all(&header.config_generation.to_le_bytes())?;
+                out.write_all(&(data.len() as u32).to_le_bytes())?;
+                out.write_all(data)
+            }
+        }
+    }
+
+    /// Fills in what's only known at the end. Returns the writer the container was
+    /// written to.
+    fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Matroska(writer) => writer.finish(),
+            Self::Ivf(writer) => writer.finish(),
+            Self::Raw(out) => Ok(out),
+        }
+    }
+}
+
+/// A Matroska file of a single video track, in clusters that start at keyframes.
+struct MatroskaWriter<W> {
+    out: W,
+    /// Where the size of the segment goes, once it's known
+    segment_size_at: u64,
+    /// The blocks of the cluster being put together
+    cluster: Vec<u8>,
+    cluster_timestamp_ms: u64,
+}
+
+impl<W: Write + Seek> MatroskaWriter<W> {
+    fn new(mut out: W, codec_id: &str, width: u32, height: u32) -> io::Result<Self> {
+        let mut header = Vec::new();
+        ebml_uint(&mut header, EBML_VERSION, 1);
+        ebml_uint(&mut header, EBML_READ_VERSION, 1);
+        ebml_uint(&mut header, EBML_MAX_ID_LENGTH, 4);
+        ebml_uint(&mut header, EBML_MAX_SIZE_LENGTH, 8);
+        ebml_element(&mut header, DOC_TYPE, b"matroska");
+        ebml_uint(&mut header, DOC_TYPE_VERSION, 4);
+        ebml_uint(&mut header, DOC_TYPE_READ_VERSION, 2);
+        let mut head = Vec::new();
+        ebml_element(&mut head, EBML, &header);
+        out.write_all(&head)?;
+
+        // The segment's size is written as unknown for now
+        let mut segment = Vec::new();
+        ebml_id(&mut segment, SEGMENT);
+        out.write_all(&segment)?;
+        let segment_size_at = out.stream_position()?;
+        out.write_all(&ebml_size_of_width(u64::MAX, 8))?;
+
+        let mut info = Vec::new();
+        // Timestamps are in milliseconds
+        ebml_uint(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        ebml_element(&mut info, MUXING_APP, b"dev-disp");
+        ebml_element(&mut info, WRITING_APP, b"dev-disp");
+
+        let mut video = Vec::new();
+        ebml_uint(&mut video, PIXEL_WIDTH, width as u64);
+        ebml_uint(&mut video, PIXEL_HEIGHT, height as u64);
+        let mut track = Vec::new();
+        ebml_uint(&mut track, TRACK_NUMBER, 1);
+        ebml_uint(&mut track, TRACK_UID, 1);
+        ebml_uint(&mut track, TRACK_TYPE, 1);
+        ebml_element(&mut track, CODEC_ID, codec_id.as_bytes());
+        ebml_element(&mut track, VIDEO, &video);
+        let mut tracks = Vec::new();
+        ebml_element(&mut tracks, TRACK_ENTRY, &track);
+
+        let mut body = Vec::new();
+        ebml_element(&mut body, INFO, &info);
+        ebml_element(&mut body, TRACKS, &tracks);
+        out.write_all(&body)?;
+
+        Ok(Self {
+            out,
+            segment_size_at,
+            cluster: Vec::new(),
+            cluster_timestamp_ms: 0,
+        })
+    }
+
+    fn write_frame(&mut self, keyframe: bool, timestamp_us: u64, data: &[u8]) -> io::Result<()> {
+        let timestamp_ms = timestamp_us / 1000;
+        if !self.cluster.is_empty()
+            && (keyframe
+                || timestamp_ms.saturating_sub(self.cluster_timestamp_ms) > MATROSKA_MAX_CLUSTER_MS
+                || self.cluster.len() > MATROSKA_MAX_CLUSTER_BYTES)
+        {
+            self.write_cluster()?;
+        }
+        if self.cluster.is_empty() {
+            self.cluster_timestamp_ms = timestamp_ms;
+        }
+
+        let relative = timestamp_ms.saturating_sub(self.cluster_timestamp_ms) as i16;
+        ebml_id(&mut self.cluster, SIMPLE_BLOCK);
+        ebml_size(&mut self.cluster, 4 + data.len() as u64);
+        // Track 1, as a size-like number
+        self.cluster.push(0x81);
+        self.cluster.extend_from_slice(&relative.to_be_bytes());
+        self.cluster.push(if keyframe { 0x80 } else { 0 });
+        self.cluster.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn write_cluster(&mut self) -> io::Result<()> {
+        let mut timestamp = Vec::new();
+        ebml_uint(&mut timestamp, CLUSTER_TIMESTAMP, self.cluster_timestamp_ms);
+        let mut head = Vec::new();
+        ebml_id(&mut head, CLUSTER);
+        ebml_size(&mut head, (timestamp.len() + self.cluster.len()) as u64);
+        head.extend_from_slice(&timestamp);
+        self.out.write_all(&head)?;
+        self.out.write_all(&self.cluster)?;
+        self.cluster.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        if !self.cluster.is_empty() {
+            self.write_cluster()?;
+        }
+        let end = self.out.stream_position()?;
+        // The segment starts after its size
+        let segment_size = end - self.segment_size_at - 8;
+        self.out.seek(SeekFrom::Start(self.segment_size_at))?;
+        self.out.write_all(&ebml_size_of_width(segment_size, 8))?;
+        self.out.seek(SeekFrom::Start(end))?;
+        Ok(self.out)
+    }
+}
+
+/// An IVF file, timestamped in microseconds.
+struct IvfWriter<W> {
+    out: W,
+    frames: u32,
+}
+
+impl<W: Write + Seek> IvfWriter<W> {
+    fn new(mut out: W, fourcc: &[u8; 4], width: u32, height: u32) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(32);
+        header.extend_from_slice(b"DKIF");
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(&32u16.to_le_bytes());
+        header.extend_from_slice(fourcc);
+        header.extend_from_slice(&(width as u16).to_le_bytes());
+        header.extend_from_slice(&(height as u16).to_le_bytes());
+        // Time base of 1/1000000
+        header.extend_from_slice(&1_000_000u32.to_le_bytes());
+        header.extend_from_slice(&1u32.to_le_bytes());
+        // Frame count, filled in when finishing
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        out.write_all(&header)?;
+        Ok(Self { out, frames: 0 })
+    }
+
+    fn write_frame(&mut self, timestamp_us: u64, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.out.write_all(&timestamp_us.to_le_bytes())?;
+        self.out.write_all(data)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(24))?;
+        self.out.write_all(&self.frames.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        Ok(self.out)
+    }
+}
+
+fn ebml_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(3);
+    out.extend_from_slice(&bytes[start..]);
+}
+
+/// Writes a size in as few bytes as it fits in. Sizes of all ones mean "unknown", so
+/// those take a byte more.
+fn ebml_size(out: &mut Vec<u8>, size: u64) {
+    let mut width = 1;
+    while width < 8 && size >= (1 << (7 * width)) - 1 {
+        width += 1;
+    }
+    out.extend_from_slice(&ebml_size_of_width(size, width));
+}
+
+/// A size taking exactly `width` bytes. `u64::MAX` is written as unknown.
+fn ebml_size_of_width(size: u64, width: usize) -> Vec<u8> {
+    let max = (1u64 << (7 * width)) - 1;
+    let value = size.min(max) | (1 << (7 * width));
+    value.to_be_bytes()[8 - width..].to_vec()
+}
+
+fn ebml_element(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+    ebml_id(out, id);
+    ebml_size(out, body.len() as u64);
+    out.extend_from_slice(body);
+}
+
+fn ebml_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+    ebml_element(out, id, &bytes[start..]);
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Cursor};
+
+    use crate::host::{EncoderPossibleConfiguration, FrameHeader};
+
+    use super::{CLUSTER, ContainerWriter, RecordingFormat, ebml_size};
+
+    fn encoding(encoder_family: &str) -> EncoderPossibleConfiguration {
+        EncoderPossibleConfiguration {
+            encoder_name: encoder_family.to_string(),
+            encoded_resolution: (1280, 720),
+            encoder_family: encoder_family.to_string(),
+            parameters: HashMap::new(),
+        }
+    }
+
+    fn frame(sequence: u64, keyframe: bool) -> FrameHeader {
+        FrameHeader {
+            keyframe,
+            ..FrameHeader::new(sequence, 0)
+        }
+    }
+
+    fn record(encoder_family: &str, frames: &[(bool, u64, &[u8])]) -> Vec<u8> {
+        let format = RecordingFormat::for_family(encoder_family);
+        let mut writer =
+            ContainerWriter::new(format, Cursor::new(Vec::new()), &encoding(encoder_family))
+                .unwrap();
+        for (sequence, (keyframe, timestamp_us, data)) in frames.iter().enumerate() {
+            writer
+                .write_frame(&frame(sequence as u64, *keyframe), *timestamp_us, data)
+                .unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn count(haystack: &[u8], needle: &[u8]) -> usize {
+        haystack
+            .windows(needle.len())
+            .filter(|window| *window == needle)
+            .count()
+    }
+
+    #[test]
+    fn test_ebml_sizes() {
+        let sizes = |size| {
+            let mut out = Vec::new();
+            ebml_size(&mut out, size);
+            out
+        };
+        assert_eq!(sizes(5), [0x85]);
+        // All ones would mean "unknown"
+        assert_eq!(sizes(127), [0x40, 0x7F]);
+        assert_eq!(sizes(300), [0x41, 0x2C]);
+    }
+
+    #[test]
+    fn test_ivf_counts_frames() {
+        let file = record("vp09", &[(true, 0, b"key"), (false, 16_666, b"delta")]);
+
+        assert_eq!(&file[..4], b"DKIF");
+        assert_eq!(&file[8..12], b"VP90");
+        assert_eq!(u32::from_le_bytes(file[24..28].try_into().unwrap()), 2);
+        // The second frame, after the header and the first frame
+        let second = 32 + 12 + 3;
+        assert_eq!(
+            u64::from_le_bytes(file[second + 4..second + 12].try_into().unwrap()),
+            16_666
+        );
+        assert_eq!(&file[second + 12..], b"delta");
+    }
+
+    #[test]
+    fn test_matroska_clusters_start_at_keyframes() {
+        let file = record(
+            "h264",
+            &[
+                (true, 0, b"key"),
+                (false, 16_000, b"delta"),
+                (true, 33_000, b"key"),
+                // Too far from the keyframe for the same cluster
+                (false, 40_000_000, b"delta"),
+            ],
+        );
+
+        assert_eq!(count(&file, &CLUSTER.to_be_bytes()), 3);
+        assert_eq!(count(&file, b"V_MPEG4/ISO/AVC"), 1);
+        // The segment's size was filled in
+        let segment_size_at = file.windows(4).position(|w| w == [0x18, 0x53, 0x80, 0x67]);
+        let segment_size_at = segment_size_at.unwrap() + 4;
+        assert_eq!(file[segment_size_at], 0x01);
+        let size = u64::from_be_bytes(
+            file[segment_size_at..segment_size_at + 8]
+                .try_into()
+                .unwrap(),
+        ) & 0x00FF_FFFF_FFFF_FFFF;
+        assert_eq!(size as usize, file.len() - segment_size_at - 8);
+    }
+}
@@ -0,0 +1,88 @@
+use std::{fmt::Display, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// How often heartbeats are sent when nothing else says otherwise.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long the other side may stay silent before we give up on it, when nothing
+/// else says otherwise. A few heartbeats may go missing before that happens.
+pub const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Why a session is being closed, sent along with a `Goodbye` in either direction.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoodbyeReason {
+    /// Someone chose to disconnect the display host
+    UserDisconnect,
+    /// The host is shutting down, or its screen went away
+    HostShutdown,
+    /// The screen couldn't be encoded anymore
+    EncoderFailure,
+    /// The virtual screen stopped working
+    ScreenFailure,
+    /// Nothing was heard from the other side for too long
+    Idle,
+    /// The display host is no longer allowed to connect
+    AuthRevoked,
+    /// A reason this build doesn't know about, given by a newer peer
+    #[serde(other)]
+    Unknown,
+}
+
+impl Display for GoodbyeReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            GoodbyeReason::UserDisconnect => "disconnected by the user",
+            GoodbyeReason::HostShutdown => "the host is shutting down",
+            GoodbyeReason::EncoderFailure => "the encoder failed",
+            GoodbyeReason::ScreenFailure => "the virtual screen failed",
+            GoodbyeReason::Idle => "the connection went idle",
+            GoodbyeReason::AuthRevoked => "authorization was revoked",
+            GoodbyeReason::Unknown => "unknown reason",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// Handed to a display host when its session starts. A display host that lost its
+/// connection shows it again when reconnecting, to resume the session it had.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionToken(String);
+
+impl SessionToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for SessionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Only a prefix, the token shouldn't end up in logs whole
+        let prefix: String = self.0.chars().take(8).collect();
+        write!(f, "{prefix}...")
+    }
+}
+
+/// How a transport keeps an eye on the connection, for transports that send
+/// heartbeats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// How often a heartbeat is sent to the other side
+    pub interval: Duration,
+    /// How long the other side may stay silent before the connection is
+    /// considered dead
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    core::{FrameTimings, LatencyPercentiles},
+    host::EncoderPossibleConfiguration,
+};
+
+/// How often a session reports its statistics.
+pub const SESSION_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many sent frames the latency percentiles are computed over.
+const STATS_WINDOW: usize = 120;
+
+/// How a session has been doing lately.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SessionStats {
+    /// Frames sent per second since the last report
+    pub fps: f64,
+    /// Bits sent per second since the last report
+    pub bitrate: u64,
+    pub encode_latency: Option<LatencyPercentiles>,
+    pub send_latency: Option<LatencyPercentiles>,
+    /// Frames dropped because sending couldn't keep up, over the whole session
+    pub dropped_frames: u64,
+    /// Failed transmissions over the whole session
+    pub transport_errors: u64,
+    /// Something like "h264" or "raw"
+    pub codec: String,
+    /// Something like "h264_nvenc"
+    pub encoder: String,
+    /// The resolution the screen is encoded at
+    pub resolution: (u32, u32),
+}
+
+impl Display for SessionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}) at {}x{}, {:.1} fps, {} kbps",
+            self.codec,
+            self.encoder,
+            self.resolution.0,
+            self.resolution.1,
+            self.fps,
+            self.bitrate / 1000
+        )?;
+        if self.dropped_frames > 0 {
+            write!(f, ", {} dropped frames", self.dropped_frames)?;
+        }
+        if self.transport_errors > 0 {
+            write!(f, ", {} transport errors", self.transport_errors)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds up what happens to a session's frames into `SessionStats`.
+#[derive(Debug)]
+pub struct SessionStatsCollector {
+    codec: String,
+    encoder: String,
+    resolution: (u32, u32),
+    /// Since when frames and bytes were counted
+    window_start: Instant,
+    frames: u32,
+    bytes: u64,
+    encode: VecDeque<Duration>,
+    send: VecDeque<Duration>,
+    dropped_frames: u64,
+    transport_errors: u64,
+}
+
+impl SessionStatsCollector {
+    pub fn new(encoding: &EncoderPossibleConfiguration, now: Instant) -> Self {
+        Self {
+            codec: encoding.encoder_family.clone(),
+            encoder: encoding.encoder_name.clone(),
+            resolution: encoding.encoded_resolution,
+            window_start: now,
+            frames: 0,
+            bytes: 0,
+            encode: VecDeque::with_capacity(STATS_WINDOW),
+            send: VecDeque::with_capacity(STATS_WINDOW),
+            dropped_frames: 0,
+            transport_errors: 0,
+        }
+    }
+
+    /// Switches to a renegotiated encoding. Timings of the old one are forgotten.
+    pub fn set_encoding(&mut self, encoding: &EncoderPossibleConfiguration) {
+        self.codec = encoding.encoder_family.clone();
+        self.encoder = encoding.encoder_name.clone();
+        self.resolution = encoding.encoded_resolution;
+        self.encode.clear();
+        self.send.clear();
+    }
+
+    pub fn on_frame_sent(&mut self, bytes: usize, timings: &FrameTimings) {
+        self.frames += 1;
+        self.bytes += bytes as u64;
+        for (window, value) in [
+            (&mut self.encode, timings.encode),
+            (&mut self.send, timings.send),
+        ] {
+            if window.len() >= STATS_WINDOW {
+                window.pop_front();
+            }
+            window.push_back(value);
+        }
+    }
+
+    pub fn on_frames_dropped(&mut self, count: u64) {
+        self.dropped_frames += count;
+    }
+
+    pub fn on_transport_error(&mut self) {
+        self.transport_errors += 1;
+    }
+
+    /// The stats as of `now`. Rates are counted anew from here on.
+    pub fn report(&mut self, now: Instant) -> SessionStats {
+        let elapsed = now.duration_since(self.window_start).as_secs_f64();
+        let per_second = |count: f64| if elapsed > 0.0 { count / elapsed } else { 0.0 };
+        let stats = SessionStats {
+            fps: per_second(self.frames as f64),
+            bitrate: per_second(self.bytes as f64 * 8.0) as u64,
+            encode_latency: LatencyPercentiles::of(self.encode.iter().copied().collect()),
+            send_latency: LatencyPercentiles::of(self.send.iter().copied().collect()),
+            dropped_frames: self.dropped_frames,
+            transport_errors: self.transport_errors,
+            codec: self.codec.clone(),
+            encoder: self.encoder.clone(),
+            resolution: self.resolution,
+        };
+        self.window_start = now;
+        self.frames = 0;
+        self.bytes = 0;
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use crate::{core::FrameTimings, host::EncoderPossibleConfiguration};
+
+    use super::SessionStatsCollector;
+
+    fn encoding() -> EncoderPossibleConfiguration {
+        EncoderPossibleConfiguration {
+            encoder_name: "libx264".to_string(),
+            encoded_resolution: (1920, 1080),
+            encoder_family: "h264".to_string(),
+            parameters: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_rates_are_per_report() {
+        let start = Instant::now();
+        let mut collector = SessionStatsCollector::new(&encoding(), start);
+        for _ in 0..30 {
+            collector.on_frame_sent(1000, &FrameTimings::default());
+        }
+        collector.on_frames_dropped(2);
+
+        let stats = collector.report(start + Duration::from_millis(500));
+        assert_eq!(stats.fps, 60.0);
+        assert_eq!(stats.bitrate, 480_000);
+        assert_eq!(stats.dropped_frames, 2);
+        assert_eq!(stats.codec, "h264");
+
+        // Nothing was sent since, but the session's totals stay
+        let stats = collector.report(start + Duration::from_millis(1500));
+        assert_eq!(stats.fps, 0.0);
+        assert_eq!(stats.dropped_frames, 2);
+    }
+
+    #[test]
+    fn test_latency_percentiles() {
+        let start = Instant::now();
+        let mut collector = SessionStatsCollector::new(&encoding(), start);
+        assert_eq!(collector.report(start).encode_latency, None);
+        for ms in 1..=100 {
+            collector.on_frame_sent(
+                0,
+                &FrameTimings {
+                    encode: Duration::from_millis(ms),
+                    send: Duration::from_millis(2),
+                    ..Default::default()
+                },
+            );
+        }
+        let stats = collector.report(start + Duration::from_secs(1));
+        let encode = stats.encode_latency.unwrap();
+        assert_eq!(encode.p50, Duration::from_millis(51));
+        assert_eq!(encode.p99, Duration::from_millis(99));
+        assert_eq!(stats.send_latency.unwrap().p90, Duration::from_millis(2));
+    }
+}
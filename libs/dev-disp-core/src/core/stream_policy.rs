@@ -0,0 +1,253 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::RateLimits,
+    host::{DisplayParameters, RateTarget},
+};
+
+/// How much picture to aim for when no starting bitrate is given.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum QualityTier {
+    /// Go easy on the link, for slow or metered connections
+    Low,
+    #[default]
+    Balanced,
+    /// Spend bandwidth on sharper text and motion
+    High,
+}
+
+impl QualityTier {
+    /// Bits spent on each pixel of each frame, to work out a starting bitrate.
+    fn bits_per_pixel(&self) -> f64 {
+        match self {
+            QualityTier::Low => 0.04,
+            QualityTier::Balanced => 0.07,
+            QualityTier::High => 0.12,
+        }
+    }
+}
+
+impl Display for QualityTier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QualityTier::Low => write!(f, "low"),
+            QualityTier::Balanced => write!(f, "balanced"),
+            QualityTier::High => write!(f, "high"),
+        }
+    }
+}
+
+/// How a session streams its screen.
+///
+/// Resolved for each session from the global configuration, the display host's
+/// overrides, and what the display host reported about itself.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StreamPolicy {
+    /// Bitrate to start at, in bits per second. Worked out from the quality tier
+    /// and the encoded resolution when not set.
+    pub target_bitrate: Option<u32>,
+    /// The most bitrate the session may ever use, in bits per second
+    pub max_bitrate: u32,
+    pub max_fps: u32,
+    /// Frames between forced keyframes. Keyframes are only sent when needed if
+    /// not set.
+    pub keyframe_interval: Option<u32>,
+    pub quality: QualityTier,
+    /// The largest resolution the screen is encoded at. Larger screens are
+    /// scaled down to fit, keeping their aspect ratio.
+    pub max_encode_resolution: Option<(u32, u32)>,
+}
+
+impl Default for StreamPolicy {
+    fn default() -> Self {
+        let limits = RateLimits::default();
+        Self {
+            target_bitrate: None,
+            max_bitrate: limits.max_bitrate,
+            max_fps: limits.max_fps,
+            keyframe_interval: None,
+            quality: QualityTier::default(),
+            max_encode_resolution: None,
+        }
+    }
+}
+
+/// Changes to parts of a stream policy, leaving the rest as it was.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StreamPolicyOverrides {
+    pub target_bitrate: Option<u32>,
+    pub max_bitrate: Option<u32>,
+    pub max_fps: Option<u32>,
+    pub keyframe_interval: Option<u32>,
+    pub quality: Option<QualityTier>,
+    pub max_encode_resolution: Option<(u32, u32)>,
+}
+
+impl StreamPolicy {
+    /// This policy with the given overrides applied on top.
+    pub fn with_overrides(self, overrides: &StreamPolicyOverrides) -> Self {
+        Self {
+            target_bitrate: overrides.target_bitrate.or(self.target_bitrate),
+            max_bitrate: overrides.max_bitrate.unwrap_or(self.max_bitrate),
+            max_fps: overrides.max_fps.unwrap_or(self.max_fps),
+            keyframe_interval: overrides.keyframe_interval.or(self.keyframe_interval),
+            quality: overrides.quality.unwrap_or(self.quality),
+            max_encode_resolution: overrides
+                .max_encode_resolution
+                .or(self.max_encode_resolution),
+        }
+    }
+
+    /// This policy narrowed down to what the display host can show. Hints only
+    /// ever lower the limits, never raise them.
+    pub fn with_client_hints(self, params: &DisplayParameters) -> Self {
+        let max_fps = match params.characteristics.refresh_rate_hz {
+            // Frames past the display's refresh rate are never seen
+            Some(refresh_rate) => self.max_fps.min(refresh_rate.max(1)),
+            None => self.max_fps,
+        };
+        Self { max_fps, ..self }
+    }
+
+    /// The resolution a screen of the given size is encoded at.
+    pub fn encode_resolution(&self, screen: (u32, u32)) -> (u32, u32) {
+        let Some((max_width, max_height)) = self.max_encode_resolution else {
+            return screen;
+        };
+        let (width, height) = screen;
+        if width <= max_width && height <= max_height {
+            return screen;
+        }
+        let scale = (max_width as f64 / width as f64).min(max_height as f64 / height as f64);
+        // Encoders want even sizes for their chroma planes
+        let even = |size: f64| ((size as u32) & !1).max(2);
+        (even(width as f64 * scale), even(height as f64 * scale))
+    }
+
+    /// The rate a session encoding at the given resolution starts at.
+    pub fn initial_rate(&self, encoded: (u32, u32)) -> RateTarget {
+        let limits = self.rate_limits();
+        let bitrate = self.target_bitrate.unwrap_or_else(|| {
+            let pixels_per_second = encoded.0 as f64 * encoded.1 as f64 * self.max_fps as f64;
+            (pixels_per_second * self.quality.bits_per_pixel()) as u32
+        });
+        RateTarget::new(
+            bitrate.clamp(limits.min_bitrate, limits.max_bitrate),
+            self.max_fps,
+        )
+    }
+
+    /// The range the rate controller may move the rate in.
+    pub fn rate_limits(&self) -> RateLimits {
+        let defaults = RateLimits::default();
+        RateLimits {
+            min_bitrate: defaults.min_bitrate.min(self.max_bitrate),
+            max_bitrate: self.max_bitrate,
+            min_fps: defaults.min_fps.min(self.max_fps),
+            max_fps: self.max_fps,
+        }
+    }
+}
+
+impl Display for StreamPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.target_bitrate {
+            Some(bitrate) => write!(f, "{} kbps", bitrate / 1000)?,
+            None => write!(f, "{} quality", self.quality)?,
+        }
+        write!(
+            f,
+            " (max {} kbps), up to {} fps",
+            self.max_bitrate / 1000,
+            self.max_fps
+        )?;
+        if let Some(interval) = self.keyframe_interval {
+            write!(f, ", keyframe every {interval} frames")?;
+        }
+        if let Some((width, height)) = self.max_encode_resolution {
+            write!(f, ", at most {width}x{height}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::host::{DisplayCharacteristics, DisplayParameters};
+
+    use super::{QualityTier, StreamPolicy, StreamPolicyOverrides};
+
+    fn display(refresh_rate_hz: Option<u32>) -> DisplayParameters {
+        DisplayParameters {
+            host_dev_name: "test".to_string(),
+            resolution: (1920, 1080),
+            characteristics: DisplayCharacteristics {
+                refresh_rate_hz,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_overrides_replace_only_what_they_set() {
+        let device = StreamPolicyOverrides {
+            max_fps: Some(30),
+            quality: Some(QualityTier::High),
+            ..Default::default()
+        };
+        let policy = StreamPolicy::default().with_overrides(&device);
+        assert_eq!(policy.max_fps, 30);
+        assert_eq!(policy.quality, QualityTier::High);
+        assert_eq!(policy.max_bitrate, StreamPolicy::default().max_bitrate);
+    }
+
+    #[test]
+    fn test_client_hints_only_lower_fps() {
+        let policy = StreamPolicy {
+            max_fps: 60,
+            ..Default::default()
+        };
+        assert_eq!(policy.with_client_hints(&display(Some(30))).max_fps, 30);
+        assert_eq!(policy.with_client_hints(&display(Some(120))).max_fps, 60);
+        assert_eq!(policy.with_client_hints(&display(None)).max_fps, 60);
+    }
+
+    #[test]
+    fn test_encode_resolution_keeps_aspect_ratio() {
+        let policy = StreamPolicy {
+            max_encode_resolution: Some((1920, 1920)),
+            ..Default::default()
+        };
+        assert_eq!(policy.encode_resolution((3840, 2160)), (1920, 1080));
+        assert_eq!(policy.encode_resolution((1280, 720)), (1280, 720));
+        assert_eq!(
+            StreamPolicy::default().encode_resolution((3840, 2160)),
+            (3840, 2160)
+        );
+    }
+
+    #[test]
+    fn test_initial_rate_respects_limits() {
+        let policy = StreamPolicy {
+            target_bitrate: Some(80_000_000),
+            max_bitrate: 20_000_000,
+            ..Default::default()
+        };
+        assert_eq!(policy.initial_rate((1920, 1080)).bitrate, 20_000_000);
+
+        let low = StreamPolicy {
+            quality: QualityTier::Low,
+            ..Default::default()
+        };
+        let high = StreamPolicy {
+            quality: QualityTier::High,
+            ..Default::default()
+        };
+        assert!(low.initial_rate((1920, 1080)).bitrate < high.initial_rate((1920, 1080)).bitrate);
+    }
+}
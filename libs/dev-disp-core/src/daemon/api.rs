@@ -1,4 +1,9 @@
-use crate::util::{PinnedFuture, PinnedStream};
+use thiserror::Error;
+
+use crate::{
+    core::{ErrorKind, SessionError, SessionStats, StreamPolicy},
+    util::{PinnedFuture, PinnedStream},
+};
 
 pub type DiscoveryId = String;
 pub type DisplayHostId = String;
@@ -19,6 +24,8 @@ pub enum InitializationState {
     NegotiatingCodecs,
     InitializingEncoder,
     SettingClientCodec,
+    Renegotiating,
+    AwaitingReconnect,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
@@ -32,12 +39,49 @@ pub enum DisplayHostStatus {
     Error(String),
 }
 
+/// Why a request to the daemon failed. The kind survives the trip through the API,
+/// so callers can react to it without reading the message.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{message}")]
+pub struct ApiError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<SessionError> for ApiError {
+    fn from(e: SessionError) -> Self {
+        Self::new(e.kind(), e.to_string())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DisplayHostRef {
     pub name: String,
     pub discovery_id: DiscoveryId,
     pub id: DisplayHostId,
     pub status: DisplayHostStatus,
+    /// The policy the device's session streams with, once it has settled on one
+    pub stream_policy: Option<StreamPolicy>,
+    /// The device whose screen this one mirrors, if it joined another's session
+    pub mirror_of: Option<MirrorSourceRef>,
+    /// Whether what the device is sent is being recorded
+    pub recording: bool,
+}
+
+/// The device whose session a mirror joined.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorSourceRef {
+    pub discovery_id: DiscoveryId,
+    pub device_id: DisplayHostId,
 }
 
 #[derive(Debug, Clone)]
@@ -63,22 +107,51 @@ pub trait DevDispApi {
     >;
     fn stream_devices(&self) -> PinnedStream<'static, DeviceCollectionStatus>;
 
-    /// TODO: Better error handling
+    /// Starts a session with a device. Resolves once the session is up and running,
+    /// or with why it couldn't get there.
     fn initialize_device(
         &self,
         discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), String>>;
+    ) -> PinnedFuture<'static, Result<(), ApiError>>;
 
     fn disconnect_device(
         &self,
         discovery_id: DiscoveryId,
         device_id: DisplayHostId,
-    ) -> PinnedFuture<'static, Result<(), String>>;
+    ) -> PinnedFuture<'static, Result<(), ApiError>>;
+
+    /// Has a device join the session of a connected one, to be shown the same
+    /// screen. Resolves once the device is shown the screen, or with why it couldn't
+    /// be. The mirror is disconnected like any other device.
+    fn mirror_device(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        source_discovery_id: DiscoveryId,
+        source_device_id: DisplayHostId,
+    ) -> PinnedFuture<'static, Result<(), ApiError>>;
+
+    /// Starts or stops recording what a connected device is sent, to debug what it
+    /// shows. Mirrors can't be recorded, the device they mirror can.
+    fn set_recording(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+        enabled: bool,
+    ) -> PinnedFuture<'static, Result<(), ApiError>>;
 
     fn get_discovery_methods(
         &self,
     ) -> PinnedFuture<'static, Result<Vec<DiscoveryRef>, Box<dyn std::error::Error + Send + Sync>>>;
 
+    /// Statistics of a connected device's session as they come in. Ends with the
+    /// session, or right away if the device isn't connected.
+    fn stream_session_stats(
+        &self,
+        discovery_id: DiscoveryId,
+        device_id: DisplayHostId,
+    ) -> PinnedStream<'static, SessionStats>;
+
     // TODO: Do we need a stream for discovery methods changes?
 }
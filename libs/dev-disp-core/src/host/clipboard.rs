@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+use futures::{FutureExt, StreamExt, channel::mpsc, future};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    core::ErrorKind,
+    util::{PinnedLocalFuture, PinnedLocalStream},
+};
+
+/// UTF-8 text, which every clipboard should be able to hold.
+pub const CLIPBOARD_TEXT_MIME_TYPE: &str = "text/plain;charset=utf-8";
+
+/// Clipboard traffic, which looks the same in both directions. Content only
+/// travels when the other side asks for it, so copying something large costs
+/// nothing until it is pasted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ClipboardMessage {
+    /// The sender's clipboard changed, and now holds these types of content
+    Offer {
+        serial: u32,
+        mime_types: Vec<String>,
+    },
+    /// Asks for the content of one of the other side's offers
+    Request { serial: u32, mime_type: String },
+    /// The answer to a request. `None` when the offer is no longer current, or
+    /// the content couldn't be read.
+    Data {
+        serial: u32,
+        mime_type: String,
+        data: Option<Vec<u8>>,
+    },
+}
+
+impl Display for ClipboardMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClipboardMessage::Offer { serial, mime_types } => {
+                write!(f, "Offer (#{}, {})", serial, mime_types.join(", "))
+            }
+            ClipboardMessage::Request { serial, mime_type } => {
+                write!(f, "Request (#{}, {})", serial, mime_type)
+            }
+            ClipboardMessage::Data {
+                serial,
+                mime_type,
+                data,
+            } => match data {
+                Some(data) => write!(f, "Data (#{}, {}, {} bytes)", serial, mime_type, data.len()),
+                None => write!(f, "Data (#{}, {}, unavailable)", serial, mime_type),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ClipboardError {
+    #[error("No clipboard available: {0}")]
+    Unavailable(String),
+    /// The clipboard holds nothing of the type asked for
+    #[error("Clipboard has no {0} content")]
+    NoContent(String),
+    #[error("Unsupported clipboard type {0}")]
+    UnsupportedType(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ClipboardError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ClipboardError::Unavailable(_) => ErrorKind::Unavailable,
+            ClipboardError::NoContent(_) => ErrorKind::NotFound,
+            ClipboardError::UnsupportedType(_) => ErrorKind::Unsupported,
+            ClipboardError::Other(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+/// The host's clipboard.
+pub trait ClipboardBackend {
+    /// The types of content this clipboard can hold, most preferred first.
+    fn supported_mime_types(&self) -> Vec<String>;
+
+    /// Follows changes made to the clipboard by anyone but this backend, as the
+    /// types of content now on it. Only the first call gets the stream.
+    fn take_change_stream(&mut self) -> Option<PinnedLocalStream<'static, Vec<String>>>;
+
+    fn read(&mut self, mime_type: &str) -> PinnedLocalFuture<'_, Result<Vec<u8>, ClipboardError>>;
+
+    /// Replaces the clipboard's content.
+    fn write(
+        &mut self,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> PinnedLocalFuture<'_, Result<(), ClipboardError>>;
+}
+
+pub trait ClipboardProvider {
+    type BackendType: ClipboardBackend + 'static;
+
+    fn create_backend(&self) -> PinnedLocalFuture<'_, Result<Self::BackendType, ClipboardError>>;
+}
+
+#[derive(Debug, Default)]
+struct MemoryClipboardState {
+    contents: HashMap<String, Vec<u8>>,
+    /// Change listeners, by the backend they belong to
+    watchers: Vec<(usize, mpsc::UnboundedSender<Vec<String>>)>,
+    next_backend_id: usize,
+}
+
+impl MemoryClipboardState {
+    fn replace(&mut self, contents: HashMap<String, Vec<u8>>, changed_by: Option<usize>) {
+        self.contents = contents;
+        let mime_types: Vec<String> = self.contents.keys().cloned().collect();
+        self.watchers.retain(|(id, tx)| {
+            Some(*id) == changed_by || tx.unbounded_send(mime_types.clone()).is_ok()
+        });
+    }
+}
+
+/// A clipboard that only lives in memory. Useful for testing, and for hosts
+/// without a clipboard of their own. Every backend handed out by the same
+/// provider shares one clipboard, like sessions on a real host would.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryClipboardProvider {
+    state: Arc<Mutex<MemoryClipboardState>>,
+}
+
+impl MemoryClipboardProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Copies something to the clipboard, as if an application on the host did.
+    pub fn copy(&self, mime_type: impl Into<String>, data: Vec<u8>) {
+        if let Ok(mut state) = self.state.lock() {
+            state.replace(HashMap::from([(mime_type.into(), data)]), None);
+        }
+    }
+
+    /// The clipboard's content of the given type, if it has any.
+    pub fn contents(&self, mime_type: &str) -> Option<Vec<u8>> {
+        self.state
+            .lock()
+            .ok()
+            .and_then(|state| state.contents.get(mime_type).cloned())
+    }
+}
+
+impl ClipboardProvider for MemoryClipboardProvider {
+    type BackendType = MemoryClipboard;
+
+    fn create_backend(&self) -> PinnedLocalFuture<'_, Result<Self::BackendType, ClipboardError>> {
+        let backend = self
+            .state
+            .lock()
+            .map(|mut state| {
+                let id = state.next_backend_id;
+                state.next_backend_id += 1;
+                MemoryClipboard {
+                    id,
+                    state: self.state.clone(),
+                }
+            })
+            .map_err(|_| ClipboardError::Other("Memory clipboard lock was poisoned".to_string()));
+        future::ready(backend).boxed_local()
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryClipboard {
+    id: usize,
+    state: Arc<Mutex<MemoryClipboardState>>,
+}
+
+impl ClipboardBackend for MemoryClipboard {
+    fn supported_mime_types(&self) -> Vec<String> {
+        vec![
+            CLIPBOARD_TEXT_MIME_TYPE.to_string(),
+            "text/html".to_string(),
+        ]
+    }
+
+    fn take_change_stream(&mut self) -> Option<PinnedLocalStream<'static, Vec<String>>> {
+        let mut state = self.state.lock().ok()?;
+        if state.watchers.iter().any(|(id, _)| *id == self.id) {
+            return None;
+        }
+        let (tx, rx) = mpsc::unbounded();
+        state.watchers.push((self.id, tx));
+        Some(rx.boxed_local())
+    }
+
+    fn read(&mut self, mime_type: &str) -> PinnedLocalFuture<'_, Result<Vec<u8>, ClipboardError>> {
+        let contents = self
+            .state
+            .lock()
+            .map_err(|_| ClipboardError::Other("Memory clipboard lock was poisoned".to_string()))
+            .and_then(|state| {
+                state
+                    .contents
+                    .get(mime_type)
+                    .cloned()
+                    .ok_or_else(|| ClipboardError::NoContent(mime_type.to_string()))
+            });
+        future::ready(contents).boxed_local()
+    }
+
+    fn write(
+        &mut self,
+        mime_type: &str,
+        data: Vec<u8>,
+    ) -> PinnedLocalFuture<'_, Result<(), ClipboardError>> {
+        let result = self
+            .state
+            .lock()
+            .map(|mut state| {
+                state.replace(
+                    HashMap::from([(mime_type.to_string(), data)]),
+                    Some(self.id),
+                )
+            })
+            .map_err(|_| ClipboardError::Other("Memory clipboard lock was poisoned".to_string()));
+        future::ready(result).boxed_local()
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+use crate::host::{DamageRect, ScreenOutputParameters};
+
+/// The image of the mouse cursor.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CursorImage {
+    pub width: u32,
+    pub height: u32,
+
+    /// The pixel within the image that points at the cursor position
+    pub hot_x: u32,
+    pub hot_y: u32,
+
+    /// Tightly packed RGBA8888 pixels, without premultiplied alpha
+    pub pixels: Vec<u8>,
+}
+
+impl CursorImage {
+    /// The rectangle the image covers when the cursor is at (`x`, `y`), clipped to
+    /// a `width` x `height` screen.
+    pub fn rect_at(&self, x: i32, y: i32, width: u32, height: u32) -> Option<DamageRect> {
+        let left = (x as i64 - self.hot_x as i64).max(0);
+        let top = (y as i64 - self.hot_y as i64).max(0);
+        let right = (x as i64 - self.hot_x as i64 + self.width as i64).min(width as i64);
+        let bottom = (y as i64 - self.hot_y as i64 + self.height as i64).min(height as i64);
+        if right <= left || bottom <= top {
+            return None;
+        }
+        Some(DamageRect::new(
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        ))
+    }
+}
+
+/// A change to the mouse cursor, sent separately from screen frames.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum CursorUpdate {
+    /// The cursor's look changed
+    Image(CursorImage),
+    /// The cursor moved. The position is where the cursor points, in screen pixels,
+    /// and may be outside of the screen.
+    Move { x: i32, y: i32 },
+    /// The cursor was shown or hidden
+    Visibility(bool),
+}
+
+impl Display for CursorUpdate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CursorUpdate::Image(image) => write!(
+                f,
+                "Image ({}x{}, hotspot {},{})",
+                image.width, image.height, image.hot_x, image.hot_y
+            ),
+            CursorUpdate::Move { x, y } => write!(f, "Move ({}, {})", x, y),
+            CursorUpdate::Visibility(visible) => write!(f, "Visibility ({})", visible),
+        }
+    }
+}
+
+/// The cursor as it currently is, built up from `CursorUpdate`s. Used to draw the
+/// cursor into frames for clients that can't draw it themselves.
+#[derive(Debug, Clone, Default)]
+pub struct CursorState {
+    pub image: Option<CursorImage>,
+    pub x: i32,
+    pub y: i32,
+    pub visible: bool,
+}
+
+impl CursorState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The area of a `width` x `height` screen the cursor is drawn over, if any.
+    pub fn drawn_rect(&self, width: u32, height: u32) -> Option<DamageRect> {
+        if !self.visible {
+            return None;
+        }
+        self.image
+            .as_ref()
+            .and_then(|image| image.rect_at(self.x, self.y, width, height))
+    }
+
+    /// Applies an update, returning the areas of a `width` x `height` screen that
+    /// need to be redrawn because of it.
+    pub fn apply(&mut self, update: CursorUpdate, width: u32, height: u32) -> Vec<DamageRect> {
+        let before = self.drawn_rect(width, height);
+        let changed = match update {
+            CursorUpdate::Image(image) => {
+                let changed = self.image.as_ref() != Some(&image);
+                self.image = Some(image);
+                changed
+            }
+            CursorUpdate::Move { x, y } => {
+                let changed = (self.x, self.y) != (x, y);
+                self.x = x;
+                self.y = y;
+                changed
+            }
+            CursorUpdate::Visibility(visible) => {
+                let changed = self.visible != visible;
+                self.visible = visible;
+                changed
+            }
+        };
+
+        if !changed {
+            return Vec::new();
+        }
+        before
+            .into_iter()
+            .chain(self.drawn_rect(width, height))
+            .collect()
+    }
+
+    /// Draws the cursor onto a frame laid out as described by `params`.
+    pub fn composite_onto(&self, frame: &mut [u8], params: &ScreenOutputParameters) {
+        let Some(rect) = self.drawn_rect(params.width, params.height) else {
+            return;
+        };
+        let Some(image) = &self.image else {
+            return;
+        };
+
+        let bpp = params.format.bytes_per_pixel() as usize;
+        let stride = params.stride as usize;
+        let (r_offset, g_offset, b_offset) = params.format.rgb_offsets();
+        // Where the visible part of the cursor starts within the image
+        let image_x = (rect.x as i64 - (self.x as i64 - image.hot_x as i64)) as usize;
+        let image_y = (rect.y as i64 - (self.y as i64 - image.hot_y as i64)) as usize;
+
+        for row in 0..rect.height as usize {
+            let src_row = (image_y + row) * image.width as usize * 4;
+            let dst_row = (rect.y as usize + row) * stride;
+            for col in 0..rect.width as usize {
+                let src = src_row + (image_x + col) * 4;
+                let dst = dst_row + (rect.x as usize + col) * bpp;
+                let (Some(src), Some(dst)) = (
+                    image.pixels.get(src..src + 4),
+                    frame.get_mut(dst..dst + bpp),
+                ) else {
+                    continue;
+                };
+
+                let alpha = src[3] as u32;
+                if alpha == 0 {
+                    continue;
+                }
+                for (channel, offset) in [(0, r_offset), (1, g_offset), (2, b_offset)] {
+                    let blended =
+                        (src[channel] as u32 * alpha + dst[offset] as u32 * (255 - alpha)) / 255;
+                    dst[offset] = blended as u8;
+                }
+            }
+        }
+    }
+}
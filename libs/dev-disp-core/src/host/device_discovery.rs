@@ -1,19 +1,35 @@
-use std::{pin::Pin, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    pin::Pin,
+    time::Duration,
+};
 
 use futures_core::Stream;
-use futures_util::{FutureExt, StreamExt, stream::unfold};
+use futures_util::{
+    FutureExt, StreamExt, TryFutureExt,
+    future::ready,
+    stream::{iter, unfold},
+};
+use thiserror::Error;
 
 use crate::{
-    client::{DisplayHost, ScreenTransport},
-    util::PinnedFuture,
+    client::{DisplayHost, ScreenTransport, SomeScreenTransport},
+    core::SessionToken,
+    util::{PinnedFuture, PinnedLocalFuture, PinnedStream},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ConnectableDeviceInfo {
     pub name: String,
     pub device_type: String,
     pub id: String,
     pub description: Option<String>,
+    /// Set when the device was discovered but can't be connected to, such as when
+    /// it speaks an incompatible protocol. This is shown to the user as-is.
+    pub unavailable_reason: Option<String>,
+    /// The token of the session the device had before it lost its connection, if
+    /// it came back to resume it
+    pub resume_token: Option<SessionToken>,
 }
 
 /// A trait for something that can connect to a device and provide a host for screen
@@ -54,18 +70,94 @@ pub trait DeviceDiscovery {
     }
 }
 
+/// A change to the devices a discovery has found. Devices are told apart by the `id`
+/// of their info, which stays the same for as long as they are around.
+#[derive(Debug, Clone)]
+pub enum DeviceEvent<C> {
+    /// A device showed up.
+    Added(C),
+    /// A device that was already around has new info, like having become unavailable.
+    /// It replaces the one from before.
+    Changed(C),
+    /// The device with this ID is gone.
+    Removed(String),
+}
+
+impl<C> DeviceEvent<C> {
+    pub fn map<D>(self, f: impl FnOnce(C) -> D) -> DeviceEvent<D> {
+        match self {
+            DeviceEvent::Added(device) => DeviceEvent::Added(f(device)),
+            DeviceEvent::Changed(device) => DeviceEvent::Changed(f(device)),
+            DeviceEvent::Removed(id) => DeviceEvent::Removed(id),
+        }
+    }
+}
+
+/// Tells what changed between successive lists of found devices.
+#[derive(Debug, Default)]
+pub struct DeviceListDiff {
+    known: HashMap<String, ConnectableDeviceInfo>,
+}
+
+impl DeviceListDiff {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Events that get from the last list to `devices`. Only the first of devices
+    /// sharing an ID counts.
+    pub fn update<C: ConnectableDevice>(&mut self, devices: Vec<C>) -> Vec<DeviceEvent<C>> {
+        let mut events = Vec::new();
+        let mut found = HashMap::new();
+        for device in devices {
+            let info = device.get_info();
+            if found.contains_key(&info.id) {
+                continue;
+            }
+            match self.known.get(&info.id) {
+                None => events.push(DeviceEvent::Added(device)),
+                Some(known) if *known != info => events.push(DeviceEvent::Changed(device)),
+                Some(_) => {}
+            }
+            found.insert(info.id.clone(), info);
+        }
+        let removed = self.known.keys().filter(|id| !found.contains_key(*id));
+        events.extend(removed.cloned().map(DeviceEvent::Removed));
+        self.known = found;
+        events
+    }
+}
+
+/// Turns a stream of whole lists of found devices into events about what changed
+/// between them.
+pub fn diff_device_lists<C>(
+    lists: impl Stream<Item = Vec<C>> + Send + 'static,
+) -> PinnedStream<'static, DeviceEvent<C>>
+where
+    C: ConnectableDevice + Send + 'static,
+{
+    lists
+        .scan(DeviceListDiff::new(), |diff, devices| {
+            ready(Some(iter(diff.update(devices))))
+        })
+        .flatten()
+        .boxed()
+}
+
 /// Something that can discover devices and asynchronously automatically provide updates
 /// about new devices as they become available.
 pub trait StreamingDeviceDiscovery: DeviceDiscovery {
-    /// Convert this discovery into a stream of device lists.
+    /// Convert this discovery into a stream of changes to the available devices.
     ///
-    /// Each item yielded by the stream is a list of currently available devices.
-    /// The stream should yield a new list whenever the set of available devices changes.
-    fn into_stream(self) -> Pin<Box<dyn Stream<Item = Vec<Self::DeviceCandidate>> + Send>>;
+    /// The stream should yield an event whenever a device shows up, changes or goes
+    /// away. Discoveries that only know whole lists of devices can use
+    /// `diff_device_lists`.
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = DeviceEvent<Self::DeviceCandidate>> + Send>>;
 }
 
 /// Helper struct that wraps a DeviceDiscovery and polls it at regular intervals
-/// to provide a streaming device discovery.
+/// to provide a streaming device discovery. Successive polls are diffed, so only
+/// what changed between them is streamed.
 pub struct PollingDeviceDiscovery<D>
 where
     D: DeviceDiscovery,
@@ -112,7 +204,7 @@ where
     D: DeviceDiscovery + Send + 'static,
     <D as DeviceDiscovery>::DeviceCandidate: Send + 'static,
 {
-    fn into_stream(self) -> Pin<Box<dyn Stream<Item = Vec<Self::DeviceCandidate>> + Send>> {
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = DeviceEvent<Self::DeviceCandidate>> + Send>> {
         let discovery_stream = async move {
             let initial_discovery = self.inner.discover_devices().await;
 
@@ -126,41 +218,216 @@ where
         }
         .flatten_stream();
 
-        Box::pin(discovery_stream)
+        diff_device_lists(discovery_stream)
+    }
+}
+
+/// A connectable device with the type of its transport erased, so devices of every
+/// kind of discovery can be handled alike. Every `ConnectableDevice` is one.
+pub trait DynConnectableDevice: Send {
+    fn connect_boxed(
+        self: Box<Self>,
+    ) -> PinnedFuture<
+        'static,
+        Result<DisplayHost<SomeScreenTransport>, Box<dyn std::error::Error + Send + Sync>>,
+    >;
+
+    fn device_info(&self) -> ConnectableDeviceInfo;
+}
+
+impl<C> DynConnectableDevice for C
+where
+    C: ConnectableDevice + Send + 'static,
+    C::Transport: 'static,
+{
+    fn connect_boxed(
+        self: Box<Self>,
+    ) -> PinnedFuture<
+        'static,
+        Result<DisplayHost<SomeScreenTransport>, Box<dyn std::error::Error + Send + Sync>>,
+    > {
+        (*self)
+            .connect()
+            .map_ok(DisplayHost::to_some_transport)
+            .boxed()
+    }
+
+    fn device_info(&self) -> ConnectableDeviceInfo {
+        self.get_info()
     }
 }
 
-// TODO: Below is an attempt to generalize the device discovery, but there are issues
-// with traites with GATs and dyn-compatibility. There may be a way to fix this. Needs
-// further investigation.
+impl ConnectableDevice for Box<dyn DynConnectableDevice> {
+    type Transport = SomeScreenTransport;
 
-// pub struct GenericDeviceDiscovery {
-//     inner: Box<dyn StreamingDeviceDiscovery<DeviceFacade = GenericDeviceFacade>>,
-// }
+    fn connect(
+        self,
+    ) -> PinnedFuture<
+        'static,
+        Result<DisplayHost<Self::Transport>, Box<dyn std::error::Error + Send + Sync>>,
+    > {
+        <dyn DynConnectableDevice>::connect_boxed(self)
+    }
+
+    fn get_info(&self) -> ConnectableDeviceInfo {
+        (**self).device_info()
+    }
+}
 
-// pub struct GenericDeviceFacade {
-//     inner: Box<dyn ConnectableDevice<Transport = SomeScreenTransport>>,
-// }
+/// A streaming device discovery with the type of its devices erased, so discoveries
+/// can be picked at runtime and handled alike. Every `StreamingDeviceDiscovery` is
+/// one.
+pub trait DynDeviceDiscovery: Send {
+    fn discover_boxed(&self) -> PinnedFuture<'_, Vec<Box<dyn DynConnectableDevice>>>;
 
-// impl ConnectableDevice for GenericDeviceFacade {
-//     type Transport = SomeScreenTransport;
+    fn display_name(&self) -> String;
 
-//     fn connect(
-//         self,
-//     ) -> PinnedFuture<
-//         'static,
-//         Result<DisplayHost<Self::Transport>, Box<dyn std::error::Error + Send + Sync>>,
-//     > {
-//         (self.inner).connect()
-//     }
+    fn description(&self) -> Option<String>;
 
-//     fn get_info(&self) -> ConnectableDeviceInfo {
-//         self.inner.get_info()
-//     }
-// }
+    fn into_boxed_stream(
+        self: Box<Self>,
+    ) -> PinnedStream<'static, DeviceEvent<Box<dyn DynConnectableDevice>>>;
+}
 
-// impl DeviceDiscovery for GenericDeviceDiscovery {
-//     type DeviceFacade = GenericDeviceFacade;
+fn erase_device<C>(device: C) -> Box<dyn DynConnectableDevice>
+where
+    C: DynConnectableDevice + 'static,
+{
+    Box::new(device)
+}
 
-//     fn discover_devices(&self) -> PinnedFuture<'_, Vec<Self::DeviceFacade>> {}
-// }
+impl<D> DynDeviceDiscovery for D
+where
+    D: StreamingDeviceDiscovery + Send + 'static,
+    D::DeviceCandidate: Send + 'static,
+    <D::DeviceCandidate as ConnectableDevice>::Transport: 'static,
+{
+    fn discover_boxed(&self) -> PinnedFuture<'_, Vec<Box<dyn DynConnectableDevice>>> {
+        self.discover_devices()
+            .map(|devices| devices.into_iter().map(erase_device).collect())
+            .boxed()
+    }
+
+    fn display_name(&self) -> String {
+        self.get_display_name()
+    }
+
+    fn description(&self) -> Option<String> {
+        self.get_description()
+    }
+
+    fn into_boxed_stream(
+        self: Box<Self>,
+    ) -> PinnedStream<'static, DeviceEvent<Box<dyn DynConnectableDevice>>> {
+        (*self)
+            .into_stream()
+            .map(|event| event.map(erase_device))
+            .boxed()
+    }
+}
+
+impl DeviceDiscovery for Box<dyn DynDeviceDiscovery> {
+    type DeviceCandidate = Box<dyn DynConnectableDevice>;
+
+    fn discover_devices(&self) -> PinnedFuture<'_, Vec<Self::DeviceCandidate>> {
+        (**self).discover_boxed()
+    }
+
+    fn get_display_name(&self) -> String {
+        (**self).display_name()
+    }
+
+    fn get_description(&self) -> Option<String> {
+        (**self).description()
+    }
+}
+
+impl StreamingDeviceDiscovery for Box<dyn DynDeviceDiscovery> {
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = DeviceEvent<Self::DeviceCandidate>> + Send>> {
+        <dyn DynDeviceDiscovery>::into_boxed_stream(self)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum DiscoveryRegistryError {
+    #[error("No discovery named '{0}'")]
+    Unknown(String),
+    #[error("Failed to start discovery '{name}': {source}")]
+    Start {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// A discovery that was started, along with what has to keep running for it to find
+/// anything, like a listening socket.
+pub struct StartedDiscovery {
+    pub discovery: Box<dyn DynDeviceDiscovery>,
+    pub background: Option<PinnedLocalFuture<'static, ()>>,
+}
+
+impl StartedDiscovery {
+    pub fn new<D: DynDeviceDiscovery + 'static>(discovery: D) -> Self {
+        Self {
+            discovery: Box::new(discovery),
+            background: None,
+        }
+    }
+
+    pub fn with_background(self, background: impl Future<Output = ()> + 'static) -> Self {
+        Self {
+            background: Some(background.boxed_local()),
+            ..self
+        }
+    }
+}
+
+type DiscoveryFactory = Box<
+    dyn Fn() -> PinnedLocalFuture<
+        'static,
+        Result<StartedDiscovery, Box<dyn std::error::Error + Send + Sync>>,
+    >,
+>;
+
+/// Ways of discovering devices by name, so which of them run can be decided at
+/// runtime, like from a configuration file.
+#[derive(Default)]
+pub struct DeviceDiscoveryRegistry {
+    factories: BTreeMap<String, DiscoveryFactory>,
+}
+
+impl DeviceDiscoveryRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes `factory` start the discovery called `name`. A discovery registered
+    /// under a name that's taken replaces the one before.
+    pub fn register<F, Fut>(&mut self, name: &str, factory: F)
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = Result<StartedDiscovery, Box<dyn std::error::Error + Send + Sync>>>
+            + 'static,
+    {
+        self.factories
+            .insert(name.to_string(), Box::new(move || factory().boxed_local()));
+    }
+
+    /// Names of every registered discovery, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    pub async fn start(&self, name: &str) -> Result<StartedDiscovery, DiscoveryRegistryError> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| DiscoveryRegistryError::Unknown(name.to_string()))?;
+        factory()
+            .await
+            .map_err(|source| DiscoveryRegistryError::Start {
+                name: name.to_string(),
+                source,
+            })
+    }
+}
@@ -1,9 +1,14 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
-use futures::FutureExt;
+use futures::{FutureExt, future};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crate::util::PinnedLocalFuture;
+use crate::{
+    core::ErrorKind,
+    host::DamageRect,
+    util::{PinnedLocalFuture, unix_time_micros},
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum VirtualScreenPixelFormat {
@@ -15,6 +20,31 @@ pub enum VirtualScreenPixelFormat {
     Abgr8888,
 }
 
+impl VirtualScreenPixelFormat {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            VirtualScreenPixelFormat::Rgb888 | VirtualScreenPixelFormat::Bgr888 => 3,
+            VirtualScreenPixelFormat::Rgba8888
+            | VirtualScreenPixelFormat::Bgra8888
+            | VirtualScreenPixelFormat::Argb8888
+            | VirtualScreenPixelFormat::Abgr8888 => 4,
+        }
+    }
+
+    /// The byte offsets of the red, green and blue channels within a pixel, in
+    /// memory order. The packed DRM formats (`Argb8888`, `Abgr8888`) are little-endian.
+    pub fn rgb_offsets(&self) -> (usize, usize, usize) {
+        match self {
+            VirtualScreenPixelFormat::Rgb888
+            | VirtualScreenPixelFormat::Rgba8888
+            | VirtualScreenPixelFormat::Abgr8888 => (0, 1, 2),
+            VirtualScreenPixelFormat::Bgr888
+            | VirtualScreenPixelFormat::Bgra8888
+            | VirtualScreenPixelFormat::Argb8888 => (2, 1, 0),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScreenOutputParameters {
     /// Our intermediate pixel format representation.
@@ -57,6 +87,36 @@ pub struct EncoderContentParameters {
     pub encoder_input_parameters: ScreenOutputParameters,
 }
 
+/// The bitrate and frame rate an encoder should aim for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateTarget {
+    /// Bits per second
+    pub bitrate: u32,
+    pub fps: u32,
+}
+
+impl RateTarget {
+    pub fn new(bitrate: u32, fps: u32) -> Self {
+        Self { bitrate, fps }
+    }
+}
+
+/// How an encoder took a new rate.
+#[derive(Debug, Clone)]
+pub enum RateChange {
+    /// The stream carries on as it was
+    Live,
+    /// The encoder was opened again for the new rate, and starts a new stream of
+    /// this configuration with a keyframe
+    Reopened(EncoderPossibleConfiguration),
+}
+
+impl Default for RateTarget {
+    fn default() -> Self {
+        Self::new(8_000_000, 60)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncoderPossibleConfiguration {
     /// The name of the encoder, e.g., "h264_nvenc"
@@ -70,50 +130,209 @@ pub struct EncoderPossibleConfiguration {
     pub parameters: HashMap<String, String>,
 }
 
+/// Metadata that travels alongside every frame sent to a display host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameHeader {
+    /// Monotonically increasing frame number, starting at 0 for a session.
+    /// Gaps indicate dropped frames.
+    pub sequence: u64,
+
+    /// When the raw frame was captured from the screen, in microseconds since the
+    /// Unix epoch.
+    pub capture_timestamp_us: u64,
+
+    /// When the encoder finished encoding the frame, in microseconds since the
+    /// Unix epoch.
+    pub encode_timestamp_us: u64,
+
+    /// Whether this frame can be decoded without any previous frames.
+    pub keyframe: bool,
+
+    /// Incremented every time the encoder is (re)configured, so clients can tell
+    /// which codec configuration a frame belongs to.
+    pub config_generation: u32,
+}
+
+impl FrameHeader {
+    /// Creates a header for a frame captured right now.
+    pub fn new(sequence: u64, config_generation: u32) -> Self {
+        Self::captured_at(sequence, config_generation, unix_time_micros())
+    }
+
+    /// Creates a header for a frame captured at the given time, in microseconds
+    /// since the Unix epoch.
+    pub fn captured_at(sequence: u64, config_generation: u32, capture_timestamp_us: u64) -> Self {
+        Self {
+            sequence,
+            capture_timestamp_us,
+            encode_timestamp_us: capture_timestamp_us,
+            keyframe: false,
+            config_generation,
+        }
+    }
+
+    /// Marks this frame as encoded right now.
+    pub fn encoded(self, keyframe: bool) -> Self {
+        Self {
+            encode_timestamp_us: unix_time_micros(),
+            keyframe,
+            ..self
+        }
+    }
+}
+
+/// How long the steps of encoding the last frame took.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EncodeTimings {
+    /// Copying the raw frame into the encoder's buffer
+    pub copy: Duration,
+    /// Converting the frame to the encoder's pixel format
+    pub scale: Duration,
+    /// The encoder itself
+    pub encode: Duration,
+}
+
+/// A single encoded frame, along with its metadata.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EncodedFrame<'a> {
+    pub header: FrameHeader,
+    pub data: &'a [u8],
+}
+
+/// The encoded data for a single damaged region of a frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodedRegion<'a> {
+    /// Where this region lives on the screen
+    pub rect: DamageRect,
+    /// The encoded region payload
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Error)]
+pub enum EncoderError {
+    /// None of the configurations to pick from could be set up
+    #[error("No usable encoder configuration")]
+    NoUsableConfiguration,
+    #[error("Encoder '{0}' not found")]
+    NotFound(String),
+    #[error("Encoder not initialized")]
+    NotInitialized,
+    #[error("Not supported by this encoder: {0}")]
+    Unsupported(String),
+    #[error("Failed to initialize the encoder: {0}")]
+    Initialization(String),
+    #[error("Failed to encode: {0}")]
+    Encoding(String),
+}
+
+impl EncoderError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            EncoderError::NoUsableConfiguration | EncoderError::Unsupported(_) => {
+                ErrorKind::Unsupported
+            }
+            EncoderError::NotFound(_) => ErrorKind::Unavailable,
+            EncoderError::NotInitialized
+            | EncoderError::Initialization(_)
+            | EncoderError::Encoding(_) => ErrorKind::Internal,
+        }
+    }
+}
+
 pub trait Encoder {
     fn get_supported_configurations(
         &mut self,
         parameters: &EncoderContentParameters,
-    ) -> Result<Vec<EncoderPossibleConfiguration>, String>;
+    ) -> Result<Vec<EncoderPossibleConfiguration>, EncoderError>;
 
     /// Called first, to initialize the encoder with the given parameters.
     /// Must return the successfully initialized encoder configuration.
-    /// TODO: Better error type
     fn init(
         &mut self,
         parameters: EncoderContentParameters,
         preferred_encoders: Option<Vec<EncoderPossibleConfiguration>>,
-    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, String>>;
+    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, EncoderError>>;
 
-    /// Encodes a frame of raw data, returning the encoded data.
-    /// TODO: Better error type
+    /// Encodes a frame of raw data, returning the encoded data. The given header
+    /// should be marked as encoded with `FrameHeader::encoded` before being returned.
     /// TODO: Consider changing the future to be non-boxed if possible for performance
     fn encode<'s, 'a>(
         &'s mut self,
         raw_data: &'a [u8],
-    ) -> PinnedLocalFuture<'s, Result<&'s [u8], String>>
+        header: FrameHeader,
+    ) -> PinnedLocalFuture<'s, Result<EncodedFrame<'s>, EncoderError>>
     where
         'a: 's;
+
+    /// Makes the next encoded frame a keyframe, so a client that lost track of the
+    /// stream can pick it back up. Encoders whose every frame stands on its own can
+    /// ignore this.
+    fn force_keyframe(&mut self) {}
+
+    /// Changes the bitrate of an initialized encoder, ideally without starting over.
+    /// The frame rate is the session's to keep, by not handing the encoder the
+    /// frames it doesn't want sent.
+    fn set_rate(&mut self, _rate: RateTarget) -> Result<RateChange, EncoderError> {
+        Err(EncoderError::Unsupported("changing the rate".to_string()))
+    }
+
+    /// How long the steps of the last `encode` call took, for encoders that keep
+    /// track.
+    fn last_encode_timings(&self) -> Option<EncodeTimings> {
+        None
+    }
+
+    /// Whether this encoder can encode individual damaged regions of a frame with
+    /// `encode_regions`, instead of only whole frames.
+    fn supports_region_updates(&self) -> bool {
+        false
+    }
+
+    /// Encodes only the given regions of a frame of raw data. Only called when
+    /// `supports_region_updates` returns true.
+    fn encode_regions<'s, 'a>(
+        &'s mut self,
+        _raw_data: &'a [u8],
+        _regions: &[DamageRect],
+    ) -> PinnedLocalFuture<'s, Result<Vec<EncodedRegion<'s>>, EncoderError>>
+    where
+        'a: 's,
+    {
+        future::ready(Err(EncoderError::Unsupported("region updates".to_string()))).boxed_local()
+    }
 }
 
 pub trait EncoderProvider {
     type EncoderType: Encoder + 'static;
 
-    fn init(&mut self) -> PinnedLocalFuture<'_, Result<(), String>> {
+    fn init(&mut self) -> PinnedLocalFuture<'_, Result<(), EncoderError>> {
         async move { Ok(()) }.boxed_local()
     }
 
-    // TODO: Better error type, async!
-    fn create_encoder(&self) -> PinnedLocalFuture<'_, Result<Self::EncoderType, String>>;
+    fn create_encoder(&self) -> PinnedLocalFuture<'_, Result<Self::EncoderType, EncoderError>>;
 }
 
-pub struct RawEncoder;
+/// An encoder that passes raw screen data through untouched.
+///
+/// Since raw data has no inter-frame state, this encoder can also cut individual
+/// damaged regions out of a frame.
+#[derive(Debug, Default)]
+pub struct RawEncoder {
+    input_parameters: Option<ScreenOutputParameters>,
+    region_buf: Vec<u8>,
+}
+
+impl RawEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
 
 impl Encoder for RawEncoder {
     fn get_supported_configurations(
         &mut self,
         screen_parameters: &EncoderContentParameters,
-    ) -> Result<Vec<EncoderPossibleConfiguration>, String> {
+    ) -> Result<Vec<EncoderPossibleConfiguration>, EncoderError> {
         Ok(vec![EncoderPossibleConfiguration {
             encoder_name: "raw".to_string(),
             encoder_family: "raw".to_string(),
@@ -126,7 +345,8 @@ impl Encoder for RawEncoder {
         &mut self,
         screen_parameters: EncoderContentParameters,
         _preferred_encoders: Option<Vec<EncoderPossibleConfiguration>>,
-    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, String>> {
+    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, EncoderError>> {
+        self.input_parameters = Some(screen_parameters.encoder_input_parameters.clone());
         async move {
             // No initialization needed for raw encoder
             Ok(EncoderPossibleConfiguration {
@@ -142,13 +362,76 @@ impl Encoder for RawEncoder {
     fn encode<'s, 'a>(
         &'s mut self,
         raw_data: &'a [u8],
-    ) -> PinnedLocalFuture<'s, Result<&'s [u8], String>>
+        header: FrameHeader,
+    ) -> PinnedLocalFuture<'s, Result<EncodedFrame<'s>, EncoderError>>
     where
         'a: 's,
     {
         async move {
-            // For raw encoder, just return the input data as is
-            Ok(raw_data)
+            // For raw encoder, just return the input data as is. Every raw frame
+            // stands on its own.
+            Ok(EncodedFrame {
+                header: header.encoded(true),
+                data: raw_data,
+            })
+        }
+        .boxed_local()
+    }
+
+    fn supports_region_updates(&self) -> bool {
+        true
+    }
+
+    fn encode_regions<'s, 'a>(
+        &'s mut self,
+        raw_data: &'a [u8],
+        regions: &[DamageRect],
+    ) -> PinnedLocalFuture<'s, Result<Vec<EncodedRegion<'s>>, EncoderError>>
+    where
+        'a: 's,
+    {
+        let regions = regions.to_vec();
+        async move {
+            let params = self
+                .input_parameters
+                .as_ref()
+                .ok_or(EncoderError::NotInitialized)?;
+            let bpp = params.format.bytes_per_pixel() as usize;
+            let stride = params.stride as usize;
+
+            // Copy every region into one tightly-packed buffer first, then hand out
+            // slices of it once we're done growing it.
+            self.region_buf.clear();
+            let mut spans = Vec::with_capacity(regions.len());
+            for rect in regions
+                .iter()
+                .filter_map(|r| r.clip_to(params.width, params.height))
+            {
+                let start = self.region_buf.len();
+                let row_len = rect.width as usize * bpp;
+                for row in rect.y..rect.y + rect.height {
+                    let src_start = row as usize * stride + rect.x as usize * bpp;
+                    let src = raw_data
+                        .get(src_start..src_start + row_len)
+                        .ok_or_else(|| {
+                            EncoderError::Encoding(format!(
+                                "Region {:?} is outside of the frame",
+                                rect
+                            ))
+                        })?;
+                    self.region_buf.extend_from_slice(src);
+                }
+                spans.push((rect, start..self.region_buf.len()));
+            }
+
+            let region_buf = &self.region_buf;
+            Ok(spans
+                .into_iter()
+                .map(|(rect, span)| EncodedRegion {
+                    rect,
+                    data: &region_buf[span],
+                })
+                .collect())
         }
         .boxed_local()
     }
@@ -0,0 +1,169 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{FutureExt, future};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    core::{ErrorKind, InputEvent, NormalizedPosition},
+    util::PinnedLocalFuture,
+};
+
+/// Where a virtual screen sits on the host's desktop, in pixels.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DesktopPlacement {
+    /// Position of the screen's top-left corner on the desktop
+    pub x: i32,
+    pub y: i32,
+
+    /// Size of the whole desktop, spanning every screen
+    pub desktop_width: u32,
+    pub desktop_height: u32,
+}
+
+/// The screen that injected input should land on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputTarget {
+    /// Size of the virtual screen in pixels
+    pub width: u32,
+    pub height: u32,
+
+    /// Where the screen is on the desktop, if known. When it isn't known, the
+    /// absolute coordinates only span the screen itself, and it's up to the
+    /// compositor to map the input device onto the right output.
+    pub placement: Option<DesktopPlacement>,
+}
+
+impl InputTarget {
+    pub fn new(width: u32, height: u32, placement: Option<DesktopPlacement>) -> Self {
+        Self {
+            width,
+            height,
+            placement,
+        }
+    }
+
+    /// The size of the absolute coordinate space, in pixels.
+    pub fn absolute_size(&self) -> (u32, u32) {
+        match self.placement {
+            Some(p) => (p.desktop_width, p.desktop_height),
+            None => (self.width, self.height),
+        }
+    }
+
+    /// Maps a normalized position on the client's view of the screen to an absolute
+    /// position in the coordinate space given by `absolute_size`.
+    pub fn to_absolute(&self, position: NormalizedPosition) -> (i32, i32) {
+        let position = position.clamped();
+        let (offset_x, offset_y) = self.placement.map(|p| (p.x, p.y)).unwrap_or((0, 0));
+        // The last pixel is at `size - 1`, so a position of 1.0 stays on the screen
+        let x = (position.x * self.width.saturating_sub(1) as f32).round() as i32;
+        let y = (position.y * self.height.saturating_sub(1) as f32).round() as i32;
+        (offset_x + x, offset_y + y)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum InputError {
+    #[error("Not allowed to inject input: {0}")]
+    PermissionDenied(String),
+    #[error("No input injection available: {0}")]
+    Unavailable(String),
+    /// The client sent an event that can't be injected, like an unknown key
+    #[error("Invalid input event: {0}")]
+    InvalidEvent(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl InputError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            InputError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            InputError::Unavailable(_) => ErrorKind::Unavailable,
+            InputError::InvalidEvent(_) => ErrorKind::Unsupported,
+            InputError::Other(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+/// Something that can inject input events into the host, such as a virtual
+/// input device.
+pub trait InputInjector {
+    fn inject(&mut self, event: InputEvent) -> Result<(), InputError>;
+}
+
+pub trait InputInjectorProvider {
+    type InjectorType: InputInjector + 'static;
+
+    fn create_injector(
+        &self,
+        target: InputTarget,
+    ) -> PinnedLocalFuture<'_, Result<Self::InjectorType, InputError>>;
+}
+
+/// An injector that only records the events it is given. Useful for testing, and
+/// for hosts that shouldn't receive input at all.
+#[derive(Debug, Clone, Default)]
+pub struct MockInputInjector {
+    target: Option<InputTarget>,
+    events: Arc<Mutex<Vec<InputEvent>>>,
+}
+
+impl MockInputInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The target this injector was created for, if it came from a provider.
+    pub fn target(&self) -> Option<InputTarget> {
+        self.target
+    }
+
+    /// All events injected so far, by this injector and any of its clones.
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.events.lock().map(|e| e.clone()).unwrap_or_default()
+    }
+}
+
+impl InputInjector for MockInputInjector {
+    fn inject(&mut self, event: InputEvent) -> Result<(), InputError> {
+        self.events
+            .lock()
+            .map_err(|_| InputError::Other("Mock input injector lock was poisoned".to_string()))?
+            .push(event);
+        Ok(())
+    }
+}
+
+/// Hands out `MockInputInjector`s that all record into the same shared list.
+#[derive(Debug, Clone, Default)]
+pub struct MockInputInjectorProvider {
+    injector: MockInputInjector,
+}
+
+impl MockInputInjectorProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// All events injected so far, by every injector this provider created.
+    pub fn events(&self) -> Vec<InputEvent> {
+        self.injector.events()
+    }
+}
+
+impl InputInjectorProvider for MockInputInjectorProvider {
+    type InjectorType = MockInputInjector;
+
+    fn create_injector(
+        &self,
+        target: InputTarget,
+    ) -> PinnedLocalFuture<'_, Result<Self::InjectorType, InputError>> {
+        let injector = MockInputInjector {
+            target: Some(target),
+            events: self.injector.events.clone(),
+        };
+        future::ready(Ok(injector)).boxed_local()
+    }
+}
@@ -1,7 +1,13 @@
+mod clipboard;
+mod cursor;
 mod device_discovery;
 mod encoder;
+mod input_injector;
 mod screen_provider;
 
+pub use clipboard::*;
+pub use cursor::*;
 pub use device_discovery::*;
 pub use encoder::*;
+pub use input_injector::*;
 pub use screen_provider::*;
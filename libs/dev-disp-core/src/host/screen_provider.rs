@@ -1,25 +1,85 @@
 use std::fmt::Display;
 
 use edid::{
-    Edid, EdidDigitalBitDepth, EdidDigitalVideoInterface, EdidEstablishedTimingSupport,
-    descriptors::{DigitalSyncFlags, EdidDescriptor},
+    Edid, EdidDigitalBitDepth, EdidDigitalVideoInterface, EdidDpmsFeatures,
+    EdidEstablishedTimingSupport,
+    descriptors::{EdidDescriptor, EdidDetailedTimingDescriptor},
 };
 use futures::{FutureExt, future};
 use log::debug;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use crate::{
     client::DisplayHost,
-    host::ScreenOutputParameters,
-    util::{PinnedFuture, PinnedLocalFuture},
+    core::{ErrorKind, SessionError},
+    host::{CursorUpdate, DesktopPlacement, ScreenOutputParameters},
+    util::{PinnedFuture, PinnedLocalFuture, PinnedLocalStream},
 };
 
-pub type DisplayHostResult<T> = Result<DisplayHost<T>, String>;
+pub type DisplayHostResult<T> = Result<DisplayHost<T>, SessionError>;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Refresh rate assumed when a display host doesn't report one.
+pub const DEFAULT_REFRESH_RATE_HZ: u32 = 60;
+
+/// Bits per color channel assumed when a display host doesn't report them.
+pub const DEFAULT_BIT_DEPTH: u8 = 8;
+
+/// The pixel density desktops treat as a scale of 1.
+const BASELINE_DPI: f64 = 96.0;
+
+const MM_PER_INCH: f64 = 25.4;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct DisplayParameters {
     pub host_dev_name: String,
     pub resolution: (u32, u32),
+    pub characteristics: DisplayCharacteristics,
+}
+
+/// What a display host knows about its physical screen, so the virtual screen
+/// can describe itself truthfully to the desktop. Anything left out is guessed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DisplayCharacteristics {
+    /// Size of the visible area, in millimeters
+    pub physical_size_mm: Option<(u32, u32)>,
+    /// Pixel density, used when the physical size isn't known
+    pub dpi: Option<u32>,
+    pub refresh_rate_hz: Option<u32>,
+    /// Bits per color channel
+    pub bit_depth: Option<u8>,
+    /// Scale factor the host would like, in percent (`150` is 1.5x). Used to
+    /// size the screen when neither its physical size nor its DPI are known.
+    pub preferred_scale_percent: Option<u32>,
+}
+
+impl DisplayParameters {
+    /// Size of the visible area in millimeters. Worked out from the DPI or the
+    /// preferred scale when the host didn't report it.
+    pub fn physical_size_mm(&self) -> (u32, u32) {
+        if let Some(size) = self.characteristics.physical_size_mm {
+            return size;
+        }
+        let dpi = match self.characteristics.dpi {
+            Some(dpi) => dpi as f64,
+            None => {
+                let scale = self.characteristics.preferred_scale_percent.unwrap_or(100);
+                BASELINE_DPI * scale as f64 / 100.0
+            }
+        };
+        let to_mm = |px: u32| (px as f64 / dpi.max(1.0) * MM_PER_INCH).round() as u32;
+        (to_mm(self.resolution.0), to_mm(self.resolution.1))
+    }
+
+    pub fn refresh_rate_hz(&self) -> u32 {
+        self.characteristics
+            .refresh_rate_hz
+            .unwrap_or(DEFAULT_REFRESH_RATE_HZ)
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.characteristics.bit_depth.unwrap_or(DEFAULT_BIT_DEPTH)
+    }
 }
 
 impl Display for DisplayParameters {
@@ -27,60 +87,118 @@ impl Display for DisplayParameters {
         let name = &self.host_dev_name;
         let w = self.resolution.0;
         let h = self.resolution.1;
-        write!(f, "{name} ({w}x{h})")
+        let hz = self.refresh_rate_hz();
+        write!(f, "{name} ({w}x{h} @ {hz} Hz)")
     }
 }
 
 impl From<DisplayParameters> for Edid {
     fn from(params: DisplayParameters) -> Self {
+        let (width_mm, height_mm) = params.physical_size_mm();
+        // The base block only has room for whole centimeters
+        let to_cm = |mm: u32| (mm as f64 / 10.0).round().clamp(1.0, 255.0) as u8;
+        // Detailed timings have 12 bits for each
+        let to_image_size = |mm: u32| mm.min(0xFFF) as u16;
+
         Edid {
             display_parameters: edid::EdidDisplayParameters::Digital((
-                EdidDigitalBitDepth::Eight,
+                EdidDigitalBitDepth::from_bits_per_channel(params.bit_depth()),
                 EdidDigitalVideoInterface::DisplayPort,
             )),
+            width: to_cm(width_mm),
+            height: to_cm(height_mm),
+            dpms_features: EdidDpmsFeatures {
+                preferred_timing_mode: true,
+                ..Default::default()
+            },
             descriptor_1: Some(EdidDescriptor::DetailedTiming(
-                edid::descriptors::EdidDetailedTimingDescriptor {
-                    pixel_clock: 14850,
-                    horizontal_active_pixels: params.resolution.0 as u16,
-                    vertical_active_lines: params.resolution.1 as u16,
-
-                    // I totally guessed with the rest of these values. They
-                    // may not matter for our use case using a virtual display.
-                    // TODO: Properly calculate these values using a timing calculator!
-                    // https://edidcraft.com/?tab=timing-calculator-tab
-                    horizontal_blanking_pixels: 100,
-                    vertical_blanking_lines: 25,
-                    horizontal_sync_offset: 10,
-                    horizontal_sync_pulse_width: 5,
-                    vertical_sync_offset: 10,
-                    vertical_sync_pulse_width: 5,
-                    horizontal_image_size_mm: 100,
-                    vertical_image_size_mm: 50,
-                    horizontal_border: 0,
-                    vertical_border: 0,
-                    features: edid::descriptors::FeaturesMap {
-                        signal_type: edid::descriptors::SignalInterfaceType::NonInterlaced,
-                        stereo_mode: edid::descriptors::StereoMode::BiInterleavedLeftImageEvenLines,
-                        sync_type: edid::descriptors::SyncType::Digital(DigitalSyncFlags {
-                            ..Default::default()
-                        }),
-                    },
-                },
+                EdidDetailedTimingDescriptor::reduced_blanking(
+                    params.resolution.0 as u16,
+                    params.resolution.1 as u16,
+                    params.refresh_rate_hz(),
+                    (to_image_size(width_mm), to_image_size(height_mm)),
+                ),
             )),
             ..Default::default()
         }
     }
 }
 
+#[derive(Debug, Error)]
+pub enum ScreenError {
+    #[error("Not allowed to create a virtual screen: {0}")]
+    PermissionDenied(String),
+    #[error("No virtual screen available: {0}")]
+    Unavailable(String),
+    /// The screen works in a way we can't deal with, like an unknown pixel format
+    #[error("Unsupported virtual screen: {0}")]
+    Unsupported(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl ScreenError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ScreenError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            ScreenError::Unavailable(_) => ErrorKind::Unavailable,
+            ScreenError::Unsupported(_) => ErrorKind::Unsupported,
+            ScreenError::Other(_) => ErrorKind::Internal,
+        }
+    }
+}
+
 /// A screen provider is something that provides a screen
 pub trait ScreenProvider: Clone + Send + Sync + 'static {
     type ScreenType: Screen;
 
-    // TODO: Better error type!
     fn get_screen(
         &self,
         params: DisplayParameters,
-    ) -> impl Future<Output = Result<Self::ScreenType, String>>;
+    ) -> impl Future<Output = Result<Self::ScreenType, ScreenError>>;
+}
+
+/// A rectangular region of a screen, in pixels, with the origin at the top-left.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DamageRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DamageRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    /// Clip this rectangle to the bounds of a `width` x `height` screen. Returns `None`
+    /// if nothing of the rectangle is left after clipping.
+    pub fn clip_to(&self, width: u32, height: u32) -> Option<Self> {
+        if self.x >= width || self.y >= height {
+            return None;
+        }
+        let clipped = Self {
+            x: self.x,
+            y: self.y,
+            width: self.width.min(width - self.x),
+            height: self.height.min(height - self.y),
+        };
+        if clipped.area() == 0 {
+            None
+        } else {
+            Some(clipped)
+        }
+    }
 }
 
 pub enum ScreenReadyStatus {
@@ -97,17 +215,49 @@ pub trait Screen {
 
     /// Background task started before the screen is used during looping. Cannot
     /// hold onto self reference.
-    fn background<'s, 'a>(&'s mut self) -> PinnedFuture<'a, Result<(), String>> {
+    fn background<'s, 'a>(&'s mut self) -> PinnedFuture<'a, Result<(), ScreenError>> {
         debug!("Default screen background impl");
         future::ready(Ok(())).boxed()
     }
 
-    // TODO: Better error type!
-    fn get_ready(&mut self) -> impl Future<Output = Result<ScreenReadyStatus, String>>;
+    fn get_ready(&mut self) -> impl Future<Output = Result<ScreenReadyStatus, ScreenError>>;
     fn get_bytes(&self) -> Option<&[u8]>;
 
-    // TODO: Better error type!
-    fn close(self) -> PinnedLocalFuture<'static, Result<(), String>>
+    /// The regions of the screen that changed for the frame last made ready.
+    ///
+    /// `None` means the damage is not known, and the whole frame must be treated as
+    /// changed. An empty list means nothing changed since the last frame.
+    fn get_damage(&self) -> Option<Vec<DamageRect>> {
+        None
+    }
+
+    /// Where this screen sits on the host's desktop, used to aim injected input.
+    /// `None` if the screen provider can't tell.
+    fn get_desktop_placement(&self) -> Option<DesktopPlacement> {
+        None
+    }
+
+    /// Takes the stream of cursor changes, for screens that keep the cursor out of
+    /// their frames. `None` means the cursor is already drawn into the frames, or
+    /// the stream was already taken.
+    fn take_cursor_stream(&mut self) -> Option<PinnedLocalStream<'static, CursorUpdate>> {
+        None
+    }
+
+    /// Takes new display parameters in place, like a new EDID for the same device,
+    /// for when the display host changes its own. A new cursor stream may be ready
+    /// to take afterwards. Screens that can't be reconfigured are replaced by a new
+    /// one from their provider instead.
+    fn reconfigure(
+        &mut self,
+        params: DisplayParameters,
+    ) -> impl Future<Output = Result<(), ScreenError>> {
+        future::ready(Err(ScreenError::Unsupported(format!(
+            "Can't reconfigure the screen for {params}"
+        ))))
+    }
+
+    fn close(self) -> PinnedLocalFuture<'static, Result<(), ScreenError>>
     where
         // Hmm, what happens when we `Box<dyn Screen>`?
         Self: Sized,
@@ -115,3 +265,39 @@ pub trait Screen {
         future::ready(Ok(())).boxed()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::DamageRect;
+
+    #[test]
+    fn test_clip_to_keeps_rects_inside() {
+        let rect = DamageRect::new(10, 20, 30, 40);
+        assert_eq!(rect.clip_to(100, 100), Some(rect));
+
+        let full = DamageRect::new(0, 0, 100, 100);
+        assert_eq!(full.clip_to(100, 100), Some(full));
+    }
+
+    #[test]
+    fn test_clip_to_cuts_rects_straddling_the_edge() {
+        assert_eq!(
+            DamageRect::new(90, 95, 20, 20).clip_to(100, 100),
+            Some(DamageRect::new(90, 95, 10, 5))
+        );
+        assert_eq!(
+            DamageRect::new(99, 99, 5, 5).clip_to(100, 100),
+            Some(DamageRect::new(99, 99, 1, 1))
+        );
+    }
+
+    #[test]
+    fn test_clip_to_drops_empty_and_outside_rects() {
+        assert_eq!(DamageRect::new(10, 10, 0, 5).clip_to(100, 100), None);
+        assert_eq!(DamageRect::new(10, 10, 5, 0).clip_to(100, 100), None);
+        assert_eq!(DamageRect::new(100, 10, 5, 5).clip_to(100, 100), None);
+        assert_eq!(DamageRect::new(10, 100, 5, 5).clip_to(100, 100), None);
+        assert_eq!(DamageRect::new(200, 200, 5, 5).clip_to(100, 100), None);
+        assert_eq!(DamageRect::new(0, 0, 5, 5).clip_to(0, 0), None);
+    }
+}
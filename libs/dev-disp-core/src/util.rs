@@ -1,5 +1,8 @@
 use futures::Stream;
-use std::pin::Pin;
+use std::{
+    pin::Pin,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 pub type PinnedFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
 
@@ -9,3 +12,11 @@ pub type PinnedLocalFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
 pub type PinnedStream<'a, T> = Pin<Box<dyn Stream<Item = T> + Send + 'a>>;
 
 pub type PinnedLocalStream<'a, T> = Pin<Box<dyn Stream<Item = T> + 'a>>;
+
+/// The current time in microseconds since the Unix epoch.
+pub fn unix_time_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
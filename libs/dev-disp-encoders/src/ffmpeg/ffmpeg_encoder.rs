@@ -2,8 +2,9 @@ use std::{fmt::Debug, time::{Duration, Instant}};
 
 use dev_disp_core::{
     host::{
-        Encoder as DevDispEncoder, EncoderContentParameters, EncoderPossibleConfiguration,
-        EncoderProvider,
+        EncodedFrame, Encoder as DevDispEncoder, EncoderContentParameters, EncoderError,
+        EncodeTimings, EncoderPossibleConfiguration, EncoderProvider, FrameHeader, RateChange,
+        RateTarget,
     },
     util::PinnedLocalFuture,
 };
@@ -21,13 +22,21 @@ use crate::{
     util::ffmpeg_format_from_internal_format,
 };
 
+/// Encoders that pick up a new bitrate on the next frame, without being opened
+/// again. Everything else is opened again with the new rate.
+const LIVE_BITRATE_ENCODERS: &[&str] = &["libx264", "h264_nvenc", "hevc_nvenc", "av1_nvenc"];
+
 struct FfmpegEncoderState {
     encoder: VideoEncoder,
+    configuration: FfmpegEncoderConfiguration,
     scaler: Option<ScalingContext>,
     encoder_fmt: Pixel,
     given_params: EncoderContentParameters,
     frame_index: u64,
     out_buf: Vec<u8>,
+    /// Whether the next frame must be a keyframe
+    force_keyframe: bool,
+    last_timings: Option<EncodeTimings>,
 }
 
 impl Debug for FfmpegEncoderState {
@@ -36,12 +45,17 @@ impl Debug for FfmpegEncoderState {
             .field("encoder_fmt", &self.encoder_fmt)
             .field("given_params", &self.given_params)
             .field("frame_index", &self.frame_index)
+            .field("force_keyframe", &self.force_keyframe)
             .field("encoder", &format!("video::Encoder@{:p}", &self.encoder))
             .field("scaler", &format!("scaling::Context@{:p}", &self.scaler))
             .finish()
     }
 }
 
+/// Encodes whole frames with one of the configured ffmpeg encoders.
+///
+/// Doesn't do region updates: the video codecs it drives can't decode a region on
+/// its own. Only the `RawEncoder` can, for now.
 #[derive(Debug, Default)]
 pub struct FfmpegEncoder {
     state: Option<FfmpegEncoderState>,
@@ -51,21 +65,26 @@ pub struct FfmpegEncoder {
 pub fn setup_ffmpeg_encoder(
     parameters: &EncoderContentParameters,
     configuration: &FfmpegEncoderConfiguration,
-) -> Result<VideoEncoder, String> {
+) -> Result<VideoEncoder, EncoderError> {
     let codec = ffmpeg::encoder::find_by_name(&configuration.encoder_name)
-        .ok_or_else(|| format!("Encoder '{}' not found", configuration.encoder_name))?;
+        .ok_or_else(|| EncoderError::NotFound(configuration.encoder_name.clone()))?;
 
     debug!("Initializing ffmpeg encoder: {}", codec.name(),);
 
     let mut context = ffmpeg::codec::context::Context::new_with_codec(codec)
         .encoder()
         .video()
-        .map_err(|e| format!("Failed to create video codec context: {}", e))?;
+        .map_err(|e| {
+            EncoderError::Initialization(format!("Failed to create video codec context: {}", e))
+        })?;
 
     context.set_height(parameters.height);
     context.set_width(parameters.width);
     context.set_format(configuration.pixel_format);
     context.set_time_base((1, parameters.fps as i32));
+    context.set_frame_rate(Some((parameters.fps as i32, 1)));
+    context.set_bit_rate(parameters.bitrate as usize);
+    context.set_max_bit_rate(parameters.bitrate as usize);
 
     
     
@@ -78,7 +97,7 @@ pub fn setup_ffmpeg_encoder(
     let options = Dictionary::from_iter(configuration.encoder_options.clone().into_iter());
     context
         .open_with(options)
-        .map_err(|e| format!("Failed to open encoder: {}", e))
+        .map_err(|e| EncoderError::Initialization(format!("Failed to open encoder: {}", e)))
 }
 
 impl FfmpegEncoder {
@@ -94,18 +113,26 @@ impl FfmpegEncoder {
         &mut self,
         parameters: EncoderContentParameters,
         configuration: FfmpegEncoderConfiguration,
-    ) -> Result<FfmpegEncoderState, String> {
+    ) -> Result<FfmpegEncoderState, EncoderError> {
         let encoder = setup_ffmpeg_encoder(&parameters, &configuration)?;
 
         let src_format =
             ffmpeg_format_from_internal_format(&parameters.encoder_input_parameters.format);
         let dst_format = configuration.pixel_format;
 
-        // If the source format matches the encoder's required format, no
-        // scaling required (since we are not changing resolution here).
-        let scaler = if dst_format == src_format {
+        // If the source format and size match what the encoder wants, no
+        // scaling required.
+        let resized = parameters.width != parameters.encoder_input_parameters.width
+            || parameters.height != parameters.encoder_input_parameters.height;
+        let scaler = if dst_format == src_format && !resized {
             None
         } else {
+            // Point sampling is only good enough when the pixels don't move
+            let flags = if resized {
+                ffmpeg::software::scaling::flag::Flags::BILINEAR
+            } else {
+                ffmpeg::software::scaling::flag::Flags::POINT
+            };
             Some(
                 ScalingContext::get(
                     src_format,
@@ -114,9 +141,11 @@ impl FfmpegEncoder {
                     configuration.pixel_format,
                     parameters.width,
                     parameters.height,
-                    ffmpeg::software::scaling::flag::Flags::POINT,
+                    flags,
                 )
-                .map_err(|e| format!("Failed to create scaler: {}", e))?,
+                .map_err(|e| {
+                    EncoderError::Initialization(format!("Failed to create scaler: {}", e))
+                })?,
             )
         };
 
@@ -127,24 +156,40 @@ impl FfmpegEncoder {
 
         let state = FfmpegEncoderState {
             encoder,
+            configuration,
             scaler,
             given_params: parameters,
             frame_index: 0,
             encoder_fmt: configuration.pixel_format,
             // 16 KB initial buffer size for output
             out_buf: Vec::with_capacity(1024 * 16),
+            force_keyframe: false,
+            last_timings: None,
         };
 
         Ok(state)
     }
 }
 
+/// The configuration an opened encoder is told to the client with.
+fn possible_configuration(
+    configuration: &FfmpegEncoderConfiguration,
+    state: &FfmpegEncoderState,
+) -> EncoderPossibleConfiguration {
+    EncoderPossibleConfiguration {
+        encoder_name: configuration.encoder_name.clone(),
+        encoder_family: configuration.encoder_family.clone(),
+        encoded_resolution: (state.given_params.width, state.given_params.height),
+        parameters: get_relevant_codec_parameters(configuration, &state.encoder),
+    }
+}
+
 impl DevDispEncoder for FfmpegEncoder {
 
     fn get_supported_configurations(
         &mut self,
         parameters: &EncoderContentParameters,
-    ) -> Result<Vec<EncoderPossibleConfiguration>, String> {
+    ) -> Result<Vec<EncoderPossibleConfiguration>, EncoderError> {
 
         // TODO: Try encoders in the provider, not here on every connection!
 
@@ -184,9 +229,11 @@ impl DevDispEncoder for FfmpegEncoder {
         &mut self,
         parameters: EncoderContentParameters,
         preferred_encoders: Option<Vec<EncoderPossibleConfiguration>>,
-    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, String>> {
+    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, EncoderError>> {
         async move {
-            ffmpeg::init().map_err(|e| format!("Failed to initialize ffmpeg: {}", e))?;
+            ffmpeg::init().map_err(|e| {
+                EncoderError::Initialization(format!("Failed to initialize ffmpeg: {}", e))
+            })?;
 
             let mut encoders: Box<dyn Iterator<Item = FfmpegEncoderConfiguration>>;
 
@@ -239,16 +286,7 @@ impl DevDispEncoder for FfmpegEncoder {
                             has_scaler_str
                         );
 
-                        let codec_params =
-                            get_relevant_codec_parameters(&configuration, &state.encoder);
-
-
-                        let configuration = EncoderPossibleConfiguration {
-                            encoder_name: configuration.encoder_name,
-                            encoder_family: configuration.encoder_family,
-                            encoded_resolution: (parameters.width, parameters.height),
-                            parameters: codec_params,
-                        };
+                        let configuration = possible_configuration(&configuration, &state);
 
                         self.state = Some(state);
 
@@ -263,20 +301,67 @@ impl DevDispEncoder for FfmpegEncoder {
                 }
             }
 
-            Err("Failed to find a codec to use!".to_string())
+            Err(EncoderError::NoUsableConfiguration)
         }
         .boxed_local()
     }
 
+    fn force_keyframe(&mut self) {
+        if let Some(state) = self.state.as_mut() {
+            state.force_keyframe = true;
+        }
+    }
+
+    fn set_rate(&mut self, rate: RateTarget) -> Result<RateChange, EncoderError> {
+        let state = self.state.as_mut().ok_or(EncoderError::NotInitialized)?;
+        // The frame rate is baked into the time base, which can't change once opened.
+        // The session paces frames to the new rate instead.
+        if state.given_params.bitrate == rate.bitrate {
+            return Ok(RateChange::Live);
+        }
+
+        if LIVE_BITRATE_ENCODERS.contains(&state.configuration.encoder_name.as_str()) {
+            // SAFETY: The pointer is to the codec context of the opened encoder,
+            // which `state` owns and nothing else uses in the meantime. Both fields
+            // are plain integers, which these encoders read again before every frame.
+            unsafe {
+                let ptr = state.encoder.as_mut_ptr();
+                (*ptr).bit_rate = rate.bitrate as i64;
+                (*ptr).rc_max_rate = rate.bitrate as i64;
+            }
+            state.given_params.bitrate = rate.bitrate;
+            return Ok(RateChange::Live);
+        }
+
+        debug!(
+            "Opening encoder {} again for the new rate",
+            state.configuration.encoder_name
+        );
+        let mut parameters = state.given_params.clone();
+        parameters.bitrate = rate.bitrate;
+        let configuration = state.configuration.clone();
+        // The old encoder carries on as it was if the new one can't be opened
+        let new_state = self.try_init(parameters, configuration.clone())?;
+        let encoding = possible_configuration(&configuration, &new_state);
+        // The new encoder starts with a keyframe, so the client can carry on
+        self.state = Some(new_state);
+        Ok(RateChange::Reopened(encoding))
+    }
+
+    fn last_encode_timings(&self) -> Option<EncodeTimings> {
+        self.state.as_ref().and_then(|state| state.last_timings)
+    }
+
     fn encode<'s, 'a>(
         &'s mut self,
         raw_data: &'a [u8],
-    ) -> PinnedLocalFuture<'s, Result<&'s [u8], String>>
+        header: FrameHeader,
+    ) -> PinnedLocalFuture<'s, Result<EncodedFrame<'s>, EncoderError>>
     where
         'a: 's,
     {
         async move {
-            let state = self.state.as_mut().ok_or("Encoder not initialized")?;
+            let state = self.state.as_mut().ok_or(EncoderError::NotInitialized)?;
 
             // Perform encoding on the raw data
             // Return the encoded data
@@ -298,11 +383,11 @@ impl DevDispEncoder for FfmpegEncoder {
 
             let expected_data = src_stride * height;
             if raw_data.len() < expected_data {
-                return Err(format!(
+                return Err(EncoderError::Encoding(format!(
                     "Input buffer too small. Expected {}, got {}",
                     expected_data,
                     raw_data.len()
-                ));
+                )));
             }
 
             let copy_start = Instant::now();
@@ -317,7 +402,7 @@ impl DevDispEncoder for FfmpegEncoder {
 
             // The output frame after scaling.
             let mut scale_time = Duration::from_secs(0);
-            let formatted_frame = if let Some(scaler) = state.scaler.as_mut() {
+            let mut formatted_frame = if let Some(scaler) = state.scaler.as_mut() {
                 let mut formatted_frame = Video::new(
                     state.encoder_fmt,
                     state.given_params.width,
@@ -327,7 +412,7 @@ impl DevDispEncoder for FfmpegEncoder {
                 let scale_start = Instant::now();
                 scaler
                     .run(&input_frame, &mut formatted_frame)
-                    .map_err(|e| format!("Failed to scale frame: {}", e))?;
+                    .map_err(|e| EncoderError::Encoding(format!("Failed to scale frame: {}", e)))?;
 
                 formatted_frame.set_pts(Some(state.frame_index as i64));
                 state.frame_index += 1;
@@ -339,18 +424,31 @@ impl DevDispEncoder for FfmpegEncoder {
             };
             
 
+            // Asking for an I-frame makes the encoder start a new group of pictures here
+            if state.force_keyframe {
+                debug!("Forcing a keyframe at frame #{}", header.sequence);
+                formatted_frame.set_kind(ffmpeg::picture::Type::I);
+                state.force_keyframe = false;
+            } else {
+                formatted_frame.set_kind(ffmpeg::picture::Type::None);
+            }
+
             // Send for encoding
             let encode_start = Instant::now();
             state
                 .encoder
                 .send_frame(&formatted_frame)
-                .map_err(|e| format!("Failed to send frame to encoder: {}", e))?;
+                .map_err(|e| {
+                    EncoderError::Encoding(format!("Failed to send frame to encoder: {}", e))
+                })?;
 
             state.out_buf.clear();
             let mut packet = ffmpeg::Packet::empty();
             let mut consumed_len = 0;
+            let mut keyframe = false;
             // TODO: Stream this data!
             while let Ok(_) = state.encoder.receive_packet(&mut packet) {
+                keyframe |= packet.is_key();
                 match packet.data() {
                     Some(data) => {
                         consumed_len += data.len();
@@ -368,11 +466,19 @@ impl DevDispEncoder for FfmpegEncoder {
                 scale_time.as_millis(),
                 encode_time.as_millis()
             );
+            state.last_timings = Some(EncodeTimings {
+                copy: alloc_input_frame + copy_time,
+                scale: scale_time,
+                encode: encode_time,
+            });
 
             // Only return the used portion of the buffer
             let ret = &state.out_buf[..consumed_len];
 
-            Ok(ret)
+            Ok(EncodedFrame {
+                header: header.encoded(keyframe),
+                data: ret,
+            })
         }
         .boxed_local()
     }
@@ -392,7 +498,7 @@ impl FfmpegEncoderProvider {
 impl EncoderProvider for FfmpegEncoderProvider {
     type EncoderType = FfmpegEncoder;
 
-    fn create_encoder(&self) -> PinnedLocalFuture<'_, Result<Self::EncoderType, String>> {
+    fn create_encoder(&self) -> PinnedLocalFuture<'_, Result<Self::EncoderType, EncoderError>> {
         futures::future::ready(Ok(FfmpegEncoder::new(self.configuration.clone()))).boxed_local()
     }
 }
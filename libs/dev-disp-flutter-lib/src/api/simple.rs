@@ -75,7 +75,11 @@ fn process_buffer(msg_buffer: &[u8]) -> Result<(MessageToAndroid, usize), String
 fn handle_message(msg: MessageToAndroid) -> Result<(), String> {
     info!("Received message from Android: {:?}", msg);
     match msg {
-        MessageToAndroid::GetScreenInfo(_) => {
+        MessageToAndroid::GetScreenInfo(request) => {
+            // The host negotiates the protocol from the screen info we answer with,
+            // and tells us with a `Reject` if we can't be used.
+            info!("Host speaks {}", request.payload.protocol.versions);
+
             let sink_get_screen_cell = &SINK_GET_SCREEN;
             let guard = sink_get_screen_cell
                 .lock()
@@ -89,9 +93,20 @@ fn handle_message(msg: MessageToAndroid) -> Result<(), String> {
             }
         }
         MessageToAndroid::ScreenUpdate(data) => {
-            info!("Received screen update of size: {}", data.payload.len());
+            info!(
+                "Received screen update #{} of size: {}",
+                data.payload.header.sequence,
+                data.payload.data.len()
+            );
             // Here you would typically process the screen data, e.g., update a UI component
         }
+        MessageToAndroid::Heartbeat(_) => {}
+        MessageToAndroid::Goodbye(msg) => {
+            info!("Host closed the session: {}", msg.payload.reason);
+        }
+        MessageToAndroid::Reject(msg) => {
+            warn!("Host turned us away: {}", msg.payload.rejection);
+        }
         _ => {
             return Err(format!("Unhandled message: {:?}", msg));
         }
@@ -110,7 +125,9 @@ impl TryFrom<MessageToAndroid> for MessageToDart {
     fn try_from(value: MessageToAndroid) -> Result<Self, Self::Error> {
         match value {
             MessageToAndroid::GetScreenInfo(_) => Ok(MessageToDart::GetScreenInfo),
-            MessageToAndroid::ScreenUpdate(data) => Ok(MessageToDart::ScreenUpdate(data.payload)),
+            MessageToAndroid::ScreenUpdate(data) => {
+                Ok(MessageToDart::ScreenUpdate(data.payload.data))
+            }
             _ => Err(format!("Cannot convert {:?} to MessageToDart", value)),
         }
     }
@@ -143,6 +160,20 @@ pub fn initialize_streaming(fd: i32, sink: StreamSink<MessageToDart>) -> Result<
                             consumed = msg_consumed;
                             buffer.drain(0..consumed);
 
+                            match &msg {
+                                // Only there to tell us the host is still around
+                                MessageToAndroid::Heartbeat(_) => continue,
+                                MessageToAndroid::Goodbye(goodbye) => {
+                                    info!("Host closed the session: {}", goodbye.payload.reason);
+                                    continue;
+                                }
+                                MessageToAndroid::Reject(reject) => {
+                                    warn!("Host turned us away: {}", reject.payload.rejection);
+                                    continue;
+                                }
+                                _ => {}
+                            }
+
                             let send_result = MessageToDart::try_from(msg).and_then(|dart_msg| {
                                 sink.add(dart_msg)
                                     .map_err(|e| format!("Failed to send message to Dart: {}", e))
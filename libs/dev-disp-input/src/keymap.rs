@@ -0,0 +1,142 @@
+use evdev::KeyCode;
+
+/// Web `KeyboardEvent.code` names and the Linux key codes they map to.
+///
+/// See <https://developer.mozilla.org/en-US/docs/Web/API/UI_Events/Keyboard_event_code_values>
+pub(crate) const KEYMAP: &[(&str, KeyCode)] = &[
+    ("KeyA", KeyCode::KEY_A),
+    ("KeyB", KeyCode::KEY_B),
+    ("KeyC", KeyCode::KEY_C),
+    ("KeyD", KeyCode::KEY_D),
+    ("KeyE", KeyCode::KEY_E),
+    ("KeyF", KeyCode::KEY_F),
+    ("KeyG", KeyCode::KEY_G),
+    ("KeyH", KeyCode::KEY_H),
+    ("KeyI", KeyCode::KEY_I),
+    ("KeyJ", KeyCode::KEY_J),
+    ("KeyK", KeyCode::KEY_K),
+    ("KeyL", KeyCode::KEY_L),
+    ("KeyM", KeyCode::KEY_M),
+    ("KeyN", KeyCode::KEY_N),
+    ("KeyO", KeyCode::KEY_O),
+    ("KeyP", KeyCode::KEY_P),
+    ("KeyQ", KeyCode::KEY_Q),
+    ("KeyR", KeyCode::KEY_R),
+    ("KeyS", KeyCode::KEY_S),
+    ("KeyT", KeyCode::KEY_T),
+    ("KeyU", KeyCode::KEY_U),
+    ("KeyV", KeyCode::KEY_V),
+    ("KeyW", KeyCode::KEY_W),
+    ("KeyX", KeyCode::KEY_X),
+    ("KeyY", KeyCode::KEY_Y),
+    ("KeyZ", KeyCode::KEY_Z),
+    ("Digit0", KeyCode::KEY_0),
+    ("Digit1", KeyCode::KEY_1),
+    ("Digit2", KeyCode::KEY_2),
+    ("Digit3", KeyCode::KEY_3),
+    ("Digit4", KeyCode::KEY_4),
+    ("Digit5", KeyCode::KEY_5),
+    ("Digit6", KeyCode::KEY_6),
+    ("Digit7", KeyCode::KEY_7),
+    ("Digit8", KeyCode::KEY_8),
+    ("Digit9", KeyCode::KEY_9),
+    ("F1", KeyCode::KEY_F1),
+    ("F2", KeyCode::KEY_F2),
+    ("F3", KeyCode::KEY_F3),
+    ("F4", KeyCode::KEY_F4),
+    ("F5", KeyCode::KEY_F5),
+    ("F6", KeyCode::KEY_F6),
+    ("F7", KeyCode::KEY_F7),
+    ("F8", KeyCode::KEY_F8),
+    ("F9", KeyCode::KEY_F9),
+    ("F10", KeyCode::KEY_F10),
+    ("F11", KeyCode::KEY_F11),
+    ("F12", KeyCode::KEY_F12),
+    ("F13", KeyCode::KEY_F13),
+    ("F14", KeyCode::KEY_F14),
+    ("F15", KeyCode::KEY_F15),
+    ("F16", KeyCode::KEY_F16),
+    ("F17", KeyCode::KEY_F17),
+    ("F18", KeyCode::KEY_F18),
+    ("F19", KeyCode::KEY_F19),
+    ("F20", KeyCode::KEY_F20),
+    ("F21", KeyCode::KEY_F21),
+    ("F22", KeyCode::KEY_F22),
+    ("F23", KeyCode::KEY_F23),
+    ("F24", KeyCode::KEY_F24),
+    ("Enter", KeyCode::KEY_ENTER),
+    ("Escape", KeyCode::KEY_ESC),
+    ("Backspace", KeyCode::KEY_BACKSPACE),
+    ("Tab", KeyCode::KEY_TAB),
+    ("Space", KeyCode::KEY_SPACE),
+    ("Minus", KeyCode::KEY_MINUS),
+    ("Equal", KeyCode::KEY_EQUAL),
+    ("BracketLeft", KeyCode::KEY_LEFTBRACE),
+    ("BracketRight", KeyCode::KEY_RIGHTBRACE),
+    ("Backslash", KeyCode::KEY_BACKSLASH),
+    ("Semicolon", KeyCode::KEY_SEMICOLON),
+    ("Quote", KeyCode::KEY_APOSTROPHE),
+    ("Backquote", KeyCode::KEY_GRAVE),
+    ("Comma", KeyCode::KEY_COMMA),
+    ("Period", KeyCode::KEY_DOT),
+    ("Slash", KeyCode::KEY_SLASH),
+    ("IntlBackslash", KeyCode::KEY_102ND),
+    ("IntlRo", KeyCode::KEY_RO),
+    ("IntlYen", KeyCode::KEY_YEN),
+    ("CapsLock", KeyCode::KEY_CAPSLOCK),
+    ("ScrollLock", KeyCode::KEY_SCROLLLOCK),
+    ("NumLock", KeyCode::KEY_NUMLOCK),
+    ("PrintScreen", KeyCode::KEY_SYSRQ),
+    ("Pause", KeyCode::KEY_PAUSE),
+    ("ContextMenu", KeyCode::KEY_COMPOSE),
+    ("Insert", KeyCode::KEY_INSERT),
+    ("Delete", KeyCode::KEY_DELETE),
+    ("Home", KeyCode::KEY_HOME),
+    ("End", KeyCode::KEY_END),
+    ("PageUp", KeyCode::KEY_PAGEUP),
+    ("PageDown", KeyCode::KEY_PAGEDOWN),
+    ("ArrowUp", KeyCode::KEY_UP),
+    ("ArrowDown", KeyCode::KEY_DOWN),
+    ("ArrowLeft", KeyCode::KEY_LEFT),
+    ("ArrowRight", KeyCode::KEY_RIGHT),
+    ("ShiftLeft", KeyCode::KEY_LEFTSHIFT),
+    ("ShiftRight", KeyCode::KEY_RIGHTSHIFT),
+    ("ControlLeft", KeyCode::KEY_LEFTCTRL),
+    ("ControlRight", KeyCode::KEY_RIGHTCTRL),
+    ("AltLeft", KeyCode::KEY_LEFTALT),
+    ("AltRight", KeyCode::KEY_RIGHTALT),
+    ("MetaLeft", KeyCode::KEY_LEFTMETA),
+    ("MetaRight", KeyCode::KEY_RIGHTMETA),
+    ("Numpad0", KeyCode::KEY_KP0),
+    ("Numpad1", KeyCode::KEY_KP1),
+    ("Numpad2", KeyCode::KEY_KP2),
+    ("Numpad3", KeyCode::KEY_KP3),
+    ("Numpad4", KeyCode::KEY_KP4),
+    ("Numpad5", KeyCode::KEY_KP5),
+    ("Numpad6", KeyCode::KEY_KP6),
+    ("Numpad7", KeyCode::KEY_KP7),
+    ("Numpad8", KeyCode::KEY_KP8),
+    ("Numpad9", KeyCode::KEY_KP9),
+    ("NumpadAdd", KeyCode::KEY_KPPLUS),
+    ("NumpadSubtract", KeyCode::KEY_KPMINUS),
+    ("NumpadMultiply", KeyCode::KEY_KPASTERISK),
+    ("NumpadDivide", KeyCode::KEY_KPSLASH),
+    ("NumpadDecimal", KeyCode::KEY_KPDOT),
+    ("NumpadEnter", KeyCode::KEY_KPENTER),
+    ("NumpadEqual", KeyCode::KEY_KPEQUAL),
+    ("AudioVolumeMute", KeyCode::KEY_MUTE),
+    ("AudioVolumeDown", KeyCode::KEY_VOLUMEDOWN),
+    ("AudioVolumeUp", KeyCode::KEY_VOLUMEUP),
+    ("MediaPlayPause", KeyCode::KEY_PLAYPAUSE),
+    ("MediaStop", KeyCode::KEY_STOPCD),
+    ("MediaTrackNext", KeyCode::KEY_NEXTSONG),
+    ("MediaTrackPrevious", KeyCode::KEY_PREVIOUSSONG),
+];
+
+/// Looks up the Linux key code for a web `KeyboardEvent.code`.
+pub(crate) fn key_code_for(code: &str) -> Option<KeyCode> {
+    KEYMAP
+        .iter()
+        .find(|(web_code, _)| *web_code == code)
+        .map(|(_, key)| *key)
+}
@@ -0,0 +1,6 @@
+pub(crate) mod keymap;
+mod translate;
+mod uinput;
+
+pub use translate::*;
+pub use uinput::*;
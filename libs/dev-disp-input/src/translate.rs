@@ -0,0 +1,459 @@
+use dev_disp_core::{
+    core::{
+        ButtonState, InputEvent, KeyEvent, NormalizedPosition, PenEvent, PenTool, PointerButton,
+        ScrollEvent, TouchEvent, TouchPhase,
+    },
+    host::{InputError, InputTarget},
+};
+use evdev::{AbsoluteAxisCode, EventType, KeyCode, RelativeAxisCode};
+
+use crate::keymap::key_code_for;
+
+/// How many touch contacts can be down at the same time.
+pub const MAX_TOUCH_SLOTS: usize = 10;
+
+/// The maximum value of the pen pressure axis.
+pub const PEN_PRESSURE_MAX: i32 = 4096;
+
+/// High-resolution scroll units that make up one wheel notch, as defined by the kernel.
+const HI_RES_SCROLL_PER_NOTCH: i32 = 120;
+
+/// Which of the virtual devices an event belongs to. Pointers, touch screens and
+/// pens are split into their own devices so the compositor classifies each
+/// correctly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UinputDeviceKind {
+    Pointer,
+    Keyboard,
+    Touch,
+    Pen,
+}
+
+/// Translates client input events into evdev events, keeping track of the state
+/// needed to do so, such as which touch contacts are down.
+#[derive(Debug, Clone)]
+pub struct InputEventTranslator {
+    target: InputTarget,
+    touch_slots: [Option<u32>; MAX_TOUCH_SLOTS],
+    next_tracking_id: i32,
+    pen_tool: Option<PenTool>,
+    pen_touching: bool,
+    pen_barrel_button: bool,
+    scroll_remainder: (i32, i32),
+}
+
+impl InputEventTranslator {
+    pub fn new(target: InputTarget) -> Self {
+        Self {
+            target,
+            touch_slots: [None; MAX_TOUCH_SLOTS],
+            next_tracking_id: 0,
+            pen_tool: None,
+            pen_touching: false,
+            pen_barrel_button: false,
+            scroll_remainder: (0, 0),
+        }
+    }
+
+    pub fn target(&self) -> &InputTarget {
+        &self.target
+    }
+
+    /// Translates a single client event into a batch of evdev events for one of
+    /// the virtual devices. The batch may be empty if nothing needs to be emitted.
+    pub fn translate(
+        &mut self,
+        event: &InputEvent,
+    ) -> Result<(UinputDeviceKind, Vec<evdev::InputEvent>), InputError> {
+        match event {
+            InputEvent::PointerMove(position) => {
+                Ok((UinputDeviceKind::Pointer, self.absolute_position(*position)))
+            }
+            InputEvent::PointerButton {
+                position,
+                button,
+                state,
+            } => {
+                let mut events = self.absolute_position(*position);
+                events.push(key(pointer_button_code(*button), *state == ButtonState::Pressed));
+                Ok((UinputDeviceKind::Pointer, events))
+            }
+            InputEvent::Touch(touch) => Ok((UinputDeviceKind::Touch, self.touch(touch)?)),
+            InputEvent::Pen(pen) => Ok((UinputDeviceKind::Pen, self.pen(pen))),
+            InputEvent::Scroll(scroll) => Ok((UinputDeviceKind::Pointer, self.scroll(scroll))),
+            InputEvent::Key(key_event) => Ok((UinputDeviceKind::Keyboard, self.key(key_event)?)),
+        }
+    }
+
+    fn absolute_position(&self, position: NormalizedPosition) -> Vec<evdev::InputEvent> {
+        let (x, y) = self.target.to_absolute(position);
+        vec![abs(AbsoluteAxisCode::ABS_X, x), abs(AbsoluteAxisCode::ABS_Y, y)]
+    }
+
+    fn touch(&mut self, touch: &TouchEvent) -> Result<Vec<evdev::InputEvent>, InputError> {
+        let was_touching = self.touch_slots.iter().any(|s| s.is_some());
+        let slot = match touch.phase {
+            TouchPhase::Start => {
+                if self.touch_slots.contains(&Some(touch.id)) {
+                    return Err(InputError::InvalidEvent(format!(
+                        "Touch contact {} is already down",
+                        touch.id
+                    )));
+                }
+                let slot = self
+                    .touch_slots
+                    .iter()
+                    .position(|s| s.is_none())
+                    .ok_or_else(|| {
+                        InputError::InvalidEvent(format!(
+                            "No free touch slot for contact {}",
+                            touch.id
+                        ))
+                    })?;
+                self.touch_slots[slot] = Some(touch.id);
+                slot
+            }
+            TouchPhase::Move | TouchPhase::End | TouchPhase::Cancel => self
+                .touch_slots
+                .iter()
+                .position(|s| *s == Some(touch.id))
+                .ok_or_else(|| {
+                    InputError::InvalidEvent(format!("Touch contact {} is not down", touch.id))
+                })?,
+        };
+
+        let (x, y) = self.target.to_absolute(touch.position);
+        let mut events = vec![abs(AbsoluteAxisCode::ABS_MT_SLOT, slot as i32)];
+        match touch.phase {
+            TouchPhase::Start | TouchPhase::Move => {
+                if touch.phase == TouchPhase::Start {
+                    events.push(abs(
+                        AbsoluteAxisCode::ABS_MT_TRACKING_ID,
+                        self.next_tracking_id,
+                    ));
+                    self.next_tracking_id = (self.next_tracking_id + 1) % i32::from(u16::MAX);
+                }
+                events.extend([
+                    abs(AbsoluteAxisCode::ABS_MT_POSITION_X, x),
+                    abs(AbsoluteAxisCode::ABS_MT_POSITION_Y, y),
+                    abs(AbsoluteAxisCode::ABS_X, x),
+                    abs(AbsoluteAxisCode::ABS_Y, y),
+                ]);
+            }
+            TouchPhase::End | TouchPhase::Cancel => {
+                self.touch_slots[slot] = None;
+                events.push(abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1));
+            }
+        }
+
+        let is_touching = self.touch_slots.iter().any(|s| s.is_some());
+        if is_touching != was_touching {
+            events.push(key(KeyCode::BTN_TOUCH, is_touching));
+        }
+        Ok(events)
+    }
+
+    fn pen(&mut self, pen: &PenEvent) -> Vec<evdev::InputEvent> {
+        let mut events = Vec::new();
+
+        // Swap tools if the client switched, such as by flipping to the eraser
+        if self.pen_tool != Some(pen.tool) {
+            if let Some(old_tool) = self.pen_tool.take() {
+                events.push(key(pen_tool_code(old_tool), false));
+            }
+            events.push(key(pen_tool_code(pen.tool), true));
+            self.pen_tool = Some(pen.tool);
+        }
+
+        let touching = match pen.phase {
+            TouchPhase::Start => true,
+            TouchPhase::Move => self.pen_touching && pen.pressure > 0.0,
+            TouchPhase::End | TouchPhase::Cancel => false,
+        };
+        let pressure = if touching {
+            (pen.pressure.clamp(0.0, 1.0) * PEN_PRESSURE_MAX as f32).round() as i32
+        } else {
+            0
+        };
+
+        events.extend(self.absolute_position(pen.position));
+        events.extend([
+            abs(AbsoluteAxisCode::ABS_PRESSURE, pressure),
+            abs(
+                AbsoluteAxisCode::ABS_TILT_X,
+                pen.tilt_x.clamp(-90.0, 90.0).round() as i32,
+            ),
+            abs(
+                AbsoluteAxisCode::ABS_TILT_Y,
+                pen.tilt_y.clamp(-90.0, 90.0).round() as i32,
+            ),
+        ]);
+
+        if touching != self.pen_touching {
+            events.push(key(KeyCode::BTN_TOUCH, touching));
+            self.pen_touching = touching;
+        }
+        if pen.barrel_button != self.pen_barrel_button {
+            events.push(key(KeyCode::BTN_STYLUS, pen.barrel_button));
+            self.pen_barrel_button = pen.barrel_button;
+        }
+
+        // Cancel means the pen left the screen's range entirely
+        if pen.phase == TouchPhase::Cancel {
+            events.push(key(pen_tool_code(pen.tool), false));
+            self.pen_tool = None;
+        }
+        events
+    }
+
+    fn scroll(&mut self, scroll: &ScrollEvent) -> Vec<evdev::InputEvent> {
+        // Wheel values are positive scrolling up, unlike our scroll events
+        let hi_res_x = (scroll.delta_x * HI_RES_SCROLL_PER_NOTCH as f32).round() as i32;
+        let hi_res_y = -(scroll.delta_y * HI_RES_SCROLL_PER_NOTCH as f32).round() as i32;
+
+        let mut events = Vec::new();
+        for (hi_res, remainder, hi_res_code, notch_code) in [
+            (
+                hi_res_x,
+                &mut self.scroll_remainder.0,
+                RelativeAxisCode::REL_HWHEEL_HI_RES,
+                RelativeAxisCode::REL_HWHEEL,
+            ),
+            (
+                hi_res_y,
+                &mut self.scroll_remainder.1,
+                RelativeAxisCode::REL_WHEEL_HI_RES,
+                RelativeAxisCode::REL_WHEEL,
+            ),
+        ] {
+            if hi_res == 0 {
+                continue;
+            }
+            // Only report whole notches on the legacy axis, carrying over the rest
+            *remainder += hi_res;
+            let notches = *remainder / HI_RES_SCROLL_PER_NOTCH;
+            *remainder -= notches * HI_RES_SCROLL_PER_NOTCH;
+
+            events.push(rel(hi_res_code, hi_res));
+            if notches != 0 {
+                events.push(rel(notch_code, notches));
+            }
+        }
+        events
+    }
+
+    fn key(&self, key_event: &KeyEvent) -> Result<Vec<evdev::InputEvent>, InputError> {
+        let code = key_code_for(&key_event.code).ok_or_else(|| {
+            InputError::InvalidEvent(format!("Unknown key code \"{}\"", key_event.code))
+        })?;
+        Ok(vec![key(code, key_event.state == ButtonState::Pressed)])
+    }
+}
+
+pub(crate) fn pointer_button_code(button: PointerButton) -> KeyCode {
+    match button {
+        PointerButton::Left => KeyCode::BTN_LEFT,
+        PointerButton::Right => KeyCode::BTN_RIGHT,
+        PointerButton::Middle => KeyCode::BTN_MIDDLE,
+        PointerButton::Back => KeyCode::BTN_SIDE,
+        PointerButton::Forward => KeyCode::BTN_EXTRA,
+    }
+}
+
+fn pen_tool_code(tool: PenTool) -> KeyCode {
+    match tool {
+        PenTool::Pen => KeyCode::BTN_TOOL_PEN,
+        PenTool::Eraser => KeyCode::BTN_TOOL_RUBBER,
+    }
+}
+
+fn abs(code: AbsoluteAxisCode, value: i32) -> evdev::InputEvent {
+    evdev::InputEvent::new(EventType::ABSOLUTE.0, code.0, value)
+}
+
+fn rel(code: RelativeAxisCode, value: i32) -> evdev::InputEvent {
+    evdev::InputEvent::new(EventType::RELATIVE.0, code.0, value)
+}
+
+fn key(code: KeyCode, pressed: bool) -> evdev::InputEvent {
+    evdev::InputEvent::new(EventType::KEY.0, code.0, pressed as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use dev_disp_core::{
+        core::{
+            ButtonState, InputEvent, KeyEvent, NormalizedPosition, PointerButton, ScrollEvent,
+            TouchEvent, TouchPhase,
+        },
+        host::{DesktopPlacement, InputTarget},
+    };
+    use evdev::{AbsoluteAxisCode, KeyCode, RelativeAxisCode};
+
+    use super::{InputEventTranslator, MAX_TOUCH_SLOTS, UinputDeviceKind, abs, key, rel};
+
+    fn touch(id: u32, phase: TouchPhase, x: f32, y: f32) -> InputEvent {
+        InputEvent::Touch(TouchEvent {
+            id,
+            phase,
+            position: NormalizedPosition::new(x, y),
+        })
+    }
+
+    #[test]
+    fn test_maps_onto_screen() {
+        let mut translator = InputEventTranslator::new(InputTarget::new(1920, 1080, None));
+
+        let (kind, events) = translator
+            .translate(&InputEvent::PointerMove(NormalizedPosition::new(1.0, 0.5)))
+            .unwrap();
+        assert_eq!(kind, UinputDeviceKind::Pointer);
+        assert_eq!(
+            events,
+            vec![
+                abs(AbsoluteAxisCode::ABS_X, 1919),
+                abs(AbsoluteAxisCode::ABS_Y, 540)
+            ]
+        );
+
+        // Positions outside of the screen are clamped onto its edge
+        let (_, events) = translator
+            .translate(&InputEvent::PointerMove(NormalizedPosition::new(-0.5, 2.0)))
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                abs(AbsoluteAxisCode::ABS_X, 0),
+                abs(AbsoluteAxisCode::ABS_Y, 1079)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_maps_onto_desktop_placement() {
+        // A 1280x800 screen placed right of a 1920x1080 screen
+        let target = InputTarget::new(
+            1280,
+            800,
+            Some(DesktopPlacement {
+                x: 1920,
+                y: 0,
+                desktop_width: 3200,
+                desktop_height: 1080,
+            }),
+        );
+        assert_eq!(target.absolute_size(), (3200, 1080));
+
+        let mut translator = InputEventTranslator::new(target);
+        let (_, events) = translator
+            .translate(&InputEvent::PointerButton {
+                position: NormalizedPosition::new(0.0, 1.0),
+                button: PointerButton::Right,
+                state: ButtonState::Pressed,
+            })
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                abs(AbsoluteAxisCode::ABS_X, 1920),
+                abs(AbsoluteAxisCode::ABS_Y, 799),
+                key(KeyCode::BTN_RIGHT, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_touch_slots() {
+        let mut translator = InputEventTranslator::new(InputTarget::new(101, 101, None));
+
+        let (kind, events) = translator
+            .translate(&touch(7, TouchPhase::Start, 0.5, 0.5))
+            .unwrap();
+        assert_eq!(kind, UinputDeviceKind::Touch);
+        assert_eq!(events.first(), Some(&abs(AbsoluteAxisCode::ABS_MT_SLOT, 0)));
+        assert!(events.contains(&abs(AbsoluteAxisCode::ABS_MT_POSITION_X, 50)));
+        assert_eq!(events.last(), Some(&key(KeyCode::BTN_TOUCH, true)));
+
+        // A second finger gets the next slot, and doesn't touch down again
+        let (_, events) = translator
+            .translate(&touch(3, TouchPhase::Start, 0.0, 0.0))
+            .unwrap();
+        assert_eq!(events.first(), Some(&abs(AbsoluteAxisCode::ABS_MT_SLOT, 1)));
+        assert!(!events.contains(&key(KeyCode::BTN_TOUCH, true)));
+
+        let (_, events) = translator
+            .translate(&touch(7, TouchPhase::End, 0.5, 0.5))
+            .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                abs(AbsoluteAxisCode::ABS_MT_SLOT, 0),
+                abs(AbsoluteAxisCode::ABS_MT_TRACKING_ID, -1)
+            ]
+        );
+
+        // Lifting the last finger lifts the touch
+        let (_, events) = translator
+            .translate(&touch(3, TouchPhase::Cancel, 0.0, 0.0))
+            .unwrap();
+        assert_eq!(events.last(), Some(&key(KeyCode::BTN_TOUCH, false)));
+
+        assert!(translator
+            .translate(&touch(3, TouchPhase::Move, 0.0, 0.0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_touch_slots_run_out() {
+        let mut translator = InputEventTranslator::new(InputTarget::new(100, 100, None));
+        for id in 0..MAX_TOUCH_SLOTS as u32 {
+            translator
+                .translate(&touch(id, TouchPhase::Start, 0.0, 0.0))
+                .unwrap();
+        }
+        assert!(translator
+            .translate(&touch(MAX_TOUCH_SLOTS as u32, TouchPhase::Start, 0.0, 0.0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_scroll_carries_partial_notches() {
+        let mut translator = InputEventTranslator::new(InputTarget::new(100, 100, None));
+        let scroll = InputEvent::Scroll(ScrollEvent {
+            delta_x: 0.0,
+            delta_y: 0.5,
+        });
+
+        let (_, events) = translator.translate(&scroll).unwrap();
+        assert_eq!(events, vec![rel(RelativeAxisCode::REL_WHEEL_HI_RES, -60)]);
+
+        let (_, events) = translator.translate(&scroll).unwrap();
+        assert_eq!(
+            events,
+            vec![
+                rel(RelativeAxisCode::REL_WHEEL_HI_RES, -60),
+                rel(RelativeAxisCode::REL_WHEEL, -1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_keys() {
+        let mut translator = InputEventTranslator::new(InputTarget::new(100, 100, None));
+        let (kind, events) = translator
+            .translate(&InputEvent::Key(KeyEvent {
+                code: "ShiftLeft".to_string(),
+                state: ButtonState::Pressed,
+            }))
+            .unwrap();
+        assert_eq!(kind, UinputDeviceKind::Keyboard);
+        assert_eq!(events, vec![key(KeyCode::KEY_LEFTSHIFT, true)]);
+
+        assert!(translator
+            .translate(&InputEvent::Key(KeyEvent {
+                code: "NotAKey".to_string(),
+                state: ButtonState::Pressed,
+            }))
+            .is_err());
+    }
+}
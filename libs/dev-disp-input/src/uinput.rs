@@ -0,0 +1,294 @@
+use std::{fs::OpenOptions, io};
+
+use dev_disp_core::{
+    core::{InputEvent, PointerButton},
+    host::{InputError, InputInjector, InputInjectorProvider, InputTarget},
+    util::PinnedLocalFuture,
+};
+use evdev::{
+    AbsInfo, AbsoluteAxisCode, AttributeSet, KeyCode, PropType, RelativeAxisCode, UinputAbsSetup,
+    uinput::VirtualDevice,
+};
+use futures::{FutureExt, future};
+use log::{debug, trace};
+
+use crate::{
+    keymap::KEYMAP,
+    translate::{
+        InputEventTranslator, MAX_TOUCH_SLOTS, PEN_PRESSURE_MAX, UinputDeviceKind,
+        pointer_button_code,
+    },
+};
+
+pub const UINPUT_PATH: &str = "/dev/uinput";
+
+/// Absolute axes need a resolution in units per millimeter, and libinput won't
+/// take a tablet without one. We don't know the client's real size, so assume
+/// a typical ~96 DPI screen.
+const ASSUMED_UNITS_PER_MM: i32 = 4;
+
+/// Creates a set of virtual input devices through uinput for every session.
+#[derive(Debug, Clone, Default)]
+pub struct UinputInjectorProvider {
+    name_prefix: Option<String>,
+}
+
+impl UinputInjectorProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prefix for the names of the virtual devices, to tell sessions apart.
+    pub fn with_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Whether `/dev/uinput` exists and we're allowed to write to it.
+    pub fn is_available() -> bool {
+        OpenOptions::new().write(true).open(UINPUT_PATH).is_ok()
+    }
+}
+
+impl InputInjectorProvider for UinputInjectorProvider {
+    type InjectorType = UinputInjector;
+
+    fn create_injector(
+        &self,
+        target: InputTarget,
+    ) -> PinnedLocalFuture<'_, Result<Self::InjectorType, InputError>> {
+        let prefix = self.name_prefix.as_deref().unwrap_or("Dev Disp");
+        future::ready(UinputInjector::new(prefix, target)).boxed_local()
+    }
+}
+
+/// Injects input through virtual uinput devices, one per kind of input. The
+/// devices are removed when the injector is dropped.
+pub struct UinputInjector {
+    translator: InputEventTranslator,
+    pointer: VirtualDevice,
+    keyboard: VirtualDevice,
+    touch: VirtualDevice,
+    pen: VirtualDevice,
+}
+
+impl UinputInjector {
+    pub fn new(name_prefix: &str, target: InputTarget) -> Result<Self, InputError> {
+        let (width, height) = target.absolute_size();
+        let injector = Self {
+            translator: InputEventTranslator::new(target),
+            pointer: create_pointer(&format!("{name_prefix} Pointer"), width, height)
+                .map_err(|e| create_device_error("pointer", e))?,
+            keyboard: create_keyboard(&format!("{name_prefix} Keyboard"))
+                .map_err(|e| create_device_error("keyboard", e))?,
+            touch: create_touch(&format!("{name_prefix} Touch"), width, height)
+                .map_err(|e| create_device_error("touch screen", e))?,
+            pen: create_pen(&format!("{name_prefix} Pen"), width, height)
+                .map_err(|e| create_device_error("pen", e))?,
+        };
+        debug!(
+            "Created uinput devices with a {}x{} absolute space",
+            width, height
+        );
+        Ok(injector)
+    }
+
+    pub fn target(&self) -> &InputTarget {
+        self.translator.target()
+    }
+}
+
+/// Tells apart not being allowed to use uinput from it not being there at all.
+fn create_device_error(device: &str, e: io::Error) -> InputError {
+    let what = format!("Failed to create virtual {device}: {e}");
+    match e.kind() {
+        io::ErrorKind::PermissionDenied => InputError::PermissionDenied(what),
+        io::ErrorKind::NotFound => InputError::Unavailable(what),
+        _ => InputError::Other(what),
+    }
+}
+
+impl InputInjector for UinputInjector {
+    fn inject(&mut self, event: InputEvent) -> Result<(), InputError> {
+        let (kind, events) = self.translator.translate(&event)?;
+        if events.is_empty() {
+            return Ok(());
+        }
+        trace!("Emitting {} evdev event(s) on {:?}", events.len(), kind);
+
+        let device = match kind {
+            UinputDeviceKind::Pointer => &mut self.pointer,
+            UinputDeviceKind::Keyboard => &mut self.keyboard,
+            UinputDeviceKind::Touch => &mut self.touch,
+            UinputDeviceKind::Pen => &mut self.pen,
+        };
+        device
+            .emit(&events)
+            .map_err(|e| InputError::Other(format!("Failed to emit {:?} events: {}", kind, e)))
+    }
+}
+
+fn abs_setup(code: AbsoluteAxisCode, min: i32, max: i32, resolution: i32) -> UinputAbsSetup {
+    UinputAbsSetup::new(code, AbsInfo::new(0, min, max, 0, 0, resolution))
+}
+
+fn position_axes(width: u32, height: u32, codes: [AbsoluteAxisCode; 2]) -> [UinputAbsSetup; 2] {
+    let [x_code, y_code] = codes;
+    [
+        abs_setup(
+            x_code,
+            0,
+            width.saturating_sub(1) as i32,
+            ASSUMED_UNITS_PER_MM,
+        ),
+        abs_setup(
+            y_code,
+            0,
+            height.saturating_sub(1) as i32,
+            ASSUMED_UNITS_PER_MM,
+        ),
+    ]
+}
+
+fn create_pointer(name: &str, width: u32, height: u32) -> std::io::Result<VirtualDevice> {
+    let buttons: AttributeSet<KeyCode> = [
+        PointerButton::Left,
+        PointerButton::Right,
+        PointerButton::Middle,
+        PointerButton::Back,
+        PointerButton::Forward,
+    ]
+    .into_iter()
+    .map(pointer_button_code)
+    .collect();
+    let wheels: AttributeSet<RelativeAxisCode> = [
+        RelativeAxisCode::REL_WHEEL,
+        RelativeAxisCode::REL_HWHEEL,
+        RelativeAxisCode::REL_WHEEL_HI_RES,
+        RelativeAxisCode::REL_HWHEEL_HI_RES,
+    ]
+    .into_iter()
+    .collect();
+
+    let mut builder = VirtualDevice::builder()?
+        .name(name)
+        .with_keys(&buttons)?
+        .with_relative_axes(&wheels)?;
+    for axis in position_axes(
+        width,
+        height,
+        [AbsoluteAxisCode::ABS_X, AbsoluteAxisCode::ABS_Y],
+    ) {
+        builder = builder.with_absolute_axis(&axis)?;
+    }
+    builder.build()
+}
+
+fn create_keyboard(name: &str) -> std::io::Result<VirtualDevice> {
+    let keys: AttributeSet<KeyCode> = KEYMAP.iter().map(|(_, key)| *key).collect();
+    VirtualDevice::builder()?
+        .name(name)
+        .with_keys(&keys)?
+        .build()
+}
+
+fn create_touch(name: &str, width: u32, height: u32) -> std::io::Result<VirtualDevice> {
+    let keys: AttributeSet<KeyCode> = [KeyCode::BTN_TOUCH].into_iter().collect();
+    let props: AttributeSet<PropType> = [PropType::DIRECT].into_iter().collect();
+
+    let mut builder = VirtualDevice::builder()?
+        .name(name)
+        .with_keys(&keys)?
+        .with_properties(&props)?
+        .with_absolute_axis(&abs_setup(
+            AbsoluteAxisCode::ABS_MT_SLOT,
+            0,
+            MAX_TOUCH_SLOTS as i32 - 1,
+            0,
+        ))?
+        .with_absolute_axis(&abs_setup(
+            AbsoluteAxisCode::ABS_MT_TRACKING_ID,
+            0,
+            i32::from(u16::MAX),
+            0,
+        ))?;
+    for axis in position_axes(
+        width,
+        height,
+        [AbsoluteAxisCode::ABS_X, AbsoluteAxisCode::ABS_Y],
+    )
+    .into_iter()
+    .chain(position_axes(
+        width,
+        height,
+        [
+            AbsoluteAxisCode::ABS_MT_POSITION_X,
+            AbsoluteAxisCode::ABS_MT_POSITION_Y,
+        ],
+    )) {
+        builder = builder.with_absolute_axis(&axis)?;
+    }
+    builder.build()
+}
+
+fn create_pen(name: &str, width: u32, height: u32) -> std::io::Result<VirtualDevice> {
+    let keys: AttributeSet<KeyCode> = [
+        KeyCode::BTN_TOOL_PEN,
+        KeyCode::BTN_TOOL_RUBBER,
+        KeyCode::BTN_TOUCH,
+        KeyCode::BTN_STYLUS,
+    ]
+    .into_iter()
+    .collect();
+    let props: AttributeSet<PropType> = [PropType::DIRECT].into_iter().collect();
+
+    let mut builder = VirtualDevice::builder()?
+        .name(name)
+        .with_keys(&keys)?
+        .with_properties(&props)?
+        .with_absolute_axis(&abs_setup(
+            AbsoluteAxisCode::ABS_PRESSURE,
+            0,
+            PEN_PRESSURE_MAX,
+            0,
+        ))?
+        .with_absolute_axis(&abs_setup(AbsoluteAxisCode::ABS_TILT_X, -90, 90, 0))?
+        .with_absolute_axis(&abs_setup(AbsoluteAxisCode::ABS_TILT_Y, -90, 90, 0))?;
+    for axis in position_axes(
+        width,
+        height,
+        [AbsoluteAxisCode::ABS_X, AbsoluteAxisCode::ABS_Y],
+    ) {
+        builder = builder.with_absolute_axis(&axis)?;
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod test {
+    use dev_disp_core::{
+        core::{InputEvent, NormalizedPosition, TouchEvent, TouchPhase},
+        host::{InputInjector, InputTarget},
+    };
+
+    use super::UinputInjector;
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput"]
+    fn test_injects_into_uinput() {
+        let mut injector =
+            UinputInjector::new("Dev Disp Test", InputTarget::new(1920, 1080, None)).unwrap();
+        injector
+            .inject(InputEvent::PointerMove(NormalizedPosition::new(0.5, 0.5)))
+            .unwrap();
+        for phase in [TouchPhase::Start, TouchPhase::Move, TouchPhase::End] {
+            injector
+                .inject(InputEvent::Touch(TouchEvent {
+                    id: 1,
+                    phase,
+                    position: NormalizedPosition::new(0.25, 0.75),
+                }))
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use dev_disp_core::{
+    host::{CursorImage, CursorUpdate, ScreenError},
+    util::PinnedLocalStream,
+};
+use evdi::{
+    events::{AwaitEventError, CursorEvent, CursorMove, CursorSet},
+    handle::Handle as EvdiHandle,
+};
+use futures::{StreamExt, stream};
+use log::{debug, trace, warn};
+
+use crate::util::evdi_format_to_internal_format;
+
+/// How long to wait for a cursor event before checking in again. The cursor can
+/// sit still for a long time, so a timeout here is not an error.
+const CURSOR_EVENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Follow the cursor events of an EVDI device. Cursor events must be enabled on the
+/// handle for anything to come through.
+pub fn cursor_updates(handle: &EvdiHandle) -> PinnedLocalStream<'static, CursorUpdate> {
+    let events = handle.events.clone();
+    stream::unfold(
+        (events, CursorTracker::default()),
+        |(events, mut tracker)| async move {
+            loop {
+                match events.await_cursor(CURSOR_EVENT_TIMEOUT).await {
+                    Ok(event) => {
+                        let updates = tracker.on_event(event);
+                        return Some((stream::iter(updates), (events, tracker)));
+                    }
+                    Err(AwaitEventError::Timeout) => continue,
+                    Err(e) => {
+                        debug!("Stopped receiving EVDI cursor events: {}", e);
+                        return None;
+                    }
+                }
+            }
+        },
+    )
+    .flatten()
+    .boxed_local()
+}
+
+/// EVDI reports where the top-left of the cursor image is, while we report where
+/// the cursor points, so keep track of both the position and the hotspot.
+#[derive(Debug, Default)]
+struct CursorTracker {
+    plane_x: i32,
+    plane_y: i32,
+    hot_x: i32,
+    hot_y: i32,
+}
+
+impl CursorTracker {
+    fn on_event(&mut self, event: CursorEvent) -> Vec<CursorUpdate> {
+        match event {
+            CursorEvent::Set(set) => self.on_set(set),
+            CursorEvent::Move(CursorMove { x, y }) => {
+                self.plane_x = x;
+                self.plane_y = y;
+                vec![self.position()]
+            }
+        }
+    }
+
+    fn on_set(&mut self, set: CursorSet) -> Vec<CursorUpdate> {
+        trace!(
+            "EVDI cursor set: {}x{}, hotspot {},{}, enabled: {}",
+            set.width, set.height, set.hot_x, set.hot_y, set.enabled
+        );
+        let mut updates = vec![CursorUpdate::Visibility(set.enabled)];
+        if !set.enabled {
+            return updates;
+        }
+
+        match cursor_image(&set) {
+            Ok(image) => updates.push(CursorUpdate::Image(image)),
+            Err(e) => warn!("Ignoring EVDI cursor image: {}", e),
+        }
+
+        if (self.hot_x, self.hot_y) != (set.hot_x, set.hot_y) {
+            self.hot_x = set.hot_x;
+            self.hot_y = set.hot_y;
+            updates.push(self.position());
+        }
+        updates
+    }
+
+    fn position(&self) -> CursorUpdate {
+        CursorUpdate::Move {
+            x: self.plane_x + self.hot_x,
+            y: self.plane_y + self.hot_y,
+        }
+    }
+}
+
+/// Convert an EVDI cursor buffer, which has premultiplied alpha, to a tightly packed
+/// RGBA image.
+fn cursor_image(set: &CursorSet) -> Result<CursorImage, ScreenError> {
+    let format = evdi_format_to_internal_format(set.pixel_format)
+        .map_err(|e| ScreenError::Unsupported(e.to_string()))?;
+    if format.bytes_per_pixel() != 4 {
+        return Err(ScreenError::Unsupported(format!(
+            "Cursor format {:?} has no alpha channel",
+            format
+        )));
+    }
+    let (r_offset, g_offset, b_offset) = format.rgb_offsets();
+
+    let width = set.width as usize;
+    let height = set.height as usize;
+    let stride = set.stride as usize;
+    if set.buffer.len() < stride * height.saturating_sub(1) + width * 4 {
+        return Err(ScreenError::Other(format!(
+            "Cursor buffer is too small ({} bytes for {}x{})",
+            set.buffer.len(),
+            width,
+            height
+        )));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height * 4);
+    for row in set.buffer.chunks(stride).take(height) {
+        for pixel in row[..width * 4].chunks_exact(4) {
+            let alpha = pixel[3];
+            let unpremultiply = |c: u8| match alpha {
+                0 => 0,
+                a => ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8,
+            };
+            pixels.extend_from_slice(&[
+                unpremultiply(pixel[r_offset]),
+                unpremultiply(pixel[g_offset]),
+                unpremultiply(pixel[b_offset]),
+                alpha,
+            ]);
+        }
+    }
+
+    Ok(CursorImage {
+        width: set.width,
+        height: set.height,
+        hot_x: set.hot_x.max(0) as u32,
+        hot_y: set.hot_y.max(0) as u32,
+        pixels,
+    })
+}
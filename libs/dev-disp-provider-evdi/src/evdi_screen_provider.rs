@@ -1,15 +1,18 @@
 use std::{
     fmt::Display,
+    fs::OpenOptions,
+    io,
+    path::Path,
     sync::{Arc, atomic::AtomicBool},
     time::Duration,
 };
 
 use dev_disp_core::{
     host::{
-        DisplayParameters, Screen, ScreenOutputParameters, ScreenProvider, ScreenReadyStatus,
-        VirtualScreenPixelFormat,
+        CursorUpdate, DisplayParameters, Screen, ScreenError, ScreenOutputParameters,
+        ScreenProvider, ScreenReadyStatus, VirtualScreenPixelFormat,
     },
-    util::PinnedLocalFuture,
+    util::{PinnedLocalFuture, PinnedLocalStream},
 };
 use edid::Edid;
 use evdi::{
@@ -24,7 +27,7 @@ use log::{debug, error, info, warn};
 use thiserror::Error;
 use thread_future::ThreadFuture;
 
-use crate::util::evdi_format_to_internal_format;
+use crate::{evdi_cursor::cursor_updates, util::evdi_format_to_internal_format};
 
 const RECEIVE_INITIAL_MODE_TIMEOUT: Duration = Duration::from_secs(10);
 const UPDATE_BUFFER_TIMEOUT: Duration = Duration::from_secs(5);
@@ -64,22 +67,10 @@ impl EvdiScreenProvider {
 impl ScreenProvider for EvdiScreenProvider {
     type ScreenType = EvdiScreen;
 
-    async fn get_screen(&self, params: DisplayParameters) -> Result<Self::ScreenType, String> {
+    async fn get_screen(&self, params: DisplayParameters) -> Result<Self::ScreenType, ScreenError> {
         info!("Getting an EVDI screen for params {params}");
 
-        let edid: Edid = params.clone().into();
-
-        let edid_bytes = match edid.to_bytes() {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                error!("Failed to convert EDID to bytes: {}", e);
-                return Err(HandleClientError::Unknown.to_string());
-            }
-        };
-
-        let device_config =
-            DeviceConfig::new(&edid_bytes, params.resolution.0, params.resolution.1);
-        debug!("Using device config: {device_config:?}");
+        let device_config = device_config(&params)?;
 
         // TODO: This seems to be blocking! How can we unblock here without a specific runtime?
 
@@ -98,15 +89,15 @@ impl ScreenProvider for EvdiScreenProvider {
             Ok(Some(Ok(dev))) => dev,
             Ok(Some(Err(e))) => {
                 error!("Failed to open an evdi device: {}", e);
-                return Err(HandleClientError::EvdiDeviceOpenFailed(e).to_string());
+                return Err(open_device_error(e));
             }
             Ok(None) => {
                 info!("ThreadFuture was cancelled before starting work, exiting get_screen");
-                return Err(HandleClientError::Unknown.to_string());
+                return Err(ScreenError::Other(HandleClientError::Unknown.to_string()));
             }
             Err(_) => {
                 info!("ThreadFuture was cancelled while waiting for EVDI device, exiting");
-                return Err(HandleClientError::Unknown.to_string());
+                return Err(ScreenError::Other(HandleClientError::Unknown.to_string()));
             }
         };
         debug!("Opened EVDI device");
@@ -114,57 +105,34 @@ impl ScreenProvider for EvdiScreenProvider {
         let handle = unconnected_handle.connect(&device_config);
         debug!("Connected to EVDI device");
 
-        // For simplicity don't handle the mode changing after we start
-        // TODO: Handle mode changes in EvdiScreen!
-        let mode = match handle.events.await_mode(RECEIVE_INITIAL_MODE_TIMEOUT).await {
-            Ok(mode) => mode,
-            Err(e) => {
-                error!("Failed to receive initial EVDI device mode: {}", e);
-                return Err(HandleClientError::EvdiModeChangeError(e).to_string());
-            }
-        };
-
-        info!("Received initial EVDI device mode: {mode:?}");
+        // A display host changing its display parameters reconfigures the screen
+        // with a new EDID, so the mode only changes otherwise if it's picked on our
+        // side (like in the display settings).
+        let (mode, pixel_format) = await_mode(&handle).await?;
 
-        let pixel_format = match mode.pixel_format {
-            Ok(format) => format,
-            Err(e) => {
-                error!("Failed to get pixel format from EVDI mode: {}", e);
-                return Err(HandleClientError::Unknown.to_string());
-            }
-        };
-
-        let evdi_pixel_format = pixel_format;
-        let pixel_format = match evdi_format_to_internal_format(evdi_pixel_format as u32) {
-            Ok(fmt) => {
-                debug!(
-                    "Mapped EVDI pixel format from {} to internal format {:?}",
-                    evdi_pixel_format as u32, fmt
-                );
-                fmt
-            }
-            Err(e) => {
-                error!("Unsupported EVDI pixel format: {}", e);
-                return Err(e.to_string());
-            }
-        };
-
-        // Redundant, but left here so you know this is default behavior
-        // handle.enable_cursor_events(false);
+        // Keep the cursor out of the frames, it is sent on its own plane instead
+        handle.enable_cursor_events(true);
+        let cursor_stream = cursor_updates(&handle);
 
         // For simplicity, use only one buffer. We may want to use more than one buffer so that you
         // can send the contents of one buffer while updating another.
 
-        Ok(EvdiScreen::new(handle, mode, pixel_format))
+        Ok(EvdiScreen::new(handle, mode, pixel_format).with_cursor_stream(cursor_stream))
     }
 }
 
+/// A virtual screen backed by an evdi device.
+///
+/// Doesn't report damage yet, so sessions on it always send whole frames even
+/// when the display host takes region updates.
 pub struct EvdiScreen {
     stop_flag: AtomicBool,
-    handle: EvdiHandle,
+    /// Only missing while reconnecting with a new EDID
+    handle: Option<EvdiHandle>,
     buffer_id: BufferId,
     mode: Mode,
     pixel_format: VirtualScreenPixelFormat,
+    cursor_stream: Option<PinnedLocalStream<'static, CursorUpdate>>,
 }
 
 impl EvdiScreen {
@@ -173,12 +141,28 @@ impl EvdiScreen {
 
         Self {
             stop_flag: false.into(),
-            handle,
+            handle: Some(handle),
             buffer_id,
             mode,
             pixel_format,
+            cursor_stream: None,
         }
     }
+
+    /// Use the given cursor updates instead of the cursor being in the frames.
+    pub fn with_cursor_stream(
+        mut self,
+        cursor_stream: PinnedLocalStream<'static, CursorUpdate>,
+    ) -> Self {
+        self.cursor_stream = Some(cursor_stream);
+        self
+    }
+
+    fn handle(&self) -> &EvdiHandle {
+        self.handle
+            .as_ref()
+            .expect("The EVDI handle is only taken while reconnecting")
+    }
 }
 
 impl Screen for EvdiScreen {
@@ -194,14 +178,14 @@ impl Screen for EvdiScreen {
         }
     }
 
-    async fn get_ready(&mut self) -> Result<ScreenReadyStatus, String> {
+    async fn get_ready(&mut self) -> Result<ScreenReadyStatus, ScreenError> {
         if self.stop_flag.load(std::sync::atomic::Ordering::SeqCst) {
             info!("Stop flag set, exiting");
             return Ok(ScreenReadyStatus::Finished);
         }
 
         if let Err(e) = self
-            .handle
+            .handle()
             .request_update(self.buffer_id, UPDATE_BUFFER_TIMEOUT)
             .await
         {
@@ -212,7 +196,7 @@ impl Screen for EvdiScreen {
         Ok(ScreenReadyStatus::Ready)
     }
     fn get_bytes(&self) -> Option<&[u8]> {
-        let buf = match self.handle.get_buffer(self.buffer_id) {
+        let buf = match self.handle().get_buffer(self.buffer_id) {
             Some(buf) => buf,
             None => {
                 warn!("EVDI buffer not available yet");
@@ -225,19 +209,128 @@ impl Screen for EvdiScreen {
         Some(bytes)
     }
 
-    fn close(self) -> PinnedLocalFuture<'static, Result<(), String>>
+    fn take_cursor_stream(&mut self) -> Option<PinnedLocalStream<'static, CursorUpdate>> {
+        self.cursor_stream.take()
+    }
+
+    async fn reconfigure(&mut self, params: DisplayParameters) -> Result<(), ScreenError> {
+        info!("Reconfiguring EVDI screen for params {params}");
+        let device_config = device_config(&params)?;
+
+        // The same device is connected again, with the new EDID
+        if let Some(handle) = self.handle.take() {
+            self.handle = Some(handle.disconnect().connect(&device_config));
+        }
+        let (mode, pixel_format) = await_mode(self.handle()).await?;
+
+        let handle = self
+            .handle
+            .as_mut()
+            .expect("The EVDI handle is only taken while reconnecting");
+        self.buffer_id = handle.new_buffer(&mode);
+        handle.enable_cursor_events(true);
+        self.cursor_stream = Some(cursor_updates(handle));
+        self.mode = mode;
+        self.pixel_format = pixel_format;
+        Ok(())
+    }
+
+    fn close(self) -> PinnedLocalFuture<'static, Result<(), ScreenError>>
     where
         Self: Sized,
     {
         async move {
             info!("Closing EVDI screen");
-            self.handle.disconnect();
+            if let Some(handle) = self.handle {
+                handle.disconnect();
+            }
             Ok(())
         }
         .boxed_local()
     }
 }
 
+/// The evdi device config that makes the virtual display look like the display host.
+fn device_config(params: &DisplayParameters) -> Result<DeviceConfig, ScreenError> {
+    let edid: Edid = params.clone().into();
+
+    let edid_bytes = match edid.to_bytes() {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to convert EDID to bytes: {}", e);
+            return Err(ScreenError::Other(HandleClientError::Unknown.to_string()));
+        }
+    };
+
+    let device_config = DeviceConfig::new(&edid_bytes, params.resolution.0, params.resolution.1);
+    debug!("Using device config: {device_config:?}");
+    Ok(device_config)
+}
+
+/// Wait for the mode the desktop picks for a freshly connected device.
+async fn await_mode(handle: &EvdiHandle) -> Result<(Mode, VirtualScreenPixelFormat), ScreenError> {
+    let mode = match handle.events.await_mode(RECEIVE_INITIAL_MODE_TIMEOUT).await {
+        Ok(mode) => mode,
+        Err(e) => {
+            error!("Failed to receive initial EVDI device mode: {}", e);
+            return Err(ScreenError::Other(
+                HandleClientError::EvdiModeChangeError(e).to_string(),
+            ));
+        }
+    };
+
+    info!("Received initial EVDI device mode: {mode:?}");
+
+    let pixel_format = match mode.pixel_format {
+        Ok(format) => format,
+        Err(e) => {
+            error!("Failed to get pixel format from EVDI mode: {}", e);
+            return Err(ScreenError::Unsupported(e.to_string()));
+        }
+    };
+
+    let evdi_pixel_format = pixel_format;
+    let pixel_format = match evdi_format_to_internal_format(evdi_pixel_format as u32) {
+        Ok(fmt) => {
+            debug!(
+                "Mapped EVDI pixel format from {} to internal format {:?}",
+                evdi_pixel_format as u32, fmt
+            );
+            fmt
+        }
+        Err(e) => {
+            error!("Unsupported EVDI pixel format: {}", e);
+            return Err(ScreenError::Unsupported(e.to_string()));
+        }
+    };
+    Ok((mode, pixel_format))
+}
+
+/// Opening an evdi device fails the same way whatever went wrong, so look into the
+/// usual suspects to tell why.
+fn open_device_error(e: OpenDeviceError) -> ScreenError {
+    if !Path::new("/sys/module/evdi").exists() {
+        return ScreenError::Unavailable("The evdi kernel module isn't loaded".to_string());
+    }
+    let denied = std::fs::read_dir("/dev/dri")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("card"))
+        .any(|entry| {
+            let opened = OpenOptions::new().read(true).write(true).open(entry.path());
+            matches!(opened, Err(e) if e.kind() == io::ErrorKind::PermissionDenied)
+        });
+    if denied {
+        ScreenError::PermissionDenied(format!(
+            "{}, no access to the devices in /dev/dri",
+            HandleClientError::EvdiDeviceOpenFailed(e)
+        ))
+    } else {
+        ScreenError::Unavailable(HandleClientError::EvdiDeviceOpenFailed(e).to_string())
+    }
+}
+
 #[derive(Error, Debug)]
 pub struct NoDeviceError;
 
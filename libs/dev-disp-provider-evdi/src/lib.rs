@@ -1,3 +1,4 @@
+mod evdi_cursor;
 mod evdi_screen_provider;
 
 pub use evdi_screen_provider::*;
@@ -0,0 +1,140 @@
+use dev_disp_core::host::{DamageRect, VirtualScreenPixelFormat};
+
+use crate::font::{GLYPH_HEIGHT, GLYPH_WIDTH, glyph};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub const fn gray(level: u8) -> Self {
+        Self::rgb(level, level, level)
+    }
+}
+
+/// A frame to draw on, laid out in memory the way its pixel format says, with rows
+/// right after one another.
+pub(crate) struct Canvas {
+    format: VirtualScreenPixelFormat,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(format: VirtualScreenPixelFormat, width: u32, height: u32) -> Self {
+        let stride = width * format.bytes_per_pixel();
+        Self {
+            format,
+            width,
+            height,
+            data: vec![0; (stride * height) as usize],
+        }
+    }
+
+    pub fn format(&self) -> &VirtualScreenPixelFormat {
+        &self.format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.width * self.format.bytes_per_pixel()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bounds(&self) -> DamageRect {
+        DamageRect::new(0, 0, self.width, self.height)
+    }
+
+    /// A pixel of this color, in the canvas' format. Alpha, if there is any, is
+    /// always opaque.
+    fn pixel(&self, color: Color) -> Vec<u8> {
+        let mut pixel = vec![0xFF; self.format.bytes_per_pixel() as usize];
+        let (r, g, b) = self.format.rgb_offsets();
+        pixel[r] = color.r;
+        pixel[g] = color.g;
+        pixel[b] = color.b;
+        pixel
+    }
+
+    pub fn fill(&mut self, color: Color) {
+        self.fill_rect(self.bounds(), color);
+    }
+
+    /// Fills whatever of `rect` is on the canvas with `color`.
+    pub fn fill_rect(&mut self, rect: DamageRect, color: Color) {
+        let Some(rect) = rect.clip_to(self.width, self.height) else {
+            return;
+        };
+        let pixel = self.pixel(color);
+        let stride = self.stride() as usize;
+        let start = rect.x as usize * pixel.len();
+        let end = start + rect.width as usize * pixel.len();
+        for y in rect.y..rect.y + rect.height {
+            let row = y as usize * stride;
+            for chunk in self.data[row + start..row + end].chunks_exact_mut(pixel.len()) {
+                chunk.copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    /// The size `text` takes up when drawn at `scale`, with a font pixel of spacing
+    /// between characters.
+    pub fn text_size(text: &str, scale: u32) -> (u32, u32) {
+        let chars = text.chars().count() as u32;
+        let width = (chars * (GLYPH_WIDTH + 1)).saturating_sub(1);
+        (width * scale, GLYPH_HEIGHT * scale)
+    }
+
+    /// Draws `text` with its top-left corner at `x`, `y`, every font pixel `scale`
+    /// pixels wide. Text may run off the canvas.
+    pub fn draw_text(&mut self, x: i64, y: i64, scale: u32, text: &str, color: Color) {
+        let step = ((GLYPH_WIDTH + 1) * scale) as i64;
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i as i64 * step;
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                        continue;
+                    }
+                    let px = left + (col * scale) as i64;
+                    let py = y + (row as u32 * scale) as i64;
+                    self.fill_rect_at(px, py, scale, scale, color);
+                }
+            }
+        }
+    }
+
+    /// Like `fill_rect`, for a rectangle that may start off the canvas.
+    fn fill_rect_at(&mut self, x: i64, y: i64, width: u32, height: u32, color: Color) {
+        let (Ok(left), Ok(top)) = (u32::try_from(x.max(0)), u32::try_from(y.max(0))) else {
+            return;
+        };
+        let right = (x + width as i64).max(0);
+        let bottom = (y + height as i64).max(0);
+        let rect = DamageRect::new(
+            left,
+            top,
+            (right - left as i64) as u32,
+            (bottom - top as i64) as u32,
+        );
+        self.fill_rect(rect, color);
+    }
+}
@@ -0,0 +1,10 @@
+//! A virtual screen that draws test patterns instead of showing a desktop, for
+//! running sessions without EVDI, like in CI or while working on a client.
+
+mod canvas;
+mod font;
+mod pattern;
+mod synthetic_screen_provider;
+
+pub use pattern::*;
+pub use synthetic_screen_provider::*;
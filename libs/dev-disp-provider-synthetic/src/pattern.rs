@@ -0,0 +1,196 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use dev_disp_core::host::DamageRect;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    canvas::{Canvas, Color},
+    font::GLYPH_HEIGHT,
+};
+
+/// What a synthetic screen shows.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SyntheticPattern {
+    /// Color bars over a gray ramp, for checking colors. They never change.
+    #[default]
+    ColorBars,
+    /// A box bouncing around, for checking motion and partial updates
+    MovingBox,
+    /// A page of text scrolling up, for checking how sharp text stays in motion
+    ScrollingText,
+    /// The number of the frame and the time it was drawn, for spotting dropped
+    /// frames and measuring latency
+    FrameCounter,
+}
+
+/// 75% white, yellow, cyan, green, magenta, red and blue
+const BARS: [Color; 7] = [
+    Color::gray(191),
+    Color::rgb(191, 191, 0),
+    Color::rgb(0, 191, 191),
+    Color::rgb(0, 191, 0),
+    Color::rgb(191, 0, 191),
+    Color::rgb(191, 0, 0),
+    Color::rgb(0, 0, 191),
+];
+const RAMP_STEPS: u32 = 8;
+
+const BACKGROUND: Color = Color::gray(16);
+const FOREGROUND: Color = Color::gray(235);
+const BOX: Color = Color::rgb(230, 120, 30);
+
+const PAGE_LINE: &str = "THE QUICK BROWN FOX JUMPS OVER THE LAZY DOG. 0123456789";
+
+impl SyntheticPattern {
+    /// Draws frame number `frame` of the pattern, on a canvas that has the frame
+    /// before it. Returns the regions that changed, or `None` when the whole canvas
+    /// was drawn anew.
+    pub(crate) fn draw(&self, canvas: &mut Canvas, frame: u64) -> Option<Vec<DamageRect>> {
+        match self {
+            SyntheticPattern::ColorBars => draw_color_bars(canvas, frame),
+            SyntheticPattern::MovingBox => draw_moving_box(canvas, frame),
+            SyntheticPattern::ScrollingText => draw_scrolling_text(canvas, frame),
+            SyntheticPattern::FrameCounter => draw_frame_counter(canvas, frame),
+        }
+    }
+}
+
+fn draw_color_bars(canvas: &mut Canvas, frame: u64) -> Option<Vec<DamageRect>> {
+    if frame > 0 {
+        return Some(Vec::new());
+    }
+    let (width, height) = (canvas.width(), canvas.height());
+    let bars_height = height * 3 / 4;
+    let count = BARS.len() as u32;
+    for (i, color) in (0..).zip(BARS) {
+        let left = width * i / count;
+        let right = width * (i + 1) / count;
+        canvas.fill_rect(DamageRect::new(left, 0, right - left, bars_height), color);
+    }
+    // From black to white underneath
+    for i in 0..RAMP_STEPS {
+        let left = width * i / RAMP_STEPS;
+        let right = width * (i + 1) / RAMP_STEPS;
+        let level = (255 * i / (RAMP_STEPS - 1)) as u8;
+        let rect = DamageRect::new(left, bars_height, right - left, height - bars_height);
+        canvas.fill_rect(rect, Color::gray(level));
+    }
+    None
+}
+
+/// Where the box is on frame number `frame`. It moves a little every frame, and
+/// bounces off the edges.
+fn box_rect(canvas: &Canvas, frame: u64) -> DamageRect {
+    let (width, height) = (canvas.width(), canvas.height());
+    let size = (width.min(height) / 6).max(1);
+    let speed_x = (width / 90).max(1) as u64;
+    let speed_y = (height / 70).max(1) as u64;
+    DamageRect::new(
+        bounce(frame * speed_x, width - size),
+        bounce(frame * speed_y, height - size),
+        size,
+        size,
+    )
+}
+
+/// Where something that went `distance` ends up, going back and forth between 0 and
+/// `range`.
+fn bounce(distance: u64, range: u32) -> u32 {
+    if range == 0 {
+        return 0;
+    }
+    let period = 2 * range as u64;
+    let along = distance % period;
+    (if along <= range as u64 {
+        along
+    } else {
+        period - along
+    }) as u32
+}
+
+fn draw_moving_box(canvas: &mut Canvas, frame: u64) -> Option<Vec<DamageRect>> {
+    let rect = box_rect(canvas, frame);
+    if frame == 0 {
+        canvas.fill(BACKGROUND);
+        canvas.fill_rect(rect, BOX);
+        return None;
+    }
+    let before = box_rect(canvas, frame - 1);
+    canvas.fill_rect(before, BACKGROUND);
+    canvas.fill_rect(rect, BOX);
+    Some(vec![before, rect])
+}
+
+fn draw_scrolling_text(canvas: &mut Canvas, frame: u64) -> Option<Vec<DamageRect>> {
+    let scale = (canvas.height() / 270).max(1);
+    let line_height = ((GLYPH_HEIGHT + 3) * scale) as u64;
+    // A font pixel every frame
+    let offset = frame * scale as u64;
+    let margin = (scale * 2) as i64;
+
+    canvas.fill(BACKGROUND);
+    let first = offset / line_height;
+    let visible = canvas.height() as u64 / line_height + 2;
+    for line in first..first + visible {
+        let y = (line * line_height) as i64 - offset as i64 + margin;
+        let text = format!("{:04} {}", line % 10_000, PAGE_LINE);
+        canvas.draw_text(margin, y, scale, &text, FOREGROUND);
+    }
+    (frame > 0).then(|| vec![canvas.bounds()])
+}
+
+fn draw_frame_counter(canvas: &mut Canvas, frame: u64) -> Option<Vec<DamageRect>> {
+    let (width, height) = (canvas.width(), canvas.height());
+    let counter = format!("FRAME {frame:08}");
+    let clock = clock_text(SystemTime::now());
+
+    // As big as fits in three quarters of the width, and a quarter of the height
+    let (unscaled_width, _) = Canvas::text_size(&counter, 1);
+    let scale = (width * 3 / 4 / unscaled_width.max(1))
+        .min(height / 4 / GLYPH_HEIGHT)
+        .max(1);
+    let (text_width, text_height) = Canvas::text_size(&counter, scale);
+    let gap = text_height / 2;
+    let area = DamageRect::new(
+        width.saturating_sub(text_width) / 2,
+        (height / 2).saturating_sub(text_height + gap / 2),
+        text_width,
+        text_height * 2 + gap,
+    );
+
+    if frame == 0 {
+        canvas.fill(BACKGROUND);
+    } else {
+        canvas.fill_rect(area, BACKGROUND);
+    }
+    let (x, y) = (area.x as i64, area.y as i64);
+    canvas.draw_text(x, y, scale, &counter, FOREGROUND);
+    let (clock_width, _) = Canvas::text_size(&clock, scale);
+    let clock_x = x + (text_width as i64 - clock_width as i64) / 2;
+    canvas.draw_text(
+        clock_x,
+        y + (text_height + gap) as i64,
+        scale,
+        &clock,
+        FOREGROUND,
+    );
+
+    if frame == 0 {
+        return None;
+    }
+    Some(area.clip_to(width, height).into_iter().collect())
+}
+
+/// The UTC time of day, down to the millisecond.
+fn clock_text(now: SystemTime) -> String {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() % 86_400;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
@@ -0,0 +1,258 @@
+use std::time::{Duration, Instant};
+
+use dev_disp_core::host::{
+    DamageRect, DisplayParameters, Screen, ScreenError, ScreenOutputParameters, ScreenProvider,
+    ScreenReadyStatus, VirtualScreenPixelFormat,
+};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{SyntheticPattern, canvas::Canvas};
+
+/// How synthetic screens look and behave.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SyntheticScreenConfiguration {
+    pub pattern: SyntheticPattern,
+    pub format: VirtualScreenPixelFormat,
+    /// How many times a second the pattern changes. The display host's refresh rate,
+    /// unless set.
+    pub damage_rate_hz: Option<u32>,
+}
+
+impl Default for SyntheticScreenConfiguration {
+    fn default() -> Self {
+        Self {
+            pattern: SyntheticPattern::default(),
+            format: VirtualScreenPixelFormat::Bgra8888,
+            damage_rate_hz: None,
+        }
+    }
+}
+
+/// Hands out screens that draw a test pattern, instead of showing a desktop. Needs
+/// neither kernel support nor special permissions, so sessions can run anywhere.
+#[derive(Debug, Clone, Default)]
+pub struct SyntheticScreenProvider {
+    config: SyntheticScreenConfiguration,
+}
+
+impl SyntheticScreenProvider {
+    pub fn new(config: SyntheticScreenConfiguration) -> Self {
+        Self { config }
+    }
+}
+
+impl ScreenProvider for SyntheticScreenProvider {
+    type ScreenType = SyntheticScreen;
+
+    async fn get_screen(&self, params: DisplayParameters) -> Result<Self::ScreenType, ScreenError> {
+        let (width, height) = drawable_resolution(&params)?;
+        info!(
+            "Drawing {:?} in {:?} for params {params}",
+            self.config.pattern, self.config.format
+        );
+        Ok(SyntheticScreen {
+            pattern: self.config.pattern,
+            canvas: Canvas::new(self.config.format.clone(), width, height),
+            damage_rate_hz: self.config.damage_rate_hz,
+            frame_interval: frame_interval(self.config.damage_rate_hz, &params),
+            next_frame_at: None,
+            frame_count: 0,
+            damage: None,
+        })
+    }
+}
+
+fn drawable_resolution(params: &DisplayParameters) -> Result<(u32, u32), ScreenError> {
+    let (width, height) = params.resolution;
+    if width == 0 || height == 0 {
+        return Err(ScreenError::Unsupported(format!(
+            "Can't draw on a {width}x{height} screen"
+        )));
+    }
+    Ok((width, height))
+}
+
+fn frame_interval(damage_rate_hz: Option<u32>, params: &DisplayParameters) -> Duration {
+    let damage_rate = damage_rate_hz.unwrap_or_else(|| params.refresh_rate_hz());
+    Duration::from_secs(1) / damage_rate.max(1)
+}
+
+/// A screen that draws a new frame of its pattern every time the pattern is due to
+/// change.
+pub struct SyntheticScreen {
+    pattern: SyntheticPattern,
+    canvas: Canvas,
+    /// As configured, so a reconfigured screen can follow the new refresh rate
+    damage_rate_hz: Option<u32>,
+    frame_interval: Duration,
+    /// `None` until the first frame, which is drawn right away
+    next_frame_at: Option<Instant>,
+    frame_count: u64,
+    damage: Option<Vec<DamageRect>>,
+}
+
+impl Screen for SyntheticScreen {
+    fn get_format_parameters(&self) -> ScreenOutputParameters {
+        ScreenOutputParameters {
+            format: self.canvas.format().clone(),
+            width: self.canvas.width(),
+            height: self.canvas.height(),
+            stride: self.canvas.stride(),
+            meta_data: None,
+        }
+    }
+
+    async fn get_ready(&mut self) -> Result<ScreenReadyStatus, ScreenError> {
+        if let Some(due) = self.next_frame_at {
+            futures_timer::Delay::new(due.saturating_duration_since(Instant::now())).await;
+        }
+        // Frames that fell behind aren't made up for
+        let now = Instant::now();
+        let next = self.next_frame_at.map_or(now, |due| due.max(now));
+        self.next_frame_at = Some(next + self.frame_interval);
+
+        self.damage = self.pattern.draw(&mut self.canvas, self.frame_count);
+        self.frame_count += 1;
+        Ok(ScreenReadyStatus::Ready)
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        (self.frame_count > 0).then(|| self.canvas.bytes())
+    }
+
+    fn get_damage(&self) -> Option<Vec<DamageRect>> {
+        self.damage.clone()
+    }
+
+    async fn reconfigure(&mut self, params: DisplayParameters) -> Result<(), ScreenError> {
+        let (width, height) = drawable_resolution(&params)?;
+        info!("Redrawing {:?} for params {params}", self.pattern);
+        // The pattern starts over on the new canvas, with a whole first frame
+        self.canvas = Canvas::new(self.canvas.format().clone(), width, height);
+        self.frame_interval = frame_interval(self.damage_rate_hz, &params);
+        self.next_frame_at = None;
+        self.frame_count = 0;
+        self.damage = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use dev_disp_core::host::{
+        DisplayCharacteristics, DisplayParameters, Screen, ScreenProvider, VirtualScreenPixelFormat,
+    };
+    use futures::executor::block_on;
+
+    use super::{SyntheticScreen, SyntheticScreenConfiguration, SyntheticScreenProvider};
+    use crate::SyntheticPattern;
+
+    fn screen(pattern: SyntheticPattern, format: VirtualScreenPixelFormat) -> SyntheticScreen {
+        let provider = SyntheticScreenProvider::new(SyntheticScreenConfiguration {
+            pattern,
+            format,
+            damage_rate_hz: Some(1000),
+        });
+        let params = DisplayParameters {
+            host_dev_name: "Test display".to_string(),
+            resolution: (140, 80),
+            characteristics: DisplayCharacteristics::default(),
+        };
+        block_on(provider.get_screen(params)).unwrap()
+    }
+
+    #[test]
+    fn test_color_bars_in_every_format() {
+        let formats = [
+            VirtualScreenPixelFormat::Rgb888,
+            VirtualScreenPixelFormat::Bgr888,
+            VirtualScreenPixelFormat::Rgba8888,
+            VirtualScreenPixelFormat::Bgra8888,
+            VirtualScreenPixelFormat::Argb8888,
+            VirtualScreenPixelFormat::Abgr8888,
+        ];
+        for format in formats {
+            let mut screen = screen(SyntheticPattern::ColorBars, format.clone());
+            block_on(screen.get_ready()).unwrap();
+            let params = screen.get_format_parameters();
+            let bytes = screen.get_bytes().unwrap();
+            assert_eq!(bytes.len(), (params.stride * params.height) as usize);
+
+            // Top-left of the yellow bar
+            let bpp = format.bytes_per_pixel() as usize;
+            let pixel = &bytes[20 * bpp..21 * bpp];
+            let (r, g, b) = format.rgb_offsets();
+            assert_eq!((pixel[r], pixel[g], pixel[b]), (191, 191, 0), "{format:?}");
+            assert_eq!(screen.get_damage(), None);
+
+            // Bars stay put
+            block_on(screen.get_ready()).unwrap();
+            assert_eq!(screen.get_damage(), Some(vec![]));
+        }
+    }
+
+    #[test]
+    fn test_moving_box_damages_where_it_was_and_is() {
+        let mut screen = screen(
+            SyntheticPattern::MovingBox,
+            VirtualScreenPixelFormat::Bgra8888,
+        );
+        block_on(screen.get_ready()).unwrap();
+        let first = screen.get_bytes().unwrap().to_vec();
+
+        block_on(screen.get_ready()).unwrap();
+        let damage = screen.get_damage().unwrap();
+        assert_eq!(damage.len(), 2);
+        assert_ne!(damage[0], damage[1]);
+        for rect in &damage {
+            assert_eq!(rect.clip_to(140, 80), Some(*rect));
+        }
+        assert_ne!(screen.get_bytes().unwrap(), first);
+    }
+
+    #[test]
+    fn test_text_patterns_change_every_frame() {
+        for pattern in [
+            SyntheticPattern::ScrollingText,
+            SyntheticPattern::FrameCounter,
+        ] {
+            let mut screen = screen(pattern, VirtualScreenPixelFormat::Rgb888);
+            block_on(screen.get_ready()).unwrap();
+            let first = screen.get_bytes().unwrap().to_vec();
+            block_on(screen.get_ready()).unwrap();
+            assert_ne!(screen.get_bytes().unwrap(), first, "{pattern:?}");
+            assert!(!screen.get_damage().unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_reconfigure_redraws_at_the_new_size() {
+        let mut screen = screen(
+            SyntheticPattern::MovingBox,
+            VirtualScreenPixelFormat::Rgb888,
+        );
+        block_on(screen.get_ready()).unwrap();
+        block_on(screen.get_ready()).unwrap();
+
+        let params = DisplayParameters {
+            host_dev_name: "Test display".to_string(),
+            resolution: (60, 100),
+            characteristics: DisplayCharacteristics::default(),
+        };
+        block_on(screen.reconfigure(params)).unwrap();
+        let format = screen.get_format_parameters();
+        assert_eq!((format.width, format.height), (60, 100));
+        assert_eq!(format.format, VirtualScreenPixelFormat::Rgb888);
+        assert_eq!(screen.get_bytes(), None);
+
+        block_on(screen.get_ready()).unwrap();
+        assert_eq!(
+            screen.get_bytes().unwrap().len(),
+            (format.stride * 100) as usize
+        );
+        // The first frame on the new canvas is a whole one
+        assert_eq!(screen.get_damage(), None);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use dev_disp_core::{
+    client::DisplayHost,
+    core::SessionToken,
+    host::{
+        ConnectableDevice, ConnectableDeviceInfo, DeviceDiscovery, DeviceEvent, DisplayParameters,
+        StreamingDeviceDiscovery, diff_device_lists,
+    },
+    util::PinnedFuture,
+};
+use futures::{FutureExt, Stream, channel::mpsc, future};
+
+use crate::{MockClient, MockTransport, lock};
+
+/// A device that connects over a `MockTransport`. Clones are the same device, and
+/// only one of them can connect.
+#[derive(Debug, Clone)]
+pub struct MockDevice {
+    info: ConnectableDeviceInfo,
+    transport: Arc<Mutex<Option<MockTransport>>>,
+}
+
+impl MockDevice {
+    /// Returns the device, along with the client on the other end of its connection.
+    pub fn new(id: &str, display_params: DisplayParameters) -> (Self, MockClient) {
+        let (transport, client) = MockTransport::pair(display_params);
+        let device = Self {
+            info: ConnectableDeviceInfo {
+                name: format!("Mock device {id}"),
+                device_type: "mock".to_string(),
+                id: id.to_string(),
+                description: None,
+                unavailable_reason: None,
+                resume_token: None,
+            },
+            transport: Arc::new(Mutex::new(Some(transport))),
+        };
+        (device, client)
+    }
+
+    /// Makes the device show up as one that can't be connected to, for this reason.
+    pub fn unavailable(mut self, reason: &str) -> Self {
+        self.info.unavailable_reason = Some(reason.to_string());
+        self
+    }
+
+    /// Makes the device show up as coming back to resume the session of this token.
+    pub fn resuming(mut self, token: SessionToken) -> Self {
+        self.info.resume_token = Some(token);
+        self
+    }
+}
+
+impl ConnectableDevice for MockDevice {
+    type Transport = MockTransport;
+
+    fn connect(
+        self,
+    ) -> PinnedFuture<
+        'static,
+        Result<DisplayHost<Self::Transport>, Box<dyn std::error::Error + Send + Sync>>,
+    > {
+        let result = match lock(&self.transport).take() {
+            Some(transport) => Ok(DisplayHost::new(0, self.info.name.clone(), transport)),
+            None => Err(format!("{} is already connected", self.info.name).into()),
+        };
+        future::ready(result).boxed()
+    }
+
+    fn get_info(&self) -> ConnectableDeviceInfo {
+        self.info.clone()
+    }
+}
+
+#[derive(Debug, Default)]
+struct DiscoveryState {
+    devices: Vec<MockDevice>,
+    /// Streams to tell when the devices change
+    watchers: Vec<mpsc::UnboundedSender<Vec<MockDevice>>>,
+}
+
+/// Discovers whichever devices it was last given. Clones share the devices, so a test
+/// can keep changing them after handing the discovery off.
+#[derive(Debug, Clone)]
+pub struct MockDiscovery {
+    name: String,
+    state: Arc<Mutex<DiscoveryState>>,
+}
+
+impl MockDiscovery {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            state: Default::default(),
+        }
+    }
+
+    /// Replaces the discovered devices, and tells every stream of this discovery what
+    /// changed.
+    pub fn set_devices(&self, devices: Vec<MockDevice>) {
+        let mut state = lock(&self.state);
+        state
+            .watchers
+            .retain(|watcher| watcher.unbounded_send(devices.clone()).is_ok());
+        state.devices = devices;
+    }
+}
+
+impl DeviceDiscovery for MockDiscovery {
+    type DeviceCandidate = MockDevice;
+
+    fn discover_devices(&self) -> PinnedFuture<'_, Vec<Self::DeviceCandidate>> {
+        future::ready(lock(&self.state).devices.clone()).boxed()
+    }
+
+    fn get_display_name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+impl StreamingDeviceDiscovery for MockDiscovery {
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = DeviceEvent<Self::DeviceCandidate>> + Send>> {
+        let (watcher, devices) = mpsc::unbounded();
+        let mut state = lock(&self.state);
+        // Starts off with the devices there are now
+        let _ = watcher.unbounded_send(state.devices.clone());
+        state.watchers.push(watcher);
+        diff_device_lists(devices)
+    }
+}
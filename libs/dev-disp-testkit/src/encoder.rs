@@ -0,0 +1,200 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use dev_disp_core::{
+    host::{
+        EncodedFrame, Encoder, EncoderContentParameters, EncoderError,
+        EncoderPossibleConfiguration, EncoderProvider, FrameHeader, RateChange, RateTarget,
+    },
+    util::PinnedLocalFuture,
+};
+use futures::{FutureExt, future};
+
+use crate::lock;
+
+/// Name and codec family of the encoder `MockEncoderProvider::new` offers.
+pub const MOCK_ENCODER: (&str, &str) = ("mock", "mock");
+
+#[derive(Debug, Default)]
+struct EncoderState {
+    /// Names and codec families of the encoders on offer, the most preferred first
+    encoders: Vec<(String, String)>,
+    /// Encoders that fail every frame
+    failing: HashSet<String>,
+    /// Why setting an encoder up fails, if it does
+    init_failure: Option<String>,
+    initialized: Vec<EncoderPossibleConfiguration>,
+    encoded_frames: u64,
+}
+
+/// Hands out `MockEncoder`s, which can be any of a list of made up encoders.
+#[derive(Debug, Clone)]
+pub struct MockEncoderProvider {
+    state: Arc<Mutex<EncoderState>>,
+}
+
+impl MockEncoderProvider {
+    /// A provider with only `MOCK_ENCODER` on offer.
+    pub fn new() -> Self {
+        Self::with_encoders(&[MOCK_ENCODER])
+    }
+
+    /// A provider with these encoders on offer, given by name and codec family, the
+    /// most preferred first.
+    pub fn with_encoders(encoders: &[(&str, &str)]) -> Self {
+        let encoders = encoders
+            .iter()
+            .map(|(name, family)| (name.to_string(), family.to_string()))
+            .collect();
+        Self {
+            state: Arc::new(Mutex::new(EncoderState {
+                encoders,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Makes every frame given to this encoder fail to encode from now on.
+    pub fn fail_encoding(&self, encoder_name: &str) {
+        lock(&self.state).failing.insert(encoder_name.to_string());
+    }
+
+    /// Makes setting encoders up fail from now on, or work again with `None`.
+    pub fn set_init_failure(&self, reason: Option<&str>) {
+        lock(&self.state).init_failure = reason.map(str::to_string);
+    }
+
+    /// Every configuration encoders were set up with so far.
+    pub fn initialized(&self) -> Vec<EncoderPossibleConfiguration> {
+        lock(&self.state).initialized.clone()
+    }
+
+    /// How many frames were encoded so far, by every encoder.
+    pub fn encoded_frames(&self) -> u64 {
+        lock(&self.state).encoded_frames
+    }
+}
+
+impl Default for MockEncoderProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EncoderProvider for MockEncoderProvider {
+    type EncoderType = MockEncoder;
+
+    fn create_encoder(&self) -> PinnedLocalFuture<'_, Result<Self::EncoderType, EncoderError>> {
+        let encoder = MockEncoder {
+            state: self.state.clone(),
+            configuration: None,
+            keyframe_due: true,
+        };
+        future::ready(Ok(encoder)).boxed_local()
+    }
+}
+
+/// An encoder that passes frames through untouched, as if it had encoded them with
+/// whichever configuration it was set up with.
+#[derive(Debug)]
+pub struct MockEncoder {
+    state: Arc<Mutex<EncoderState>>,
+    configuration: Option<EncoderPossibleConfiguration>,
+    keyframe_due: bool,
+}
+
+impl MockEncoder {
+    /// The configuration this encoder was last set up with.
+    pub fn configuration(&self) -> Option<&EncoderPossibleConfiguration> {
+        self.configuration.as_ref()
+    }
+}
+
+impl Encoder for MockEncoder {
+    fn get_supported_configurations(
+        &mut self,
+        parameters: &EncoderContentParameters,
+    ) -> Result<Vec<EncoderPossibleConfiguration>, EncoderError> {
+        Ok(lock(&self.state)
+            .encoders
+            .iter()
+            .map(|(name, family)| EncoderPossibleConfiguration {
+                encoder_name: name.clone(),
+                encoded_resolution: (parameters.width, parameters.height),
+                encoder_family: family.clone(),
+                parameters: HashMap::new(),
+            })
+            .collect())
+    }
+
+    fn init(
+        &mut self,
+        parameters: EncoderContentParameters,
+        preferred_encoders: Option<Vec<EncoderPossibleConfiguration>>,
+    ) -> PinnedLocalFuture<'_, Result<EncoderPossibleConfiguration, EncoderError>> {
+        let result = self
+            .get_supported_configurations(&parameters)
+            .and_then(|supported| {
+                let mut state = lock(&self.state);
+                if let Some(reason) = &state.init_failure {
+                    return Err(EncoderError::Initialization(reason.clone()));
+                }
+                let chosen = match preferred_encoders {
+                    Some(preferred) => preferred.into_iter().find(|config| {
+                        supported
+                            .iter()
+                            .any(|s| s.encoder_name == config.encoder_name)
+                    }),
+                    None => supported.into_iter().next(),
+                };
+                let chosen = chosen.ok_or(EncoderError::NoUsableConfiguration)?;
+                state.initialized.push(chosen.clone());
+                Ok(chosen)
+            });
+        if let Ok(chosen) = &result {
+            self.configuration = Some(chosen.clone());
+            self.keyframe_due = true;
+        }
+        future::ready(result).boxed_local()
+    }
+
+    fn encode<'s, 'a>(
+        &'s mut self,
+        raw_data: &'a [u8],
+        header: FrameHeader,
+    ) -> PinnedLocalFuture<'s, Result<EncodedFrame<'s>, EncoderError>>
+    where
+        'a: 's,
+    {
+        let result = match &self.configuration {
+            None => Err(EncoderError::NotInitialized),
+            Some(config) => {
+                let mut state = lock(&self.state);
+                if state.failing.contains(&config.encoder_name) {
+                    Err(EncoderError::Encoding(format!(
+                        "{} was told to fail",
+                        config.encoder_name
+                    )))
+                } else {
+                    state.encoded_frames += 1;
+                    let keyframe = std::mem::take(&mut self.keyframe_due);
+                    Ok(EncodedFrame {
+                        header: header.encoded(keyframe),
+                        data: raw_data,
+                    })
+                }
+            }
+        };
+        future::ready(result).boxed_local()
+    }
+
+    fn force_keyframe(&mut self) {
+        self.keyframe_due = true;
+    }
+
+    fn set_rate(&mut self, _rate: RateTarget) -> Result<RateChange, EncoderError> {
+        Ok(RateChange::Live)
+    }
+}
@@ -0,0 +1,20 @@
+//! Stand-ins for everything a session talks to: the virtual screen, the encoder, the
+//! transport to the display host and device discovery. Lets tests drive a whole
+//! session with `handle_display_host`, without any hardware or network.
+
+mod discovery;
+mod encoder;
+mod screen;
+mod transport;
+
+pub use discovery::*;
+pub use encoder::*;
+pub use screen::*;
+pub use transport::*;
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// A mock panicking halfway through shouldn't take the rest of the test down with it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
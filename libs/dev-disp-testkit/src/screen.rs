@@ -0,0 +1,277 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use dev_disp_core::{
+    host::{
+        CursorUpdate, DisplayParameters, Screen, ScreenError, ScreenOutputParameters,
+        ScreenProvider, ScreenReadyStatus, VirtualScreenPixelFormat,
+    },
+    util::{PinnedLocalFuture, PinnedLocalStream},
+};
+use futures::{
+    FutureExt, StreamExt,
+    channel::mpsc,
+    future::{self, Either},
+    lock::Mutex as AsyncMutex,
+    stream,
+};
+
+use crate::lock;
+
+/// What a mock screen does the next time it's asked for a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockScreenStep {
+    /// Have a new frame ready, different from the one before
+    Frame,
+    /// Have the same frame ready again, unchanged
+    Repeat,
+    NotReady,
+    /// The screen went away
+    Finish,
+    /// The screen stopped working, for this reason
+    Fail(String),
+}
+
+#[derive(Debug, Default)]
+struct ProviderState {
+    requested: Vec<DisplayParameters>,
+    reconfigured: Vec<DisplayParameters>,
+    closed: usize,
+    /// Why screens can't be had, if they can't
+    failure: Option<String>,
+}
+
+/// Hands out `MockScreen`s the size the display host asked for.
+///
+/// Screens play the steps pushed to the provider first, in order. Once they run out,
+/// screens of a live provider have a new frame ready at their refresh rate, like a
+/// busy desktop, while those of a scripted one wait for more steps.
+#[derive(Clone)]
+pub struct MockScreenProvider {
+    live: bool,
+    /// Whether screens keep the cursor out of their frames
+    with_cursor: bool,
+    /// Whether screens take new display parameters in place
+    reconfigurable: bool,
+    state: Arc<Mutex<ProviderState>>,
+    steps_tx: mpsc::UnboundedSender<MockScreenStep>,
+    // Shared, since a session goes through a screen for every renegotiation
+    steps: Arc<AsyncMutex<mpsc::UnboundedReceiver<MockScreenStep>>>,
+    cursor_tx: mpsc::UnboundedSender<CursorUpdate>,
+    cursor: Arc<AsyncMutex<mpsc::UnboundedReceiver<CursorUpdate>>>,
+}
+
+impl MockScreenProvider {
+    /// A provider of screens that always have new frames.
+    pub fn new() -> Self {
+        Self::with_live(true)
+    }
+
+    /// A provider of screens that only do what they're told with `push`.
+    pub fn scripted() -> Self {
+        Self::with_live(false)
+    }
+
+    fn with_live(live: bool) -> Self {
+        let (steps_tx, steps) = mpsc::unbounded();
+        let (cursor_tx, cursor) = mpsc::unbounded();
+        Self {
+            live,
+            with_cursor: false,
+            reconfigurable: false,
+            state: Default::default(),
+            steps_tx,
+            steps: Arc::new(AsyncMutex::new(steps)),
+            cursor_tx,
+            cursor: Arc::new(AsyncMutex::new(cursor)),
+        }
+    }
+
+    /// Makes screens hand out the cursor updates of `move_cursor`, instead of
+    /// drawing the cursor into their frames.
+    pub fn with_cursor(mut self) -> Self {
+        self.with_cursor = true;
+        self
+    }
+
+    /// Makes screens take new display parameters in place, instead of being replaced
+    /// by new ones.
+    pub fn reconfigurable(mut self) -> Self {
+        self.reconfigurable = true;
+        self
+    }
+
+    pub fn move_cursor(&self, update: CursorUpdate) {
+        let _ = self.cursor_tx.unbounded_send(update);
+    }
+
+    pub fn push(&self, step: MockScreenStep) {
+        let _ = self.steps_tx.unbounded_send(step);
+    }
+
+    pub fn push_frames(&self, count: usize) {
+        for _ in 0..count {
+            self.push(MockScreenStep::Frame);
+        }
+    }
+
+    /// Makes asking for a screen fail from now on, or work again with `None`.
+    pub fn set_failure(&self, reason: Option<&str>) {
+        lock(&self.state).failure = reason.map(str::to_string);
+    }
+
+    /// The display parameters of every screen asked for so far.
+    pub fn requested(&self) -> Vec<DisplayParameters> {
+        lock(&self.state).requested.clone()
+    }
+
+    /// The display parameters screens were reconfigured for so far.
+    pub fn reconfigured(&self) -> Vec<DisplayParameters> {
+        lock(&self.state).reconfigured.clone()
+    }
+
+    /// How many screens were closed so far.
+    pub fn closed_screens(&self) -> usize {
+        lock(&self.state).closed
+    }
+}
+
+impl Default for MockScreenProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScreenProvider for MockScreenProvider {
+    type ScreenType = MockScreen;
+
+    fn get_screen(
+        &self,
+        params: DisplayParameters,
+    ) -> impl Future<Output = Result<Self::ScreenType, ScreenError>> {
+        let mut state = lock(&self.state);
+        state.requested.push(params.clone());
+        let screen = match &state.failure {
+            Some(reason) => Err(ScreenError::Unavailable(reason.clone())),
+            None => Ok(MockScreen::new(self, &params)),
+        };
+        future::ready(screen)
+    }
+}
+
+/// A screen that makes up its frames. Every frame is filled with a single byte, one
+/// more than the frame before, wrapping around.
+pub struct MockScreen {
+    params: ScreenOutputParameters,
+    /// `None` for screens that wait for steps
+    frame_interval: Option<Duration>,
+    frame: Vec<u8>,
+    frame_count: u64,
+    reconfigurable: bool,
+    steps: Arc<AsyncMutex<mpsc::UnboundedReceiver<MockScreenStep>>>,
+    /// Until the cursor stream is taken, for screens that have one
+    cursor: Option<Arc<AsyncMutex<mpsc::UnboundedReceiver<CursorUpdate>>>>,
+    state: Arc<Mutex<ProviderState>>,
+}
+
+impl MockScreen {
+    fn new(provider: &MockScreenProvider, display_params: &DisplayParameters) -> Self {
+        let mut screen = Self {
+            params: ScreenOutputParameters {
+                format: VirtualScreenPixelFormat::Bgra8888,
+                width: 0,
+                height: 0,
+                stride: 0,
+                meta_data: None,
+            },
+            frame_interval: provider.live.then_some(Duration::ZERO),
+            frame: Vec::new(),
+            frame_count: 0,
+            reconfigurable: provider.reconfigurable,
+            steps: provider.steps.clone(),
+            cursor: provider.with_cursor.then(|| provider.cursor.clone()),
+            state: provider.state.clone(),
+        };
+        screen.resize(display_params);
+        screen
+    }
+
+    fn resize(&mut self, display_params: &DisplayParameters) {
+        let (width, height) = display_params.resolution;
+        let stride = width * self.params.format.bytes_per_pixel();
+        self.params.width = width;
+        self.params.height = height;
+        self.params.stride = stride;
+        if let Some(interval) = &mut self.frame_interval {
+            *interval = Duration::from_secs(1) / display_params.refresh_rate_hz().max(1);
+        }
+        self.frame = vec![0; (stride * height) as usize];
+        self.frame_count = 0;
+    }
+
+    async fn next_step(&self) -> MockScreenStep {
+        let mut steps = self.steps.lock().await;
+        let Some(interval) = self.frame_interval else {
+            return steps.next().await.unwrap_or(MockScreenStep::Finish);
+        };
+        // Steps pushed in the meantime go before the frame that's due
+        let delay = futures_timer::Delay::new(interval);
+        match future::select(steps.next(), delay).await {
+            Either::Left((step, _)) => step.unwrap_or(MockScreenStep::Frame),
+            Either::Right(_) => MockScreenStep::Frame,
+        }
+    }
+}
+
+impl Screen for MockScreen {
+    fn get_format_parameters(&self) -> ScreenOutputParameters {
+        self.params.clone()
+    }
+
+    async fn get_ready(&mut self) -> Result<ScreenReadyStatus, ScreenError> {
+        match self.next_step().await {
+            MockScreenStep::Frame => {
+                self.frame_count += 1;
+                self.frame.fill(self.frame_count as u8);
+                Ok(ScreenReadyStatus::Ready)
+            }
+            MockScreenStep::Repeat => Ok(ScreenReadyStatus::Ready),
+            MockScreenStep::NotReady => Ok(ScreenReadyStatus::NotReady),
+            MockScreenStep::Finish => Ok(ScreenReadyStatus::Finished),
+            MockScreenStep::Fail(reason) => Err(ScreenError::Other(reason)),
+        }
+    }
+
+    fn get_bytes(&self) -> Option<&[u8]> {
+        (self.frame_count > 0).then_some(self.frame.as_slice())
+    }
+
+    fn take_cursor_stream(&mut self) -> Option<PinnedLocalStream<'static, CursorUpdate>> {
+        let cursor = self.cursor.take()?;
+        Some(
+            stream::unfold(cursor, |cursor| async move {
+                let update = cursor.lock().await.next().await?;
+                Some((update, cursor))
+            })
+            .boxed_local(),
+        )
+    }
+
+    async fn reconfigure(&mut self, params: DisplayParameters) -> Result<(), ScreenError> {
+        if !self.reconfigurable {
+            return Err(ScreenError::Unsupported(format!(
+                "Mock screens can't be reconfigured for {params}"
+            )));
+        }
+        self.resize(&params);
+        lock(&self.state).reconfigured.push(params);
+        Ok(())
+    }
+
+    fn close(self) -> PinnedLocalFuture<'static, Result<(), ScreenError>> {
+        lock(&self.state).closed += 1;
+        future::ready(Ok(())).boxed_local()
+    }
+}
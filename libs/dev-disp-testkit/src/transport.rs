@@ -0,0 +1,330 @@
+use std::sync::{Arc, Mutex};
+
+use dev_disp_core::{
+    client::{ScreenTransport, TransportError},
+    core::{GoodbyeReason, SessionToken},
+    host::{
+        DisplayCharacteristics, DisplayParameters, EncodedFrame, EncoderPossibleConfiguration,
+        FrameHeader,
+    },
+    util::{PinnedFuture, PinnedStream},
+};
+use futures::{
+    FutureExt, StreamExt,
+    channel::{mpsc, oneshot},
+    future,
+};
+
+use crate::lock;
+
+/// Display parameters of a made up display host, with nothing but its resolution.
+pub fn mock_display_parameters(width: u32, height: u32) -> DisplayParameters {
+    DisplayParameters {
+        host_dev_name: "Mock display".to_string(),
+        resolution: (width, height),
+        characteristics: DisplayCharacteristics::default(),
+    }
+}
+
+/// A part of setting up a session that a `MockClient` can be told to fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockTransportStep {
+    Initialize,
+    GetDisplayConfig,
+    GetPreferredEncodings,
+    SetEncoding,
+}
+
+/// A frame that made it to a `MockClient`.
+#[derive(Debug, Clone)]
+pub struct ReceivedFrame {
+    pub header: FrameHeader,
+    pub data: Vec<u8>,
+}
+
+/// What both ends of a mock connection know about it.
+#[derive(Debug)]
+struct Connection {
+    connected: bool,
+    closed: bool,
+    display_params: DisplayParameters,
+    /// Codec families the client takes, or `None` for any
+    accepted_families: Option<Vec<String>>,
+    failing: Vec<MockTransportStep>,
+    encodings: Vec<EncoderPossibleConfiguration>,
+    goodbye: Option<GoodbyeReason>,
+    session_token: Option<SessionToken>,
+}
+
+impl Connection {
+    fn check(&self, step: MockTransportStep) -> Result<(), TransportError> {
+        if !self.connected {
+            return Err(TransportError::NoConnection);
+        }
+        if self.failing.contains(&step) {
+            return Err(TransportError::Rejected(format!("{step:?}")));
+        }
+        Ok(())
+    }
+}
+
+/// The host's end of an in-memory connection to a `MockClient`.
+#[derive(Debug)]
+pub struct MockTransport {
+    connection: Arc<Mutex<Connection>>,
+    frames: mpsc::UnboundedSender<ReceivedFrame>,
+    keyframe_requests: Option<mpsc::UnboundedReceiver<()>>,
+    display_parameters: Option<mpsc::UnboundedReceiver<DisplayParameters>>,
+    goodbyes: Option<mpsc::UnboundedReceiver<GoodbyeReason>>,
+    disconnected: Option<oneshot::Receiver<()>>,
+}
+
+impl MockTransport {
+    /// Connects a transport to a client that reports `display_params` and takes any
+    /// encoding it's offered.
+    pub fn pair(display_params: DisplayParameters) -> (Self, MockClient) {
+        let connection = Arc::new(Mutex::new(Connection {
+            connected: true,
+            closed: false,
+            display_params,
+            accepted_families: None,
+            failing: Vec::new(),
+            encodings: Vec::new(),
+            goodbye: None,
+            session_token: None,
+        }));
+        let (frames_tx, frames_rx) = mpsc::unbounded();
+        let (keyframe_tx, keyframe_rx) = mpsc::unbounded();
+        let (display_parameters_tx, display_parameters_rx) = mpsc::unbounded();
+        let (goodbye_tx, goodbye_rx) = mpsc::unbounded();
+        let (disconnect_tx, disconnect_rx) = oneshot::channel();
+
+        let transport = Self {
+            connection: connection.clone(),
+            frames: frames_tx,
+            keyframe_requests: Some(keyframe_rx),
+            display_parameters: Some(display_parameters_rx),
+            goodbyes: Some(goodbye_rx),
+            disconnected: Some(disconnect_rx),
+        };
+        let client = MockClient {
+            connection,
+            frames: frames_rx,
+            keyframe_requests: keyframe_tx,
+            display_parameters: display_parameters_tx,
+            goodbyes: goodbye_tx,
+            disconnect: Some(disconnect_tx),
+        };
+        (transport, client)
+    }
+
+    fn check(&self, step: MockTransportStep) -> Result<(), TransportError> {
+        lock(&self.connection).check(step)
+    }
+}
+
+impl ScreenTransport for MockTransport {
+    fn initialize(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        future::ready(self.check(MockTransportStep::Initialize)).boxed()
+    }
+
+    fn notify_loading_screen(&self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        future::ready(Ok(())).boxed()
+    }
+
+    fn get_display_config(
+        &mut self,
+    ) -> PinnedFuture<'_, Result<DisplayParameters, TransportError>> {
+        let connection = lock(&self.connection);
+        let result = connection
+            .check(MockTransportStep::GetDisplayConfig)
+            .map(|()| connection.display_params.clone());
+        future::ready(result).boxed()
+    }
+
+    fn take_keyframe_request_stream(&mut self) -> Option<PinnedStream<'static, ()>> {
+        Some(self.keyframe_requests.take()?.boxed())
+    }
+
+    fn take_display_parameters_stream(
+        &mut self,
+    ) -> Option<PinnedStream<'static, DisplayParameters>> {
+        Some(self.display_parameters.take()?.boxed())
+    }
+
+    fn take_goodbye_stream(&mut self) -> Option<PinnedStream<'static, GoodbyeReason>> {
+        Some(self.goodbyes.take()?.boxed())
+    }
+
+    fn close(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        lock(&self.connection).closed = true;
+        future::ready(Ok(())).boxed()
+    }
+
+    /// Runs until the client drops the connection.
+    fn background<'a>(&mut self) -> PinnedFuture<'a, Result<(), TransportError>> {
+        match self.disconnected.take() {
+            Some(disconnected) => async move {
+                let _ = disconnected.await;
+                Err(TransportError::NoConnection)
+            }
+            .boxed(),
+            None => future::pending().boxed(),
+        }
+    }
+
+    fn get_preferred_encodings(
+        &mut self,
+        configurations: Vec<EncoderPossibleConfiguration>,
+    ) -> PinnedFuture<'_, Result<Vec<EncoderPossibleConfiguration>, TransportError>> {
+        let connection = lock(&self.connection);
+        let result = connection
+            .check(MockTransportStep::GetPreferredEncodings)
+            .map(|()| match &connection.accepted_families {
+                Some(families) => configurations
+                    .into_iter()
+                    .filter(|config| families.contains(&config.encoder_family))
+                    .collect(),
+                None => configurations,
+            });
+        future::ready(result).boxed()
+    }
+
+    fn set_encoding(
+        &mut self,
+        configuration: EncoderPossibleConfiguration,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        let mut connection = lock(&self.connection);
+        let result = connection
+            .check(MockTransportStep::SetEncoding)
+            .map(|()| connection.encodings.push(configuration));
+        future::ready(result).boxed()
+    }
+
+    fn send_screen_data<'s, 'a>(
+        &'s mut self,
+        frame: EncodedFrame<'a>,
+    ) -> PinnedFuture<'s, Result<(), TransportError>>
+    where
+        'a: 's,
+    {
+        let result = if lock(&self.connection).connected {
+            self.frames
+                .unbounded_send(ReceivedFrame {
+                    header: frame.header,
+                    data: frame.data.to_vec(),
+                })
+                .map_err(|_| TransportError::NoConnection)
+        } else {
+            Err(TransportError::NoConnection)
+        };
+        future::ready(result).boxed()
+    }
+
+    fn send_goodbye(
+        &mut self,
+        reason: GoodbyeReason,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        let mut connection = lock(&self.connection);
+        let result = if connection.connected {
+            connection.goodbye = Some(reason);
+            Ok(())
+        } else {
+            Err(TransportError::NoConnection)
+        };
+        future::ready(result).boxed()
+    }
+
+    fn send_session_token(
+        &mut self,
+        token: SessionToken,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        let mut connection = lock(&self.connection);
+        let result = if connection.connected {
+            connection.session_token = Some(token);
+            Ok(())
+        } else {
+            Err(TransportError::NoConnection)
+        };
+        future::ready(result).boxed()
+    }
+}
+
+/// The display host's end of an in-memory connection to a `MockTransport`. Dropping
+/// it drops the connection.
+pub struct MockClient {
+    connection: Arc<Mutex<Connection>>,
+    frames: mpsc::UnboundedReceiver<ReceivedFrame>,
+    keyframe_requests: mpsc::UnboundedSender<()>,
+    display_parameters: mpsc::UnboundedSender<DisplayParameters>,
+    goodbyes: mpsc::UnboundedSender<GoodbyeReason>,
+    disconnect: Option<oneshot::Sender<()>>,
+}
+
+impl MockClient {
+    /// Only takes encodings of these codec families from now on. With none, every
+    /// encoding is turned down.
+    pub fn accept_only(&self, families: &[&str]) {
+        lock(&self.connection).accepted_families =
+            Some(families.iter().map(|f| f.to_string()).collect());
+    }
+
+    /// Makes this part of setting up a session fail from now on.
+    pub fn fail_on(&self, step: MockTransportStep) {
+        lock(&self.connection).failing.push(step);
+    }
+
+    /// Waits for the next frame. `None` once the transport is gone.
+    pub async fn next_frame(&mut self) -> Option<ReceivedFrame> {
+        self.frames.next().await
+    }
+
+    pub fn request_keyframe(&self) {
+        let _ = self.keyframe_requests.unbounded_send(());
+    }
+
+    /// Reports new display parameters, like when the display host is rotated.
+    pub fn update_display_parameters(&self, params: DisplayParameters) {
+        lock(&self.connection).display_params = params.clone();
+        let _ = self.display_parameters.unbounded_send(params);
+    }
+
+    /// Closes the session from the display host's side.
+    pub fn say_goodbye(&self, reason: GoodbyeReason) {
+        let _ = self.goodbyes.unbounded_send(reason);
+    }
+
+    /// Drops the connection without a word, like a cable being pulled.
+    pub fn disconnect(&mut self) {
+        lock(&self.connection).connected = false;
+        if let Some(disconnect) = self.disconnect.take() {
+            let _ = disconnect.send(());
+        }
+    }
+
+    /// Every encoding the client was told to use so far.
+    pub fn encodings(&self) -> Vec<EncoderPossibleConfiguration> {
+        lock(&self.connection).encodings.clone()
+    }
+
+    /// Why the host closed the session, if it said.
+    pub fn goodbye(&self) -> Option<GoodbyeReason> {
+        lock(&self.connection).goodbye
+    }
+
+    /// The token the host handed out to resume the session with, if any.
+    pub fn session_token(&self) -> Option<SessionToken> {
+        lock(&self.connection).session_token.clone()
+    }
+
+    /// Whether the host closed its end of the connection.
+    pub fn is_closed(&self) -> bool {
+        lock(&self.connection).closed
+    }
+}
+
+impl Drop for MockClient {
+    fn drop(&mut self) {
+        self.disconnect();
+    }
+}
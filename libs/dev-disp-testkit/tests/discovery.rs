@@ -0,0 +1,107 @@
+use dev_disp_core::host::{
+    ConnectableDevice, DeviceDiscoveryRegistry, DeviceEvent, DiscoveryRegistryError,
+    DynDeviceDiscovery, StartedDiscovery, StreamingDeviceDiscovery,
+};
+use dev_disp_testkit::{MockDevice, MockDiscovery, mock_display_parameters};
+use futures::StreamExt;
+use tokio::runtime::Runtime;
+
+#[test]
+fn test_erased_discovery_connects_devices() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let discovery = MockDiscovery::new("Mock discovery");
+        let (device, client) = MockDevice::new("tablet", mock_display_parameters(64, 48));
+        discovery.set_devices(vec![device]);
+
+        let erased: Box<dyn DynDeviceDiscovery> = Box::new(discovery.clone());
+        assert_eq!(erased.display_name(), "Mock discovery");
+        let mut events = erased.into_stream();
+        let Some(DeviceEvent::Added(device)) = events.next().await else {
+            panic!("The tablet wasn't added");
+        };
+        assert_eq!(device.get_info().id, "tablet");
+
+        let mut host = device.connect().await.unwrap();
+        host.initialize().await.unwrap();
+        assert_eq!(
+            host.get_display_config().await.unwrap().resolution,
+            (64, 48)
+        );
+        host.close().await.unwrap();
+        assert!(client.is_closed());
+
+        // Devices found later make it through as well
+        let (other, _) = MockDevice::new("phone", mock_display_parameters(48, 64));
+        discovery.set_devices(vec![other]);
+        let Some(DeviceEvent::Added(device)) = events.next().await else {
+            panic!("The phone wasn't added");
+        };
+        assert_eq!(device.get_info().id, "phone");
+        assert!(matches!(
+            events.next().await,
+            Some(DeviceEvent::Removed(id)) if id == "tablet"
+        ));
+    });
+}
+
+#[test]
+fn test_discovery_streams_only_what_changed() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let discovery = MockDiscovery::new("Mock discovery");
+        let (tablet, _) = MockDevice::new("tablet", mock_display_parameters(64, 48));
+        let (phone, _) = MockDevice::new("phone", mock_display_parameters(48, 64));
+        discovery.set_devices(vec![tablet.clone(), phone.clone()]);
+        let mut events = discovery.clone().into_stream();
+        for id in ["tablet", "phone"] {
+            assert!(matches!(
+                events.next().await,
+                Some(DeviceEvent::Added(device)) if device.get_info().id == id
+            ));
+        }
+
+        // Nothing changed, nothing to tell
+        discovery.set_devices(vec![phone.clone(), tablet.clone()]);
+        discovery.set_devices(vec![tablet.clone(), phone.unavailable("Busy")]);
+        let Some(DeviceEvent::Changed(device)) = events.next().await else {
+            panic!("The phone didn't change");
+        };
+        assert_eq!(
+            device.get_info().unavailable_reason.as_deref(),
+            Some("Busy")
+        );
+
+        discovery.set_devices(vec![tablet]);
+        assert!(matches!(
+            events.next().await,
+            Some(DeviceEvent::Removed(id)) if id == "phone"
+        ));
+    });
+}
+
+#[test]
+fn test_registry_starts_discoveries_by_name() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut registry = DeviceDiscoveryRegistry::new();
+        registry.register("mock", || async {
+            Ok(StartedDiscovery::new(MockDiscovery::new("Mock discovery")))
+        });
+        registry.register("broken", || async { Err("No such hardware".into()) });
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["broken", "mock"]);
+
+        let started = registry.start("mock").await.unwrap();
+        assert_eq!(started.discovery.display_name(), "Mock discovery");
+        assert!(started.background.is_none());
+
+        assert!(matches!(
+            registry.start("broken").await,
+            Err(DiscoveryRegistryError::Start { .. })
+        ));
+        assert!(matches!(
+            registry.start("usb").await,
+            Err(DiscoveryRegistryError::Unknown(_))
+        ));
+    });
+}
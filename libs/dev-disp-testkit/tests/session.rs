@@ -0,0 +1,438 @@
+use std::{pin::pin, time::Duration};
+
+use dev_disp_core::{
+    client::DisplayHost,
+    core::{
+        GoodbyeReason, SessionContext, SessionError, SessionResume, SessionToken, StreamPolicy,
+        SystemState, handle_display_host,
+    },
+    host::{
+        ConnectableDevice, CursorUpdate, DeviceEvent, DisplayHostResult, MemoryClipboardProvider,
+        MockInputInjectorProvider, StreamingDeviceDiscovery,
+    },
+};
+use dev_disp_testkit::{
+    MockClient, MockDevice, MockDiscovery, MockEncoderProvider, MockScreenProvider, MockScreenStep,
+    MockTransport, mock_display_parameters,
+};
+use futures::{
+    Stream, StreamExt,
+    channel::mpsc,
+    future::{self, Either},
+    sink, stream,
+};
+use tokio::runtime::Runtime;
+
+/// How long a session gets before the test gives up on it.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the controller goes through to get a session running.
+const STARTUP: [SystemState; 9] = [
+    SystemState::InitializingTransport,
+    SystemState::GettingDisplayParameters,
+    SystemState::NotifyClientLoading,
+    SystemState::GettingScreen,
+    SystemState::GettingEncoder,
+    SystemState::NegotiatingCodecs,
+    SystemState::InitializingEncoder,
+    SystemState::SettingClientCodec,
+    SystemState::Running,
+];
+
+/// Runs a session with `host` alongside `drive`, until both are done. Returns how the
+/// session ended, and every state it went through.
+fn run_session<C>(
+    screens: MockScreenProvider,
+    encoders: MockEncoderProvider,
+    host: DisplayHost<MockTransport>,
+    resume: Option<SessionResume<MockTransport>>,
+    cancel: C,
+    drive: impl Future<Output = ()>,
+) -> (DisplayHostResult<MockTransport>, Vec<SystemState>)
+where
+    C: Stream<Item = GoodbyeReason> + Unpin + 'static,
+{
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (status_tx, status_rx) = mpsc::unbounded();
+        let context = SessionContext {
+            policy: StreamPolicy::default(),
+            input_provider: MockInputInjectorProvider::new(),
+            clipboard_provider: None::<MemoryClipboardProvider>,
+            mirrors: stream::empty().boxed_local(),
+            recordings: stream::empty().boxed_local(),
+            status_sink: status_tx,
+            policy_sink: sink::drain(),
+            stats_sink: sink::drain(),
+        };
+        let session = handle_display_host(screens, encoders, host, resume, context, cancel);
+        let (result, ()) = tokio::time::timeout(SESSION_TIMEOUT, future::join(session, drive))
+            .await
+            .expect("Session didn't wrap up in time");
+        (result, status_rx.collect().await)
+    })
+}
+
+fn mock_host(width: u32, height: u32) -> (DisplayHost<MockTransport>, MockClient) {
+    let (transport, client) = MockTransport::pair(mock_display_parameters(width, height));
+    (
+        DisplayHost::new(1, "Mock host".to_string(), transport),
+        client,
+    )
+}
+
+#[test]
+fn test_session_runs_and_renegotiates() {
+    let screens = MockScreenProvider::new();
+    let discovery = MockDiscovery::new("Mock discovery");
+    let (device, mut client) = MockDevice::new("tablet", mock_display_parameters(64, 48));
+    discovery.set_devices(vec![device]);
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let rt = Runtime::new().unwrap();
+    let host = rt.block_on(async {
+        let mut events = discovery.clone().into_stream();
+        let Some(DeviceEvent::Added(device)) = events.next().await else {
+            panic!("The tablet wasn't added");
+        };
+        device.connect().await.unwrap()
+    });
+
+    let (result, states) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        cancel_rx,
+        async {
+            let first = client.next_frame().await.unwrap();
+            assert!(first.header.keyframe);
+            assert_eq!(first.data.len(), 64 * 48 * 4);
+
+            // Rotated
+            client.update_display_parameters(mock_display_parameters(48, 64));
+            loop {
+                let frame = client.next_frame().await.unwrap();
+                if frame.header.config_generation == 1 {
+                    assert!(frame.header.keyframe);
+                    break;
+                }
+            }
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+    let renegotiation = [
+        SystemState::Renegotiating,
+        SystemState::GettingScreen,
+        SystemState::NegotiatingCodecs,
+        SystemState::InitializingEncoder,
+        SystemState::SettingClientCodec,
+        SystemState::Running,
+    ];
+    assert_eq!(states, [&STARTUP[..], &renegotiation[..]].concat());
+    assert_eq!(client.goodbye(), Some(GoodbyeReason::UserDisconnect));
+    assert!(client.is_closed());
+    assert_eq!(client.encodings().len(), 2);
+    let resolutions: Vec<_> = screens.requested().iter().map(|p| p.resolution).collect();
+    assert_eq!(resolutions, [(64, 48), (48, 64)]);
+    assert_eq!(screens.closed_screens(), 2);
+}
+
+#[test]
+fn test_screen_is_reconfigured_in_place() {
+    let screens = MockScreenProvider::new().reconfigurable();
+    let (host, mut client) = mock_host(64, 48);
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let (result, _) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        cancel_rx,
+        async {
+            client.next_frame().await.unwrap();
+            client.update_display_parameters(mock_display_parameters(48, 64));
+            loop {
+                let frame = client.next_frame().await.unwrap();
+                if frame.header.config_generation == 1 {
+                    assert!(frame.header.keyframe);
+                    assert_eq!(frame.data.len(), 48 * 64 * 4);
+                    break;
+                }
+            }
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+    // The screen took the new parameters, nothing had to be made again
+    let requested: Vec<_> = screens.requested().iter().map(|p| p.resolution).collect();
+    assert_eq!(requested, [(64, 48)]);
+    let reconfigured: Vec<_> = screens
+        .reconfigured()
+        .iter()
+        .map(|p| p.resolution)
+        .collect();
+    assert_eq!(reconfigured, [(48, 64)]);
+    assert_eq!(screens.closed_screens(), 1);
+}
+
+#[test]
+fn test_codec_rejection_ends_session() {
+    let (host, client) = mock_host(64, 48);
+    client.accept_only(&[]);
+
+    let (result, states) = run_session(
+        MockScreenProvider::new(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        stream::pending(),
+        async {},
+    );
+
+    assert!(matches!(result, Err(SessionError::NoCommonCodec)));
+    assert_eq!(states.last(), Some(&SystemState::NegotiatingCodecs));
+    assert!(client.encodings().is_empty());
+    assert!(client.is_closed());
+}
+
+#[test]
+fn test_transport_drop_loses_session() {
+    let screens = MockScreenProvider::new();
+    let (host, mut client) = mock_host(64, 48);
+
+    let (result, states) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        stream::pending(),
+        async {
+            client.next_frame().await.unwrap();
+            client.disconnect();
+        },
+    );
+
+    assert!(matches!(result, Err(SessionError::ConnectionLost)));
+    assert_eq!(states, STARTUP);
+    // Nobody was listening anymore
+    assert_eq!(client.goodbye(), None);
+    assert_eq!(screens.closed_screens(), 1);
+}
+
+#[test]
+fn test_session_resumes_after_transport_drop() {
+    let (host, mut client) = mock_host(64, 48);
+    let (reconnected, mut reconnected_client) = mock_host(64, 48);
+    let (reconnections_tx, reconnections_rx) = mpsc::unbounded();
+    let token = SessionToken::new("mock-session-token");
+    let resume = SessionResume {
+        token: token.clone(),
+        grace_period: Duration::from_secs(5),
+        reconnections: reconnections_rx.boxed_local(),
+    };
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let (result, states) = run_session(
+        MockScreenProvider::new(),
+        MockEncoderProvider::new(),
+        host,
+        Some(resume),
+        cancel_rx,
+        async {
+            let first = client.next_frame().await.unwrap();
+            assert_eq!(client.session_token(), Some(token.clone()));
+            client.disconnect();
+
+            reconnections_tx.unbounded_send(reconnected).unwrap();
+            let frame = reconnected_client.next_frame().await.unwrap();
+            assert!(frame.header.keyframe);
+            // Frames carry on from where they were, in a new encoding
+            assert!(frame.header.sequence > first.header.sequence);
+            assert!(frame.header.config_generation > first.header.config_generation);
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+    let resumption = [
+        SystemState::AwaitingReconnect,
+        SystemState::InitializingTransport,
+        SystemState::NegotiatingCodecs,
+        SystemState::InitializingEncoder,
+        SystemState::SettingClientCodec,
+        SystemState::Running,
+    ];
+    assert_eq!(states, [&STARTUP[..], &resumption[..]].concat());
+    assert_eq!(reconnected_client.session_token(), Some(token));
+    assert_eq!(
+        reconnected_client.goodbye(),
+        Some(GoodbyeReason::UserDisconnect)
+    );
+}
+
+#[test]
+fn test_screen_failure_ends_session() {
+    let screens = MockScreenProvider::scripted();
+    screens.push(MockScreenStep::Frame);
+    let (host, mut client) = mock_host(64, 48);
+
+    let (result, _) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        stream::pending(),
+        async {
+            client.next_frame().await.unwrap();
+            screens.push(MockScreenStep::Fail("Unplugged".to_string()));
+        },
+    );
+
+    assert!(matches!(result, Err(SessionError::Screen(_))));
+    assert_eq!(client.goodbye(), Some(GoodbyeReason::ScreenFailure));
+    assert_eq!(screens.closed_screens(), 1);
+}
+
+#[test]
+fn test_falls_back_to_working_encoder() {
+    let encoders = MockEncoderProvider::with_encoders(&[("mock_hw", "mock"), ("mock_sw", "mock")]);
+    encoders.fail_encoding("mock_hw");
+    let (host, mut client) = mock_host(64, 48);
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let (result, _) = run_session(
+        MockScreenProvider::new(),
+        encoders.clone(),
+        host,
+        None,
+        cancel_rx,
+        async {
+            client.next_frame().await.unwrap();
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+    let names: Vec<_> = client
+        .encodings()
+        .into_iter()
+        .map(|config| config.encoder_name)
+        .collect();
+    assert_eq!(names, ["mock_hw", "mock_sw"]);
+    assert!(encoders.encoded_frames() > 0);
+}
+
+#[test]
+fn test_cursor_moves_dont_stall_frames() {
+    let screens = MockScreenProvider::new().with_cursor();
+    let (host, mut client) = mock_host(64, 48);
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let (result, _) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        cancel_rx,
+        async {
+            // Moves come in far more often than the screen has frames
+            let moves = async {
+                for x in 0..500 {
+                    screens.move_cursor(CursorUpdate::Move { x, y: 0 });
+                    tokio::time::sleep(Duration::from_millis(2)).await;
+                }
+            };
+            let frames = async {
+                for _ in 0..3 {
+                    client.next_frame().await.unwrap();
+                }
+            };
+            if let Either::Right(_) = future::select(pin!(frames), pin!(moves)).await {
+                panic!("No frames made it while the cursor was moving");
+            }
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn test_screen_is_asked_again_after_not_ready() {
+    let screens = MockScreenProvider::scripted();
+    screens.push(MockScreenStep::Frame);
+    let (host, mut client) = mock_host(64, 48);
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let (result, _) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        cancel_rx,
+        async {
+            let first = client.next_frame().await.unwrap();
+            screens.push(MockScreenStep::NotReady);
+            screens.push(MockScreenStep::NotReady);
+            // Answered while the screen rests
+            client.request_keyframe();
+            let resent = client.next_frame().await.unwrap();
+            assert!(resent.header.keyframe);
+            assert_eq!(resent.data, first.data);
+
+            screens.push(MockScreenStep::Frame);
+            let next = client.next_frame().await.unwrap();
+            assert_ne!(next.data, first.data);
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+}
+
+#[test]
+fn test_duplicate_frames_are_skipped() {
+    let screens = MockScreenProvider::scripted();
+    screens.push(MockScreenStep::Frame);
+    let (host, mut client) = mock_host(64, 48);
+    let (cancel, cancel_rx) = mpsc::unbounded();
+
+    let (result, _) = run_session(
+        screens.clone(),
+        MockEncoderProvider::new(),
+        host,
+        None,
+        cancel_rx,
+        async {
+            let first = client.next_frame().await.unwrap();
+            screens.push(MockScreenStep::Repeat);
+            screens.push(MockScreenStep::Repeat);
+            screens.push(MockScreenStep::Frame);
+            // The repeats never made it, the frame after them did
+            let second = client.next_frame().await.unwrap();
+            assert_ne!(second.data, first.data);
+            cancel
+                .unbounded_send(GoodbyeReason::UserDisconnect)
+                .unwrap();
+        },
+    );
+
+    assert!(result.is_ok());
+}
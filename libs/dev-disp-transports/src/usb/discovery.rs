@@ -1,8 +1,17 @@
-use std::{iter::empty, pin::Pin};
+use std::{
+    collections::HashMap,
+    iter::empty,
+    pin::Pin,
+    sync::{LazyLock, Mutex},
+};
 
 use dev_disp_core::{
     client::{DisplayHost, SomeScreenTransport},
-    host::{ConnectableDevice, ConnectableDeviceInfo, DeviceDiscovery, StreamingDeviceDiscovery},
+    core::SessionToken,
+    host::{
+        ConnectableDevice, ConnectableDeviceInfo, DeviceDiscovery, DeviceEvent,
+        StreamingDeviceDiscovery, diff_device_lists,
+    },
     util::PinnedFuture,
 };
 use futures_util::{FutureExt, Stream, StreamExt};
@@ -13,6 +22,22 @@ use crate::usb::{
     strategies::android_aoa::android_accessory::connect_usb_android_accessory,
 };
 
+/// Session tokens handed to USB devices, by serial number. Android devices don't
+/// keep them, so they are kept here and shown for the device when it comes back.
+static SESSION_TOKENS: LazyLock<Mutex<HashMap<String, SessionToken>>> =
+    LazyLock::new(Default::default);
+
+pub(crate) fn remember_session_token(serial: &str, token: SessionToken) {
+    if let Ok(mut tokens) = SESSION_TOKENS.lock() {
+        tokens.insert(serial.to_string(), token);
+    }
+}
+
+/// The token of the last session the device with this serial number had, if any.
+fn session_token_for(serial: &str) -> Option<SessionToken> {
+    SESSION_TOKENS.lock().ok()?.get(serial).cloned()
+}
+
 /// Connect to a USB device using the specified strategy and return a transport
 pub async fn connect_usb(
     device_info: DeviceInfo,
@@ -69,6 +94,8 @@ impl ConnectableDevice for UsbDeviceCandidate {
                 .to_string(),
             device_type: "USB".to_string(),
             description: None,
+            unavailable_reason: None,
+            resume_token: self.device_info.serial_number().and_then(session_token_for),
             id: self
                 .device_info
                 .serial_number()
@@ -94,12 +121,12 @@ impl DeviceDiscovery for UsbDiscovery {
 }
 
 impl StreamingDeviceDiscovery for UsbDiscovery {
-    fn into_stream(self) -> Pin<Box<dyn Stream<Item = Vec<Self::DeviceCandidate>> + Send>> {
-        nusb::watch_devices()
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = DeviceEvent<Self::DeviceCandidate>> + Send>> {
+        let lists = nusb::watch_devices()
             .map(|hotplugs| hotplugs.then(|_| nusb_list_usb_candidates()))
             .map(|st| st.boxed())
-            .unwrap_or_else(|_| futures_util::stream::empty().boxed())
-            .boxed()
+            .unwrap_or_else(|_| futures_util::stream::empty().boxed());
+        diff_device_lists(lists)
     }
 }
 
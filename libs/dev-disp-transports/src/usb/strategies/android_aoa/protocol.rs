@@ -2,6 +2,10 @@ use bincode::{
     Decode, Encode,
     error::{DecodeError, EncodeError},
 };
+pub use dev_disp_core::core::{
+    GoodbyeReason, InputEvent, ProtocolHello, ProtocolRejection, ProtocolVersionRange,
+};
+use dev_disp_core::host::FrameHeader;
 
 pub type MessageId = u16;
 
@@ -11,11 +15,67 @@ pub struct Message<T> {
     pub payload: T,
 }
 
+/// Wire representation of the core `FrameHeader`.
+#[derive(Encode, Decode, Debug, Clone, Copy)]
+pub struct ScreenUpdateHeader {
+    pub sequence: u64,
+    pub capture_timestamp_us: u64,
+    pub encode_timestamp_us: u64,
+    pub keyframe: bool,
+    pub config_generation: u32,
+}
+
+impl From<FrameHeader> for ScreenUpdateHeader {
+    fn from(header: FrameHeader) -> Self {
+        Self {
+            sequence: header.sequence,
+            capture_timestamp_us: header.capture_timestamp_us,
+            encode_timestamp_us: header.encode_timestamp_us,
+            keyframe: header.keyframe,
+            config_generation: header.config_generation,
+        }
+    }
+}
+
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct ScreenUpdate {
+    pub header: ScreenUpdateHeader,
+    pub data: Vec<u8>,
+}
+
+/// Sent to ask the Android device for its screen info, advertising the protocol
+/// versions and capabilities we speak.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct ScreenInfoRequest {
+    #[bincode(with_serde)]
+    pub protocol: ProtocolHello,
+}
+
+/// Sent instead of anything else when the Android device's `ScreenInfo` shows it
+/// speaks no protocol we can agree on.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct ScreenInfoRejection {
+    #[bincode(with_serde)]
+    pub rejection: ProtocolRejection,
+}
+
+/// Why the session is being closed, sent by either side right before it is.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct Goodbye {
+    #[bincode(with_serde)]
+    pub reason: GoodbyeReason,
+}
+
 #[derive(Encode, Decode, Debug, Clone)]
 pub enum MessageToAndroid {
-    ScreenUpdate(Message<Vec<u8>>),
-    GetScreenInfo(Message<()>),
+    ScreenUpdate(Message<ScreenUpdate>),
+    GetScreenInfo(Message<ScreenInfoRequest>),
     Quit(Message<()>),
+    /// The host is still there
+    Heartbeat(Message<()>),
+    Goodbye(Message<Goodbye>),
+    /// The Android device can't be used with this host
+    Reject(Message<ScreenInfoRejection>),
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -24,6 +84,16 @@ pub struct ScreenInfo {
     pub height: u16,
     pub bpp: u8,
     pub refresh_rate: u8,
+    /// The protocol versions and capabilities the Android device speaks
+    #[bincode(with_serde)]
+    pub protocol: ProtocolHello,
+}
+
+/// Input from the Android device's touch screen, stylus or keyboard.
+#[derive(Encode, Decode, Debug, Clone)]
+pub struct InputUpdate {
+    #[bincode(with_serde)]
+    pub event: InputEvent,
 }
 
 #[derive(Encode, Decode, Debug, Clone)]
@@ -31,6 +101,13 @@ pub enum MessageFromAndroid {
     ScreenInfo(Message<ScreenInfo>),
     Ack(Message<u16>),
     Quit(Message<()>),
+    Input(Message<InputUpdate>),
+    /// The Android device can't decode the stream anymore and needs a keyframe
+    RequestKeyframe(Message<()>),
+    /// The Android device is still there. Once it has sent one, it is expected to
+    /// keep sending them.
+    Heartbeat(Message<()>),
+    Goodbye(Message<Goodbye>),
 }
 
 impl MessageToAndroid {
@@ -39,6 +116,9 @@ impl MessageToAndroid {
             MessageToAndroid::ScreenUpdate(msg) => msg.id,
             MessageToAndroid::GetScreenInfo(msg) => msg.id,
             MessageToAndroid::Quit(msg) => msg.id,
+            MessageToAndroid::Heartbeat(msg) => msg.id,
+            MessageToAndroid::Goodbye(msg) => msg.id,
+            MessageToAndroid::Reject(msg) => msg.id,
         }
     }
 
@@ -63,6 +143,10 @@ impl MessageFromAndroid {
             MessageFromAndroid::ScreenInfo(msg) => msg.id,
             MessageFromAndroid::Ack(msg) => msg.id,
             MessageFromAndroid::Quit(msg) => msg.id,
+            MessageFromAndroid::Input(msg) => msg.id,
+            MessageFromAndroid::RequestKeyframe(msg) => msg.id,
+            MessageFromAndroid::Heartbeat(msg) => msg.id,
+            MessageFromAndroid::Goodbye(msg) => msg.id,
         }
     }
 
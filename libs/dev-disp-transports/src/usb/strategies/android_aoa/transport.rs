@@ -1,21 +1,63 @@
 use std::{pin::Pin, time::Duration};
 
 use dev_disp_core::{
-    client::{ScreenTransport, TransportError},
-    host::DisplayParameters,
-    util::PinnedFuture,
+    client::{ScreenTransport, TransportError, with_timeout},
+    core::{
+        GoodbyeReason, HeartbeatConfig, InputEvent, NegotiatedProtocol, ProtocolCapability,
+        ProtocolHello, ProtocolRejection, SessionToken,
+    },
+    host::{DisplayCharacteristics, DisplayParameters, EncodedFrame},
+    util::{PinnedFuture, PinnedStream},
+};
+use futures::{
+    StreamExt,
+    channel::{mpsc, oneshot},
 };
 use futures_util::{FutureExt, future};
-use log::debug;
+use log::{debug, info, trace, warn};
 use nusb::{
     Device, DeviceInfo, Endpoint, Interface,
     transfer::{Buffer, Bulk, In, Out},
 };
 
-use crate::usb::strategies::android_aoa::protocol::{Message, MessageToAndroid};
+use crate::usb::{
+    discovery::remember_session_token,
+    strategies::android_aoa::protocol::{
+        Goodbye, Message, MessageFromAndroid, MessageToAndroid, ScreenInfoRejection,
+        ScreenInfoRequest, ScreenUpdate,
+    },
+};
 
+/// How long the Android device has to take a message written to it.
 const USB_TIMEOUT: Duration = Duration::from_millis(200);
 
+/// How long the Android device has to answer with its screen info.
+const SCREEN_INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How much is read from the Android device at once. Messages from Android are
+/// small, and each is written in one go.
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+/// How many input events may be queued before new ones are dropped.
+const INPUT_BUFFER_SIZE: usize = 256;
+
+/// Keyframe requests pile up while one is already pending, there is no use in
+/// queueing more than that.
+const KEYFRAME_REQUEST_BUFFER_SIZE: usize = 1;
+
+/// The Android device only says goodbye once.
+const GOODBYE_BUFFER_SIZE: usize = 1;
+
+struct BackgroundContext {
+    bulk_in: Endpoint<Bulk, In>,
+    tx_input: mpsc::Sender<InputEvent>,
+    tx_keyframe_request: mpsc::Sender<()>,
+    tx_goodbye: mpsc::Sender<GoodbyeReason>,
+    /// Hands over the protocol the Android device advertised in its screen info,
+    /// or `None` if it's too old to advertise one.
+    tx_screen_info: Option<oneshot::Sender<Option<ProtocolHello>>>,
+}
+
 /// The Android AOA Screen Host Transport
 ///
 /// This facilitates communication to an Android device
@@ -25,9 +67,29 @@ pub struct AndroidAoaScreenHostTransport {
     dev_info: DeviceInfo,
     dev: Device,
     ifc: Interface,
-    bulk_in: Endpoint<Bulk, In>,
     bulk_out: Endpoint<Bulk, Out>,
     out_buffer: Option<Buffer>,
+    /// Reads from the Android device, taken when the background task is started.
+    background_context: Option<BackgroundContext>,
+    rx_input: Option<mpsc::Receiver<InputEvent>>,
+    rx_keyframe_request: Option<mpsc::Receiver<()>>,
+    rx_goodbye: Option<mpsc::Receiver<GoodbyeReason>>,
+    rx_screen_info: Option<oneshot::Receiver<Option<ProtocolHello>>>,
+    /// Negotiated from the Android device's screen info during initialization
+    protocol: Option<NegotiatedProtocol>,
+    /// Enforced from the screen info on for Android devices that agreed to
+    /// heartbeats. Older ones may still send them, and are held to them after the
+    /// first.
+    heartbeat: HeartbeatConfig,
+}
+
+/// What we advertise to the Android device.
+fn server_hello() -> ProtocolHello {
+    ProtocolHello::current(vec![
+        ProtocolCapability::Heartbeat,
+        ProtocolCapability::Input,
+        ProtocolCapability::KeyframeRequests,
+    ])
 }
 
 impl AndroidAoaScreenHostTransport {
@@ -38,16 +100,47 @@ impl AndroidAoaScreenHostTransport {
         bulk_in: Endpoint<Bulk, In>,
         bulk_out: Endpoint<Bulk, Out>,
     ) -> Self {
+        let (tx_input, rx_input) = mpsc::channel(INPUT_BUFFER_SIZE);
+        let (tx_keyframe_request, rx_keyframe_request) =
+            mpsc::channel(KEYFRAME_REQUEST_BUFFER_SIZE);
+        let (tx_goodbye, rx_goodbye) = mpsc::channel(GOODBYE_BUFFER_SIZE);
+        let (tx_screen_info, rx_screen_info) = oneshot::channel();
+
         Self {
             dev: device,
             dev_info: device_info,
             ifc,
-            bulk_in,
             bulk_out,
             out_buffer: None,
+            background_context: Some(BackgroundContext {
+                bulk_in,
+                tx_input,
+                tx_keyframe_request,
+                tx_goodbye,
+                tx_screen_info: Some(tx_screen_info),
+            }),
+            rx_input: Some(rx_input),
+            rx_keyframe_request: Some(rx_keyframe_request),
+            rx_goodbye: Some(rx_goodbye),
+            rx_screen_info: Some(rx_screen_info),
+            protocol: None,
+            heartbeat: HeartbeatConfig::default(),
         }
     }
 
+    /// Sets how often heartbeats are sent, and how long the Android device may stay
+    /// silent before the connection is considered dead.
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Whether the Android device agreed to heartbeats, and the goodbyes that came
+    /// with them.
+    fn heartbeats_negotiated(&self) -> bool {
+        self.protocol.as_ref().is_some_and(agrees_to_heartbeats)
+    }
+
     pub fn into_device(self) -> Device {
         self.dev
     }
@@ -55,14 +148,162 @@ impl AndroidAoaScreenHostTransport {
     pub fn device_info(&self) -> &DeviceInfo {
         &self.dev_info
     }
+
+    fn _background_task<'a>(&mut self) -> PinnedFuture<'a, Result<(), TransportError>> {
+        let background_ctx = self.background_context.take();
+        let timeout = self.heartbeat.timeout;
+
+        async move {
+            let mut background_ctx = background_ctx.ok_or_else(|| {
+                TransportError::InvalidState("Background task already started".to_string())
+            })?;
+            let mut expect_heartbeats = false;
+
+            debug!("Starting Android AOA background task...");
+
+            loop {
+                let mut in_buffer = background_ctx.bulk_in.allocate(READ_BUFFER_SIZE);
+                in_buffer.set_requested_len(READ_BUFFER_SIZE);
+                background_ctx.bulk_in.submit(in_buffer);
+                let next_complete = background_ctx.bulk_in.next_complete();
+                let completion = if expect_heartbeats {
+                    with_timeout(timeout, next_complete)
+                        .await
+                        .inspect_err(|_| {
+                            warn!(
+                                "Heard nothing from Android for {}ms, giving up on it",
+                                timeout.as_millis()
+                            )
+                        })?
+                } else {
+                    next_complete.await
+                };
+                completion
+                    .status
+                    .map_err(|e| TransportError::Other(Box::new(e)))?;
+
+                let msg = match MessageFromAndroid::deserialize(&completion.buffer) {
+                    Ok((msg, _)) => msg,
+                    // Android apps from before version negotiation answer with a
+                    // `ScreenInfo` that no longer decodes
+                    Err(e) => match background_ctx.tx_screen_info.take() {
+                        Some(tx_screen_info) => {
+                            warn!(
+                                "Failed to decode screen info, assuming an unversioned Android app: {}",
+                                e
+                            );
+                            let _ = tx_screen_info.send(None);
+                            continue;
+                        }
+                        None => {
+                            warn!("Failed to deserialize message from Android: {}", e);
+                            continue;
+                        }
+                    },
+                };
+
+                match msg {
+                    MessageFromAndroid::Input(msg) => {
+                        // Never hold up the connection for input. If nobody is
+                        // keeping up with it, it is stale anyways.
+                        if let Err(e) = background_ctx.tx_input.try_send(msg.payload.event) {
+                            if e.is_full() {
+                                warn!("Input buffer is full, dropping input event");
+                            } else {
+                                trace!("Nobody is listening for input, dropping input event");
+                            }
+                        }
+                    }
+                    MessageFromAndroid::RequestKeyframe(_) => {
+                        // A full buffer means a request is already pending
+                        if let Err(e) = background_ctx.tx_keyframe_request.try_send(()) {
+                            if e.is_disconnected() {
+                                trace!(
+                                    "Nobody is listening for keyframe requests, dropping request"
+                                );
+                            }
+                        }
+                    }
+                    MessageFromAndroid::Heartbeat(_) => {
+                        if !expect_heartbeats {
+                            debug!("Android device sends heartbeats, expecting them from now on");
+                            expect_heartbeats = true;
+                        }
+                    }
+                    MessageFromAndroid::Goodbye(msg) => {
+                        debug!("Android device said goodbye: {}", msg.payload.reason);
+                        if let Err(e) = background_ctx.tx_goodbye.try_send(msg.payload.reason) {
+                            if e.is_disconnected() {
+                                trace!("Nobody is listening for goodbyes");
+                            }
+                        }
+                        return Ok(());
+                    }
+                    MessageFromAndroid::Quit(_) => {
+                        debug!("Android device quit");
+                        return Ok(());
+                    }
+                    MessageFromAndroid::ScreenInfo(info) => {
+                        match background_ctx.tx_screen_info.take() {
+                            Some(tx_screen_info) => {
+                                // The same negotiation `initialize` is about to do,
+                                // so a silent device is given up on right away
+                                expect_heartbeats = server_hello()
+                                    .negotiate(&info.payload.protocol)
+                                    .is_ok_and(|p| agrees_to_heartbeats(&p));
+                                let _ = tx_screen_info.send(Some(info.payload.protocol));
+                            }
+                            None => trace!("Ignoring screen info #{} from Android", info.id),
+                        }
+                    }
+                    MessageFromAndroid::Ack(ack) => {
+                        trace!("Ignoring message #{} from Android", ack.id);
+                    }
+                }
+            }
+        }
+        .boxed()
+    }
+
+    /// Sends a small message that isn't worth keeping a buffer around for, giving up
+    /// if the Android device doesn't take it in time.
+    fn send_message(
+        &mut self,
+        msg: MessageToAndroid,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        let data = match msg.serialize() {
+            Ok(data) => data,
+            Err(e) => return future::err(TransportError::Other(Box::new(e))).boxed(),
+        };
+        let mut out_buffer = self.bulk_out.allocate(data.len());
+        out_buffer.extend_fill(data.len(), 0).copy_from_slice(&data);
+
+        async move {
+            self.bulk_out.submit(out_buffer);
+            let completion = with_timeout(USB_TIMEOUT, self.bulk_out.next_complete()).await?;
+            completion
+                .status
+                .map_err(|e| TransportError::Other(Box::new(e)))
+        }
+        .boxed()
+    }
+}
+
+fn agrees_to_heartbeats(protocol: &NegotiatedProtocol) -> bool {
+    protocol.has_capability(ProtocolCapability::Heartbeat)
 }
 
 impl ScreenTransport for AndroidAoaScreenHostTransport {
     fn initialize<'s>(&'s mut self) -> PinnedFuture<'s, Result<(), TransportError>> {
+        let server_hello = server_hello();
+        let get_screen_info = MessageToAndroid::GetScreenInfo(Message {
+            id: 0,
+            payload: ScreenInfoRequest {
+                protocol: server_hello.clone(),
+            },
+        });
         let mut data = [0u8; 512];
-        let data_size = match MessageToAndroid::GetScreenInfo(Message { id: 0, payload: () })
-            .serialize_into(&mut data)
-        {
+        let data_size = match get_screen_info.serialize_into(&mut data) {
             Ok(size) => size,
             Err(e) => return future::err(TransportError::Other(Box::new(e))).boxed(),
         };
@@ -84,11 +325,114 @@ impl ScreenTransport for AndroidAoaScreenHostTransport {
             self.out_buffer.replace(completion.buffer);
             completion
                 .status
-                .map_err(|e| TransportError::Other(Box::new(e)))
+                .map_err(|e| TransportError::Other(Box::new(e)))?;
+
+            let rx_screen_info = self
+                .rx_screen_info
+                .take()
+                .ok_or_else(|| TransportError::InvalidState("Already initialized".to_string()))?;
+            let client_hello = with_timeout(SCREEN_INFO_TIMEOUT, rx_screen_info)
+                .await?
+                .map_err(|_| TransportError::NoConnection)?;
+            let negotiation = match client_hello {
+                Some(client_hello) => server_hello.negotiate(&client_hello),
+                None => Err(ProtocolRejection::Unversioned {
+                    ours: server_hello.versions,
+                }),
+            };
+
+            match negotiation {
+                Ok(protocol) => {
+                    info!(
+                        "Negotiated protocol v{} with capabilities {:?}",
+                        protocol.version, protocol.capabilities
+                    );
+                    self.protocol = Some(protocol);
+                    Ok(())
+                }
+                Err(rejection) => {
+                    warn!("Rejecting Android device: {}", rejection);
+                    let reject = MessageToAndroid::Reject(Message {
+                        id: 0,
+                        payload: ScreenInfoRejection {
+                            rejection: rejection.clone(),
+                        },
+                    });
+                    if let Err(e) = self.send_message(reject).await {
+                        warn!(
+                            "Failed to tell the Android device why it was rejected: {}",
+                            e
+                        );
+                    }
+                    Err(TransportError::Incompatible(rejection))
+                }
+            }
         }
         .boxed()
     }
 
+    fn protocol(&self) -> Option<&NegotiatedProtocol> {
+        self.protocol.as_ref()
+    }
+
+    fn background<'a>(&mut self) -> PinnedFuture<'a, Result<(), TransportError>> {
+        self._background_task()
+    }
+
+    fn take_input_stream(&mut self) -> Option<PinnedStream<'static, InputEvent>> {
+        self.rx_input.take().map(|rx| rx.boxed())
+    }
+
+    fn take_keyframe_request_stream(&mut self) -> Option<PinnedStream<'static, ()>> {
+        self.rx_keyframe_request.take().map(|rx| rx.boxed())
+    }
+
+    fn take_goodbye_stream(&mut self) -> Option<PinnedStream<'static, GoodbyeReason>> {
+        self.rx_goodbye.take().map(|rx| rx.boxed())
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeats_negotiated()
+            .then_some(self.heartbeat.interval)
+    }
+
+    fn send_heartbeat(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        if !self.heartbeats_negotiated() {
+            return future::err(TransportError::NotImplemented).boxed();
+        }
+        self.send_message(MessageToAndroid::Heartbeat(Message { id: 0, payload: () }))
+    }
+
+    fn send_goodbye(
+        &mut self,
+        reason: GoodbyeReason,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        // Goodbyes came along with heartbeats
+        if !self.heartbeats_negotiated() {
+            return future::err(TransportError::NotImplemented).boxed();
+        }
+        self.send_message(MessageToAndroid::Goodbye(Message {
+            id: 0,
+            payload: Goodbye { reason },
+        }))
+    }
+
+    fn send_session_token(
+        &mut self,
+        token: SessionToken,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        // The Android device can't keep the token, so it is kept for it by its serial
+        // number, which it shows again when it comes back
+        let result = match self.dev_info.serial_number() {
+            Some(serial) => {
+                remember_session_token(serial, token);
+                Ok(())
+            }
+            None => Err(TransportError::NotImplemented),
+        };
+        future::ready(result).boxed()
+    }
+
     fn get_display_config(
         &mut self,
     ) -> PinnedFuture<'_, Result<DisplayParameters, TransportError>> {
@@ -99,6 +443,7 @@ impl ScreenTransport for AndroidAoaScreenHostTransport {
                 .unwrap_or("Unknown")
                 .to_string(),
             resolution: (1920, 1080),
+            characteristics: DisplayCharacteristics::default(),
         }))
         .boxed()
     }
@@ -126,14 +471,17 @@ impl ScreenTransport for AndroidAoaScreenHostTransport {
 
     fn send_screen_data<'s, 'a>(
         &'s mut self,
-        data: &'a [u8],
+        frame: EncodedFrame<'a>,
     ) -> Pin<Box<dyn Future<Output = Result<(), TransportError>> + Send + 's>>
     where
         'a: 's,
     {
         let screen_update = MessageToAndroid::ScreenUpdate(Message {
             id: 0,
-            payload: data.to_vec(),
+            payload: ScreenUpdate {
+                header: frame.header.into(),
+                data: frame.data.to_vec(),
+            },
         });
         let heaped_data = match screen_update.serialize() {
             Ok(vec) => vec,
@@ -3,7 +3,11 @@ use std::{collections::HashMap, error::Error, pin::Pin, sync::Arc};
 use async_tungstenite::{WebSocketStream, tungstenite::Message};
 use dev_disp_core::{
     client::DisplayHost,
-    host::{ConnectableDevice, ConnectableDeviceInfo, DeviceDiscovery, StreamingDeviceDiscovery},
+    core::HeartbeatConfig,
+    host::{
+        ConnectableDevice, ConnectableDeviceInfo, DeviceDiscovery, DeviceEvent,
+        StreamingDeviceDiscovery, diff_device_lists,
+    },
     util::{PinnedFuture, PinnedLocalFuture},
 };
 use futures::{
@@ -17,7 +21,10 @@ use log::{debug, error, info, warn};
 use uuid::Uuid;
 
 use crate::websocket::{
-    messages::{WsMessageFromClient, WsMessageFromSource},
+    messages::{
+        NegotiatedProtocol, ProtocolCapability, ProtocolHello, ProtocolRejection, SessionToken,
+        WsMessageFromClient, WsMessageFromSource,
+    },
     transport::WsTransport,
 };
 
@@ -25,6 +32,9 @@ use crate::websocket::{
 pub struct WsDeviceCandidate<S> {
     take_ws_tx: mpsc::Sender<oneshot::Sender<WebSocketStream<S>>>,
     device_info: ConnectableDeviceInfo,
+    /// The protocol agreed on during pre-init. `None` if the client was rejected.
+    protocol: Option<NegotiatedProtocol>,
+    heartbeat: HeartbeatConfig,
 }
 
 impl<S> WsDeviceCandidate<S>
@@ -34,12 +44,21 @@ where
     pub fn new(
         take_ws_tx: mpsc::Sender<oneshot::Sender<WebSocketStream<S>>>,
         device_info: ConnectableDeviceInfo,
+        protocol: Option<NegotiatedProtocol>,
     ) -> Self {
         Self {
             take_ws_tx,
             device_info,
+            protocol,
+            heartbeat: HeartbeatConfig::default(),
         }
     }
+
+    /// Sets the heartbeat the transport keeps once the device is connected.
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
 }
 
 impl<S> Clone for WsDeviceCandidate<S> {
@@ -47,6 +66,8 @@ impl<S> Clone for WsDeviceCandidate<S> {
         Self {
             take_ws_tx: self.take_ws_tx.clone(),
             device_info: self.device_info.clone(),
+            protocol: self.protocol.clone(),
+            heartbeat: self.heartbeat,
         }
     }
 }
@@ -62,6 +83,14 @@ where
     ) -> PinnedFuture<'static, Result<DisplayHost<Self::Transport>, Box<dyn Error + Send + Sync>>>
     {
         async move {
+            let Some(protocol) = self.protocol else {
+                let reason = self
+                    .device_info
+                    .unavailable_reason
+                    .unwrap_or_else(|| "Device is unavailable".to_string());
+                return Err(reason.into());
+            };
+
             let (get_ws_tx, get_ws_rx) = oneshot::channel();
             if let Err(e) = self.take_ws_tx.send(get_ws_tx).await {
                 error!("Error requesting to takeover connection: {}", e);
@@ -77,7 +106,7 @@ where
             Ok(DisplayHost::new(
                 0,
                 self.device_info.name,
-                WsTransport::new(websocket),
+                WsTransport::new(websocket, protocol).with_heartbeat(self.heartbeat),
             ))
         }
         .boxed()
@@ -94,6 +123,7 @@ type CurrentConnections<S> = Arc<RwLock<HashMap<String, WsDeviceCandidate<S>>>>;
 struct WsDiscoveryListenCtx<S> {
     current_connections: CurrentConnections<S>,
     connections_update_tx: mpsc::Sender<()>,
+    heartbeat: HeartbeatConfig,
 }
 
 impl<S> Clone for WsDiscoveryListenCtx<S> {
@@ -101,6 +131,7 @@ impl<S> Clone for WsDiscoveryListenCtx<S> {
         Self {
             current_connections: self.current_connections.clone(),
             connections_update_tx: self.connections_update_tx.clone(),
+            heartbeat: self.heartbeat,
         }
     }
 }
@@ -138,11 +169,19 @@ where
             listen_ctx: WsDiscoveryListenCtx {
                 current_connections,
                 connections_update_tx,
+                heartbeat: HeartbeatConfig::default(),
             },
             connections_update_notification: connections_update_rx,
         }
     }
 
+    /// Sets how often connected devices get a heartbeat, and how long they may stay
+    /// silent before they are given up on.
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.listen_ctx.heartbeat = heartbeat;
+        self
+    }
+
     /// Listen for incoming WebSocket connections from devices.
     ///
     /// The provided stream should yield accepted TCP streams that
@@ -234,7 +273,18 @@ where
 
         // Do pre-init sanity check
         info!("Starting WebSocket pre-init handshake...");
-        let pre_init_req = WsMessageFromSource::RequestPreInit;
+        let server_hello = ProtocolHello::current(vec![
+            ProtocolCapability::RegionUpdates,
+            ProtocolCapability::CursorPlane,
+            ProtocolCapability::ClockSync,
+            ProtocolCapability::Clipboard,
+            ProtocolCapability::Heartbeat,
+            ProtocolCapability::SessionResume,
+            ProtocolCapability::Input,
+            ProtocolCapability::KeyframeRequests,
+            ProtocolCapability::ReceiverReports,
+        ]);
+        let pre_init_req = WsMessageFromSource::RequestPreInit(server_hello.clone());
         let pre_init_req_bytes =
             match bincode::serde::encode_to_vec(&pre_init_req, bincode::config::standard()) {
                 Ok(vec) => vec,
@@ -254,38 +304,109 @@ where
 
         info!("Waiting for pre-init response...");
 
-        let res = ws_stream.next().await.and_then(|msg| match msg {
-            Ok(Message::Binary(bin)) => {
-                let decoded: Result<(WsMessageFromClient, _), _> =
-                    bincode::serde::decode_from_slice(&bin, bincode::config::standard());
-                match decoded {
-                    Ok((msg, _)) => Some(msg),
-                    Err(e) => {
-                        error!("Failed to decode pre-init response: {}", e);
-                        None
-                    }
-                }
+        let res = match ws_stream.next().await {
+            Some(Ok(Message::Binary(bin))) => {
+                bincode::serde::decode_from_slice(&bin, bincode::config::standard())
+                    .map(|(msg, _)| msg)
             }
-            Ok(other) => {
+            Some(Ok(other)) => {
                 error!(
                     "Unexpected WebSocket message type during pre-init: {:?}",
                     other
                 );
-                None
+                return;
             }
-            Err(e) => {
+            Some(Err(e)) => {
                 error!("WebSocket error during pre-init: {}", e);
-                None
+                return;
             }
-        });
+            None => {
+                error!("WebSocket closed during pre-init.");
+                return;
+            }
+        };
+
+        let negotiation = match res {
+            Ok(WsMessageFromClient::ResponsePreInit(client_hello)) => {
+                server_hello.negotiate(&client_hello)
+            }
+            Ok(other) => {
+                error!("Did not receive valid pre-init response: {:?}", other);
+                return;
+            }
+            Err(e) => {
+                // Clients from before version negotiation answer with a bare
+                // `ResponsePreInit`, which no longer decodes.
+                warn!(
+                    "Failed to decode pre-init response, assuming an unversioned client: {}",
+                    e
+                );
+                Err(ProtocolRejection::Unversioned {
+                    ours: server_hello.versions,
+                })
+            }
+        };
+
+        let protocol = match negotiation {
+            Ok(protocol) => protocol,
+            Err(rejection) => {
+                warn!("Rejecting WebSocket client: {}", rejection);
+                let reject_msg = WsMessageFromSource::RejectPreInit(rejection.clone());
+                match bincode::serde::encode_to_vec(&reject_msg, bincode::config::standard()) {
+                    Ok(bytes) => {
+                        if let Err(e) = ws_stream.send(Message::binary(bytes)).await {
+                            error!("Failed to send pre-init rejection: {}", e);
+                        }
+                    }
+                    Err(e) => error!("Failed to encode pre-init rejection: {}", e),
+                }
+
+                // List the device anyway so the user can see why it can't be used.
+                let device_info = ConnectableDeviceInfo {
+                    id: Uuid::new_v4().to_string(),
+                    device_type: "WebSocket".to_string(),
+                    name: "Incompatible WebSocket Device".to_string(),
+                    description: Some("A device connected via WebSocket".to_string()),
+                    unavailable_reason: Some(rejection.to_string()),
+                    resume_token: None,
+                };
+                Self::hold_candidate(listen_ctx, ws_stream, device_info, None).await;
+                return;
+            }
+        };
+
+        info!(
+            "Negotiated protocol v{} with capabilities {:?}",
+            protocol.version, protocol.capabilities
+        );
 
-        if res.is_none() {
-            error!("Did not receive valid pre-init response.");
+        let accept_msg = WsMessageFromSource::AcceptPreInit(protocol.clone());
+        let accept_bytes =
+            match bincode::serde::encode_to_vec(&accept_msg, bincode::config::standard()) {
+                Ok(vec) => vec,
+                Err(e) => {
+                    error!("Failed to encode pre-init acceptance: {}", e);
+                    return;
+                }
+            };
+
+        if let Err(e) = ws_stream.send(Message::binary(accept_bytes)).await {
+            error!("Failed to send pre-init acceptance: {}", e);
             return;
         }
 
         info!("Pre-init response received.");
 
+        // Clients that can resume a session show the token of the one they had
+        let resume_token = if protocol.has_capability(ProtocolCapability::SessionResume) {
+            match Self::request_resume_token(&mut ws_stream).await {
+                Some(token) => token,
+                None => return,
+            }
+        } else {
+            None
+        };
+
         info!("Requesting device info...");
 
         // Now we do device info
@@ -341,22 +462,88 @@ where
             }
         };
 
-        let id = Uuid::new_v4().to_string();
-
-        info!("Registering device with id {}", &id);
-
-        let (take_ws_tx, mut take_ws_rx) = mpsc::channel::<oneshot::Sender<WebSocketStream<S>>>(1);
-
         let device_info = ConnectableDeviceInfo {
-            id: id.clone(),
+            id: Uuid::new_v4().to_string(),
             device_type: "WebSocket".to_string(),
             name: format!("WebSocket Device {}", dev_info.name),
             description: Some("A device connected via WebSocket".to_string()),
+            unavailable_reason: None,
+            resume_token,
         };
 
         info!("Device info received: {:?}", device_info);
 
-        let device_candidate = WsDeviceCandidate::new(take_ws_tx, device_info);
+        Self::hold_candidate(listen_ctx, ws_stream, device_info, Some(protocol)).await;
+    }
+
+    /// Asks the client for the token of the session it had before reconnecting. Returns
+    /// `None` if it didn't answer as expected.
+    async fn request_resume_token(
+        ws_stream: &mut WebSocketStream<S>,
+    ) -> Option<Option<SessionToken>> {
+        let req_bytes = match bincode::serde::encode_to_vec(
+            &WsMessageFromSource::RequestResumeToken,
+            bincode::config::standard(),
+        ) {
+            Ok(vec) => vec,
+            Err(e) => {
+                error!("Failed to encode resume token request: {}", e);
+                return None;
+            }
+        };
+
+        if let Err(e) = ws_stream.send(Message::binary(req_bytes)).await {
+            error!("Failed to send resume token request: {}", e);
+            return None;
+        }
+
+        let res = match ws_stream.next().await {
+            Some(Ok(Message::Binary(bin))) => {
+                bincode::serde::decode_from_slice(&bin, bincode::config::standard())
+                    .map(|(msg, _)| msg)
+            }
+            other => {
+                error!("Unexpected answer to resume token request: {:?}", other);
+                return None;
+            }
+        };
+
+        match res {
+            Ok(WsMessageFromClient::ResponseResumeToken(token)) => {
+                if let Some(token) = &token {
+                    info!("Client wants to resume session {}", token);
+                }
+                Some(token)
+            }
+            Ok(other) => {
+                error!("Did not receive valid resume token response: {:?}", other);
+                None
+            }
+            Err(e) => {
+                error!("Failed to decode resume token response: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Lists a device as a candidate until it is either taken, or its connection closes.
+    ///
+    /// Devices without a protocol are only listed so the user can see why they are
+    /// unavailable, and are never handed out.
+    async fn hold_candidate(
+        listen_ctx: &WsDiscoveryListenCtx<S>,
+        mut ws_stream: WebSocketStream<S>,
+        device_info: ConnectableDeviceInfo,
+        protocol: Option<NegotiatedProtocol>,
+    ) {
+        let id = device_info.id.clone();
+
+        info!("Registering device with id {}", &id);
+
+        let (take_ws_tx, mut take_ws_rx) = mpsc::channel::<oneshot::Sender<WebSocketStream<S>>>(1);
+
+        let device_candidate = WsDeviceCandidate::new(take_ws_tx, device_info, protocol)
+            .with_heartbeat(listen_ctx.heartbeat);
 
         listen_ctx
             .current_connections
@@ -421,8 +608,8 @@ impl<S> StreamingDeviceDiscovery for WsDiscovery<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    fn into_stream(self) -> Pin<Box<dyn Stream<Item = Vec<Self::DeviceCandidate>> + Send>> {
-        Box::pin(futures::stream::unfold(self, |mut this| async move {
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = DeviceEvent<Self::DeviceCandidate>> + Send>> {
+        diff_device_lists(futures::stream::unfold(self, |mut this| async move {
             let notification = this.connections_update_notification.next().await;
             notification?;
             Some((this.discover_devices().await, this))
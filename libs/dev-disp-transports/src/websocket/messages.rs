@@ -1,6 +1,15 @@
 pub use dev_disp_core::{
-    core::{DevDispMessageFromClient, DevDispMessageFromSource},
-    host::{DisplayParameters, EncoderPossibleConfiguration},
+    core::{
+        ButtonState, ClockPing, ClockPong, DEFAULT_HEARTBEAT_INTERVAL, DevDispMessageFromClient,
+        DevDispMessageFromSource, FramePresented, GoodbyeReason, InputEvent, KeyEvent,
+        NegotiatedProtocol, NormalizedPosition, PenEvent, PenTool, PointerButton,
+        ProtocolCapability, ProtocolHello, ProtocolRejection, ReceiverReport, ScrollEvent,
+        SessionToken, TouchEvent, TouchPhase,
+    },
+    host::{
+        CLIPBOARD_TEXT_MIME_TYPE, ClipboardMessage, CursorImage, CursorUpdate,
+        DisplayCharacteristics, DisplayParameters, EncoderPossibleConfiguration, FrameHeader,
+    },
 };
 use serde::{Deserialize, Serialize};
 
@@ -22,8 +31,9 @@ pub struct WsMessageDeviceInfo {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(bound(deserialize = "'de: 'a"))]
 pub enum WsMessageFromSource<'a> {
-    /// Used to ask new connection if it is in the right place
-    RequestPreInit,
+    /// Used to ask new connection if it is in the right place, advertising the
+    /// protocol versions and capabilities we speak
+    RequestPreInit(ProtocolHello),
 
     /// Used to ask connection for its device information
     RequestDeviceInformation,
@@ -33,12 +43,23 @@ pub enum WsMessageFromSource<'a> {
 
     /// Used to forward a core logic message to the client
     Core(DevDispMessageFromSource<'a>),
+
+    /// Used to tell the client which protocol was picked from the pre-init exchange
+    AcceptPreInit(NegotiatedProtocol),
+
+    /// Used to tell the client we can't talk to it, and why
+    RejectPreInit(ProtocolRejection),
+
+    /// Used to ask a client that can resume sessions for the token of the one it
+    /// had, after the pre-init exchange
+    RequestResumeToken,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum WsMessageFromClient {
-    /// Used to tell the server "I intend to be selectable"
-    ResponsePreInit,
+    /// Used to tell the server "I intend to be selectable", advertising the
+    /// protocol versions and capabilities we speak
+    ResponsePreInit(ProtocolHello),
 
     /// Used to give the server basic info used for display
     ResponseDeviceInformation(WsMessageDeviceInfo),
@@ -48,4 +69,38 @@ pub enum WsMessageFromClient {
 
     /// Used to give a core-logic message to the server
     Core(DevDispMessageFromClient),
+
+    /// Used to show the server the token of the session we had, if any
+    ResponseResumeToken(Option<SessionToken>),
+}
+
+#[cfg(test)]
+mod test {
+    use dev_disp_core::core::{ProtocolCapability, ProtocolVersionRange};
+
+    use super::WsMessageFromClient;
+
+    #[test]
+    fn test_capabilities_from_newer_clients_decode_as_unknown() {
+        // A `ResponsePreInit` from a client that knows a capability we don't, with
+        // enum variants written as their index like bincode does
+        let versions = ProtocolVersionRange { min: 2, max: 99 };
+        let capability_indices: Vec<u32> = vec![1, 9_999];
+        let bytes = bincode::serde::encode_to_vec(
+            (0u32, versions, capability_indices),
+            bincode::config::standard(),
+        )
+        .unwrap();
+
+        let (message, _): (WsMessageFromClient, _) =
+            bincode::serde::decode_from_slice(&bytes, bincode::config::standard()).unwrap();
+        let WsMessageFromClient::ResponsePreInit(hello) = message else {
+            panic!("Expected a pre-init response, got {:?}", message);
+        };
+        assert_eq!(hello.versions, versions);
+        assert_eq!(
+            hello.capabilities,
+            vec![ProtocolCapability::CursorPlane, ProtocolCapability::Unknown]
+        );
+    }
 }
@@ -1,21 +1,53 @@
+use std::time::Duration;
+
 use async_tungstenite::{
     WebSocketReceiver, WebSocketSender, WebSocketStream, tungstenite::Message,
 };
 
 use dev_disp_core::{
-    client::{ScreenTransport, TransportError},
-    core::{DevDispMessageFromClient, DevDispMessageFromSource},
-    host::{DisplayParameters, EncoderPossibleConfiguration},
-    util::PinnedFuture,
+    client::{ScreenTransport, TransportError, with_timeout},
+    core::{
+        ClockPing, ClockPong, DevDispMessageFromClient, DevDispMessageFromSource, FramePresented,
+        GoodbyeReason, HeartbeatConfig, InputEvent, NegotiatedProtocol, ProtocolCapability,
+        ReceiverReport, SessionToken,
+    },
+    host::{
+        ClipboardMessage, CursorUpdate, DisplayParameters, EncodedFrame, EncodedRegion,
+        EncoderPossibleConfiguration, FrameHeader,
+    },
+    util::{PinnedFuture, PinnedStream},
 };
 use futures::{AsyncRead, AsyncWrite, SinkExt, StreamExt, channel::mpsc};
 use futures_util::FutureExt;
-use log::{debug, error, warn};
+use log::{debug, error, trace, warn};
 
 use crate::websocket::messages::{
     WsMessageDeviceInfo, WsMessageFromClient, WsMessageFromSource, WsMessageProtocolInit,
 };
 
+/// How many input events may be queued before new ones are dropped.
+const INPUT_BUFFER_SIZE: usize = 256;
+
+/// Keyframe requests pile up while one is already pending, there is no use in
+/// queueing more than that.
+const KEYFRAME_REQUEST_BUFFER_SIZE: usize = 1;
+
+/// Receiver reports come in about once a second, a few is plenty of slack.
+const RECEIVER_REPORT_BUFFER_SIZE: usize = 4;
+
+/// Clock pings go out every few seconds, so pongs don't pile up either.
+const CLOCK_PONG_BUFFER_SIZE: usize = 8;
+
+/// One presentation report comes in for every frame. Losing some only thins out
+/// the latency measurements.
+const FRAME_PRESENTED_BUFFER_SIZE: usize = 64;
+
+/// Clipboard messages only come in when someone copies or pastes.
+const CLIPBOARD_BUFFER_SIZE: usize = 8;
+
+/// The client only says goodbye once.
+const GOODBYE_BUFFER_SIZE: usize = 1;
+
 struct BackgroundContext<S> {
     ws_rx: WebSocketReceiver<S>,
 
@@ -24,6 +56,13 @@ struct BackgroundContext<S> {
     tx_core_display_params_update: mpsc::Sender<DisplayParameters>,
     tx_core_preferred_encoding_response: mpsc::Sender<Vec<EncoderPossibleConfiguration>>,
     tx_core_set_encoding_response: mpsc::Sender<bool>,
+    tx_core_input: mpsc::Sender<InputEvent>,
+    tx_core_keyframe_request: mpsc::Sender<()>,
+    tx_core_receiver_report: mpsc::Sender<ReceiverReport>,
+    tx_core_clock_pong: mpsc::Sender<ClockPong>,
+    tx_core_frame_presented: mpsc::Sender<FramePresented>,
+    tx_core_clipboard: mpsc::Sender<ClipboardMessage>,
+    tx_core_goodbye: mpsc::Sender<GoodbyeReason>,
 }
 
 pub struct WsTransport<S> {
@@ -35,16 +74,36 @@ pub struct WsTransport<S> {
     rx_protocol_init: mpsc::Receiver<WsMessageProtocolInit>,
     rx_device_info: mpsc::Receiver<WsMessageDeviceInfo>,
 
-    rx_core_display_params_update: mpsc::Receiver<DisplayParameters>,
+    /// Used for the first display parameters, then taken by whoever follows the
+    /// client's later changes.
+    rx_core_display_params_update: Option<mpsc::Receiver<DisplayParameters>>,
     rx_core_preferred_encoding_response: mpsc::Receiver<Vec<EncoderPossibleConfiguration>>,
     rx_core_set_encoding_response: mpsc::Receiver<bool>,
+    /// Taken by whoever injects the client's input.
+    rx_core_input: Option<mpsc::Receiver<InputEvent>>,
+    /// Taken by whoever drives the encoder.
+    rx_core_keyframe_request: Option<mpsc::Receiver<()>>,
+    /// Taken by whoever adapts the rate.
+    rx_core_receiver_report: Option<mpsc::Receiver<ReceiverReport>>,
+    /// Taken by whoever measures latency.
+    rx_core_clock_pong: Option<mpsc::Receiver<ClockPong>>,
+    rx_core_frame_presented: Option<mpsc::Receiver<FramePresented>>,
+    /// Taken by whoever syncs the clipboard.
+    rx_core_clipboard: Option<mpsc::Receiver<ClipboardMessage>>,
+    /// Taken by whoever ends the session.
+    rx_core_goodbye: Option<mpsc::Receiver<GoodbyeReason>>,
+
+    /// The protocol agreed on with the client during pre-init.
+    protocol: NegotiatedProtocol,
+    /// Only enforced when the client agreed to send heartbeats.
+    heartbeat: HeartbeatConfig,
 }
 
 impl<S> WsTransport<S>
 where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    pub fn new(websocket: WebSocketStream<S>) -> Self {
+    pub fn new(websocket: WebSocketStream<S>, protocol: NegotiatedProtocol) -> Self {
         let (ws_tx, ws_rx) = websocket.split();
 
         let (tx_protocol_init, rx_protocol_init) = mpsc::channel(2);
@@ -53,6 +112,16 @@ where
         let (tx_core_preferred_encoding_response, rx_core_preferred_encoding_response) =
             mpsc::channel(2);
         let (tx_core_set_encoding_response, rx_core_set_encoding_response) = mpsc::channel(2);
+        let (tx_core_input, rx_core_input) = mpsc::channel(INPUT_BUFFER_SIZE);
+        let (tx_core_keyframe_request, rx_core_keyframe_request) =
+            mpsc::channel(KEYFRAME_REQUEST_BUFFER_SIZE);
+        let (tx_core_receiver_report, rx_core_receiver_report) =
+            mpsc::channel(RECEIVER_REPORT_BUFFER_SIZE);
+        let (tx_core_clock_pong, rx_core_clock_pong) = mpsc::channel(CLOCK_PONG_BUFFER_SIZE);
+        let (tx_core_frame_presented, rx_core_frame_presented) =
+            mpsc::channel(FRAME_PRESENTED_BUFFER_SIZE);
+        let (tx_core_clipboard, rx_core_clipboard) = mpsc::channel(CLIPBOARD_BUFFER_SIZE);
+        let (tx_core_goodbye, rx_core_goodbye) = mpsc::channel(GOODBYE_BUFFER_SIZE);
 
         let background_ctx = BackgroundContext {
             ws_rx,
@@ -61,6 +130,13 @@ where
             tx_core_display_params_update,
             tx_core_preferred_encoding_response,
             tx_core_set_encoding_response,
+            tx_core_input,
+            tx_core_keyframe_request,
+            tx_core_receiver_report,
+            tx_core_clock_pong,
+            tx_core_frame_presented,
+            tx_core_clipboard,
+            tx_core_goodbye,
         };
 
         Self {
@@ -68,37 +144,75 @@ where
             background_context: Some(background_ctx),
             rx_protocol_init,
             rx_device_info,
-            rx_core_display_params_update,
+            rx_core_display_params_update: Some(rx_core_display_params_update),
             rx_core_preferred_encoding_response,
             rx_core_set_encoding_response,
+            rx_core_input: Some(rx_core_input),
+            rx_core_keyframe_request: Some(rx_core_keyframe_request),
+            rx_core_receiver_report: Some(rx_core_receiver_report),
+            rx_core_clock_pong: Some(rx_core_clock_pong),
+            rx_core_frame_presented: Some(rx_core_frame_presented),
+            rx_core_clipboard: Some(rx_core_clipboard),
+            rx_core_goodbye: Some(rx_core_goodbye),
+            protocol,
+            heartbeat: HeartbeatConfig::default(),
         }
     }
 
+    /// Sets how often heartbeats are sent, and how long the client may stay silent
+    /// before the connection is considered dead.
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Whether the client agreed to send heartbeats, so silence means it's gone.
+    fn heartbeats_negotiated(&self) -> bool {
+        self.protocol.has_capability(ProtocolCapability::Heartbeat)
+    }
+
     async fn send_msg<'a>(&mut self, msg: WsMessageFromSource<'a>) -> Result<(), TransportError> {
         // TODO: Allocate a buffer once and reuse it! Avoid heap allocation on every send
         let bytes = bincode::serde::encode_to_vec(&msg, bincode::config::standard())
             .map_err(|_| TransportError::SerializationError)?;
-        self.ws_tx
-            .send(Message::binary(bytes))
-            .await
-            .map_err(|e| TransportError::Other(Box::new(e)))?;
+        // A half-open connection stops taking data once its buffers fill up
+        let timeout = self
+            .heartbeats_negotiated()
+            .then_some(self.heartbeat.timeout);
+        let send = self.ws_tx.send(Message::binary(bytes));
+        let sent = match timeout {
+            Some(timeout) => with_timeout(timeout, send).await?,
+            None => send.await,
+        };
+        sent.map_err(|e| TransportError::Other(Box::new(e)))?;
         Ok(())
     }
 
     fn _background_task<'a>(&mut self) -> PinnedFuture<'a, Result<(), TransportError>> {
         let background_ctx = self.background_context.take();
+        let timeout = self
+            .heartbeats_negotiated()
+            .then_some(self.heartbeat.timeout);
 
         async move {
-            let mut background_ctx = background_ctx.ok_or_else(|| TransportError::Unknown)?;
+            let mut background_ctx = background_ctx.ok_or_else(|| {
+                TransportError::InvalidState("Background task already started".to_string())
+            })?;
 
             debug!("Starting WebSocket background task...");
 
             loop {
-                let m = background_ctx
-                    .ws_rx
-                    .next()
-                    .await
-                    .ok_or(TransportError::NoConnection)?;
+                let next = background_ctx.ws_rx.next();
+                let m = match timeout {
+                    Some(timeout) => with_timeout(timeout, next).await.inspect_err(|_| {
+                        warn!(
+                            "Heard nothing from the client for {}ms, giving up on it",
+                            timeout.as_millis()
+                        )
+                    })?,
+                    None => next.await,
+                }
+                .ok_or(TransportError::NoConnection)?;
 
                 debug!("Received WebSocket message: {:?}", m);
 
@@ -150,13 +264,94 @@ where
                                         .await
                                         .map_err(|e| TransportError::Other(Box::new(e)))?;
                                 }
+                                DevDispMessageFromClient::Input(event) => {
+                                    // Never hold up the connection for input. If nobody is
+                                    // keeping up with it, it is stale anyways.
+                                    if let Err(e) = background_ctx.tx_core_input.try_send(event) {
+                                        if e.is_full() {
+                                            warn!("Input buffer is full, dropping input event");
+                                        } else {
+                                            trace!("Nobody is listening for input, dropping input event");
+                                        }
+                                    }
+                                }
+                                DevDispMessageFromClient::RequestKeyframe => {
+                                    // A full buffer means a request is already pending
+                                    if let Err(e) =
+                                        background_ctx.tx_core_keyframe_request.try_send(())
+                                    {
+                                        if e.is_disconnected() {
+                                            trace!("Nobody is listening for keyframe requests, dropping request");
+                                        }
+                                    }
+                                }
+                                DevDispMessageFromClient::ReceiverReport(report) => {
+                                    // The next report will do just as well if this one doesn't fit
+                                    if let Err(e) =
+                                        background_ctx.tx_core_receiver_report.try_send(report)
+                                    {
+                                        if e.is_full() {
+                                            debug!("Receiver report buffer is full, dropping report");
+                                        }
+                                    }
+                                }
+                                DevDispMessageFromClient::ClockPong(pong) => {
+                                    // A missed pong is made up for by the next one
+                                    if let Err(e) =
+                                        background_ctx.tx_core_clock_pong.try_send(pong)
+                                    {
+                                        if e.is_full() {
+                                            debug!("Clock pong buffer is full, dropping pong");
+                                        }
+                                    }
+                                }
+                                DevDispMessageFromClient::FramePresented(presented) => {
+                                    if let Err(e) =
+                                        background_ctx.tx_core_frame_presented.try_send(presented)
+                                    {
+                                        if e.is_full() {
+                                            trace!("Frame presentation buffer is full, dropping report");
+                                        }
+                                    }
+                                }
+                                DevDispMessageFromClient::Heartbeat => {
+                                    // Hearing anything at all is what keeps us going
+                                }
+                                DevDispMessageFromClient::Goodbye(reason) => {
+                                    debug!("Client said goodbye: {}", reason);
+                                    if let Err(e) = background_ctx.tx_core_goodbye.try_send(reason) {
+                                        if e.is_disconnected() {
+                                            trace!("Nobody is listening for goodbyes");
+                                        }
+                                    }
+                                    // Nothing follows a goodbye
+                                    return Ok(());
+                                }
+                                DevDispMessageFromClient::Clipboard(msg) => {
+                                    if let Err(e) = background_ctx.tx_core_clipboard.try_send(msg) {
+                                        if e.is_full() {
+                                            warn!("Clipboard buffer is full, dropping clipboard message");
+                                        } else {
+                                            trace!("Nobody is syncing the clipboard, dropping clipboard message");
+                                        }
+                                    }
+                                }
                             }
-                            WsMessageFromClient::ResponsePreInit => {
+                            WsMessageFromClient::ResponsePreInit(_) => {
                                 warn!("Received pre-init response when we weren't expecting it... ignoring.");
                             },
+                            WsMessageFromClient::ResponseResumeToken(_) => {
+                                warn!("Received resume token response when we weren't expecting it... ignoring.");
+                            },
                         }
                     }
-                    Ok(_) => return Err(TransportError::Unknown),
+                    Ok(Message::Close(_)) => return Err(TransportError::NoConnection),
+                    Ok(msg) => {
+                        return Err(TransportError::UnexpectedMessage(format!(
+                            "Non-binary WebSocket message {:?}",
+                            msg
+                        )));
+                    }
                     Err(e) => return Err(TransportError::Other(Box::new(e))),
                 }
             }
@@ -193,7 +388,9 @@ where
                     if resp.init_key == init_key {
                         Ok(())
                     } else {
-                        Err(TransportError::Unknown)
+                        Err(TransportError::Rejected(
+                            "Protocol init key doesn't match".to_string(),
+                        ))
                     }
                 })
         }
@@ -204,6 +401,51 @@ where
         self._background_task()
     }
 
+    fn protocol(&self) -> Option<&NegotiatedProtocol> {
+        Some(&self.protocol)
+    }
+
+    fn take_input_stream(&mut self) -> Option<PinnedStream<'static, InputEvent>> {
+        self.rx_core_input.take().map(|rx| rx.boxed())
+    }
+
+    fn take_keyframe_request_stream(&mut self) -> Option<PinnedStream<'static, ()>> {
+        self.rx_core_keyframe_request.take().map(|rx| rx.boxed())
+    }
+
+    fn take_receiver_report_stream(&mut self) -> Option<PinnedStream<'static, ReceiverReport>> {
+        self.rx_core_receiver_report.take().map(|rx| rx.boxed())
+    }
+
+    fn take_clock_pong_stream(&mut self) -> Option<PinnedStream<'static, ClockPong>> {
+        self.rx_core_clock_pong.take().map(|rx| rx.boxed())
+    }
+
+    fn take_frame_presented_stream(&mut self) -> Option<PinnedStream<'static, FramePresented>> {
+        self.rx_core_frame_presented.take().map(|rx| rx.boxed())
+    }
+
+    fn take_clipboard_stream(&mut self) -> Option<PinnedStream<'static, ClipboardMessage>> {
+        self.rx_core_clipboard.take().map(|rx| rx.boxed())
+    }
+
+    fn take_goodbye_stream(&mut self) -> Option<PinnedStream<'static, GoodbyeReason>> {
+        self.rx_core_goodbye.take().map(|rx| rx.boxed())
+    }
+
+    fn heartbeat_interval(&self) -> Option<Duration> {
+        self.heartbeats_negotiated()
+            .then_some(self.heartbeat.interval)
+    }
+
+    fn take_display_parameters_stream(
+        &mut self,
+    ) -> Option<PinnedStream<'static, DisplayParameters>> {
+        self.rx_core_display_params_update
+            .take()
+            .map(|rx| rx.boxed())
+    }
+
     fn get_display_config(
         &mut self,
     ) -> PinnedFuture<'_, Result<dev_disp_core::host::DisplayParameters, TransportError>> {
@@ -215,7 +457,12 @@ where
 
             debug!("Waiting for display parameters response...");
 
-            self.rx_core_display_params_update
+            let Some(rx_display_params_update) = self.rx_core_display_params_update.as_mut() else {
+                return Err(TransportError::InvalidState(
+                    "Display parameter updates were already taken".to_string(),
+                ));
+            };
+            rx_display_params_update
                 .next()
                 .await
                 .ok_or(TransportError::NoConnection)
@@ -262,7 +509,9 @@ where
                     if success {
                         Ok(())
                     } else {
-                        Err(TransportError::Unknown)
+                        Err(TransportError::Rejected(
+                            "The client refused the encoding".to_string(),
+                        ))
                     }
                 })
         }
@@ -271,16 +520,113 @@ where
 
     fn send_screen_data<'s, 'a>(
         &'s mut self,
-        data: &'a [u8],
+        frame: EncodedFrame<'a>,
     ) -> PinnedFuture<'s, Result<(), TransportError>>
     where
         'a: 's,
     {
         async move {
             let screen_data_msg =
-                WsMessageFromSource::Core(DevDispMessageFromSource::PutScreenData(data));
+                WsMessageFromSource::Core(DevDispMessageFromSource::PutScreenData {
+                    header: frame.header,
+                    data: frame.data,
+                });
             self.send_msg(screen_data_msg).await
         }
         .boxed()
     }
+
+    fn send_screen_regions<'s, 'a>(
+        &'s mut self,
+        header: FrameHeader,
+        regions: &'a [EncodedRegion<'a>],
+    ) -> PinnedFuture<'s, Result<(), TransportError>>
+    where
+        'a: 's,
+    {
+        async move {
+            let regions_msg =
+                WsMessageFromSource::Core(DevDispMessageFromSource::PutScreenRegions {
+                    header,
+                    regions: regions.to_vec(),
+                });
+            self.send_msg(regions_msg).await
+        }
+        .boxed()
+    }
+
+    fn send_cursor_update(
+        &mut self,
+        update: CursorUpdate,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async move {
+            let cursor_msg =
+                WsMessageFromSource::Core(DevDispMessageFromSource::UpdateCursor(update));
+            self.send_msg(cursor_msg).await
+        }
+        .boxed()
+    }
+
+    fn send_clock_ping(&mut self, ping: ClockPing) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async move {
+            let ping_msg = WsMessageFromSource::Core(DevDispMessageFromSource::ClockPing(ping));
+            self.send_msg(ping_msg).await
+        }
+        .boxed()
+    }
+
+    fn send_clipboard_message(
+        &mut self,
+        msg: ClipboardMessage,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async move {
+            let clipboard_msg = WsMessageFromSource::Core(DevDispMessageFromSource::Clipboard(msg));
+            self.send_msg(clipboard_msg).await
+        }
+        .boxed()
+    }
+
+    fn send_heartbeat(&mut self) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async move {
+            if !self.heartbeats_negotiated() {
+                return Err(TransportError::NotImplemented);
+            }
+            let heartbeat_msg = WsMessageFromSource::Core(DevDispMessageFromSource::Heartbeat);
+            self.send_msg(heartbeat_msg).await
+        }
+        .boxed()
+    }
+
+    fn send_goodbye(
+        &mut self,
+        reason: GoodbyeReason,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async move {
+            // Goodbyes came along with heartbeats
+            if !self.heartbeats_negotiated() {
+                return Err(TransportError::NotImplemented);
+            }
+            let goodbye_msg = WsMessageFromSource::Core(DevDispMessageFromSource::Goodbye(reason));
+            self.send_msg(goodbye_msg).await
+        }
+        .boxed()
+    }
+
+    fn send_session_token(
+        &mut self,
+        token: SessionToken,
+    ) -> PinnedFuture<'_, Result<(), TransportError>> {
+        async move {
+            if !self
+                .protocol
+                .has_capability(ProtocolCapability::SessionResume)
+            {
+                return Err(TransportError::NotImplemented);
+            }
+            let token_msg =
+                WsMessageFromSource::Core(DevDispMessageFromSource::SessionToken(token));
+            self.send_msg(token_msg).await
+        }
+        .boxed()
+    }
 }
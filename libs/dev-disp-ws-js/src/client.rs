@@ -1,42 +1,192 @@
-use std::fmt::Debug;
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Debug,
+    future::ready,
+    rc::Rc,
+};
 
 use dev_disp_transports::websocket::messages::{
-    DevDispMessageFromClient, DevDispMessageFromSource, DisplayParameters,
-    EncoderPossibleConfiguration, WsMessageFromClient, WsMessageFromSource,
+    ClipboardMessage, ClockPong, CursorUpdate, DevDispMessageFromClient, DevDispMessageFromSource,
+    DisplayParameters, EncoderPossibleConfiguration, FrameHeader, GoodbyeReason,
+    NegotiatedProtocol, ProtocolCapability, ProtocolHello, SessionToken, WsMessageFromClient,
+    WsMessageFromSource,
+    DEFAULT_HEARTBEAT_INTERVAL,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    stream, FutureExt, Sink, SinkExt, Stream, StreamExt,
 };
-use futures::{Sink, SinkExt, Stream, StreamExt};
-use js_sys::{Promise, SharedArrayBuffer, Uint8Array};
-use log::{debug, trace, warn};
+use js_sys::{Object, Promise, Reflect, SharedArrayBuffer, Uint8Array};
+use log::{debug, info, trace, warn};
 use wasm_bindgen::{JsCast, JsError, JsValue};
 use wasm_bindgen_futures::JsFuture;
 use ws_stream_wasm::WsMessage;
 
-use crate::types::{DevDispEvent, JsDisplayParameters, JsEncoderPossibleConfiguration, WsHandlers};
+use crate::{
+    types::{
+        DevDispEvent, JsDisplayParameters, JsEncoderPossibleConfiguration, JsFrameHeader,
+        JsFramePresented, JsInputEvent, JsReceiverReport, WsHandlers,
+    },
+    util::{client_time_us, sleep},
+};
+
+thread_local! {
+    /// The token of our session, shown to the host when we reconnect so it can
+    /// resume it. Outlives the connection, but not the page.
+    static SESSION_TOKEN: RefCell<Option<SessionToken>> = const { RefCell::new(None) };
+}
+
+/// Serials of the latest clipboard offers on both sides, shared between the
+/// dispatchers and the message listener.
+#[derive(Debug, Clone, Default)]
+pub struct ClipboardSerials {
+    pub ours: Rc<Cell<u32>>,
+    pub theirs: Rc<Cell<u32>>,
+}
+
+/// The protocol the server accepted us with, shared between the dispatchers and
+/// the message listener.
+#[derive(Debug, Clone, Default)]
+pub struct AgreedProtocol(Rc<RefCell<Option<NegotiatedProtocol>>>);
+
+impl AgreedProtocol {
+    pub fn set(&self, protocol: NegotiatedProtocol) {
+        *self.0.borrow_mut() = Some(protocol);
+    }
+
+    /// Whether the server agreed to `capability`. Nothing is agreed to before it
+    /// accepts us.
+    pub fn has_capability(&self, capability: ProtocolCapability) -> bool {
+        self.0
+            .borrow()
+            .as_ref()
+            .is_some_and(|p| p.has_capability(capability))
+    }
+}
+
+/// Heartbeats for the host, once `started` fires. Stays silent if it never does.
+pub fn heartbeats(started: oneshot::Receiver<()>) -> impl Stream<Item = ()> {
+    started
+        .into_stream()
+        .filter_map(|started| ready(started.ok()))
+        .flat_map(|_| {
+            stream::unfold((), |_| async {
+                sleep(DEFAULT_HEARTBEAT_INTERVAL).await;
+                Some(((), ()))
+            })
+        })
+}
 
 /// Helper task that listens to the given dispatcher channels, and
 /// sends appropriate message to the WebSocket TX channel/sink.
-pub async fn listen_dispatchers<A, S>(
-    mut update_display_params_rx: A,
+/// Messages the server didn't agree to in `agreed` are dropped.
+/// Ends after sending a goodbye.
+pub async fn listen_dispatchers<A, I, K, R, P, C, H, G, S>(
+    update_display_params_rx: A,
+    input_rx: I,
+    keyframe_request_rx: K,
+    receiver_report_rx: R,
+    frame_presented_rx: P,
+    clipboard_rx: C,
+    heartbeat_rx: H,
+    goodbye_rx: G,
+    agreed: AgreedProtocol,
     mut ws_tx: S,
 ) -> Result<(), JsError>
 where
     A: Stream<Item = JsDisplayParameters> + Unpin,
+    I: Stream<Item = JsInputEvent> + Unpin,
+    K: Stream<Item = ()> + Unpin,
+    R: Stream<Item = JsReceiverReport> + Unpin,
+    P: Stream<Item = JsFramePresented> + Unpin,
+    C: Stream<Item = ClipboardMessage> + Unpin,
+    H: Stream<Item = ()> + Unpin,
+    G: Stream<Item = GoodbyeReason> + Unpin,
     S: Sink<WsMessage> + Unpin,
     S::Error: Debug,
 {
     // TODO: Change to use enums instead of many channels
 
-    while let Some(params) = update_display_params_rx.next().await {
+    let update_display_params = update_display_params_rx.map(|params| {
         debug!(
             "Received request to update display parameters to: {:?}",
             params
         );
         let real_params: DisplayParameters = params.into();
-        let resp = WsMessageFromClient::Core(DevDispMessageFromClient::DisplayParametersUpdate(
-            real_params,
-        ));
-        send_ws_message(&mut ws_tx, resp).await?;
-        debug!("Sent DisplayParametersUpdate message");
+        DevDispMessageFromClient::DisplayParametersUpdate(real_params)
+    });
+    let agreed_input = agreed.clone();
+    let input = input_rx
+        .filter(move |_| ready(agreed_input.has_capability(ProtocolCapability::Input)))
+        .map(|event| {
+            trace!("Received request to send input event: {:?}", event);
+            DevDispMessageFromClient::Input(event.into())
+        });
+    let agreed_keyframes = agreed.clone();
+    let keyframe_requests = keyframe_request_rx
+        .filter(move |_| {
+            ready(agreed_keyframes.has_capability(ProtocolCapability::KeyframeRequests))
+        })
+        .map(|_| {
+            debug!("Received request to ask for a keyframe");
+            DevDispMessageFromClient::RequestKeyframe
+        });
+    let agreed_reports = agreed.clone();
+    let receiver_reports = receiver_report_rx
+        .filter(move |_| ready(agreed_reports.has_capability(ProtocolCapability::ReceiverReports)))
+        .map(|report| {
+            trace!("Received request to send receiver report: {:?}", report);
+            DevDispMessageFromClient::ReceiverReport(report.into())
+        });
+    let agreed_presented = agreed.clone();
+    let frames_presented = frame_presented_rx
+        .filter(move |_| ready(agreed_presented.has_capability(ProtocolCapability::ClockSync)))
+        .map(|presented| {
+            trace!(
+                "Received request to report frame #{} presented",
+                presented.sequence
+            );
+            DevDispMessageFromClient::FramePresented(presented.into())
+        });
+    let agreed_clipboard = agreed.clone();
+    let clipboard = clipboard_rx
+        .filter(move |_| ready(agreed_clipboard.has_capability(ProtocolCapability::Clipboard)))
+        .map(|msg| {
+            debug!("Received request to send clipboard message: {}", msg);
+            DevDispMessageFromClient::Clipboard(msg)
+        });
+    let heartbeats = heartbeat_rx.map(|_| DevDispMessageFromClient::Heartbeat);
+    let goodbyes = goodbye_rx.map(|reason| {
+        debug!("Received request to say goodbye: {}", reason);
+        // Leaving on purpose, the session isn't coming back
+        SESSION_TOKEN.set(None);
+        DevDispMessageFromClient::Goodbye(reason)
+    });
+    let mut messages = futures::stream::select(
+        futures::stream::select(
+            futures::stream::select(update_display_params, input),
+            futures::stream::select(heartbeats, goodbyes),
+        ),
+        futures::stream::select(
+            futures::stream::select(keyframe_requests, clipboard),
+            futures::stream::select(receiver_reports, frames_presented),
+        ),
+    );
+
+    while let Some(msg) = messages.next().await {
+        let msg_name = msg.to_string();
+        let is_goodbye = matches!(msg, DevDispMessageFromClient::Goodbye(_));
+        // Goodbyes came along with heartbeats, servers without them just see us go
+        if is_goodbye && !agreed.has_capability(ProtocolCapability::Heartbeat) {
+            debug!("Server doesn't take goodbyes, leaving without one");
+            break;
+        }
+        send_ws_message(&mut ws_tx, WsMessageFromClient::Core(msg)).await?;
+        trace!("Sent {} message", msg_name);
+        if is_goodbye {
+            // Nothing goes out after a goodbye
+            break;
+        }
     }
 
     debug!("WebSocket dispatcher listener task ending");
@@ -47,11 +197,17 @@ where
 /// Helper task that listens to incoming WebSocket messages on the
 /// given channel/stream, and either dispatches a response to the
 /// WebSocket TX channel/sink, or calls the appropriate handler.
+/// `session_started` fires once the session is up, if the host wants heartbeats.
+/// Ends when the host says goodbye.
 pub async fn listen_ws_messages<T, S>(
     mut stream: T,
     mut response_tx: S,
     handlers: WsHandlers,
     shared_buffer: Option<SharedArrayBuffer>,
+    clipboard_tx: mpsc::UnboundedSender<ClipboardMessage>,
+    clipboard_serials: ClipboardSerials,
+    agreed: AgreedProtocol,
+    session_started: oneshot::Sender<()>,
 ) -> Result<(), JsError>
 where
     T: Stream<Item = WsMessage> + Unpin,
@@ -70,6 +226,8 @@ where
             // I don't know how much memory we could get.
             Uint8Array::new_with_length(512 * 1024 * 1024)
         });
+    let mut session_started = Some(session_started);
+    let mut heartbeats_agreed = false;
 
     while let Some(data) = stream.next().await {
         match data {
@@ -89,8 +247,11 @@ where
                 let msg = msg.0;
 
                 match msg {
-                    WsMessageFromSource::RequestPreInit => {
-                        debug!("Received RequestPreInit message");
+                    WsMessageFromSource::RequestPreInit(server_hello) => {
+                        debug!(
+                            "Received RequestPreInit message, server speaks {}",
+                            server_hello.versions
+                        );
                         if let Some(func) = &handlers.on_pre_init {
                             let event = DevDispEvent {
                                 error: None,
@@ -98,17 +259,69 @@ where
                             };
                             let _ = func.call1(&JsValue::NULL, &event.into());
                         }
-                        let resp = WsMessageFromClient::ResponsePreInit;
+                        let mut capabilities = Vec::new();
+                        if handlers.handle_screen_regions.is_some() {
+                            capabilities.push(ProtocolCapability::RegionUpdates);
+                        }
+                        if handlers.handle_cursor_update.is_some() {
+                            capabilities.push(ProtocolCapability::CursorPlane);
+                        }
+                        if handlers.handle_clipboard_offer.is_some()
+                            && handlers.handle_clipboard_request.is_some()
+                            && handlers.handle_clipboard_data.is_some()
+                        {
+                            capabilities.push(ProtocolCapability::Clipboard);
+                        }
+                        // Clock pings are answered right here
+                        capabilities.push(ProtocolCapability::ClockSync);
+                        // So are heartbeats, and goodbyes
+                        capabilities.push(ProtocolCapability::Heartbeat);
+                        // And session tokens are kept for the next connection
+                        capabilities.push(ProtocolCapability::SessionResume);
+                        // Input is sent through the dispatchers
+                        capabilities.push(ProtocolCapability::Input);
+                        // And so are keyframe requests
+                        capabilities.push(ProtocolCapability::KeyframeRequests);
+                        // And receiver reports
+                        capabilities.push(ProtocolCapability::ReceiverReports);
+                        let resp = WsMessageFromClient::ResponsePreInit(ProtocolHello::current(
+                            capabilities,
+                        ));
                         send_ws_message(&mut response_tx, resp).await?;
                         debug!("Sent ResponsePreInit message");
+                    }
+                    WsMessageFromSource::AcceptPreInit(protocol) => {
+                        debug!(
+                            "Server accepted us with protocol v{} and capabilities {:?}",
+                            protocol.version, protocol.capabilities
+                        );
+                        heartbeats_agreed =
+                            protocol.has_capability(ProtocolCapability::Heartbeat);
+                        agreed.set(protocol.clone());
                         if let Some(func) = &handlers.on_pre_init_success {
                             let event = DevDispEvent {
                                 error: None,
+                                data: Some(JsValue::from(protocol.version)),
+                            };
+                            let _ = func.call1(&JsValue::NULL, &event.into());
+                        }
+                    }
+                    WsMessageFromSource::RejectPreInit(rejection) => {
+                        warn!("Server rejected us: {}", rejection);
+                        if let Some(func) = &handlers.on_pre_init_rejected {
+                            let event = DevDispEvent {
+                                error: Some(JsValue::from(rejection.to_string())),
                                 data: None,
                             };
                             let _ = func.call1(&JsValue::NULL, &event.into());
                         }
                     }
+                    WsMessageFromSource::RequestResumeToken => {
+                        let token = SESSION_TOKEN.with_borrow(|token| token.clone());
+                        debug!("Received RequestResumeToken message, have {:?}", token);
+                        let resp = WsMessageFromClient::ResponseResumeToken(token);
+                        send_ws_message(&mut response_tx, resp).await?;
+                    }
                     WsMessageFromSource::RequestDeviceInformation => {
                         debug!("Received RequestDeviceInformation message");
                         let event = DevDispEvent {
@@ -143,6 +356,11 @@ where
                             WsMessageFromClient::ResponseProtocolInit(ws_message_protocol_init);
                         send_ws_message(&mut response_tx, resp).await?;
                         debug!("Sent ResponseProtocolInit message");
+                        if heartbeats_agreed {
+                            if let Some(session_started) = session_started.take() {
+                                let _ = session_started.send(());
+                            }
+                        }
                         if let Some(func) = &handlers.on_protocol_init_success {
                             let event = DevDispEvent {
                                 error: None,
@@ -170,9 +388,13 @@ where
                         }
 
                         match dev_disp_message_from_source {
-                            DevDispMessageFromSource::PutScreenData(screen_data) => {
+                            DevDispMessageFromSource::PutScreenData {
+                                header,
+                                data: screen_data,
+                            } => {
                                 trace!(
-                                    "Handling PutScreenData message with {} bytes",
+                                    "Handling PutScreenData message #{} with {} bytes",
+                                    header.sequence,
                                     screen_data.len()
                                 );
 
@@ -194,9 +416,116 @@ where
                                     error: None,
                                     data: Some(js_val),
                                 };
-                                let _ = handlers
-                                    .handle_screen_data
-                                    .call1(&JsValue::NULL, &event.into());
+                                let js_header = frame_header_to_js(header)?;
+                                let _ = handlers.handle_screen_data.call2(
+                                    &JsValue::NULL,
+                                    &event.into(),
+                                    &js_header,
+                                );
+                            }
+                            DevDispMessageFromSource::PutScreenRegions { header, regions } => {
+                                trace!(
+                                    "Handling PutScreenRegions message #{} with {} regions",
+                                    header.sequence,
+                                    regions.len()
+                                );
+
+                                let Some(handle_screen_regions) = &handlers.handle_screen_regions
+                                else {
+                                    warn!(
+                                        "Received screen regions, but no handler is registered for them"
+                                    );
+                                    continue;
+                                };
+
+                                let js_regions = regions
+                                    .iter()
+                                    .map(|region| {
+                                        let obj = Object::new();
+                                        let _ =
+                                            Reflect::set(&obj, &"x".into(), &region.rect.x.into());
+                                        let _ =
+                                            Reflect::set(&obj, &"y".into(), &region.rect.y.into());
+                                        let _ = Reflect::set(
+                                            &obj,
+                                            &"width".into(),
+                                            &region.rect.width.into(),
+                                        );
+                                        let _ = Reflect::set(
+                                            &obj,
+                                            &"height".into(),
+                                            &region.rect.height.into(),
+                                        );
+                                        let _ = Reflect::set(
+                                            &obj,
+                                            &"data".into(),
+                                            &Uint8Array::from(region.data).into(),
+                                        );
+                                        obj
+                                    })
+                                    .collect::<js_sys::Array>();
+
+                                let js_header = frame_header_to_js(header)?;
+                                let _ = handle_screen_regions.call2(
+                                    &JsValue::NULL,
+                                    &js_regions.into(),
+                                    &js_header,
+                                );
+                            }
+                            DevDispMessageFromSource::UpdateCursor(update) => {
+                                trace!("Handling UpdateCursor message: {}", update);
+
+                                let Some(handle_cursor_update) = &handlers.handle_cursor_update
+                                else {
+                                    warn!(
+                                        "Received a cursor update, but no handler is registered for it"
+                                    );
+                                    continue;
+                                };
+
+                                let _ = handle_cursor_update
+                                    .call1(&JsValue::NULL, &cursor_update_to_js(update));
+                            }
+                            DevDispMessageFromSource::ClockPing(ping) => {
+                                // Answer right away, any delay throws the clock sync off
+                                let pong = WsMessageFromClient::Core(
+                                    DevDispMessageFromClient::ClockPong(ClockPong {
+                                        id: ping.id,
+                                        server_time_us: ping.server_time_us,
+                                        client_time_us: client_time_us(),
+                                    }),
+                                );
+                                send_ws_message(&mut response_tx, pong).await?;
+                                trace!("Answered clock ping #{}", ping.id);
+                            }
+                            DevDispMessageFromSource::Heartbeat => {
+                                trace!("Received heartbeat");
+                            }
+                            DevDispMessageFromSource::Goodbye(reason) => {
+                                info!("Host said goodbye: {}", reason);
+                                // The session is over, there's nothing to resume
+                                SESSION_TOKEN.set(None);
+                                if let Some(func) = &handlers.on_goodbye {
+                                    let event = DevDispEvent {
+                                        error: None,
+                                        data: Some(JsValue::from(reason.to_string())),
+                                    };
+                                    let _ = func.call1(&JsValue::NULL, &event.into());
+                                }
+                                break;
+                            }
+                            DevDispMessageFromSource::SessionToken(token) => {
+                                debug!("Host gave us session token {}", token);
+                                SESSION_TOKEN.set(Some(token));
+                            }
+                            DevDispMessageFromSource::Clipboard(msg) => {
+                                debug!("Handling Clipboard message: {}", msg);
+                                handle_clipboard_message(
+                                    msg,
+                                    &handlers,
+                                    &clipboard_tx,
+                                    &clipboard_serials,
+                                );
                             }
                             DevDispMessageFromSource::GetDisplayParametersRequest => {
                                 debug!("Handling GetDisplayParametersRequest message");
@@ -327,6 +656,118 @@ where
     Ok(())
 }
 
+fn handle_clipboard_message(
+    msg: ClipboardMessage,
+    handlers: &WsHandlers,
+    clipboard_tx: &mpsc::UnboundedSender<ClipboardMessage>,
+    serials: &ClipboardSerials,
+) {
+    match msg {
+        ClipboardMessage::Offer { serial, mime_types } => {
+            serials.theirs.set(serial);
+            if let Some(func) = &handlers.handle_clipboard_offer {
+                let mime_types = mime_types
+                    .into_iter()
+                    .map(JsValue::from)
+                    .collect::<js_sys::Array>();
+                let _ = func.call1(&JsValue::NULL, &mime_types);
+            }
+        }
+        ClipboardMessage::Request { serial, mime_type } => {
+            let handler = handlers
+                .handle_clipboard_request
+                .clone()
+                .filter(|_| serial == serials.ours.get());
+            let clipboard_tx = clipboard_tx.clone();
+            // Reading the page's clipboard may wait on the user, don't hold up frames
+            wasm_bindgen_futures::spawn_local(async move {
+                let data = match handler {
+                    Some(handler) => read_page_clipboard(&handler, &mime_type).await,
+                    None => None,
+                };
+                let _ = clipboard_tx.unbounded_send(ClipboardMessage::Data {
+                    serial,
+                    mime_type,
+                    data,
+                });
+            });
+        }
+        ClipboardMessage::Data {
+            serial,
+            mime_type,
+            data,
+        } => {
+            if serial != serials.theirs.get() {
+                debug!("Ignoring clipboard content for an outdated offer #{}", serial);
+                return;
+            }
+            if let Some(func) = &handlers.handle_clipboard_data {
+                let data = data
+                    .map(|data| JsValue::from(Uint8Array::from(&data[..])))
+                    .unwrap_or(JsValue::NULL);
+                let _ = func.call2(&JsValue::NULL, &JsValue::from(mime_type), &data);
+            }
+        }
+    }
+}
+
+async fn read_page_clipboard(handler: &js_sys::Function, mime_type: &str) -> Option<Vec<u8>> {
+    let js_value = handler
+        .call1(&JsValue::NULL, &JsValue::from(mime_type))
+        .map_err(|e| warn!("Failed to call clipboard request handler: {:?}", e))
+        .ok()?;
+    let promise = js_value
+        .dyn_into::<Promise>()
+        .map_err(|e| warn!("Clipboard request handler did not return a Promise: {:?}", e))
+        .ok()?;
+    let js_value = JsFuture::from(promise)
+        .await
+        .map_err(|e| warn!("Clipboard request handler Promise rejected: {:?}", e))
+        .ok()?;
+    js_value
+        .dyn_into::<Uint8Array>()
+        .ok()
+        .map(|data| data.to_vec())
+}
+
+fn frame_header_to_js(header: FrameHeader) -> Result<JsValue, JsError> {
+    let js_header: JsFrameHeader = header.into();
+    serde_wasm_bindgen::to_value(&js_header).map_err(|e| {
+        JsError::new(&format!(
+            "Failed to convert FrameHeader to JsValue: {:?}",
+            e
+        ))
+    })
+}
+
+fn cursor_update_to_js(update: CursorUpdate) -> JsValue {
+    let obj = Object::new();
+    match update {
+        CursorUpdate::Image(image) => {
+            let _ = Reflect::set(&obj, &"type".into(), &"image".into());
+            let _ = Reflect::set(&obj, &"width".into(), &image.width.into());
+            let _ = Reflect::set(&obj, &"height".into(), &image.height.into());
+            let _ = Reflect::set(&obj, &"hotX".into(), &image.hot_x.into());
+            let _ = Reflect::set(&obj, &"hotY".into(), &image.hot_y.into());
+            let _ = Reflect::set(
+                &obj,
+                &"pixels".into(),
+                &Uint8Array::from(&image.pixels[..]).into(),
+            );
+        }
+        CursorUpdate::Move { x, y } => {
+            let _ = Reflect::set(&obj, &"type".into(), &"move".into());
+            let _ = Reflect::set(&obj, &"x".into(), &x.into());
+            let _ = Reflect::set(&obj, &"y".into(), &y.into());
+        }
+        CursorUpdate::Visibility(visible) => {
+            let _ = Reflect::set(&obj, &"type".into(), &"visibility".into());
+            let _ = Reflect::set(&obj, &"visible".into(), &visible.into());
+        }
+    }
+    obj.into()
+}
+
 pub async fn send_ws_message<T>(sink: &mut T, msg: WsMessageFromClient) -> Result<(), JsError>
 where
     T: Sink<WsMessage> + Unpin,
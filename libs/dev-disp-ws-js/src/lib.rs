@@ -1,21 +1,34 @@
-use std::{cell::Cell, panic, rc::Rc};
+use std::{cell::Cell, panic, rc::Rc, time::Duration};
 
-use futures::{channel::mpsc, stream::FuturesUnordered, FutureExt, SinkExt, StreamExt};
-use js_sys::{Reflect, SharedArrayBuffer};
+use dev_disp_transports::websocket::messages::{ClipboardMessage, GoodbyeReason};
+use futures::{
+    channel::{mpsc, oneshot},
+    stream::FuturesUnordered,
+    FutureExt, SinkExt, StreamExt,
+};
+use js_sys::{Array, Reflect, SharedArrayBuffer};
 use log::{debug, error, info};
 use wasm_bindgen::prelude::*;
 use ws_stream_wasm::{WsMessage, WsMeta};
 
 use crate::{
-    client::{listen_dispatchers, listen_ws_messages},
-    types::{DevDispEvent, JsDisplayParameters, WsDispatchers, WsHandlers},
-    util::{shared_array_buffer_new_fallible, OnDrop},
+    client::{
+        heartbeats, listen_dispatchers, listen_ws_messages, AgreedProtocol, ClipboardSerials,
+    },
+    types::{
+        DevDispEvent, JsDisplayParameters, JsFramePresented, JsInputEvent, JsReceiverReport,
+        WsDispatchers, WsHandlers,
+    },
+    util::{shared_array_buffer_new_fallible, sleep, OnDrop},
 };
 
 mod client;
 mod types;
 mod util;
 
+/// How long our goodbye gets to reach the host before the connection is dropped.
+const GOODBYE_GRACE_PERIOD: Duration = Duration::from_millis(250);
+
 // TODO: Please design and think through a better interface here.
 
 /// Connect to a DevDisp server at the given address, and set up
@@ -32,6 +45,18 @@ pub fn connect_dev_disp_server(
 
     let (update_display_params_tx, update_display_params_rx) =
         mpsc::unbounded::<JsDisplayParameters>();
+    let (send_input_tx, send_input_rx) = mpsc::unbounded::<JsInputEvent>();
+    let (request_keyframe_tx, request_keyframe_rx) = mpsc::unbounded::<()>();
+    let (receiver_report_tx, receiver_report_rx) = mpsc::unbounded::<JsReceiverReport>();
+    let (frame_presented_tx, frame_presented_rx) = mpsc::unbounded::<JsFramePresented>();
+    let (clipboard_tx, clipboard_rx) = mpsc::unbounded::<ClipboardMessage>();
+    let clipboard_serials = ClipboardSerials::default();
+    let clipboard_tx_1 = clipboard_tx.clone();
+    let clipboard_serials_1 = clipboard_serials.clone();
+    let (goodbye_tx, goodbye_rx) = mpsc::unbounded::<GoodbyeReason>();
+    let (session_started_tx, session_started_rx) = oneshot::channel::<()>();
+    let agreed = AgreedProtocol::default();
+    let agreed_1 = agreed.clone();
 
     let closed = Rc::new(Cell::new(false));
     let closed_outer = closed.clone();
@@ -64,16 +89,36 @@ pub fn connect_dev_disp_server(
         let (ws_fwd_tx, mut ws_fwd_rx) = mpsc::channel::<WsMessage>(100);
         let (ws_tx_original, ws_rx) = ws_stream.split();
 
-        let task_rx_update_display_params =
-            listen_dispatchers(update_display_params_rx, ws_fwd_tx.clone()).boxed_local();
-        let task_rx = listen_ws_messages(ws_rx, ws_fwd_tx, handlers.clone(), shared_buffer_clone)
-            .then(|r| async move {
-                // Call cancel token
-                let _ = cancel_token.send(()).await;
-
-                r
-            })
-            .boxed_local();
+        let task_rx_update_display_params = listen_dispatchers(
+            update_display_params_rx,
+            send_input_rx,
+            request_keyframe_rx,
+            receiver_report_rx,
+            frame_presented_rx,
+            clipboard_rx,
+            heartbeats(session_started_rx).boxed_local(),
+            goodbye_rx,
+            agreed,
+            ws_fwd_tx.clone(),
+        )
+        .boxed_local();
+        let task_rx = listen_ws_messages(
+            ws_rx,
+            ws_fwd_tx,
+            handlers.clone(),
+            shared_buffer_clone,
+            clipboard_tx_1,
+            clipboard_serials_1,
+            agreed_1,
+            session_started_tx,
+        )
+        .then(|r| async move {
+            // Call cancel token
+            let _ = cancel_token.send(()).await;
+
+            r
+        })
+        .boxed_local();
 
         let task_forward_tx = async move {
             let mut ws_tx = ws_tx_original;
@@ -133,6 +178,13 @@ pub fn connect_dev_disp_server(
         cancel_token_outer,
         closed_outer,
         update_display_params_tx,
+        send_input_tx,
+        request_keyframe_tx,
+        receiver_report_tx,
+        frame_presented_tx,
+        clipboard_tx,
+        clipboard_serials,
+        goodbye_tx,
         shared_buffer,
     );
     Ok(dispatchers)
@@ -142,6 +194,13 @@ fn create_dispatchers(
     cancel_token: mpsc::UnboundedSender<()>,
     closed: Rc<Cell<bool>>,
     update_display_params_tx: mpsc::UnboundedSender<JsDisplayParameters>,
+    send_input_tx: mpsc::UnboundedSender<JsInputEvent>,
+    request_keyframe_tx: mpsc::UnboundedSender<()>,
+    receiver_report_tx: mpsc::UnboundedSender<JsReceiverReport>,
+    frame_presented_tx: mpsc::UnboundedSender<JsFramePresented>,
+    clipboard_tx: mpsc::UnboundedSender<ClipboardMessage>,
+    clipboard_serials: ClipboardSerials,
+    goodbye_tx: mpsc::UnboundedSender<GoodbyeReason>,
     shared_buffer: Option<SharedArrayBuffer>,
 ) -> WsDispatchers {
     // Wrapper that will tell us when the JS side has GC'ed the closure
@@ -153,8 +212,11 @@ fn create_dispatchers(
             // If this has already been called, do nothing.
             if !closed.get() {
                 debug!("Closing websocket connection");
+                // Let the host know we're leaving, and give that a moment to go out
+                let _ = goodbye_tx.unbounded_send(GoodbyeReason::UserDisconnect);
                 let mut cancel_token = cancel_token.clone();
                 wasm_bindgen_futures::spawn_local(async move {
+                    sleep(GOODBYE_GRACE_PERIOD).await;
                     cancel_token.send(()).await.ok();
                     debug!("Close notification sent");
                 });
@@ -181,9 +243,66 @@ fn create_dispatchers(
         })
             as Box<dyn FnMut(JsDisplayParameters) -> Result<(), JsError>>);
 
+    let send_input_closure = Closure::wrap(Box::new(move |event: JsInputEvent| {
+        send_input_tx
+            .unbounded_send(event)
+            .map_err(|e| JsError::new(&format!("Failed to send input event: {:?}", e)))
+    })
+        as Box<dyn FnMut(JsInputEvent) -> Result<(), JsError>>);
+
+    let request_keyframe_closure = Closure::wrap(Box::new(move || {
+        request_keyframe_tx
+            .unbounded_send(())
+            .map_err(|e| JsError::new(&format!("Failed to request a keyframe: {:?}", e)))
+    }) as Box<dyn FnMut() -> Result<(), JsError>>);
+
+    let send_receiver_report_closure = Closure::wrap(Box::new(move |report: JsReceiverReport| {
+        receiver_report_tx
+            .unbounded_send(report)
+            .map_err(|e| JsError::new(&format!("Failed to send receiver report: {:?}", e)))
+    })
+        as Box<dyn FnMut(JsReceiverReport) -> Result<(), JsError>>);
+
+    let frame_presented_closure = Closure::wrap(Box::new(move |presented: JsFramePresented| {
+        frame_presented_tx
+            .unbounded_send(presented)
+            .map_err(|e| JsError::new(&format!("Failed to send frame presentation: {:?}", e)))
+    })
+        as Box<dyn FnMut(JsFramePresented) -> Result<(), JsError>>);
+
+    let offer_clipboard_tx = clipboard_tx.clone();
+    let our_serial = clipboard_serials.ours;
+    let offer_clipboard_closure = Closure::wrap(Box::new(move |mime_types: Array| {
+        our_serial.set(our_serial.get().wrapping_add(1));
+        let offer = ClipboardMessage::Offer {
+            serial: our_serial.get(),
+            mime_types: mime_types.iter().filter_map(|v| v.as_string()).collect(),
+        };
+        offer_clipboard_tx
+            .unbounded_send(offer)
+            .map_err(|e| JsError::new(&format!("Failed to offer the clipboard: {:?}", e)))
+    }) as Box<dyn FnMut(Array) -> Result<(), JsError>>);
+
+    let their_serial = clipboard_serials.theirs;
+    let request_clipboard_closure = Closure::wrap(Box::new(move |mime_type: String| {
+        let request = ClipboardMessage::Request {
+            serial: their_serial.get(),
+            mime_type,
+        };
+        clipboard_tx
+            .unbounded_send(request)
+            .map_err(|e| JsError::new(&format!("Failed to request the clipboard: {:?}", e)))
+    }) as Box<dyn FnMut(String) -> Result<(), JsError>>);
+
     let dispatchers = WsDispatchers {
         close_connection: cancel_closure.into_js_value().into(),
         update_display_parameters: update_display_params_closure.into_js_value().into(),
+        send_input: send_input_closure.into_js_value().into(),
+        request_keyframe: request_keyframe_closure.into_js_value().into(),
+        send_receiver_report: send_receiver_report_closure.into_js_value().into(),
+        frame_presented: frame_presented_closure.into_js_value().into(),
+        offer_clipboard: offer_clipboard_closure.into_js_value().into(),
+        request_clipboard: request_clipboard_closure.into_js_value().into(),
         screen_data: shared_buffer,
     };
 
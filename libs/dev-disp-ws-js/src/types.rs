@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
 use dev_disp_transports::websocket::messages::{
-    DisplayParameters, EncoderPossibleConfiguration, WsMessageDeviceInfo,
+    ButtonState, DisplayCharacteristics, DisplayParameters, EncoderPossibleConfiguration,
+    FrameHeader, FramePresented, InputEvent, KeyEvent, NormalizedPosition, PenEvent, PenTool,
+    PointerButton, ReceiverReport, ScrollEvent, TouchEvent, TouchPhase, WsMessageDeviceInfo,
 };
 use js_sys::{Function, SharedArrayBuffer};
 use serde::{Deserialize, Serialize};
@@ -111,6 +113,20 @@ pub struct DevDispEvent {
 pub struct JsDisplayParameters {
     pub name: String,
     pub resolution: (u32, u32),
+    /// Size of the visible area, in millimeters
+    #[tsify(optional)]
+    pub physical_size_mm: Option<(u32, u32)>,
+    /// Pixel density, when the physical size isn't known
+    #[tsify(optional)]
+    pub dpi: Option<u32>,
+    #[tsify(optional)]
+    pub refresh_rate_hz: Option<u32>,
+    /// Bits per color channel
+    #[tsify(optional)]
+    pub bit_depth: Option<u8>,
+    /// Scale factor the display would like, such as `window.devicePixelRatio`
+    #[tsify(optional)]
+    pub preferred_scale: Option<f64>,
 }
 
 impl From<JsDisplayParameters> for DisplayParameters {
@@ -118,6 +134,15 @@ impl From<JsDisplayParameters> for DisplayParameters {
         DisplayParameters {
             host_dev_name: val.name,
             resolution: val.resolution,
+            characteristics: DisplayCharacteristics {
+                physical_size_mm: val.physical_size_mm,
+                dpi: val.dpi,
+                refresh_rate_hz: val.refresh_rate_hz,
+                bit_depth: val.bit_depth,
+                preferred_scale_percent: val
+                    .preferred_scale
+                    .map(|scale| (scale * 100.0).round() as u32),
+            },
         }
     }
 }
@@ -163,6 +188,233 @@ impl From<EncoderPossibleConfiguration> for JsEncoderPossibleConfiguration {
     }
 }
 
+#[derive(Tsify, Serialize, Clone, Debug)]
+#[tsify(into_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct JsFrameHeader {
+    pub sequence: u64,
+    pub capture_timestamp_us: u64,
+    pub encode_timestamp_us: u64,
+    pub keyframe: bool,
+    pub config_generation: u32,
+}
+
+impl From<FrameHeader> for JsFrameHeader {
+    fn from(val: FrameHeader) -> Self {
+        JsFrameHeader {
+            sequence: val.sequence,
+            capture_timestamp_us: val.capture_timestamp_us,
+            encode_timestamp_us: val.encode_timestamp_us,
+            keyframe: val.keyframe,
+            config_generation: val.config_generation,
+        }
+    }
+}
+
+/// How well the client kept up with the stream since its last report. All
+/// values must be whole numbers.
+#[derive(Tsify, Deserialize, Clone, Copy, Debug)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct JsReceiverReport {
+    pub interval_ms: u32,
+    pub bytes_received: u64,
+    pub frames_decoded: u32,
+    pub avg_decode_time_us: u32,
+    pub queue_depth: u32,
+    pub dropped_frames: u32,
+}
+
+impl From<JsReceiverReport> for ReceiverReport {
+    fn from(val: JsReceiverReport) -> Self {
+        ReceiverReport {
+            interval_ms: val.interval_ms,
+            bytes_received: val.bytes_received,
+            frames_decoded: val.frames_decoded,
+            avg_decode_time_us: val.avg_decode_time_us,
+            queue_depth: val.queue_depth,
+            dropped_frames: val.dropped_frames,
+        }
+    }
+}
+
+/// When a frame made it to the screen. Times are whole microseconds since the
+/// Unix epoch, from `(performance.timeOrigin + performance.now()) * 1000`.
+#[derive(Tsify, Deserialize, Clone, Copy, Debug)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub struct JsFramePresented {
+    /// The sequence number from the frame's header
+    pub sequence: u64,
+    pub received_at_us: u64,
+    pub decoded_at_us: u64,
+    pub presented_at_us: u64,
+}
+
+impl From<JsFramePresented> for FramePresented {
+    fn from(val: JsFramePresented) -> Self {
+        FramePresented {
+            sequence: val.sequence,
+            received_at_us: val.received_at_us,
+            decoded_at_us: val.decoded_at_us,
+            presented_at_us: val.presented_at_us,
+        }
+    }
+}
+
+#[derive(Tsify, Deserialize, Clone, Copy, Debug)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub enum JsPointerButton {
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+}
+
+impl From<JsPointerButton> for PointerButton {
+    fn from(val: JsPointerButton) -> Self {
+        match val {
+            JsPointerButton::Left => PointerButton::Left,
+            JsPointerButton::Right => PointerButton::Right,
+            JsPointerButton::Middle => PointerButton::Middle,
+            JsPointerButton::Back => PointerButton::Back,
+            JsPointerButton::Forward => PointerButton::Forward,
+        }
+    }
+}
+
+#[derive(Tsify, Deserialize, Clone, Copy, Debug)]
+#[tsify(from_wasm_abi)]
+#[serde(rename_all = "camelCase")]
+pub enum JsTouchPhase {
+    Start,
+    Move,
+    End,
+    Cancel,
+}
+
+impl From<JsTouchPhase> for TouchPhase {
+    fn from(val: JsTouchPhase) -> Self {
+        match val {
+            JsTouchPhase::Start => TouchPhase::Start,
+            JsTouchPhase::Move => TouchPhase::Move,
+            JsTouchPhase::End => TouchPhase::End,
+            JsTouchPhase::Cancel => TouchPhase::Cancel,
+        }
+    }
+}
+
+fn button_state(pressed: bool) -> ButtonState {
+    if pressed {
+        ButtonState::Pressed
+    } else {
+        ButtonState::Released
+    }
+}
+
+/// An input event to send to the server. Positions are normalized to the
+/// displayed frame, from 0 (top/left) to 1 (bottom/right).
+#[derive(Tsify, Deserialize, Clone, Debug)]
+#[tsify(from_wasm_abi)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum JsInputEvent {
+    PointerMove {
+        x: f32,
+        y: f32,
+    },
+    PointerButton {
+        x: f32,
+        y: f32,
+        button: JsPointerButton,
+        pressed: bool,
+    },
+    Touch {
+        id: u32,
+        phase: JsTouchPhase,
+        x: f32,
+        y: f32,
+    },
+    Pen {
+        phase: JsTouchPhase,
+        x: f32,
+        y: f32,
+        pressure: f32,
+        tilt_x: f32,
+        tilt_y: f32,
+        eraser: bool,
+        barrel_button: bool,
+    },
+    Scroll {
+        delta_x: f32,
+        delta_y: f32,
+    },
+    /// `code` is a `KeyboardEvent.code`
+    Key {
+        code: String,
+        pressed: bool,
+    },
+}
+
+impl From<JsInputEvent> for InputEvent {
+    fn from(val: JsInputEvent) -> Self {
+        match val {
+            JsInputEvent::PointerMove { x, y } => {
+                InputEvent::PointerMove(NormalizedPosition::new(x, y))
+            }
+            JsInputEvent::PointerButton {
+                x,
+                y,
+                button,
+                pressed,
+            } => InputEvent::PointerButton {
+                position: NormalizedPosition::new(x, y),
+                button: button.into(),
+                state: button_state(pressed),
+            },
+            JsInputEvent::Touch { id, phase, x, y } => InputEvent::Touch(TouchEvent {
+                id,
+                phase: phase.into(),
+                position: NormalizedPosition::new(x, y),
+            }),
+            JsInputEvent::Pen {
+                phase,
+                x,
+                y,
+                pressure,
+                tilt_x,
+                tilt_y,
+                eraser,
+                barrel_button,
+            } => InputEvent::Pen(PenEvent {
+                tool: if eraser {
+                    PenTool::Eraser
+                } else {
+                    PenTool::Pen
+                },
+                phase: phase.into(),
+                position: NormalizedPosition::new(x, y),
+                pressure,
+                tilt_x,
+                tilt_y,
+                barrel_button,
+            }),
+            JsInputEvent::Scroll { delta_x, delta_y } => {
+                InputEvent::Scroll(ScrollEvent { delta_x, delta_y })
+            }
+            JsInputEvent::Key { code, pressed } => InputEvent::Key(KeyEvent {
+                code,
+                state: button_state(pressed),
+            }),
+        }
+    }
+}
+
 #[wasm_bindgen(typescript_custom_section)]
 const WS_HANDLER_FN_TYPE_CONTENT: &str = r#"
 export type WsNotificationFunction = (event: DevDispEvent) => void;
@@ -175,7 +427,29 @@ export type WsHandlerRequestDeviceInfo = (event: DevDispEvent) => JsDisplayParam
 
 #[wasm_bindgen(typescript_custom_section)]
 const WS_HANDLER_SCREEN_DATA: &str = r#"
-export type WsHandlerScreenData = (event: DevDispEvent | null) => void;
+export type WsHandlerScreenData = (event: DevDispEvent | null, header: JsFrameHeader) => void;
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WS_HANDLER_SCREEN_REGIONS: &str = r#"
+export type JsScreenRegion = { x: number, y: number, width: number, height: number, data: Uint8Array };
+export type WsHandlerScreenRegions = (regions: JsScreenRegion[], header: JsFrameHeader) => void;
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WS_HANDLER_CURSOR_UPDATE: &str = r#"
+export type JsCursorUpdate =
+    | { type: "image", width: number, height: number, hotX: number, hotY: number, pixels: Uint8Array }
+    | { type: "move", x: number, y: number }
+    | { type: "visibility", visible: boolean };
+export type WsHandlerCursorUpdate = (update: JsCursorUpdate) => void;
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WS_HANDLER_CLIPBOARD: &str = r#"
+export type WsHandlerClipboardOffer = (mimeTypes: string[]) => void;
+export type WsHandlerClipboardRequest = (mimeType: string) => Promise<Uint8Array | null>;
+export type WsHandlerClipboardData = (mimeType: string, data: Uint8Array | null) => void;
 "#;
 
 #[wasm_bindgen(typescript_custom_section)]
@@ -205,6 +479,10 @@ pub struct WsHandlers {
     #[tsify(type = "WsNotificationFunction", optional)]
     pub on_pre_init_success: Option<Function>,
 
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsNotificationFunction", optional)]
+    pub on_pre_init_rejected: Option<Function>,
+
     #[serde(with = "serialize_option_function", default)]
     #[tsify(type = "WsNotificationFunction", optional)]
     pub on_protocol_init: Option<Function>,
@@ -223,6 +501,11 @@ pub struct WsHandlers {
     #[serde(with = "serialize_option_function", default)]
     #[tsify(type = "WsNotificationFunction", optional)]
     pub on_disconnect: Option<Function>,
+    /// The host closed the session, with the reason in the event's `data`.
+    /// `onDisconnect` follows.
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsNotificationFunction", optional)]
+    pub on_goodbye: Option<Function>,
 
     #[serde(with = "serialize_function")]
     #[tsify(type = "WsHandlerRequestDeviceInfo")]
@@ -232,6 +515,29 @@ pub struct WsHandlers {
     #[tsify(type = "WsHandlerScreenData")]
     pub handle_screen_data: Function,
 
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsHandlerScreenRegions", optional)]
+    pub handle_screen_regions: Option<Function>,
+
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsHandlerCursorUpdate", optional)]
+    pub handle_cursor_update: Option<Function>,
+
+    /// The host copied something. Call `requestClipboard` to get it.
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsHandlerClipboardOffer", optional)]
+    pub handle_clipboard_offer: Option<Function>,
+
+    /// The host wants the content of our latest `offerClipboard`.
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsHandlerClipboardRequest", optional)]
+    pub handle_clipboard_request: Option<Function>,
+
+    /// The answer to a `requestClipboard`.
+    #[serde(with = "serialize_option_function", default)]
+    #[tsify(type = "WsHandlerClipboardData", optional)]
+    pub handle_clipboard_data: Option<Function>,
+
     #[serde(with = "serialize_function")]
     #[tsify(type = "WsHandlerRequestDisplayParameters")]
     pub handle_request_display_parameters: Function,
@@ -250,6 +556,27 @@ const WS_DISPATCHER_UPDATE_DISPLAY_PARAMETERS: &str = r#"
 export type WsDispatcherUpdateDisplayParameters = (event: JsDisplayParameters) => void;
 "#;
 
+#[wasm_bindgen(typescript_custom_section)]
+const WS_DISPATCHER_SEND_INPUT: &str = r#"
+export type WsDispatcherSendInput = (event: JsInputEvent) => void;
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WS_DISPATCHER_SEND_RECEIVER_REPORT: &str = r#"
+export type WsDispatcherSendReceiverReport = (report: JsReceiverReport) => void;
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WS_DISPATCHER_FRAME_PRESENTED: &str = r#"
+export type WsDispatcherFramePresented = (presented: JsFramePresented) => void;
+"#;
+
+#[wasm_bindgen(typescript_custom_section)]
+const WS_DISPATCHER_CLIPBOARD: &str = r#"
+export type WsDispatcherOfferClipboard = (mimeTypes: string[]) => void;
+export type WsDispatcherRequestClipboard = (mimeType: string) => void;
+"#;
+
 #[derive(Tsify, Serialize, Deserialize, Clone, Debug)]
 #[tsify(into_wasm_abi)]
 #[serde(rename_all = "camelCase")]
@@ -262,6 +589,32 @@ pub struct WsDispatchers {
     #[tsify(type = "WsDispatcherUpdateDisplayParameters")]
     pub update_display_parameters: Function,
 
+    #[serde(with = "serialize_function")]
+    #[tsify(type = "WsDispatcherSendInput")]
+    pub send_input: Function,
+
+    #[serde(with = "serialize_function")]
+    #[tsify(type = "() => void")]
+    pub request_keyframe: Function,
+
+    #[serde(with = "serialize_function")]
+    #[tsify(type = "WsDispatcherSendReceiverReport")]
+    pub send_receiver_report: Function,
+
+    #[serde(with = "serialize_function")]
+    #[tsify(type = "WsDispatcherFramePresented")]
+    pub frame_presented: Function,
+
+    /// Tells the host our clipboard changed
+    #[serde(with = "serialize_function")]
+    #[tsify(type = "WsDispatcherOfferClipboard")]
+    pub offer_clipboard: Function,
+
+    /// Asks for the content of the host's latest clipboard offer
+    #[serde(with = "serialize_function")]
+    #[tsify(type = "WsDispatcherRequestClipboard")]
+    pub request_clipboard: Function,
+
     #[serde(with = "serialize_option_sab")]
     pub screen_data: Option<SharedArrayBuffer>,
 }
@@ -1,7 +1,11 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
 
-use js_sys::SharedArrayBuffer;
-use wasm_bindgen::{prelude::wasm_bindgen, JsValue};
+use js_sys::{Function, Promise, Reflect, SharedArrayBuffer};
+use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
 
 pub struct OnDropFn<F>
 where
@@ -90,3 +94,46 @@ extern "C" {
     pub fn shared_array_buffer_new_fallible(size: u32) -> Result<SharedArrayBuffer, JsValue>;
 
 }
+
+/// The current time in microseconds since the Unix epoch, from the high resolution
+/// `performance` clock where there is one. Pages should use the same clock
+/// (`performance.timeOrigin + performance.now()`) for the timestamps they report.
+pub fn client_time_us() -> u64 {
+    let millis = high_resolution_now().unwrap_or_else(js_sys::Date::now);
+    (millis * 1000.0) as u64
+}
+
+/// Waits for the given time on the JS event loop, with `setTimeout`.
+pub async fn sleep(duration: Duration) {
+    let promise = Promise::new(&mut |resolve, _| {
+        let global = js_sys::global();
+        let set_timeout = Reflect::get(&global, &"setTimeout".into())
+            .ok()
+            .and_then(|f| f.dyn_into::<Function>().ok());
+        match set_timeout {
+            Some(set_timeout) => {
+                let millis = JsValue::from(duration.as_millis() as f64);
+                let _ = set_timeout.call2(&global, &resolve, &millis);
+            }
+            None => {
+                let _ = resolve.call0(&JsValue::NULL);
+            }
+        }
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+fn high_resolution_now() -> Option<f64> {
+    let performance = Reflect::get(&js_sys::global(), &"performance".into()).ok()?;
+    let time_origin = Reflect::get(&performance, &"timeOrigin".into())
+        .ok()?
+        .as_f64()?;
+    let now = Reflect::get(&performance, &"now".into())
+        .ok()?
+        .dyn_into::<Function>()
+        .ok()?
+        .call0(&performance)
+        .ok()?
+        .as_f64()?;
+    Some(time_origin + now)
+}
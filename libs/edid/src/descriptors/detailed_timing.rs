@@ -271,3 +271,111 @@ impl EdidDetailedTimingDescriptor {
         bytes
     }
 }
+
+/// Minimum vertical blanking time for CVT reduced blanking, in microseconds
+const RB_MIN_V_BLANK_US: f64 = 460.0;
+const RB_H_BLANK: u16 = 160;
+const RB_H_FRONT_PORCH: u16 = 48;
+const RB_H_SYNC: u16 = 32;
+const RB_V_FRONT_PORCH: u8 = 3;
+const RB_MIN_V_BACK_PORCH: u8 = 6;
+/// Pixel clocks are rounded down to 0.25 MHz steps, here in 10 kHz units
+const RB_CLOCK_STEP: f64 = 25.0;
+
+impl EdidDetailedTimingDescriptor {
+    /// Timing for a mode per the VESA CVT reduced blanking formula, which suits
+    /// digital displays, virtual ones included.
+    ///
+    /// Pixel clocks past what EDID can express are capped.
+    pub fn reduced_blanking(
+        width: u16,
+        height: u16,
+        refresh_rate_hz: u32,
+        image_size_mm: (u16, u16),
+    ) -> Self {
+        let refresh_rate_hz = refresh_rate_hz.max(1) as f64;
+        let v_sync = cvt_vertical_sync_width(width, height);
+
+        let h_period_estimate_us =
+            (1_000_000.0 / refresh_rate_hz - RB_MIN_V_BLANK_US) / height.max(1) as f64;
+        let min_v_blanking = (RB_V_FRONT_PORCH + v_sync + RB_MIN_V_BACK_PORCH) as u16;
+        let v_blanking = if h_period_estimate_us > 0.0 {
+            ((RB_MIN_V_BLANK_US / h_period_estimate_us).floor() as u16 + 1).max(min_v_blanking)
+        } else {
+            min_v_blanking
+        };
+
+        let total_pixels = (width + RB_H_BLANK) as f64 * (height + v_blanking) as f64;
+        let pixel_clock =
+            (refresh_rate_hz * total_pixels / 10_000.0 / RB_CLOCK_STEP).floor() * RB_CLOCK_STEP;
+
+        Self {
+            pixel_clock: pixel_clock.min(u16::MAX as f64) as u16,
+            horizontal_active_pixels: width,
+            horizontal_blanking_pixels: RB_H_BLANK,
+            vertical_active_lines: height,
+            vertical_blanking_lines: v_blanking,
+            horizontal_sync_offset: RB_H_FRONT_PORCH,
+            horizontal_sync_pulse_width: RB_H_SYNC,
+            vertical_sync_offset: RB_V_FRONT_PORCH,
+            vertical_sync_pulse_width: v_sync,
+            horizontal_image_size_mm: image_size_mm.0,
+            vertical_image_size_mm: image_size_mm.1,
+            horizontal_border: 0,
+            vertical_border: 0,
+            features: FeaturesMap {
+                signal_type: SignalInterfaceType::NonInterlaced,
+                stereo_mode: StereoMode::None,
+                // Reduced blanking uses a positive H-sync and a negative V-sync
+                sync_type: SyncType::Digital(DigitalSyncFlags {
+                    v_sync_polarity: false,
+                    h_sync_positive: true,
+                }),
+            },
+        }
+    }
+}
+
+/// CVT picks the V-sync width by aspect ratio, so the mode's aspect ratio can be
+/// told from its timing.
+fn cvt_vertical_sync_width(width: u16, height: u16) -> u8 {
+    let (w, h) = (width as u32, height as u32);
+    if w * 3 == h * 4 {
+        4
+    } else if w * 9 == h * 16 {
+        5
+    } else if w * 10 == h * 16 {
+        6
+    } else if w * 4 == h * 5 || w * 9 == h * 15 {
+        7
+    } else {
+        10
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reduced_blanking_matches_vesa_1080p60() {
+        let timing = EdidDetailedTimingDescriptor::reduced_blanking(1920, 1080, 60, (527, 296));
+        assert_eq!(timing.pixel_clock, 13850);
+        assert_eq!(timing.horizontal_blanking_pixels, 160);
+        assert_eq!(timing.vertical_blanking_lines, 31);
+        assert_eq!(timing.vertical_sync_pulse_width, 5);
+    }
+
+    #[test]
+    fn reduced_blanking_matches_vesa_1440p60() {
+        let timing = EdidDetailedTimingDescriptor::reduced_blanking(2560, 1440, 60, (597, 336));
+        assert_eq!(timing.pixel_clock, 24150);
+        assert_eq!(timing.vertical_blanking_lines, 41);
+    }
+
+    #[test]
+    fn reduced_blanking_caps_pixel_clock() {
+        let timing = EdidDetailedTimingDescriptor::reduced_blanking(3840, 2160, 240, (600, 340));
+        assert_eq!(timing.pixel_clock, u16::MAX);
+    }
+}
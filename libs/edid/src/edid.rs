@@ -12,6 +12,22 @@ pub enum EdidDigitalBitDepth {
     Sixteen = 0b110,
 }
 
+impl EdidDigitalBitDepth {
+    /// The depth for the given bits per color channel, or `Undefined` when EDID
+    /// has no such depth.
+    pub fn from_bits_per_channel(bits: u8) -> Self {
+        match bits {
+            6 => Self::Six,
+            8 => Self::Eight,
+            10 => Self::Ten,
+            12 => Self::Twelve,
+            14 => Self::Fourteen,
+            16 => Self::Sixteen,
+            _ => Self::Undefined,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub enum EdidDigitalVideoInterface {
     Undefined = 0b0000,